@@ -1,5 +1,3 @@
-use core::str;
-
 use alloc::vec;
 use alloc::{format, string::String};
 use noli::net::{lookup_host, SocketAddr, TcpStream};
@@ -66,9 +64,6 @@ impl HttpClient {
             received.extend_from_slice(&buf[..bytes_read]);
         }
 
-        match str::from_utf8(&received) {
-            Ok(response) => HttpResponse::new(response.into()),
-            Err(e) => Err(Error::Network(format!("Invalid received response: {e}"))),
-        }
+        HttpResponse::from_bytes(&received)
     }
 }
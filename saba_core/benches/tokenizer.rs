@@ -0,0 +1,137 @@
+//! 数 MB の HTML や深く入れ子にした HTML をトークナイズ、パースするのにかかる時間と、確保したメモリの
+//! 量を測るベンチマーク
+//!
+//! `cargo bench --bench tokenizer` で実行する。メモリの量は、処理中に確保されていたヒープの最大量
+//! (入力の文字列を除く) と、確保した回数を表示する。
+//!
+//! `baseline` は、以前のトークナイザのように入力を `Vec<char>` に写して 1文字ずつ追加するだけの処理で、
+//! 状態遷移を含まない分、以前の方式で最低限かかる時間とメモリの量の目安になる。`tokenize` と比べる。
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+use saba_core::renderer::html::{parser::HtmlParser, token::HtmlTokenizer};
+
+/// 生成する HTML のおおよその大きさ (バイト)
+const DOCUMENT_SIZE: usize = 4 * 1024 * 1024;
+
+/// 深く入れ子にした文書の、要素の入れ子の深さ
+const NESTING_DEPTH: usize = 30000;
+
+/// 何回測定して最も速かったものを表示するか
+const ITERATIONS: usize = 5;
+
+/// 確保中のメモリの量とその最大値、確保した回数を記録するアロケータ
+struct CountingAllocator;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let current = CURRENT.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(current, Ordering::Relaxed);
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// 本文、属性、文字参照、日本語を含む HTML を `size` バイト程度になるまで繰り返して作る
+fn generate_document(size: usize) -> String {
+    let mut html = String::from("<!DOCTYPE html>\n<html><head><title>benchmark</title></head><body>\n");
+    let mut i = 0;
+    while html.len() < size {
+        html.push_str(&format!(
+            "<div class=\"item\" id=\"item-{i}\">\n  <h2>Item {i}</h2>\n  <p>Lorem ipsum dolor sit \
+             amet, consectetur adipiscing elit &amp; sed do eiusmod tempor incididunt ut labore \
+             et dolore magna aliqua.</p>\n  <p>これは日本語の文章です。ブラウザのしくみを作って\
+             学ぶ。</p>\n  <ul><li><a href=\"/items/{i}\">link</a></li><li>second</li></ul>\n\
+             </div>\n"
+        ));
+        i += 1;
+    }
+    html.push_str("</body></html>\n");
+    html
+}
+
+/// 以前のトークナイザと同じように、入力全体を `Vec<char>` に写し、1文字ずつ `String` に追加する
+fn copy_per_char(html: String) -> (Vec<char>, String) {
+    let chars: Vec<char> = html.chars().collect();
+    let mut text = String::new();
+    for c in &chars {
+        text.push(*c);
+    }
+    (chars, text)
+}
+
+/// `f` を実行し、かかった時間と確保したメモリの量を表示する
+fn measure<T>(name: &str, input: &str, f: impl Fn(String) -> T) {
+    let mut best = None;
+    let mut peak = 0;
+    let mut allocations = 0;
+
+    for _ in 0..ITERATIONS {
+        let input = input.to_string();
+        let base = CURRENT.load(Ordering::Relaxed);
+        PEAK.store(base, Ordering::Relaxed);
+        ALLOCATIONS.store(0, Ordering::Relaxed);
+
+        let start = Instant::now();
+        let result = black_box(f(input));
+        let elapsed = start.elapsed();
+
+        // 入力の文字列の分は除く
+        peak = PEAK.load(Ordering::Relaxed) - base;
+        allocations = ALLOCATIONS.load(Ordering::Relaxed);
+        drop(result);
+
+        if best.map_or(true, |best| elapsed < best) {
+            best = Some(elapsed);
+        }
+    }
+
+    println!(
+        "{:<10} {:>10.2} ms {:>10.2} MiB peak {:>10} allocations",
+        name,
+        best.unwrap_or_default().as_secs_f64() * 1000.0,
+        peak as f64 / (1024.0 * 1024.0),
+        allocations,
+    );
+}
+
+fn main() {
+    let html = generate_document(DOCUMENT_SIZE);
+    println!(
+        "input: {:.2} MiB, {} chars",
+        html.len() as f64 / (1024.0 * 1024.0),
+        html.chars().count()
+    );
+
+    measure("baseline", &html, copy_per_char);
+    measure("tokenize", &html, |html| HtmlTokenizer::new(html).count());
+    measure("parse", &html, |html| {
+        HtmlParser::new(HtmlTokenizer::new(html)).construct_tree()
+    });
+
+    // 要素の数に比例した時間でパースできることを確かめるために、深く入れ子にした文書も測る
+    let deep = "<div>".repeat(NESTING_DEPTH);
+    measure("deep", &deep, |html| {
+        HtmlParser::new(HtmlTokenizer::new(html)).construct_tree()
+    });
+}
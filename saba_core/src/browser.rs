@@ -2,7 +2,7 @@ use core::cell::RefCell;
 
 use alloc::{
     rc::{Rc, Weak},
-    string::String,
+    string::{String, ToString},
     vec::Vec,
 };
 
@@ -10,7 +10,10 @@ use crate::{
     http::HttpResponse,
     renderer::{
         dom::node::Window,
-        html::{parser::HtmlParser, token::HtmlTokenizer},
+        html::{
+            encoding_sniffing::HtmlByteDecoder, parse_error::ParseError, parser::HtmlParser,
+            serializer::serialize, token::HtmlTokenizer,
+        },
     },
     utils::convert_dom_to_string,
 };
@@ -44,6 +47,14 @@ impl Browser {
 pub struct Page {
     browser: Weak<RefCell<Browser>>,
     frame: Option<Rc<RefCell<Window>>>,
+    /// 読み込み中の HTML のパーサ。読み込みが終わると None になる
+    parser: Option<HtmlParser>,
+    /// 読み込み中の HTML のバイト列を文字列に変換するデコーダ
+    decoder: Option<HtmlByteDecoder>,
+    /// 最後に読み込んだ HTML のパースの失敗
+    parse_errors: Vec<ParseError>,
+    /// 読み込む文書の URL。相対 URL を解決するのに使う
+    url: Option<String>,
 }
 
 impl Page {
@@ -51,6 +62,10 @@ impl Page {
         Self {
             browser: Weak::new(),
             frame: None,
+            parser: None,
+            decoder: None,
+            parse_errors: Vec::new(),
+            url: None,
         }
     }
 
@@ -58,22 +73,91 @@ impl Page {
         self.browser = browser;
     }
 
+    /// 次に読み込む文書の URL を設定する。`start_loading` より前に呼ぶ
+    pub fn set_url(&mut self, url: &str) {
+        self.url = Some(url.to_string());
+    }
+
     pub fn receive_response(&mut self, response: HttpResponse) -> String {
-        self.create_frame(response.body());
+        let content_type = response.header_value("Content-Type").ok();
+        self.start_loading(content_type.as_deref());
+        self.receive_bytes(response.body_bytes());
+        self.finish_loading()
+    }
 
-        // デバッグ用に DOM ツリーを文字列として返す
-        if let Some(frame) = &self.frame {
-            let dom = frame.borrow().document().clone();
-            convert_dom_to_string(&Some(dom))
-        } else {
-            String::new()
+    /// HTML の読み込みを始める。本文は `receive_bytes` (または文字列に変換済みなら `receive_chunk`) で
+    /// 少しずつ渡し、最後に `finish_loading` を呼ぶ。`content_type` には Content-Type ヘッダの値を渡し、
+    /// 本文の文字コードを決めるのに使う
+    pub fn start_loading(&mut self, content_type: Option<&str>) {
+        let mut parser = HtmlParser::new(HtmlTokenizer::new_streaming());
+        parser.set_document_url(self.url.clone());
+        self.frame = None;
+        self.parser = Some(parser);
+        self.decoder = Some(HtmlByteDecoder::new(content_type));
+        self.parse_errors = Vec::new();
+    }
+
+    /// 受信した HTML のバイト列の一部を渡す。文字コードを判定して文字列に変換し、`receive_chunk` と
+    /// 同じようにパーサに渡す
+    pub fn receive_bytes(&mut self, bytes: &[u8]) {
+        let chunk = match self.decoder.as_mut() {
+            Some(decoder) => decoder.feed(bytes),
+            None => String::new(),
+        };
+        self.receive_chunk(&chunk);
+    }
+
+    /// 受信した HTML の一部を渡す。チャンクごとに文書全体を文字列にすると受信した量の 2乗に比例する
+    /// 時間がかかるので、何も返さない。本文をすべて受信する前に途中までの内容を表示したい場合は、
+    /// 必要なときに `text` や `html` で構築中の文書を取り出す
+    pub fn receive_chunk(&mut self, chunk: &str) {
+        if let Some(parser) = self.parser.as_mut() {
+            parser.feed(chunk);
+        }
+    }
+
+    /// HTML の読み込みを終え、完成した DOM ツリーを文字列として返す
+    pub fn finish_loading(&mut self) -> String {
+        if let Some(mut decoder) = self.decoder.take() {
+            let rest = decoder.finish();
+            self.receive_chunk(&rest);
         }
+        if let Some(mut parser) = self.parser.take() {
+            self.frame = Some(Rc::new(RefCell::new(parser.finish())));
+            self.parse_errors = parser.errors().to_vec();
+        }
+        self.dom_string()
+    }
+
+    /// 最後に読み込んだ HTML のパースの失敗を、見つかった順に返す
+    pub fn parse_errors(&self) -> &[ParseError] {
+        &self.parse_errors
+    }
+
+    /// 読み込んだ文書を HTML として書き出す。ページを保存するときに使う
+    pub fn html(&self) -> String {
+        self.with_window(|window| serialize(window.dom(), window.document()))
+    }
+
+    /// 読み込んだ文書のテキストを、描画したときの見た目に近い形で返す
+    pub fn text(&self) -> String {
+        self.with_window(|window| window.dom().inner_text(window.document()))
+    }
+
+    /// デバッグ用に DOM ツリーを文字列として返す
+    fn dom_string(&self) -> String {
+        self.with_window(|window| convert_dom_to_string(window.dom(), Some(window.document())))
     }
 
-    fn create_frame(&mut self, html: String) {
-        let html_tokenizer = HtmlTokenizer::new(html);
-        let frame = HtmlParser::new(html_tokenizer).construct_tree();
-        self.frame = Some(frame);
+    /// 読み込み中ならパーサが構築中の文書を、読み込み後なら完成した文書を `f` に渡す
+    fn with_window(&self, f: impl FnOnce(&Window) -> String) -> String {
+        if let Some(parser) = &self.parser {
+            f(parser.window())
+        } else if let Some(frame) = &self.frame {
+            f(&frame.borrow())
+        } else {
+            String::new()
+        }
     }
 }
 
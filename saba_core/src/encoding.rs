@@ -0,0 +1,562 @@
+//! 文字コード (エンコーディング) の名前の解決と、バイト列を文字列に変換するデコーダ
+//! https://encoding.spec.whatwg.org/
+
+pub mod jis0208;
+pub mod jis0212;
+
+use alloc::string::String;
+
+use self::{jis0208::JIS0208, jis0212::JIS0212};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Be,
+    Utf16Le,
+    /// ISO-8859-1 や US-ASCII もこのエンコーディングとして扱う
+    Windows1252,
+    ShiftJis,
+    EucJp,
+}
+
+impl Encoding {
+    /// ラベル (charset に指定される名前) からエンコーディングを求める。大文字と小文字は区別せず、
+    /// 前後の空白文字は無視する
+    /// https://encoding.spec.whatwg.org/#concept-encoding-get
+    pub fn for_label(label: &str) -> Option<Self> {
+        let label = label.trim_matches(|c| matches!(c, '\t' | '\n' | '\x0C' | '\r' | ' '));
+        LABELS
+            .iter()
+            .find(|(l, _)| l.eq_ignore_ascii_case(label))
+            .map(|(_, encoding)| *encoding)
+    }
+
+    /// https://encoding.spec.whatwg.org/#names-and-labels
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf16Be => "UTF-16BE",
+            Encoding::Utf16Le => "UTF-16LE",
+            Encoding::Windows1252 => "windows-1252",
+            Encoding::ShiftJis => "Shift_JIS",
+            Encoding::EucJp => "EUC-JP",
+        }
+    }
+
+    pub fn new_decoder(&self) -> Decoder {
+        Decoder::new(*self)
+    }
+
+    /// バイト列全体を文字列に変換する。変換できないバイト列は U+FFFD に置き換える
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        let mut decoder = self.new_decoder();
+        let mut s = decoder.decode(bytes);
+        s.push_str(&decoder.finish());
+        s
+    }
+}
+
+/// バイト列を少しずつ受け取って文字列に変換するデコーダ。複数のバイトからなる文字が
+/// チャンクの境界で分かれていても正しく変換する
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decoder {
+    encoding: Encoding,
+    state: DecoderState,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum DecoderState {
+    Utf8 {
+        code_point: u32,
+        bytes_seen: u8,
+        bytes_needed: u8,
+        lower_boundary: u8,
+        upper_boundary: u8,
+    },
+    Utf16 {
+        lead_byte: Option<u8>,
+        lead_surrogate: Option<u16>,
+    },
+    SingleByte,
+    ShiftJis {
+        lead: Option<u8>,
+    },
+    EucJp {
+        lead: Option<u8>,
+        jis0212: bool,
+    },
+}
+
+/// 1バイトを処理した結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    Continue,
+    /// 処理したバイトは次の文字の一部なので、もう一度処理する (prepend to stream)
+    Reprocess,
+}
+
+impl Decoder {
+    pub fn new(encoding: Encoding) -> Self {
+        let state = match encoding {
+            Encoding::Utf8 => DecoderState::Utf8 {
+                code_point: 0,
+                bytes_seen: 0,
+                bytes_needed: 0,
+                lower_boundary: 0x80,
+                upper_boundary: 0xBF,
+            },
+            Encoding::Utf16Be | Encoding::Utf16Le => DecoderState::Utf16 {
+                lead_byte: None,
+                lead_surrogate: None,
+            },
+            Encoding::Windows1252 => DecoderState::SingleByte,
+            Encoding::ShiftJis => DecoderState::ShiftJis { lead: None },
+            Encoding::EucJp => DecoderState::EucJp {
+                lead: None,
+                jis0212: false,
+            },
+        };
+
+        Self { encoding, state }
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// 受け取ったバイト列を変換する。最後の文字が途中で終わっている場合は、次の呼び出しまで残しておく
+    pub fn decode(&mut self, bytes: &[u8]) -> String {
+        let mut output = String::with_capacity(bytes.len());
+        for &byte in bytes {
+            while self.handle(Some(byte), &mut output) == Step::Reprocess {}
+        }
+        output
+    }
+
+    /// 入力の終わりを処理する。途中で終わっている文字は U+FFFD に置き換える
+    pub fn finish(&mut self) -> String {
+        let mut output = String::new();
+        self.handle(None, &mut output);
+        output
+    }
+
+    /// 1バイト (`None` の場合は入力の終わり) を処理する
+    fn handle(&mut self, byte: Option<u8>, output: &mut String) -> Step {
+        match self.state {
+            DecoderState::Utf8 { .. } => self.handle_utf8(byte, output),
+            DecoderState::Utf16 { .. } => self.handle_utf16(byte, output),
+            DecoderState::SingleByte => {
+                if let Some(byte) = byte {
+                    output.push(decode_windows_1252(byte));
+                }
+                Step::Continue
+            }
+            DecoderState::ShiftJis { .. } => self.handle_shift_jis(byte, output),
+            DecoderState::EucJp { .. } => self.handle_euc_jp(byte, output),
+        }
+    }
+
+    /// https://encoding.spec.whatwg.org/#utf-8-decoder
+    fn handle_utf8(&mut self, byte: Option<u8>, output: &mut String) -> Step {
+        let DecoderState::Utf8 {
+            ref mut code_point,
+            ref mut bytes_seen,
+            ref mut bytes_needed,
+            ref mut lower_boundary,
+            ref mut upper_boundary,
+        } = self.state
+        else {
+            unreachable!("the decoder should be UTF-8");
+        };
+
+        let Some(byte) = byte else {
+            if *bytes_needed != 0 {
+                *bytes_needed = 0;
+                output.push(char::REPLACEMENT_CHARACTER);
+            }
+            return Step::Continue;
+        };
+
+        if *bytes_needed == 0 {
+            match byte {
+                0x00..=0x7F => output.push(byte as char),
+                0xC2..=0xDF => {
+                    *bytes_needed = 1;
+                    *code_point = (byte & 0x1F) as u32;
+                }
+                0xE0..=0xEF => {
+                    if byte == 0xE0 {
+                        *lower_boundary = 0xA0;
+                    }
+                    if byte == 0xED {
+                        *upper_boundary = 0x9F;
+                    }
+                    *bytes_needed = 2;
+                    *code_point = (byte & 0x0F) as u32;
+                }
+                0xF0..=0xF4 => {
+                    if byte == 0xF0 {
+                        *lower_boundary = 0x90;
+                    }
+                    if byte == 0xF4 {
+                        *upper_boundary = 0x8F;
+                    }
+                    *bytes_needed = 3;
+                    *code_point = (byte & 0x07) as u32;
+                }
+                _ => output.push(char::REPLACEMENT_CHARACTER),
+            }
+            return Step::Continue;
+        }
+
+        if !(*lower_boundary..=*upper_boundary).contains(&byte) {
+            // 途中で終わった文字は U+FFFD に置き換え、現在のバイトは次の文字として処理し直す
+            *code_point = 0;
+            *bytes_needed = 0;
+            *bytes_seen = 0;
+            *lower_boundary = 0x80;
+            *upper_boundary = 0xBF;
+            output.push(char::REPLACEMENT_CHARACTER);
+            return Step::Reprocess;
+        }
+
+        *lower_boundary = 0x80;
+        *upper_boundary = 0xBF;
+        *code_point = (*code_point << 6) | (byte & 0x3F) as u32;
+        *bytes_seen += 1;
+        if *bytes_seen != *bytes_needed {
+            return Step::Continue;
+        }
+
+        output.push(char::from_u32(*code_point).unwrap_or(char::REPLACEMENT_CHARACTER));
+        *code_point = 0;
+        *bytes_needed = 0;
+        *bytes_seen = 0;
+        Step::Continue
+    }
+
+    /// https://encoding.spec.whatwg.org/#shared-utf-16-decoder
+    fn handle_utf16(&mut self, byte: Option<u8>, output: &mut String) -> Step {
+        let is_big_endian = self.encoding == Encoding::Utf16Be;
+        let DecoderState::Utf16 {
+            ref mut lead_byte,
+            ref mut lead_surrogate,
+        } = self.state
+        else {
+            unreachable!("the decoder should be UTF-16");
+        };
+
+        let Some(byte) = byte else {
+            if lead_byte.take().is_some() || lead_surrogate.take().is_some() {
+                output.push(char::REPLACEMENT_CHARACTER);
+            }
+            return Step::Continue;
+        };
+
+        let Some(lead) = lead_byte.take() else {
+            *lead_byte = Some(byte);
+            return Step::Continue;
+        };
+        let code_unit = if is_big_endian {
+            u16::from_be_bytes([lead, byte])
+        } else {
+            u16::from_le_bytes([lead, byte])
+        };
+
+        if let Some(lead) = lead_surrogate.take() {
+            if (0xDC00..=0xDFFF).contains(&code_unit) {
+                let code_point =
+                    0x10000 + ((lead as u32 - 0xD800) << 10) + (code_unit as u32 - 0xDC00);
+                output.push(char::from_u32(code_point).unwrap_or(char::REPLACEMENT_CHARACTER));
+                return Step::Continue;
+            }
+            // 対になる下位サロゲートがない。現在のコードユニットは次の文字として処理する
+            output.push(char::REPLACEMENT_CHARACTER);
+        }
+
+        match code_unit {
+            0xD800..=0xDBFF => *lead_surrogate = Some(code_unit),
+            0xDC00..=0xDFFF => output.push(char::REPLACEMENT_CHARACTER),
+            _ => {
+                output.push(char::from_u32(code_unit as u32).unwrap_or(char::REPLACEMENT_CHARACTER))
+            }
+        }
+        Step::Continue
+    }
+
+    /// https://encoding.spec.whatwg.org/#shift_jis-decoder
+    fn handle_shift_jis(&mut self, byte: Option<u8>, output: &mut String) -> Step {
+        let DecoderState::ShiftJis { ref mut lead } = self.state else {
+            unreachable!("the decoder should be Shift_JIS");
+        };
+
+        let Some(byte) = byte else {
+            if lead.take().is_some() {
+                output.push(char::REPLACEMENT_CHARACTER);
+            }
+            return Step::Continue;
+        };
+
+        if let Some(first) = lead.take() {
+            let offset = if byte < 0x7F { 0x40 } else { 0x41 };
+            let lead_offset = if first < 0xA0 { 0x81 } else { 0xC1 };
+            let pointer = matches!(byte, 0x40..=0x7E | 0x80..=0xFC)
+                .then(|| (first - lead_offset) as usize * 188 + (byte - offset) as usize);
+
+            let c = match pointer {
+                // 外字は私用領域の文字に変換する
+                Some(pointer @ 8836..=10715) => char::from_u32(0xE000 - 8836 + pointer as u32),
+                Some(pointer) => jis0208_char(pointer),
+                None => None,
+            };
+            if let Some(c) = c {
+                output.push(c);
+                return Step::Continue;
+            }
+
+            output.push(char::REPLACEMENT_CHARACTER);
+            // ASCII の文字は 2バイト目として使えないので、次の文字として処理し直す
+            return if byte.is_ascii() {
+                Step::Reprocess
+            } else {
+                Step::Continue
+            };
+        }
+
+        match byte {
+            0x00..=0x80 => output.push(byte as char),
+            // 半角カタカナ
+            0xA1..=0xDF => {
+                output.push(char::from_u32(0xFF61 - 0xA1 + byte as u32).unwrap_or_default())
+            }
+            0x81..=0x9F | 0xE0..=0xFC => *lead = Some(byte),
+            _ => output.push(char::REPLACEMENT_CHARACTER),
+        }
+        Step::Continue
+    }
+
+    /// https://encoding.spec.whatwg.org/#euc-jp-decoder
+    fn handle_euc_jp(&mut self, byte: Option<u8>, output: &mut String) -> Step {
+        let DecoderState::EucJp {
+            ref mut lead,
+            ref mut jis0212,
+        } = self.state
+        else {
+            unreachable!("the decoder should be EUC-JP");
+        };
+
+        let Some(byte) = byte else {
+            if lead.take().is_some() {
+                output.push(char::REPLACEMENT_CHARACTER);
+            }
+            return Step::Continue;
+        };
+
+        if let Some(first) = lead.take() {
+            let is_jis0212 = core::mem::take(jis0212);
+
+            // 半角カタカナ
+            if first == 0x8E && (0xA1..=0xDF).contains(&byte) {
+                output.push(char::from_u32(0xFF61 - 0xA1 + byte as u32).unwrap_or_default());
+                return Step::Continue;
+            }
+            // JIS X 0212 の文字は 0x8F に続く 2バイトで表す
+            if first == 0x8F && (0xA1..=0xFE).contains(&byte) {
+                *jis0212 = true;
+                *lead = Some(byte);
+                return Step::Continue;
+            }
+
+            let c = if (0xA1..=0xFE).contains(&first) && (0xA1..=0xFE).contains(&byte) {
+                let pointer = (first - 0xA1) as usize * 94 + (byte - 0xA1) as usize;
+                if is_jis0212 {
+                    jis0212_char(pointer)
+                } else {
+                    jis0208_char(pointer)
+                }
+            } else {
+                None
+            };
+            if let Some(c) = c {
+                output.push(c);
+                return Step::Continue;
+            }
+
+            output.push(char::REPLACEMENT_CHARACTER);
+            return if byte.is_ascii() {
+                Step::Reprocess
+            } else {
+                Step::Continue
+            };
+        }
+
+        match byte {
+            0x00..=0x7F => output.push(byte as char),
+            0x8E | 0x8F | 0xA1..=0xFE => *lead = Some(byte),
+            _ => output.push(char::REPLACEMENT_CHARACTER),
+        }
+        Step::Continue
+    }
+}
+
+/// windows-1252 の 1バイトを文字に変換する。0x80 から 0x9F 以外は ISO-8859-1 と同じ
+/// https://encoding.spec.whatwg.org/index-windows-1252.txt
+pub fn decode_windows_1252(byte: u8) -> char {
+    match byte {
+        0x80..=0x9F => {
+            let code = WINDOWS_1252_C1[(byte - 0x80) as usize];
+            char::from_u32(code as u32).unwrap_or(char::REPLACEMENT_CHARACTER)
+        }
+        _ => byte as char,
+    }
+}
+
+fn jis0208_char(pointer: usize) -> Option<char> {
+    JIS0208
+        .get(pointer)
+        .filter(|&&c| c != 0)
+        .and_then(|&c| char::from_u32(c as u32))
+}
+
+fn jis0212_char(pointer: usize) -> Option<char> {
+    JIS0212
+        .get(pointer)
+        .filter(|&&c| c != 0)
+        .and_then(|&c| char::from_u32(c as u32))
+}
+
+/// windows-1252 の 0x80 から 0x9F に対応するコードポイント
+const WINDOWS_1252_C1: [u16; 32] = [
+    0x20AC, 0x0081, 0x201A, 0x0192, 0x201E, 0x2026, 0x2020, 0x2021, 0x02C6, 0x2030, 0x0160, 0x2039,
+    0x0152, 0x008D, 0x017D, 0x008F, 0x0090, 0x2018, 0x2019, 0x201C, 0x201D, 0x2022, 0x2013, 0x2014,
+    0x02DC, 0x2122, 0x0161, 0x203A, 0x0153, 0x009D, 0x017E, 0x0178,
+];
+
+/// エンコーディングのラベルの一覧
+/// https://encoding.spec.whatwg.org/#names-and-labels
+const LABELS: &[(&str, Encoding)] = &[
+    ("unicode-1-1-utf-8", Encoding::Utf8),
+    ("unicode11utf8", Encoding::Utf8),
+    ("unicode20utf8", Encoding::Utf8),
+    ("utf-8", Encoding::Utf8),
+    ("utf8", Encoding::Utf8),
+    ("x-unicode20utf8", Encoding::Utf8),
+    ("unicodefffe", Encoding::Utf16Be),
+    ("utf-16be", Encoding::Utf16Be),
+    ("csunicode", Encoding::Utf16Le),
+    ("iso-10646-ucs-2", Encoding::Utf16Le),
+    ("ucs-2", Encoding::Utf16Le),
+    ("unicode", Encoding::Utf16Le),
+    ("unicodefeff", Encoding::Utf16Le),
+    ("utf-16", Encoding::Utf16Le),
+    ("utf-16le", Encoding::Utf16Le),
+    ("ansi_x3.4-1968", Encoding::Windows1252),
+    ("ascii", Encoding::Windows1252),
+    ("cp1252", Encoding::Windows1252),
+    ("cp819", Encoding::Windows1252),
+    ("csisolatin1", Encoding::Windows1252),
+    ("ibm819", Encoding::Windows1252),
+    ("iso-8859-1", Encoding::Windows1252),
+    ("iso-ir-100", Encoding::Windows1252),
+    ("iso8859-1", Encoding::Windows1252),
+    ("iso88591", Encoding::Windows1252),
+    ("iso_8859-1", Encoding::Windows1252),
+    ("iso_8859-1:1987", Encoding::Windows1252),
+    ("l1", Encoding::Windows1252),
+    ("latin1", Encoding::Windows1252),
+    ("us-ascii", Encoding::Windows1252),
+    ("windows-1252", Encoding::Windows1252),
+    ("x-cp1252", Encoding::Windows1252),
+    ("csshiftjis", Encoding::ShiftJis),
+    ("ms932", Encoding::ShiftJis),
+    ("ms_kanji", Encoding::ShiftJis),
+    ("shift-jis", Encoding::ShiftJis),
+    ("shift_jis", Encoding::ShiftJis),
+    ("sjis", Encoding::ShiftJis),
+    ("windows-31j", Encoding::ShiftJis),
+    ("x-sjis", Encoding::ShiftJis),
+    ("cseucpkdfmtjapanese", Encoding::EucJp),
+    ("euc-jp", Encoding::EucJp),
+    ("x-euc-jp", Encoding::EucJp),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_for_label() {
+        assert_eq!(
+            Some(Encoding::ShiftJis),
+            Encoding::for_label(" Shift_JIS\n")
+        );
+        assert_eq!(
+            Some(Encoding::Windows1252),
+            Encoding::for_label("ISO-8859-1")
+        );
+        assert_eq!(Some(Encoding::EucJp), Encoding::for_label("euc-jp"));
+        assert_eq!(None, Encoding::for_label("utf-7"));
+    }
+
+    #[test]
+    fn test_utf8() {
+        let bytes = "aあ😀".as_bytes();
+        assert_eq!("aあ😀", Encoding::Utf8.decode(bytes));
+
+        // 不正なバイト列と途中で終わる文字は U+FFFD に置き換える
+        assert_eq!(
+            "a\u{FFFD}b\u{FFFD}",
+            Encoding::Utf8.decode(b"a\xE3\x81b\xF0\x9F")
+        );
+        assert_eq!("\u{FFFD}\u{FFFD}", Encoding::Utf8.decode(b"\xC0\x80"));
+    }
+
+    #[test]
+    fn test_decode_in_chunks() {
+        let mut decoder = Encoding::Utf8.new_decoder();
+        let mut s = String::new();
+        for b in "日本語".as_bytes() {
+            s.push_str(&decoder.decode(&[*b]));
+        }
+        s.push_str(&decoder.finish());
+        assert_eq!("日本語", s);
+
+        let mut decoder = Encoding::ShiftJis.new_decoder();
+        assert_eq!("", decoder.decode(b"\x93"));
+        assert_eq!("日", decoder.decode(b"\xFA"));
+    }
+
+    #[test]
+    fn test_windows_1252() {
+        assert_eq!("€é\u{81}", Encoding::Windows1252.decode(b"\x80\xE9\x81"));
+    }
+
+    #[test]
+    fn test_shift_jis() {
+        // "日本語ｶﾅ" と外字
+        assert_eq!(
+            "日本語ｶﾅ\u{E000}",
+            Encoding::ShiftJis.decode(b"\x93\xFA\x96\x7B\x8C\xEA\xB6\xC5\xF0\x40")
+        );
+        // 2バイト目が ASCII の場合は、U+FFFD の後に ASCII の文字として扱う
+        assert_eq!("\u{FFFD} ", Encoding::ShiftJis.decode(b"\x93 "));
+        assert_eq!("〜①", Encoding::ShiftJis.decode(b"\x81\x60\x87\x40"));
+    }
+
+    #[test]
+    fn test_euc_jp() {
+        assert_eq!(
+            "日本語ｶﾅ",
+            Encoding::EucJp.decode(b"\xC6\xFC\xCB\xDC\xB8\xEC\x8E\xB6\x8E\xC5")
+        );
+        // JIS X 0212 の文字
+        assert_eq!("˘", Encoding::EucJp.decode(b"\x8F\xA2\xAF"));
+        assert_eq!("\u{FFFD}", Encoding::EucJp.decode(b"\xC6"));
+    }
+
+    #[test]
+    fn test_utf16() {
+        assert_eq!("a😀", Encoding::Utf16Le.decode(b"a\x00\x3D\xD8\x00\xDE"));
+        assert_eq!("a\u{FFFD}", Encoding::Utf16Be.decode(b"\x00a\xD8\x3D"));
+    }
+}
@@ -9,42 +9,61 @@ pub struct HttpResponse {
     status_code: u32,
     reason: String,
     headers: Vec<Header>,
-    body: String,
+    body: Vec<u8>,
 }
 
 impl HttpResponse {
     pub fn new(raw_response: String) -> Result<Self, Error> {
-        let preprocessed_response = raw_response.trim_start().replace("\r\n", "\n");
+        Self::from_bytes(raw_response.as_bytes())
+    }
 
-        let (status_line, remaining) = match preprocessed_response.split_once('\n') {
-            Some(splited) => splited,
-            None => {
-                return Err(Error::Network(format!(
-                    "invalid http response: {preprocessed_response}"
-                )));
-            }
+    /// 受信したバイト列からレスポンスを作る。ステータス行とヘッダは ASCII として解釈し、本文は文字コードが
+    /// わからないのでバイト列のまま保持する
+    pub fn from_bytes(raw_response: &[u8]) -> Result<Self, Error> {
+        let start = raw_response
+            .iter()
+            .position(|b| !b.is_ascii_whitespace())
+            .unwrap_or(raw_response.len());
+        let raw_response = &raw_response[start..];
+        let invalid_response = || {
+            Error::Network(format!(
+                "invalid http response: {}",
+                String::from_utf8_lossy(raw_response)
+            ))
         };
 
-        let (headers, body) = match remaining.split_once("\n\n") {
-            Some((h, b)) => {
-                let mut headers = vec![];
-                for header in h.split('\n') {
-                    let splitted_header: Vec<_> = header.splitn(2, ':').collect();
-                    headers.push(Header::new(
-                        splitted_header[0].trim().into(),
-                        splitted_header[1].trim().into(),
-                    ));
-                }
-                (headers, b)
+        let mut lines = Lines {
+            bytes: raw_response,
+            pos: 0,
+        };
+        let Some(status_line) = lines.next() else {
+            return Err(invalid_response());
+        };
+        let status_line_end = lines.pos;
+
+        // 空行までがヘッダで、その後ろが本文
+        let mut headers = vec![];
+        let mut body_start = None;
+        while let Some(line) = lines.next() {
+            if line.is_empty() {
+                body_start = Some(lines.pos);
+                break;
+            }
+            let (name, value) = line.split_once(':').unwrap_or((line.as_str(), ""));
+            headers.push(Header::new(name.trim().into(), value.trim().into()));
+        }
+        let body = match body_start {
+            Some(body_start) => &raw_response[body_start..],
+            None => {
+                // 空行がない場合は、ステータス行の後ろをすべて本文とする
+                headers = vec![];
+                &raw_response[status_line_end..]
             }
-            None => (vec![], remaining),
         };
 
         let statuses: Vec<_> = status_line.split(' ').collect();
         if statuses.len() != 3 {
-            return Err(Error::Network(format!(
-                "invalid http response: {preprocessed_response}"
-            )));
+            return Err(invalid_response());
         }
 
         Ok(Self {
@@ -52,7 +71,7 @@ impl HttpResponse {
             status_code: statuses[1].parse().unwrap_or(404),
             reason: statuses[2].into(),
             headers,
-            body: body.into(),
+            body: body.to_vec(),
         })
     }
 
@@ -72,8 +91,13 @@ impl HttpResponse {
         self.headers.clone()
     }
 
+    /// 本文を UTF-8 として解釈した文字列を返す。UTF-8 として不正なバイト列は U+FFFD に置き換える
     pub fn body(&self) -> String {
-        self.body.clone()
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    pub fn body_bytes(&self) -> &[u8] {
+        &self.body
     }
 
     pub fn header_value(&self, name: &str) -> Result<String, String> {
@@ -101,6 +125,24 @@ impl Header {
     }
 }
 
+/// バイト列を改行 (LF または CRLF) で区切り、ASCII として解釈した行を順に返す
+struct Lines<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Iterator for Lines<'_> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let len = self.bytes[self.pos..].iter().position(|&b| b == b'\n')?;
+        let line = &self.bytes[self.pos..self.pos + len];
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        self.pos += len + 1;
+        Some(String::from_utf8_lossy(line).into_owned())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use alloc::string::ToString;
@@ -152,6 +194,27 @@ mod tests {
         assert_eq!(res.body(), "body message".to_string());
     }
 
+    #[test]
+    fn test_crlf() {
+        let raw = "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n\r\n<p>\r\n</p>".into();
+        let res = HttpResponse::new(raw).expect("failed to parse http response");
+        assert_eq!(res.status_code(), 200);
+        assert_eq!(res.header_value("Content-Type"), Ok("text/html".into()));
+        assert_eq!(res.body(), "<p>\r\n</p>".to_string());
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        let raw = b"HTTP/1.1 200 OK\nContent-Type: text/html; charset=Shift_JIS\n\n\x93\xFA";
+        let res = HttpResponse::from_bytes(raw).expect("failed to parse http response");
+        assert_eq!(
+            res.header_value("Content-Type"),
+            Ok("text/html; charset=Shift_JIS".into())
+        );
+        assert_eq!(res.body_bytes(), b"\x93\xFA");
+        assert_eq!(res.body(), "\u{FFFD}\u{FFFD}".to_string());
+    }
+
     #[test]
     fn test_invalid() {
         let raw = "HTTP/1.1 200 OK".into();
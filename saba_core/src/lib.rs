@@ -3,6 +3,7 @@
 extern crate alloc;
 
 pub mod browser;
+pub mod encoding;
 pub mod error;
 pub mod http;
 pub mod renderer;
@@ -0,0 +1,1016 @@
+//! CSS のセレクタの解析と、DOM の要素との照合
+//! https://drafts.csswg.org/selectors-4/
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use crate::renderer::dom::node::{
+    Dom, DomException, Element, ElementKind, Namespace, NodeId, NodeKind, QuirksMode,
+};
+
+/// カンマで区切られたセレクタのリスト。どれか1つに一致すれば一致とする
+/// https://drafts.csswg.org/selectors-4/#selector-list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelectorList(Vec<ComplexSelector>);
+
+/// 結合子でつないだ複合セレクタの列。`combinators[i]` は `compounds[i]` と `compounds[i + 1]` の
+/// 間の結合子
+/// https://drafts.csswg.org/selectors-4/#complex
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComplexSelector {
+    compounds: Vec<CompoundSelector>,
+    combinators: Vec<Combinator>,
+}
+
+/// :has() の引数になる、先頭の結合子で基準の要素との関係を表すセレクタ
+/// https://drafts.csswg.org/selectors-4/#relative
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelativeSelector {
+    combinator: Combinator,
+    selector: ComplexSelector,
+}
+
+/// 空白を挟まずに並べた単純セレクタ。すべてに一致すれば一致とする
+/// https://drafts.csswg.org/selectors-4/#compound
+pub type CompoundSelector = Vec<SimpleSelector>;
+
+/// https://drafts.csswg.org/selectors-4/#selector-combinator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Combinator {
+    /// `A B`
+    Descendant,
+    /// `A > B`
+    Child,
+    /// `A + B`
+    NextSibling,
+    /// `A ~ B`
+    SubsequentSibling,
+}
+
+/// https://drafts.csswg.org/selectors-4/#simple
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SimpleSelector {
+    /// `*`
+    Universal,
+    /// `div`
+    Type(String),
+    /// `#id`
+    Id(String),
+    /// `.class`
+    Class(String),
+    /// `[name]` や `[name="value" i]`。`matcher` が `None` の場合は属性があるかだけを見る
+    Attribute {
+        name: String,
+        matcher: Option<AttributeMatcher>,
+    },
+    Not(SelectorList),
+    Is(SelectorList),
+    /// :is() と同じだが、詳細度が 0 になる
+    Where(SelectorList),
+    Has(Vec<RelativeSelector>),
+    /// `:nth-child(An+B of S)`。`of` がない場合は `of_selector` が `None`
+    NthChild {
+        a: i32,
+        b: i32,
+        of_selector: Option<SelectorList>,
+    },
+    FirstChild,
+    Empty,
+    Root,
+    Link,
+}
+
+/// 属性値の比較方法
+/// https://drafts.csswg.org/selectors-4/#attribute-representation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttributeMatcher {
+    operator: AttributeOperator,
+    value: String,
+    /// `i` フラグが付いている場合は、ASCII の大文字と小文字を区別しない
+    case_insensitive: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttributeOperator {
+    /// `=`
+    Equal,
+    /// `~=`
+    Includes,
+    /// `|=`
+    DashMatch,
+    /// `^=`
+    Prefix,
+    /// `$=`
+    Suffix,
+    /// `*=`
+    Substring,
+}
+
+impl SelectorList {
+    /// セレクタの文字列を解析する。構文が正しくない場合や、対応していない擬似クラスを含む場合は
+    /// `SyntaxError` を返す
+    /// https://drafts.csswg.org/selectors-4/#parse-a-selector
+    pub fn parse(input: &str) -> Result<Self, DomException> {
+        let mut parser = SelectorParser { input, pos: 0 };
+        let list = parser.parse_selector_list()?;
+        parser.skip_whitespace();
+        if parser.peek().is_some() {
+            return Err(DomException::SyntaxError);
+        }
+        Ok(list)
+    }
+
+    /// `element` がどれかのセレクタに一致するかどうか。要素でないノードは一致しない
+    /// https://drafts.csswg.org/selectors-4/#match-a-selector-against-an-element
+    pub fn matches(&self, dom: &Dom, element: NodeId) -> bool {
+        let quirks = dom
+            .node(dom.document())
+            .get_document()
+            .is_some_and(|d| d.mode() == QuirksMode::Quirks);
+        let context = MatchContext { dom, quirks };
+        context.matches_list(self, element)
+    }
+}
+
+/// セレクタの文字列を先頭から読む。`pos` は `input` の中のバイト位置
+struct SelectorParser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl SelectorParser<'_> {
+    fn rest(&self) -> &str {
+        &self.input[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn peek_nth(&self, n: usize) -> Option<char> {
+        self.rest().chars().nth(n)
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    /// 次の文字が `c` なら読み進めて true を返す
+    fn consume(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), DomException> {
+        if self.consume(c) {
+            Ok(())
+        } else {
+            Err(DomException::SyntaxError)
+        }
+    }
+
+    /// 空白とコメントを読み飛ばす。何か読み飛ばした場合は true を返す
+    fn skip_whitespace(&mut self) -> bool {
+        let start = self.pos;
+        loop {
+            if self.peek().is_some_and(is_whitespace) {
+                self.next_char();
+            } else if self.rest().starts_with("/*") {
+                self.pos = match self.rest()[2..].find("*/") {
+                    Some(end) => self.pos + 2 + end + 2,
+                    None => self.input.len(),
+                };
+            } else {
+                break;
+            }
+        }
+        self.pos != start
+    }
+
+    /// https://drafts.csswg.org/selectors-4/#typedef-complex-selector-list
+    fn parse_selector_list(&mut self) -> Result<SelectorList, DomException> {
+        let mut selectors = Vec::new();
+        loop {
+            self.skip_whitespace();
+            selectors.push(self.parse_complex_selector()?);
+            self.skip_whitespace();
+            if !self.consume(',') {
+                break;
+            }
+        }
+        Ok(SelectorList(selectors))
+    }
+
+    /// https://drafts.csswg.org/selectors-4/#typedef-relative-selector-list
+    fn parse_relative_selector_list(&mut self) -> Result<Vec<RelativeSelector>, DomException> {
+        let mut selectors = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let combinator = self.parse_combinator().unwrap_or(Combinator::Descendant);
+            self.skip_whitespace();
+            let selector = self.parse_complex_selector()?;
+            selectors.push(RelativeSelector {
+                combinator,
+                selector,
+            });
+            self.skip_whitespace();
+            if !self.consume(',') {
+                break;
+            }
+        }
+        Ok(selectors)
+    }
+
+    fn parse_combinator(&mut self) -> Option<Combinator> {
+        let combinator = match self.peek()? {
+            '>' => Combinator::Child,
+            '+' => Combinator::NextSibling,
+            '~' => Combinator::SubsequentSibling,
+            _ => return None,
+        };
+        self.next_char();
+        Some(combinator)
+    }
+
+    fn parse_complex_selector(&mut self) -> Result<ComplexSelector, DomException> {
+        let mut compounds = alloc::vec![self.parse_compound_selector()?];
+        let mut combinators = Vec::new();
+        loop {
+            let has_whitespace = self.skip_whitespace();
+            let combinator = match self.parse_combinator() {
+                Some(combinator) => {
+                    self.skip_whitespace();
+                    combinator
+                }
+                // 空白だけで区切られている場合は子孫結合子になる
+                None => match self.peek() {
+                    Some(c) if has_whitespace && c != ',' && c != ')' => Combinator::Descendant,
+                    _ => break,
+                },
+            };
+            combinators.push(combinator);
+            compounds.push(self.parse_compound_selector()?);
+        }
+        Ok(ComplexSelector {
+            compounds,
+            combinators,
+        })
+    }
+
+    fn parse_compound_selector(&mut self) -> Result<CompoundSelector, DomException> {
+        let mut compound = Vec::new();
+        if self.consume('*') {
+            compound.push(SimpleSelector::Universal);
+        } else if self.starts_ident() {
+            compound.push(SimpleSelector::Type(self.parse_name()));
+        }
+
+        loop {
+            let selector = match self.peek() {
+                Some('#') => {
+                    self.next_char();
+                    // `#1a` のように識別子として始まらないものは id セレクタにならない
+                    SimpleSelector::Id(self.parse_ident()?)
+                }
+                Some('.') => {
+                    self.next_char();
+                    SimpleSelector::Class(self.parse_ident()?)
+                }
+                Some('[') => {
+                    self.next_char();
+                    self.parse_attribute_selector()?
+                }
+                Some(':') => {
+                    self.next_char();
+                    self.parse_pseudo_class()?
+                }
+                _ => break,
+            };
+            compound.push(selector);
+        }
+
+        if compound.is_empty() {
+            Err(DomException::SyntaxError)
+        } else {
+            Ok(compound)
+        }
+    }
+
+    /// `[` の後から `]` までを読む
+    /// https://drafts.csswg.org/selectors-4/#attribute-selectors
+    fn parse_attribute_selector(&mut self) -> Result<SimpleSelector, DomException> {
+        self.skip_whitespace();
+        let name = self.parse_ident()?;
+        self.skip_whitespace();
+        if self.consume(']') {
+            return Ok(SimpleSelector::Attribute {
+                name,
+                matcher: None,
+            });
+        }
+
+        let operator = match self.next_char() {
+            Some('=') => AttributeOperator::Equal,
+            Some(c) => {
+                let operator = match c {
+                    '~' => AttributeOperator::Includes,
+                    '|' => AttributeOperator::DashMatch,
+                    '^' => AttributeOperator::Prefix,
+                    '$' => AttributeOperator::Suffix,
+                    '*' => AttributeOperator::Substring,
+                    _ => return Err(DomException::SyntaxError),
+                };
+                self.expect('=')?;
+                operator
+            }
+            None => return Err(DomException::SyntaxError),
+        };
+        self.skip_whitespace();
+        let value = match self.peek() {
+            Some(quote @ ('"' | '\'')) => {
+                self.next_char();
+                self.parse_string(quote)?
+            }
+            _ => self.parse_ident()?,
+        };
+        self.skip_whitespace();
+
+        let mut case_insensitive = false;
+        if self.starts_ident() {
+            case_insensitive = match self.parse_name().to_ascii_lowercase().as_str() {
+                "i" => true,
+                "s" => false,
+                _ => return Err(DomException::SyntaxError),
+            };
+            self.skip_whitespace();
+        }
+        self.expect(']')?;
+
+        Ok(SimpleSelector::Attribute {
+            name,
+            matcher: Some(AttributeMatcher {
+                operator,
+                value,
+                case_insensitive,
+            }),
+        })
+    }
+
+    /// `:` の後を読む
+    /// https://drafts.csswg.org/selectors-4/#pseudo-classes
+    fn parse_pseudo_class(&mut self) -> Result<SimpleSelector, DomException> {
+        // 擬似要素は要素に一致しないので扱わない
+        let name = self.parse_ident()?.to_ascii_lowercase();
+        if !self.consume('(') {
+            return match name.as_str() {
+                "first-child" => Ok(SimpleSelector::FirstChild),
+                "empty" => Ok(SimpleSelector::Empty),
+                "root" => Ok(SimpleSelector::Root),
+                "link" => Ok(SimpleSelector::Link),
+                _ => Err(DomException::SyntaxError),
+            };
+        }
+
+        let selector = match name.as_str() {
+            "not" => SimpleSelector::Not(self.parse_selector_list()?),
+            "is" => SimpleSelector::Is(self.parse_selector_list()?),
+            "where" => SimpleSelector::Where(self.parse_selector_list()?),
+            "has" => SimpleSelector::Has(self.parse_relative_selector_list()?),
+            "nth-child" => {
+                let (a, b) = self.parse_an_plus_b()?;
+                self.skip_whitespace();
+                let of_selector = if self.starts_keyword("of") {
+                    self.parse_name();
+                    Some(self.parse_selector_list()?)
+                } else {
+                    None
+                };
+                SimpleSelector::NthChild { a, b, of_selector }
+            }
+            _ => return Err(DomException::SyntaxError),
+        };
+        self.skip_whitespace();
+        self.expect(')')?;
+        Ok(selector)
+    }
+
+    /// `odd`、`even`、`3`、`-n+2`、`2n - 1` のような An+B の記法を読む
+    /// https://drafts.csswg.org/css-syntax-3/#anb-microsyntax
+    fn parse_an_plus_b(&mut self) -> Result<(i32, i32), DomException> {
+        self.skip_whitespace();
+        if self.starts_keyword("odd") {
+            self.parse_name();
+            return Ok((2, 1));
+        }
+        if self.starts_keyword("even") {
+            self.parse_name();
+            return Ok((2, 0));
+        }
+
+        let sign = self.parse_sign();
+        let digits = self.parse_integer()?;
+        if !self.consume('n') && !self.consume('N') {
+            let b = digits.ok_or(DomException::SyntaxError)?;
+            return Ok((0, sign * b));
+        }
+        let a = sign * digits.unwrap_or(1);
+
+        self.skip_whitespace();
+        let b = match self.peek() {
+            Some('+' | '-') => {
+                let sign = self.parse_sign();
+                self.skip_whitespace();
+                sign * self.parse_integer()?.ok_or(DomException::SyntaxError)?
+            }
+            _ => 0,
+        };
+        Ok((a, b))
+    }
+
+    fn parse_sign(&mut self) -> i32 {
+        if self.consume('-') {
+            -1
+        } else {
+            self.consume('+');
+            1
+        }
+    }
+
+    /// 数字が続く場合は整数として読む。数字がない場合は `None` を返す
+    fn parse_integer(&mut self) -> Result<Option<i32>, DomException> {
+        let len = self
+            .rest()
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(self.rest().len());
+        if len == 0 {
+            return Ok(None);
+        }
+        let digits = &self.rest()[..len];
+        let value = digits.parse().map_err(|_| DomException::SyntaxError)?;
+        self.pos += len;
+        Ok(Some(value))
+    }
+
+    /// 次に `keyword` という識別子が続くかどうか。ASCII の大文字と小文字は区別しない
+    fn starts_keyword(&self, keyword: &str) -> bool {
+        let rest = self.rest();
+        rest.len() >= keyword.len()
+            && rest.is_char_boundary(keyword.len())
+            && rest[..keyword.len()].eq_ignore_ascii_case(keyword)
+            && !rest[keyword.len()..]
+                .chars()
+                .next()
+                .is_some_and(|c| is_name_char(c) || c == '\\')
+    }
+
+    /// 次に識別子が始まるかどうか
+    /// https://drafts.csswg.org/css-syntax-3/#would-start-an-identifier
+    fn starts_ident(&self) -> bool {
+        match self.peek() {
+            Some('-') => match self.peek_nth(1) {
+                Some(c) => is_name_start_char(c) || c == '-' || c == '\\',
+                None => false,
+            },
+            Some('\\') => true,
+            Some(c) => is_name_start_char(c),
+            None => false,
+        }
+    }
+
+    fn parse_ident(&mut self) -> Result<String, DomException> {
+        if !self.starts_ident() {
+            return Err(DomException::SyntaxError);
+        }
+        Ok(self.parse_name())
+    }
+
+    /// 識別子に使える文字とエスケープを読む
+    /// https://drafts.csswg.org/css-syntax-3/#consume-name
+    fn parse_name(&mut self) -> String {
+        let mut name = String::new();
+        while let Some(c) = self.peek() {
+            if c == '\\' {
+                self.next_char();
+                name.push(self.parse_escape());
+            } else if is_name_char(c) {
+                self.next_char();
+                name.push(c);
+            } else {
+                break;
+            }
+        }
+        name
+    }
+
+    /// `\` の後のエスケープを読む
+    /// https://drafts.csswg.org/css-syntax-3/#consume-escaped-code-point
+    fn parse_escape(&mut self) -> char {
+        let len = self
+            .rest()
+            .char_indices()
+            .take_while(|(i, c)| *i < 6 && c.is_ascii_hexdigit())
+            .count();
+        if len == 0 {
+            return self.next_char().unwrap_or('\u{FFFD}');
+        }
+
+        let code = u32::from_str_radix(&self.rest()[..len], 16).unwrap_or(0);
+        self.pos += len;
+        if self.peek().is_some_and(is_whitespace) {
+            self.next_char();
+        }
+        match char::from_u32(code) {
+            Some(c) if c != '\0' => c,
+            _ => '\u{FFFD}',
+        }
+    }
+
+    /// 開始の引用符の後から、閉じる引用符までを読む。入力の終わりまで閉じていなくてもよい
+    /// https://drafts.csswg.org/css-syntax-3/#consume-string-token
+    fn parse_string(&mut self, quote: char) -> Result<String, DomException> {
+        let mut s = String::new();
+        while let Some(c) = self.next_char() {
+            match c {
+                _ if c == quote => return Ok(s),
+                '\n' => return Err(DomException::SyntaxError),
+                '\\' => match self.peek() {
+                    None => {}
+                    // 改行のエスケープは文字列を次の行に続ける
+                    Some('\n') => {
+                        self.next_char();
+                    }
+                    Some(_) => s.push(self.parse_escape()),
+                },
+                _ => s.push(c),
+            }
+        }
+        Ok(s)
+    }
+}
+
+/// https://drafts.csswg.org/css-syntax-3/#whitespace
+fn is_whitespace(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\u{0C}' | '\r' | ' ')
+}
+
+/// https://drafts.csswg.org/css-syntax-3/#ident-start-code-point
+fn is_name_start_char(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || !c.is_ascii()
+}
+
+/// https://drafts.csswg.org/css-syntax-3/#ident-code-point
+fn is_name_char(c: char) -> bool {
+    is_name_start_char(c) || c.is_ascii_digit() || c == '-'
+}
+
+/// 照合に使う文書の情報
+struct MatchContext<'a> {
+    dom: &'a Dom,
+    /// 互換モードの文書では、id と class を ASCII の大文字と小文字を区別せずに比べる
+    quirks: bool,
+}
+
+impl MatchContext<'_> {
+    fn element(&self, node: NodeId) -> Option<&Element> {
+        match self.dom.node(node).kind {
+            NodeKind::Element(ref e) => Some(e),
+            _ => None,
+        }
+    }
+
+    fn parent_element(&self, node: NodeId) -> Option<NodeId> {
+        self.dom
+            .node(node)
+            .parent()
+            .filter(|p| self.element(*p).is_some())
+    }
+
+    fn previous_element_sibling(&self, node: NodeId) -> Option<NodeId> {
+        let mut sibling = self.dom.node(node).previous_sibling();
+        while let Some(s) = sibling {
+            if self.element(s).is_some() {
+                return Some(s);
+            }
+            sibling = self.dom.node(s).previous_sibling();
+        }
+        None
+    }
+
+    fn matches_list(&self, list: &SelectorList, element: NodeId) -> bool {
+        list.0
+            .iter()
+            .any(|selector| self.matches_complex(selector, element, None))
+    }
+
+    /// 複合セレクタを右から順に照合する。`anchor` を指定した場合は、最も左の複合セレクタに一致した
+    /// 要素が、`anchor` の要素と結合子の関係にあることも確かめる (:has() の引数の照合に使う)
+    fn matches_complex(
+        &self,
+        selector: &ComplexSelector,
+        element: NodeId,
+        anchor: Option<(Combinator, NodeId)>,
+    ) -> bool {
+        self.matches_from(selector, selector.compounds.len() - 1, element, anchor)
+    }
+
+    fn matches_from(
+        &self,
+        selector: &ComplexSelector,
+        index: usize,
+        element: NodeId,
+        anchor: Option<(Combinator, NodeId)>,
+    ) -> bool {
+        if !self.matches_compound(&selector.compounds[index], element) {
+            return false;
+        }
+        let combinator = if index == 0 {
+            match anchor {
+                Some((combinator, _)) => combinator,
+                None => return true,
+            }
+        } else {
+            selector.combinators[index - 1]
+        };
+        // 結合子の左側に一致するかどうか
+        let matches_left = |n: NodeId| {
+            if index == 0 {
+                anchor.is_some_and(|(_, anchor)| n == anchor)
+            } else {
+                self.matches_from(selector, index - 1, n, anchor)
+            }
+        };
+
+        match combinator {
+            Combinator::Child => self.parent_element(element).is_some_and(matches_left),
+            Combinator::Descendant => {
+                let mut ancestor = self.parent_element(element);
+                while let Some(a) = ancestor {
+                    if matches_left(a) {
+                        return true;
+                    }
+                    ancestor = self.parent_element(a);
+                }
+                false
+            }
+            Combinator::NextSibling => self
+                .previous_element_sibling(element)
+                .is_some_and(matches_left),
+            Combinator::SubsequentSibling => {
+                let mut sibling = self.previous_element_sibling(element);
+                while let Some(s) = sibling {
+                    if matches_left(s) {
+                        return true;
+                    }
+                    sibling = self.previous_element_sibling(s);
+                }
+                false
+            }
+        }
+    }
+
+    fn matches_compound(&self, compound: &CompoundSelector, element: NodeId) -> bool {
+        compound.iter().all(|s| self.matches_simple(s, element))
+    }
+
+    fn matches_simple(&self, selector: &SimpleSelector, node: NodeId) -> bool {
+        let Some(element) = self.element(node) else {
+            return false;
+        };
+        match selector {
+            SimpleSelector::Universal => true,
+            // HTML の要素の名前は大文字と小文字を区別しない
+            SimpleSelector::Type(name) => match element.namespace() {
+                Namespace::Html => element.local_name().eq_ignore_ascii_case(name),
+                _ => element.local_name() == *name,
+            },
+            SimpleSelector::Id(id) => {
+                let value = element.id();
+                !value.is_empty() && self.compare_identifier(&value, id)
+            }
+            SimpleSelector::Class(class) => element
+                .class_list()
+                .iter()
+                .any(|c| self.compare_identifier(c, class)),
+            SimpleSelector::Attribute { name, matcher } => match element.get_attribute(name) {
+                Some(value) => matcher.as_ref().map_or(true, |m| m.matches(&value)),
+                None => false,
+            },
+            SimpleSelector::Not(list) => !self.matches_list(list, node),
+            SimpleSelector::Is(list) | SimpleSelector::Where(list) => self.matches_list(list, node),
+            SimpleSelector::Has(selectors) => selectors.iter().any(|s| self.matches_has(s, node)),
+            SimpleSelector::NthChild { a, b, of_selector } => {
+                if of_selector
+                    .as_ref()
+                    .is_some_and(|list| !self.matches_list(list, node))
+                {
+                    return false;
+                }
+                // 条件に一致する兄弟の中で何番目か (1 から数える)
+                let mut index = 1;
+                let mut sibling = self.previous_element_sibling(node);
+                while let Some(s) = sibling {
+                    if of_selector
+                        .as_ref()
+                        .map_or(true, |list| self.matches_list(list, s))
+                    {
+                        index += 1;
+                    }
+                    sibling = self.previous_element_sibling(s);
+                }
+                matches_an_plus_b(*a, *b, index)
+            }
+            SimpleSelector::FirstChild => self.previous_element_sibling(node).is_none(),
+            // コメントは子として数えない
+            SimpleSelector::Empty => self
+                .dom
+                .children(node)
+                .all(|c| match self.dom.node(c).kind {
+                    NodeKind::Element(_) => false,
+                    NodeKind::Text(ref s) => s.is_empty(),
+                    _ => true,
+                }),
+            SimpleSelector::Root => self
+                .dom
+                .node(node)
+                .parent()
+                .is_some_and(|p| matches!(self.dom.node(p).kind, NodeKind::Document(_))),
+            // 訪問履歴を持たないので、すべてのリンクを未訪問として扱う
+            SimpleSelector::Link => {
+                element.namespace() == Namespace::Html
+                    && matches!(element.kind(), ElementKind::A | ElementKind::Area)
+                    && element.has_attribute("href")
+            }
+        }
+    }
+
+    /// `anchor` を基準に、相対セレクタに一致する要素があるかどうか
+    /// https://drafts.csswg.org/selectors-4/#relational
+    fn matches_has(&self, selector: &RelativeSelector, anchor: NodeId) -> bool {
+        let dom = self.dom;
+        let anchored = Some((selector.combinator, anchor));
+        match selector.combinator {
+            Combinator::Descendant | Combinator::Child => dom
+                .descendants(anchor)
+                .any(|n| self.matches_complex(&selector.selector, n, anchored)),
+            // 兄弟の子孫も、セレクタの続きによっては候補になる
+            Combinator::NextSibling | Combinator::SubsequentSibling => {
+                dom.following_siblings(anchor).any(|sibling| {
+                    core::iter::once(sibling)
+                        .chain(dom.descendants(sibling))
+                        .any(|n| self.matches_complex(&selector.selector, n, anchored))
+                })
+            }
+        }
+    }
+
+    fn compare_identifier(&self, a: &str, b: &str) -> bool {
+        if self.quirks {
+            a.eq_ignore_ascii_case(b)
+        } else {
+            a == b
+        }
+    }
+}
+
+impl AttributeMatcher {
+    /// https://drafts.csswg.org/selectors-4/#attribute-representation
+    fn matches(&self, value: &str) -> bool {
+        let (value, expected) = if self.case_insensitive {
+            (value.to_ascii_lowercase(), self.value.to_ascii_lowercase())
+        } else {
+            (value.to_string(), self.value.clone())
+        };
+        match self.operator {
+            AttributeOperator::Equal => value == expected,
+            AttributeOperator::Includes => {
+                !expected.is_empty()
+                    && !expected.contains(is_whitespace)
+                    && value.split(is_whitespace).any(|v| v == expected)
+            }
+            AttributeOperator::DashMatch => {
+                value == expected
+                    || value
+                        .strip_prefix(expected.as_str())
+                        .is_some_and(|rest| rest.starts_with('-'))
+            }
+            AttributeOperator::Prefix => !expected.is_empty() && value.starts_with(&expected),
+            AttributeOperator::Suffix => !expected.is_empty() && value.ends_with(&expected),
+            AttributeOperator::Substring => !expected.is_empty() && value.contains(&expected),
+        }
+    }
+}
+
+/// 0 以上の整数 n で `a * n + b == index` となるものがあるかどうか。`a` や `b` が i32 の範囲の端に
+/// あっても溢れないように、i64 で計算する
+fn matches_an_plus_b(a: i32, b: i32, index: i32) -> bool {
+    let (a, b, index) = (i64::from(a), i64::from(b), i64::from(index));
+    if a == 0 {
+        return index == b;
+    }
+    let diff = index - b;
+    diff % a == 0 && diff / a >= 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::{
+        dom::node::Window,
+        html::{parser::HtmlParser, token::HtmlTokenizer},
+    };
+    use alloc::vec;
+
+    fn parse(html: &str) -> Window {
+        HtmlParser::new(HtmlTokenizer::new(html.to_string())).construct_tree()
+    }
+
+    /// 一致した要素の id 属性を木順に返す
+    fn select(window: &Window, selectors: &str) -> Vec<String> {
+        let dom = window.dom();
+        dom.query_selector_all(dom.document(), selectors)
+            .expect("failed to parse a selector")
+            .into_iter()
+            .map(|n| dom.node(n).get_element().unwrap().id())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Ok(SelectorList(vec![ComplexSelector {
+                compounds: vec![
+                    vec![SimpleSelector::Type("div".to_string())],
+                    vec![
+                        SimpleSelector::Universal,
+                        SimpleSelector::Class("a".to_string()),
+                        SimpleSelector::Attribute {
+                            name: "lang".to_string(),
+                            matcher: Some(AttributeMatcher {
+                                operator: AttributeOperator::DashMatch,
+                                value: "en".to_string(),
+                                case_insensitive: true,
+                            }),
+                        },
+                    ],
+                ],
+                combinators: vec![Combinator::Child],
+            }])),
+            SelectorList::parse("div>*.a[ lang |= 'en' i ]")
+        );
+
+        for (input, a, b) in [
+            ("odd", 2, 1),
+            ("EVEN", 2, 0),
+            ("3", 0, 3),
+            ("-n+3", -1, 3),
+            ("+n", 1, 0),
+            ("2n - 1", 2, -1),
+        ] {
+            let selector = ":nth-child(".to_string() + input + ")";
+            assert_eq!(
+                Ok(SelectorList(vec![ComplexSelector {
+                    compounds: vec![vec![SimpleSelector::NthChild {
+                        a,
+                        b,
+                        of_selector: None
+                    }]],
+                    combinators: Vec::new(),
+                }])),
+                SelectorList::parse(&selector),
+                "{}",
+                input
+            );
+        }
+
+        for invalid in [
+            "",
+            "div,",
+            "> p",
+            "a >",
+            "#",
+            "#1a",
+            "[x=]",
+            "[x=y z]",
+            ":hover",
+            "::before",
+            ":not(p",
+            ":nth-child(n+)",
+        ] {
+            assert_eq!(
+                Err(DomException::SyntaxError),
+                SelectorList::parse(invalid),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_type_id_class_and_attributes() {
+        let window = parse(
+            "<div id=a class='x y' lang=en-US><p id=b class=y title='foo bar'></p>\
+             <a id=c href=/ data-x=ABC></a><svg id=d><FOREIGNOBJECT id=e></FOREIGNOBJECT></svg></div>",
+        );
+        assert_eq!(vec!["a"], select(&window, "DIV"));
+        assert_eq!(vec!["b", "c", "d"], select(&window, "#a > *"));
+        assert_eq!(vec!["a", "b"], select(&window, ".y"));
+        assert_eq!(vec!["a"], select(&window, ".x.y"));
+        assert_eq!(vec!["e"], select(&window, "foreignObject"));
+        assert!(select(&window, "foreignobject").is_empty());
+
+        assert_eq!(vec!["a"], select(&window, "[lang|=en]"));
+        assert_eq!(vec!["b"], select(&window, "[title~=bar]"));
+        assert_eq!(vec!["b"], select(&window, "[title^='foo ']"));
+        assert_eq!(vec!["b"], select(&window, "[title$=ar]"));
+        assert_eq!(vec!["b"], select(&window, "[title*='o b']"));
+        assert!(select(&window, "[data-x=abc]").is_empty());
+        assert_eq!(vec!["c"], select(&window, "[data-x=abc i]"));
+        assert_eq!(vec!["c"], select(&window, "[DATA-X]"));
+        assert_eq!(vec!["c"], select(&window, ":link"));
+    }
+
+    #[test]
+    fn test_combinators() {
+        let window = parse(
+            "<div id=a><p id=b></p><!-- c --><p id=c><span id=d></span></p><span id=e></span></div>",
+        );
+        assert_eq!(vec!["d"], select(&window, "div span span, #a p span"));
+        assert_eq!(vec!["e"], select(&window, "div > span"));
+        // コメントは兄弟として数えない
+        assert_eq!(vec!["c"], select(&window, "#b + p"));
+        assert_eq!(vec!["c", "e"], select(&window, "#b ~ *"));
+        assert!(select(&window, "#c + #b").is_empty());
+    }
+
+    #[test]
+    fn test_pseudo_classes() {
+        let window = parse(
+            "<ul id=a><li id=b class=x></li><li id=c></li><li id=d class=x>text</li>\
+             <li id=e><!-- c --></li><li id=f class=x><b id=g></b></li></ul>",
+        );
+        assert_eq!(
+            vec!["b", "g"],
+            select(&window, "li:first-child, b:first-child")
+        );
+        assert_eq!(vec!["c", "e"], select(&window, "li:nth-child(even)"));
+        assert_eq!(vec!["b", "c", "d"], select(&window, "li:nth-child(-n+3)"));
+        assert_eq!(vec!["d"], select(&window, ":nth-child(2 of .x)"));
+        // i32 の範囲の端の値でも溢れない
+        assert_eq!(
+            vec!["b", "c", "d", "e", "f"],
+            select(&window, "li:nth-child(n-2147483647)")
+        );
+        assert!(select(&window, "li:nth-child(2147483647n-2147483647)").is_empty());
+        assert!(select(&window, "li:nth-child(-2147483647n+2147483647)").is_empty());
+        assert_eq!(
+            vec!["b", "c", "e", "g"],
+            select(&window, "li:empty, b:empty")
+        );
+        assert!(select(&window, "li:root").is_empty());
+        assert_eq!(
+            1,
+            window
+                .dom()
+                .query_selector_all(window.document(), ":root")
+                .unwrap()
+                .len()
+        );
+
+        assert_eq!(vec!["c", "e"], select(&window, "li:not(.x)"));
+        assert_eq!(vec!["b", "f"], select(&window, "li:is(#b, #f)"));
+        assert_eq!(vec!["a"], select(&window, "ul:where(:not(li))"));
+        assert_eq!(vec!["f"], select(&window, ":has(> b)"));
+        assert_eq!(vec!["e"], select(&window, "li:has(+ .x > b)"));
+        assert_eq!(vec!["b", "c", "d", "e"], select(&window, "li:has(~ li b)"));
+    }
+
+    #[test]
+    fn test_dom_methods() {
+        let window = parse("<div id=a class=x><p id=b><span id=c></span></p></div>");
+        let dom = window.dom();
+        let c = dom.get_element_by_id("c").unwrap();
+        let id = |n: Option<NodeId>| n.map(|n| dom.node(n).get_element().unwrap().id());
+
+        assert_eq!(
+            Some("b".to_string()),
+            id(dom.query_selector(dom.document(), "#a *").unwrap())
+        );
+        assert_eq!(Ok(true), dom.matches(c, "div span"));
+        assert_eq!(Ok(false), dom.matches(c, "div > span"));
+        assert_eq!(
+            Some("c".to_string()),
+            id(dom.closest(c, "span, div").unwrap())
+        );
+        assert_eq!(Some("a".to_string()), id(dom.closest(c, ".x").unwrap()));
+        assert_eq!(None, dom.closest(c, "body p div").unwrap());
+        assert_eq!(Err(DomException::SyntaxError), dom.matches(c, "span >"));
+    }
+}
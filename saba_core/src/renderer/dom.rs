@@ -1 +1,4 @@
+mod inner_text;
 pub mod node;
+pub mod token_list;
+pub mod traversal;
@@ -0,0 +1,309 @@
+//! 描画したときの見た目に近いテキストを DOM ツリーから取り出す。CSS を扱わないので、要素の種類から
+//! 既定の表示方法を決める
+//! https://html.spec.whatwg.org/multipage/dom.html#rendered-text-collection-steps
+
+use alloc::{string::String, vec::Vec};
+
+use crate::renderer::dom::node::{Dom, Element, ElementKind, Namespace, NodeId, NodeKind};
+
+pub(crate) fn inner_text(dom: &Dom, node: NodeId) -> String {
+    // 描画されない要素では textContent と同じ値を返す
+    if let NodeKind::Element(ref element) = dom.node(node).kind {
+        if !is_rendered(element) {
+            return dom.text_content(node).unwrap_or_default();
+        }
+    }
+
+    let mut builder = TextBuilder::default();
+    let mut stack = Vec::new();
+    push_children(dom, node, false, &mut stack);
+    while let Some(step) = stack.pop() {
+        match step {
+            Step::Enter(node, preserve) => enter(dom, node, preserve, &mut builder, &mut stack),
+            Step::Leave(node, breaks) => leave(dom, node, breaks, &mut builder),
+        }
+    }
+    builder.output
+}
+
+/// これから処理するもの。深い木でもスタックが溢れないように、再帰せずにスタックに積んで順に処理する
+enum Step {
+    /// ノードのテキストを追加する。真の場合は空白をまとめない
+    Enter(NodeId, bool),
+    /// 要素の子をすべて処理した後に、要素の後ろに改行やタブを入れる。数値は必要な改行の数
+    Leave(NodeId, usize),
+}
+
+/// `node` の子を、先頭の子から処理されるようにスタックに積む
+fn push_children(dom: &Dom, node: NodeId, preserve: bool, stack: &mut Vec<Step>) {
+    let start = stack.len();
+    stack.extend(dom.children(node).map(|child| Step::Enter(child, preserve)));
+    stack[start..].reverse();
+}
+
+/// `node` のテキストを `builder` に追加し、要素の場合は子をスタックに積む
+fn enter(
+    dom: &Dom,
+    node: NodeId,
+    preserve: bool,
+    builder: &mut TextBuilder,
+    stack: &mut Vec<Step>,
+) {
+    let element = match dom.node(node).kind {
+        NodeKind::Text(ref s) => {
+            builder.push_text(s, preserve);
+            return;
+        }
+        NodeKind::Element(ref element) => element,
+        _ => return,
+    };
+    if !is_rendered(element) {
+        return;
+    }
+
+    let html_kind = match element.namespace() {
+        Namespace::Html => Some(element.kind()),
+        _ => None,
+    };
+    if html_kind == Some(&ElementKind::Br) {
+        builder.push_line_break();
+        return;
+    }
+
+    let breaks = html_kind.map_or(0, required_line_breaks);
+    builder.require_line_breaks(breaks);
+    let preserve = preserve
+        || matches!(
+            html_kind,
+            Some(
+                ElementKind::Pre
+                    | ElementKind::Textarea
+                    | ElementKind::Listing
+                    | ElementKind::Plaintext
+                    | ElementKind::Xmp
+            )
+        );
+    stack.push(Step::Leave(node, breaks));
+    push_children(dom, node, preserve, stack);
+}
+
+/// 要素の子をすべて処理した後の改行とタブを追加する
+fn leave(dom: &Dom, node: NodeId, breaks: usize, builder: &mut TextBuilder) {
+    builder.require_line_breaks(breaks);
+
+    // 表のセルの間にはタブを入れる
+    let is_cell = |n: NodeId| {
+        matches!(
+            dom.node(n).element_kind(),
+            Some(ElementKind::Td | ElementKind::Th)
+        )
+    };
+    if is_cell(node) && dom.following_siblings(node).any(is_cell) {
+        builder.push_tab();
+    }
+}
+
+/// CSS の display: none に相当する要素かどうか
+/// https://html.spec.whatwg.org/multipage/rendering.html#hidden-elements
+fn is_rendered(element: &Element) -> bool {
+    if element.namespace() != Namespace::Html {
+        return true;
+    }
+    !element.hidden()
+        && !matches!(
+            element.kind(),
+            ElementKind::Area
+                | ElementKind::Base
+                | ElementKind::Basefont
+                | ElementKind::Datalist
+                | ElementKind::Head
+                | ElementKind::Link
+                | ElementKind::Meta
+                | ElementKind::Noembed
+                | ElementKind::Noframes
+                | ElementKind::Param
+                | ElementKind::Rp
+                | ElementKind::Script
+                | ElementKind::Style
+                | ElementKind::Template
+                | ElementKind::Title
+                // スクリプトが有効なものとして扱うので、<noscript> の中身は描画しない
+                | ElementKind::Noscript
+        )
+}
+
+/// 要素の前後に必要な改行の数。<p> は前後を空行で区切り、ブロック要素は改行で区切る
+fn required_line_breaks(kind: &ElementKind) -> usize {
+    match kind {
+        ElementKind::P => 2,
+        ElementKind::Address
+        | ElementKind::Article
+        | ElementKind::Aside
+        | ElementKind::Blockquote
+        | ElementKind::Caption
+        | ElementKind::Center
+        | ElementKind::Dd
+        | ElementKind::Details
+        | ElementKind::Dialog
+        | ElementKind::Dir
+        | ElementKind::Div
+        | ElementKind::Dl
+        | ElementKind::Dt
+        | ElementKind::Fieldset
+        | ElementKind::Figcaption
+        | ElementKind::Figure
+        | ElementKind::Footer
+        | ElementKind::Form
+        | ElementKind::H1
+        | ElementKind::H2
+        | ElementKind::H3
+        | ElementKind::H4
+        | ElementKind::H5
+        | ElementKind::H6
+        | ElementKind::Header
+        | ElementKind::Hgroup
+        | ElementKind::Hr
+        | ElementKind::Legend
+        | ElementKind::Li
+        | ElementKind::Listing
+        | ElementKind::Main
+        | ElementKind::Menu
+        | ElementKind::Nav
+        | ElementKind::Ol
+        | ElementKind::Plaintext
+        | ElementKind::Pre
+        | ElementKind::Search
+        | ElementKind::Section
+        | ElementKind::Summary
+        | ElementKind::Table
+        | ElementKind::Tr
+        | ElementKind::Ul
+        | ElementKind::Xmp => 1,
+        _ => 0,
+    }
+}
+
+/// 空白をまとめながら文字列を組み立てる。改行と空白は、次に文字を出力するときにまとめて追加する
+/// ので、行頭と行末の空白や、先頭と末尾の改行は出力されない
+#[derive(Default)]
+struct TextBuilder {
+    output: String,
+    /// 次の文字の前に必要な改行の数
+    pending_line_breaks: usize,
+    /// 次の文字の前に空白を1つ入れるかどうか
+    pending_space: bool,
+}
+
+impl TextBuilder {
+    fn push_text(&mut self, text: &str, preserve: bool) {
+        for c in text.chars() {
+            if preserve {
+                self.push_char(c);
+            } else if is_collapsible_whitespace(c) {
+                self.pending_space = true;
+            } else {
+                self.push_char(c);
+            }
+        }
+    }
+
+    fn push_char(&mut self, c: char) {
+        self.flush();
+        self.output.push(c);
+    }
+
+    /// <br> による改行。直前の空白は出力しない
+    fn push_line_break(&mut self) {
+        self.pending_space = false;
+        self.push_char('\n');
+    }
+
+    fn push_tab(&mut self) {
+        self.pending_space = false;
+        self.push_char('\t');
+    }
+
+    /// 少なくとも `count` 個の改行で前後を区切る。続けて要求された場合は多い方に合わせる
+    fn require_line_breaks(&mut self, count: usize) {
+        if count > 0 {
+            self.pending_line_breaks = self.pending_line_breaks.max(count);
+            self.pending_space = false;
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.pending_line_breaks > 0 {
+            if !self.output.is_empty() {
+                for _ in 0..self.pending_line_breaks {
+                    self.output.push('\n');
+                }
+            }
+            self.pending_line_breaks = 0;
+            self.pending_space = false;
+        }
+        if self.pending_space {
+            if !self.output.is_empty() && !self.output.ends_with(['\n', '\t']) {
+                self.output.push(' ');
+            }
+            self.pending_space = false;
+        }
+    }
+}
+
+/// https://drafts.csswg.org/css-text-3/#white-space-processing
+fn is_collapsible_whitespace(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\u{0C}' | '\r' | ' ')
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::renderer::html::{parser::HtmlParser, token::HtmlTokenizer};
+    use alloc::string::{String, ToString};
+
+    fn body_text(html: &str) -> String {
+        let window = HtmlParser::new(HtmlTokenizer::new(html.to_string())).construct_tree();
+        let dom = window.dom();
+        let body = dom.get_elements_by_tag_name("body")[0];
+        dom.inner_text(body)
+    }
+
+    #[test]
+    fn test_collapse_whitespace() {
+        assert_eq!("a b c", body_text("  a <b> b</b>\n\n c  "));
+        assert_eq!("a\nb", body_text("a <br> b"));
+        assert_eq!(" a\n  b ", body_text("<pre> a\n  b </pre>"));
+    }
+
+    #[test]
+    fn test_block_elements() {
+        assert_eq!(
+            "title\nfirst\n\nsecond\n\nx\ny",
+            body_text("<h1>title</h1> first <p>second</p> <ul><li>x</li> <li>y</li></ul>")
+        );
+        assert_eq!(
+            "a\tb\nc\td",
+            body_text("<table><tr><td>a</td><td>b</td></tr><tr><th>c<th>d</table>")
+        );
+    }
+
+    #[test]
+    fn test_deep_nesting() {
+        let depth = 30000;
+        let html = "<div>".repeat(depth) + "a";
+        assert_eq!("a", body_text(&html));
+    }
+
+    #[test]
+    fn test_hidden_elements() {
+        assert_eq!(
+            "a b",
+            body_text("a<script>x()</script> <style>p{}</style><span hidden>c</span><template>d</template>b")
+        );
+
+        let window = HtmlParser::new(HtmlTokenizer::new("<script>x()</script>".to_string()))
+            .construct_tree();
+        let dom = window.dom();
+        let script = dom.get_elements_by_tag_name("script")[0];
+        assert_eq!("x()", dom.inner_text(script));
+    }
+}
@@ -1,98 +1,127 @@
 use alloc::{
-    format,
-    rc::{Rc, Weak},
-    string::String,
+    collections::BTreeMap,
+    string::{String, ToString},
     vec::Vec,
 };
-use core::{cell::RefCell, str::FromStr};
+use core::{convert::Infallible, str::FromStr};
 
-use crate::renderer::html::attribute::Attribute;
+use crate::{
+    renderer::{
+        css::selector::SelectorList,
+        dom::{
+            inner_text::inner_text,
+            token_list::DomTokenList,
+            traversal::{
+                following, Ancestors, Children, Descendants, FollowingSiblings, NodeFilter,
+                NodeIterator, Preceding, TreeWalker, WhatToShow,
+            },
+        },
+        html::{attribute::Attribute, parse_error::SourcePosition},
+    },
+    url::Url,
+};
+
+/// `Dom` の中のノードを指す ID。ノードの代わりにコピーして持ち回り、`Dom::node` でノードを得る
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(usize);
 
 #[derive(Debug, Clone)]
 pub struct Node {
     pub kind: NodeKind,
-    window: Weak<RefCell<Window>>,
-    parent: Weak<RefCell<Node>>,
-    first_child: Option<Rc<RefCell<Node>>>,
-    last_child: Weak<RefCell<Node>>,
-    previous_sibling: Weak<RefCell<Node>>,
-    next_sibling: Option<Rc<RefCell<Node>>>,
+    parent: Option<NodeId>,
+    first_child: Option<NodeId>,
+    last_child: Option<NodeId>,
+    previous_sibling: Option<NodeId>,
+    next_sibling: Option<NodeId>,
+    /// パーサが作ったノードの場合、元になったトークンの入力中の位置
+    position: Option<SourcePosition>,
+    /// 文書につながっているかどうか。挿入と削除のたびに更新し、祖先をたどらずに判定できるようにする
+    connected: bool,
 }
 
 impl Node {
-    pub fn new(kind: NodeKind) -> Self {
+    fn new(kind: NodeKind) -> Self {
         Self {
             kind,
-            window: Weak::new(),
-            parent: Weak::new(),
+            parent: None,
             first_child: None,
-            last_child: Weak::new(),
-            previous_sibling: Weak::new(),
+            last_child: None,
+            previous_sibling: None,
             next_sibling: None,
+            position: None,
+            connected: false,
         }
     }
 
-    pub fn set_window(&mut self, window: Weak<RefCell<Window>>) {
-        self.window = window;
-    }
-
-    pub fn set_parent(&mut self, parent: Weak<RefCell<Node>>) {
-        self.parent = parent;
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
     }
 
-    pub fn parent(&self) -> Weak<RefCell<Node>> {
-        self.parent.clone()
+    pub fn first_child(&self) -> Option<NodeId> {
+        self.first_child
     }
 
-    pub fn set_first_child(&mut self, first_child: Option<Rc<RefCell<Node>>>) {
-        self.first_child = first_child;
+    pub fn last_child(&self) -> Option<NodeId> {
+        self.last_child
     }
 
-    pub fn first_child(&self) -> Option<Rc<RefCell<Node>>> {
-        self.first_child.clone()
+    pub fn previous_sibling(&self) -> Option<NodeId> {
+        self.previous_sibling
     }
 
-    pub fn set_last_child(&mut self, last_child: Weak<RefCell<Node>>) {
-        self.last_child = last_child;
+    pub fn next_sibling(&self) -> Option<NodeId> {
+        self.next_sibling
     }
 
-    pub fn last_child(&self) -> Weak<RefCell<Node>> {
-        self.last_child.clone()
+    pub fn set_position(&mut self, position: Option<SourcePosition>) {
+        self.position = position;
     }
 
-    pub fn set_previous_sibling(&mut self, previous_sibling: Weak<RefCell<Node>>) {
-        self.previous_sibling = previous_sibling;
+    pub fn position(&self) -> Option<SourcePosition> {
+        self.position
     }
 
-    pub fn previous_sibling(&self) -> Weak<RefCell<Node>> {
-        self.previous_sibling.clone()
+    pub fn kind(&self) -> NodeKind {
+        self.kind.clone()
     }
 
-    pub fn set_next_sibling(&mut self, next_sibling: Option<Rc<RefCell<Node>>>) {
-        self.next_sibling = next_sibling;
+    pub fn get_element(&self) -> Option<Element> {
+        self.element().cloned()
     }
 
-    pub fn next_sibling(&self) -> Option<Rc<RefCell<Node>>> {
-        self.next_sibling.clone()
+    /// 要素を複製せずに参照する。パーサのように何度も要素を調べる処理ではこちらを使う
+    pub fn element(&self) -> Option<&Element> {
+        match self.kind {
+            NodeKind::Element(ref e) => Some(e),
+            _ => None,
+        }
     }
 
-    pub fn kind(&self) -> NodeKind {
-        self.kind.clone()
+    /// 要素の属性などを書き換えるために、要素への可変参照を返す
+    pub fn get_element_mut(&mut self) -> Option<&mut Element> {
+        match self.kind {
+            NodeKind::Element(ref mut e) => Some(e),
+            _ => None,
+        }
     }
 
-    pub fn get_element(&self) -> Option<Element> {
+    pub fn get_document(&self) -> Option<Document> {
         match self.kind {
-            NodeKind::Element(ref e) => Some(e.clone()),
+            NodeKind::Document(ref d) => Some(d.clone()),
             _ => None,
         }
     }
 
-    pub fn element_kind(&self) -> Option<ElementKind> {
+    pub fn get_document_mut(&mut self) -> Option<&mut Document> {
         match self.kind {
-            NodeKind::Element(ref e) => Some(e.kind),
+            NodeKind::Document(ref mut d) => Some(d),
             _ => None,
         }
     }
+
+    pub fn element_kind(&self) -> Option<&ElementKind> {
+        self.element().map(|e| &e.kind)
+    }
 }
 
 impl PartialEq for Node {
@@ -101,17 +130,735 @@ impl PartialEq for Node {
     }
 }
 
+/// 1つの文書に属するノードをまとめて保持するアリーナ。ノード同士は `NodeId` で参照し合う。
+/// 木から取り除いたノードの領域は再利用せず、`Dom` を破棄するときにまとめて解放する
+#[derive(Debug, Clone)]
+pub struct Dom {
+    nodes: Vec<Node>,
+    /// 文書につながっている要素の、id 属性の値からの索引。ノードの挿入・削除と `set_attribute`
+    /// で更新する
+    ids: BTreeMap<String, Vec<NodeId>>,
+}
+
+impl Dom {
+    /// Document ノードだけを持つ木を作る
+    pub fn new() -> Self {
+        let mut document = Node::new(NodeKind::Document(Document::new()));
+        document.connected = true;
+        Self {
+            nodes: alloc::vec![document],
+            ids: BTreeMap::new(),
+        }
+    }
+
+    /// 木の根である Document ノード
+    pub fn document(&self) -> NodeId {
+        NodeId(0)
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id.0]
+    }
+
+    /// 親を持たないノードを作る。<template> 要素の場合は、内容を保持する DocumentFragment も作る
+    pub fn create_node(&mut self, kind: NodeKind) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        self.nodes.push(Node::new(kind));
+
+        if self.node(id).element_kind() == Some(&ElementKind::Template) {
+            let contents = self.create_node(NodeKind::DocumentFragment);
+            if let Some(element) = self.node_mut(id).get_element_mut() {
+                element.template_contents = Some(contents);
+            }
+        }
+        id
+    }
+
+    /// `parent` の子を順に返す
+    pub fn children(&self, parent: NodeId) -> Children<'_> {
+        Children::new(self, parent)
+    }
+
+    /// `root` の子孫を、`root` 自身を含めずに木順で返す
+    pub fn descendants(&self, root: NodeId) -> Descendants<'_> {
+        Descendants::new(self, root)
+    }
+
+    /// `node` の祖先を、親から根に向かって返す
+    pub fn ancestors(&self, node: NodeId) -> Ancestors<'_> {
+        Ancestors::new(self, node)
+    }
+
+    /// `node` より後ろの兄弟を順に返す
+    pub fn following_siblings(&self, node: NodeId) -> FollowingSiblings<'_> {
+        FollowingSiblings::new(self, node)
+    }
+
+    /// 木順で `node` より前にあるノードを、近いものから返す
+    pub fn preceding(&self, node: NodeId) -> Preceding<'_> {
+        Preceding::new(self, node)
+    }
+
+    /// https://dom.spec.whatwg.org/#dom-document-createtreewalker
+    pub fn create_tree_walker<'a>(
+        &'a self,
+        root: NodeId,
+        what_to_show: WhatToShow,
+        filter: Option<NodeFilter<'a>>,
+    ) -> TreeWalker<'a> {
+        TreeWalker::new(self, root, what_to_show, filter)
+    }
+
+    /// https://dom.spec.whatwg.org/#dom-document-createnodeiterator
+    pub fn create_node_iterator<'a>(
+        &'a self,
+        root: NodeId,
+        what_to_show: WhatToShow,
+        filter: Option<NodeFilter<'a>>,
+    ) -> NodeIterator<'a> {
+        NodeIterator::new(self, root, what_to_show, filter)
+    }
+
+    /// `node` が文書につながっているかどうか
+    /// https://dom.spec.whatwg.org/#connected
+    pub fn is_connected(&self, node: NodeId) -> bool {
+        self.node(node).connected
+    }
+
+    /// `root` とその子孫が文書につながっているかどうかを `connected` にする
+    fn set_connected(&mut self, root: NodeId, connected: bool) {
+        let mut node = Some(root);
+        while let Some(n) = node {
+            self.node_mut(n).connected = connected;
+            node = following(self, n, root);
+        }
+    }
+
+    /// id 属性が `id` である、木順で最初の要素を返す。索引を引くので、木全体はたどらない
+    /// https://dom.spec.whatwg.org/#dom-nonelementparentnode-getelementbyid
+    pub fn get_element_by_id(&self, id: &str) -> Option<NodeId> {
+        let candidates: Vec<NodeId> = self
+            .ids
+            .get(id)?
+            .iter()
+            .copied()
+            .filter(|n| matches!(self.node(*n).kind, NodeKind::Element(ref e) if e.id() == id))
+            .collect();
+        match candidates.len() {
+            0 => None,
+            1 => Some(candidates[0]),
+            // 同じ id の要素が複数ある場合は、木順で最初のものを返す
+            _ => self
+                .descendants(self.document())
+                .find(|n| candidates.contains(n)),
+        }
+    }
+
+    /// 文書の中で、修飾名が `name` である要素を木順に返す。`*` はすべての要素に一致する。
+    /// HTML の要素は大文字と小文字を区別しない
+    /// https://dom.spec.whatwg.org/#concept-getelementsbytagname
+    pub fn get_elements_by_tag_name(&self, name: &str) -> Vec<NodeId> {
+        let lower = name.to_ascii_lowercase();
+        self.elements(|e| {
+            name == "*"
+                || match e.namespace() {
+                    Namespace::Html => e.local_name() == lower,
+                    _ => e.local_name() == name,
+                }
+        })
+    }
+
+    /// 文書の中で、空白で区切った `class_names` のクラスをすべて持つ要素を木順に返す。互換モードの
+    /// 文書では大文字と小文字を区別しない
+    /// https://dom.spec.whatwg.org/#concept-getelementsbyclassname
+    pub fn get_elements_by_class_name(&self, class_names: &str) -> Vec<NodeId> {
+        let classes = DomTokenList::new(class_names);
+        if classes.is_empty() {
+            return Vec::new();
+        }
+        let quirks = self
+            .node(self.document())
+            .get_document()
+            .is_some_and(|d| d.mode() == QuirksMode::Quirks);
+
+        self.elements(|e| {
+            let list = e.class_list();
+            classes.iter().all(|class| {
+                if quirks {
+                    list.iter().any(|c| c.eq_ignore_ascii_case(class))
+                } else {
+                    list.contains(class)
+                }
+            })
+        })
+    }
+
+    /// 文書の中で、name 属性が `name` である HTML の要素を木順に返す
+    /// https://html.spec.whatwg.org/multipage/dom.html#dom-document-getelementsbyname
+    pub fn get_elements_by_name(&self, name: &str) -> Vec<NodeId> {
+        self.elements(|e| {
+            e.namespace() == Namespace::Html && e.get_attribute("name").as_deref() == Some(name)
+        })
+    }
+
+    /// 文書の中で `predicate` を満たす要素を木順に集める
+    fn elements(&self, predicate: impl Fn(&Element) -> bool) -> Vec<NodeId> {
+        self.descendants(self.document())
+            .filter(|n| match self.node(*n).kind {
+                NodeKind::Element(ref e) => predicate(e),
+                _ => false,
+            })
+            .collect()
+    }
+
+    /// `root` の子孫の中で、セレクタに一致する木順で最初の要素を返す
+    /// https://dom.spec.whatwg.org/#dom-parentnode-queryselector
+    pub fn query_selector(
+        &self,
+        root: NodeId,
+        selectors: &str,
+    ) -> Result<Option<NodeId>, DomException> {
+        let selectors = SelectorList::parse(selectors)?;
+        Ok(self.descendants(root).find(|n| selectors.matches(self, *n)))
+    }
+
+    /// `root` の子孫の中で、セレクタに一致する要素を木順に返す
+    /// https://dom.spec.whatwg.org/#dom-parentnode-queryselectorall
+    pub fn query_selector_all(
+        &self,
+        root: NodeId,
+        selectors: &str,
+    ) -> Result<Vec<NodeId>, DomException> {
+        let selectors = SelectorList::parse(selectors)?;
+        Ok(self
+            .descendants(root)
+            .filter(|n| selectors.matches(self, *n))
+            .collect())
+    }
+
+    /// 要素がセレクタに一致するかどうか
+    /// https://dom.spec.whatwg.org/#dom-element-matches
+    pub fn matches(&self, element: NodeId, selectors: &str) -> Result<bool, DomException> {
+        Ok(SelectorList::parse(selectors)?.matches(self, element))
+    }
+
+    /// 要素自身と祖先の中で、セレクタに一致する最も近い要素を返す
+    /// https://dom.spec.whatwg.org/#dom-element-closest
+    pub fn closest(
+        &self,
+        element: NodeId,
+        selectors: &str,
+    ) -> Result<Option<NodeId>, DomException> {
+        let selectors = SelectorList::parse(selectors)?;
+        Ok(core::iter::once(element)
+            .chain(self.ancestors(element))
+            .find(|n| selectors.matches(self, *n)))
+    }
+
+    /// 要素の属性を設定する。id 属性を変えた場合に索引を更新するので、文書につながっている要素の
+    /// id 属性は `Element::set_attribute` ではなくこちらで変える
+    pub fn set_attribute(&mut self, node: NodeId, name: &str, value: &str) {
+        self.unindex_ids(node, false);
+        if let Some(element) = self.node_mut(node).get_element_mut() {
+            element.set_attribute(name, value);
+        }
+        self.index_ids(node, false);
+    }
+
+    /// 要素の属性を取り除き、取り除いた属性を返す。id 属性の索引も更新する
+    pub fn remove_attribute(&mut self, node: NodeId, name: &str) -> Option<Attribute> {
+        self.unindex_ids(node, false);
+        let removed = self
+            .node_mut(node)
+            .get_element_mut()
+            .and_then(|e| e.remove_attribute(name));
+        self.index_ids(node, false);
+        removed
+    }
+
+    /// `root` (`subtree` が真ならその子孫も) のうち、id 属性を持つ要素とその値
+    fn ids_in(&self, root: NodeId, subtree: bool) -> Vec<(String, NodeId)> {
+        let descendants = self.descendants(root).filter(|_| subtree);
+        core::iter::once(root)
+            .chain(descendants)
+            .filter_map(|n| match self.node(n).kind {
+                NodeKind::Element(ref e) if e.has_attribute("id") => Some((e.id(), n)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// 文書につながっていれば、`root` の id 属性を索引に加える
+    fn index_ids(&mut self, root: NodeId, subtree: bool) {
+        let ids = self.ids_in(root, subtree);
+        if ids.is_empty() || !self.is_connected(root) {
+            return;
+        }
+        for (id, node) in ids {
+            self.ids.entry(id).or_default().push(node);
+        }
+    }
+
+    /// `root` の id 属性を索引から取り除く
+    fn unindex_ids(&mut self, root: NodeId, subtree: bool) {
+        let ids = self.ids_in(root, subtree);
+        if ids.is_empty() || !self.is_connected(root) {
+            return;
+        }
+        for (id, node) in ids {
+            if let Some(nodes) = self.ids.get_mut(&id) {
+                nodes.retain(|n| *n != node);
+                if nodes.is_empty() {
+                    self.ids.remove(&id);
+                }
+            }
+        }
+    }
+
+    /// 要素の href 属性の値を、文書の URL を基準に解決して返す
+    pub fn href(&self, id: NodeId) -> Option<String> {
+        let base = self.base_url();
+        self.node(id).get_element()?.href(base.as_ref())
+    }
+
+    /// 要素の src 属性の値を、文書の URL を基準に解決して返す
+    pub fn src(&self, id: NodeId) -> Option<String> {
+        let base = self.base_url();
+        self.node(id).get_element()?.src(base.as_ref())
+    }
+
+    /// 相対 URL の基準になる、文書の URL
+    fn base_url(&self) -> Option<Url> {
+        let url = self.node(self.document()).get_document()?.url()?;
+        Url::new(url).parse().ok()
+    }
+
+    /// `node` を `parent` の最後の子として追加し、追加したノードを返す。`node` がすでにどこかの子で
+    /// あれば、そこから取り除いてから追加する
+    /// https://dom.spec.whatwg.org/#dom-node-appendchild
+    pub fn append_child(&mut self, parent: NodeId, node: NodeId) -> Result<NodeId, DomException> {
+        self.insert_before(parent, node, None)
+    }
+
+    /// `node` を `parent` の子である `child` の直前に挿入し、挿入したノードを返す。`child` が `None`
+    /// の場合は最後の子として追加する。DocumentFragment を渡した場合は、その子を順に挿入する
+    /// https://dom.spec.whatwg.org/#concept-node-pre-insert
+    pub fn insert_before(
+        &mut self,
+        parent: NodeId,
+        node: NodeId,
+        child: Option<NodeId>,
+    ) -> Result<NodeId, DomException> {
+        self.ensure_pre_insertion_validity(parent, node, child, None)?;
+
+        // 自分自身の直前に挿入する場合は、取り除いた後の次のノードの直前に挿入する
+        let child = if child == Some(node) {
+            self.node(node).next_sibling()
+        } else {
+            child
+        };
+        self.insert(parent, node, child);
+        Ok(node)
+    }
+
+    /// `parent` の子 `child` を取り除き、取り除いたノードを返す
+    /// https://dom.spec.whatwg.org/#concept-node-pre-remove
+    pub fn remove_child(&mut self, parent: NodeId, child: NodeId) -> Result<NodeId, DomException> {
+        if self.node(child).parent() != Some(parent) {
+            return Err(DomException::NotFoundError);
+        }
+        self.remove(child);
+        Ok(child)
+    }
+
+    /// `parent` の子 `child` を `node` で置き換え、取り除いた `child` を返す
+    /// https://dom.spec.whatwg.org/#concept-node-replace
+    pub fn replace_child(
+        &mut self,
+        parent: NodeId,
+        node: NodeId,
+        child: NodeId,
+    ) -> Result<NodeId, DomException> {
+        self.ensure_pre_insertion_validity(parent, node, Some(child), Some(child))?;
+
+        let mut reference = self.node(child).next_sibling();
+        if reference == Some(node) {
+            reference = self.node(node).next_sibling();
+        }
+        self.remove(child);
+        self.insert(parent, node, reference);
+        Ok(child)
+    }
+
+    /// ノードを複製する。`deep` が真の場合は子孫も複製する。複製したノードは親を持たない
+    /// https://dom.spec.whatwg.org/#concept-node-clone
+    pub fn clone_node(&mut self, id: NodeId, deep: bool) -> NodeId {
+        let copy = self.create_node(copied_kind(self.node(id)));
+        if !deep {
+            return copy;
+        }
+
+        // 深い木でもスタックがあふれないように、子をまだ複製していないノードと、その複製を積んでたどる
+        let mut stack = alloc::vec![(id, copy)];
+        while let Some((original, copy)) = stack.pop() {
+            // <template> 要素の内容は、新しく作った DocumentFragment に複製する
+            if let (Some(contents), Some(copy_contents)) = (
+                self.template_contents(original),
+                self.template_contents(copy),
+            ) {
+                for child in self.children(contents).collect::<Vec<_>>() {
+                    let c = self.clone_node(child, false);
+                    self.insert(copy_contents, c, None);
+                    stack.push((child, c));
+                }
+            }
+            for child in self.children(original).collect::<Vec<_>>() {
+                let c = self.clone_node(child, false);
+                self.insert(copy, c, None);
+                stack.push((child, c));
+            }
+        }
+        copy
+    }
+
+    /// 別の文書のノード `id` を複製して、この文書のノードにする。`deep` が真の場合は子孫も複製する
+    /// https://dom.spec.whatwg.org/#dom-document-importnode
+    pub fn import_node(&mut self, source: &Dom, id: NodeId, deep: bool) -> NodeId {
+        let copy = self.create_node(copied_kind(source.node(id)));
+        self.node_mut(copy).set_position(source.node(id).position());
+        if !deep {
+            return copy;
+        }
+
+        // 深い木でもスタックがあふれないように、子をまだ複製していないノードと、その複製を積んでたどる
+        let mut stack = alloc::vec![(id, copy)];
+        while let Some((original, copy)) = stack.pop() {
+            if let (Some(contents), Some(copy_contents)) = (
+                source.template_contents(original),
+                self.template_contents(copy),
+            ) {
+                for child in source.children(contents) {
+                    let c = self.import_node(source, child, false);
+                    self.insert(copy_contents, c, None);
+                    stack.push((child, c));
+                }
+            }
+            for child in source.children(original) {
+                let c = self.import_node(source, child, false);
+                self.insert(copy, c, None);
+                stack.push((child, c));
+            }
+        }
+        copy
+    }
+
+    /// ノードの文字列としての内容。要素と DocumentFragment では子孫のテキストをつなげたもの、テキストと
+    /// コメントではその内容を返す。Document と DOCTYPE では `None` を返す
+    /// https://dom.spec.whatwg.org/#dom-node-textcontent
+    pub fn text_content(&self, node: NodeId) -> Option<String> {
+        match self.node(node).kind {
+            NodeKind::Element(_) | NodeKind::DocumentFragment => Some(
+                self.descendants(node)
+                    .filter_map(|n| match self.node(n).kind {
+                        NodeKind::Text(ref s) => Some(s.as_str()),
+                        _ => None,
+                    })
+                    .collect(),
+            ),
+            NodeKind::Text(ref s) | NodeKind::Comment(ref s) => Some(s.clone()),
+            NodeKind::Document(_) | NodeKind::DocumentType(_) => None,
+        }
+    }
+
+    /// ノードの内容を `value` にする。要素と DocumentFragment では子をすべて取り除き、`value` が空で
+    /// なければ1つのテキストノードに置き換える。Document と DOCTYPE では何もしない
+    /// https://dom.spec.whatwg.org/#dom-node-textcontent
+    pub fn set_text_content(&mut self, node: NodeId, value: &str) {
+        match self.node_mut(node).kind {
+            NodeKind::Text(ref mut s) | NodeKind::Comment(ref mut s) => *s = value.to_string(),
+            NodeKind::Element(_) | NodeKind::DocumentFragment => {
+                while let Some(child) = self.node(node).first_child() {
+                    self.remove(child);
+                }
+                if !value.is_empty() {
+                    let text = self.create_node(NodeKind::Text(value.to_string()));
+                    self.insert(node, text, None);
+                }
+            }
+            NodeKind::Document(_) | NodeKind::DocumentType(_) => {}
+        }
+    }
+
+    /// `node` の子孫の中で、空のテキストノードを取り除き、隣り合うテキストノードを1つにまとめる
+    /// https://dom.spec.whatwg.org/#dom-node-normalize
+    pub fn normalize(&mut self, node: NodeId) {
+        let texts: Vec<NodeId> = self
+            .descendants(node)
+            .filter(|n| matches!(self.node(*n).kind, NodeKind::Text(_)))
+            .collect();
+        for text in texts {
+            // 前のテキストノードにまとめられて、すでに取り除かれている
+            if self.node(text).parent().is_none() {
+                continue;
+            }
+
+            let mut data = self.text_content(text).unwrap_or_default();
+            while let Some(next) = self.node(text).next_sibling() {
+                let NodeKind::Text(ref s) = self.node(next).kind else {
+                    break;
+                };
+                data.push_str(s);
+                self.remove(next);
+            }
+            if data.is_empty() {
+                self.remove(text);
+            } else {
+                self.set_text_content(text, &data);
+            }
+        }
+    }
+
+    /// テキストノードを `offset` 文字目で2つに分け、後半の新しいテキストノードを返す。新しいノードは
+    /// 元のノードの直後に挿入する。`offset` は UTF-16 のコード単位ではなく文字 (char) の数で数える
+    /// https://dom.spec.whatwg.org/#dom-text-splittext
+    pub fn split_text(&mut self, node: NodeId, offset: usize) -> Result<NodeId, DomException> {
+        let NodeKind::Text(ref mut data) = self.node_mut(node).kind else {
+            return Err(DomException::InvalidNodeTypeError);
+        };
+        let index = match data.char_indices().nth(offset) {
+            Some((index, _)) => index,
+            None if data.chars().count() == offset => data.len(),
+            None => return Err(DomException::IndexSizeError),
+        };
+        let new_data = data.split_off(index);
+
+        let new_node = self.create_node(NodeKind::Text(new_data));
+        if let Some(parent) = self.node(node).parent() {
+            let next = self.node(node).next_sibling();
+            self.insert(parent, new_node, next);
+        }
+        Ok(new_node)
+    }
+
+    /// 描画したときの見た目に近い形でテキストを取り出す。空白をまとめ、ブロック要素の前後や <br>
+    /// で改行する。描画されない要素 (<script> など) の中身は含めない
+    /// https://html.spec.whatwg.org/multipage/dom.html#the-innertext-idl-attribute
+    pub fn inner_text(&self, node: NodeId) -> String {
+        inner_text(self, node)
+    }
+
+    /// <template> 要素の場合、その内容を保持する DocumentFragment を返す
+    pub fn template_contents(&self, id: NodeId) -> Option<NodeId> {
+        match self.node(id).kind {
+            NodeKind::Element(ref e) => e.template_contents(),
+            _ => None,
+        }
+    }
+
+    /// 検証をせずに `node` を `child` の直前 (`None` の場合は最後) に挿入する。パーサのように、
+    /// 正しい木になることが分かっている場合に使う
+    /// https://dom.spec.whatwg.org/#concept-node-insert
+    pub(crate) fn insert(&mut self, parent: NodeId, node: NodeId, child: Option<NodeId>) {
+        if let NodeKind::DocumentFragment = self.node(node).kind {
+            while let Some(c) = self.node(node).first_child() {
+                self.remove(c);
+                self.insert(parent, c, child);
+            }
+            return;
+        }
+
+        self.remove(node);
+        let previous = match child {
+            Some(child) => self.node(child).previous_sibling(),
+            None => self.node(parent).last_child(),
+        };
+        match previous {
+            Some(previous) => self.node_mut(previous).next_sibling = Some(node),
+            None => self.node_mut(parent).first_child = Some(node),
+        }
+        match child {
+            Some(child) => self.node_mut(child).previous_sibling = Some(node),
+            None => self.node_mut(parent).last_child = Some(node),
+        }
+
+        let n = self.node_mut(node);
+        n.previous_sibling = previous;
+        n.next_sibling = child;
+        n.parent = Some(parent);
+        if self.is_connected(parent) {
+            self.set_connected(node, true);
+        }
+        self.index_ids(node, true);
+    }
+
+    /// `node` を親から取り除く。親がない場合は何もしない
+    /// https://dom.spec.whatwg.org/#concept-node-remove
+    pub(crate) fn remove(&mut self, node: NodeId) {
+        let Some(parent) = self.node(node).parent() else {
+            return;
+        };
+        self.unindex_ids(node, true);
+        let previous = self.node(node).previous_sibling();
+        let next = self.node(node).next_sibling();
+
+        match previous {
+            Some(previous) => self.node_mut(previous).next_sibling = next,
+            None => self.node_mut(parent).first_child = next,
+        }
+        match next {
+            Some(next) => self.node_mut(next).previous_sibling = previous,
+            None => self.node_mut(parent).last_child = previous,
+        }
+
+        let n = self.node_mut(node);
+        n.parent = None;
+        n.previous_sibling = None;
+        n.next_sibling = None;
+        if self.is_connected(node) {
+            self.set_connected(node, false);
+        }
+    }
+
+    /// `node` を `parent` の `child` の直前に挿入してよいかを確かめる。`replaced` には、置き換えに
+    /// よって取り除かれるノードを渡す
+    /// https://dom.spec.whatwg.org/#concept-node-ensure-pre-insertion-validity
+    fn ensure_pre_insertion_validity(
+        &self,
+        parent: NodeId,
+        node: NodeId,
+        child: Option<NodeId>,
+        replaced: Option<NodeId>,
+    ) -> Result<(), DomException> {
+        let parent_is_document = match self.node(parent).kind {
+            NodeKind::Document(_) => true,
+            NodeKind::DocumentFragment | NodeKind::Element(_) => false,
+            _ => return Err(DomException::HierarchyRequestError),
+        };
+
+        // `node` が `parent` 自身かその祖先の場合は、木が循環してしまう
+        let mut ancestor = Some(parent);
+        while let Some(a) = ancestor {
+            if a == node {
+                return Err(DomException::HierarchyRequestError);
+            }
+            ancestor = self.node(a).parent();
+        }
+
+        if let Some(child) = child {
+            if self.node(child).parent() != Some(parent) {
+                return Err(DomException::NotFoundError);
+            }
+        }
+
+        match self.node(node).kind {
+            NodeKind::Document(_) => return Err(DomException::HierarchyRequestError),
+            NodeKind::Text(_) if parent_is_document => {
+                return Err(DomException::HierarchyRequestError)
+            }
+            NodeKind::DocumentType(_) if !parent_is_document => {
+                return Err(DomException::HierarchyRequestError)
+            }
+            _ => {}
+        }
+        if !parent_is_document {
+            return Ok(());
+        }
+
+        // 文書は要素と DOCTYPE を1つずつしか持てず、DOCTYPE は要素より前に置く
+        let is_element = |n: &NodeId| matches!(self.node(*n).kind, NodeKind::Element(_));
+        let is_doctype = |n: &NodeId| matches!(self.node(*n).kind, NodeKind::DocumentType(_));
+        let is_replaced = |n: &NodeId| replaced == Some(*n);
+
+        let siblings: Vec<_> = self.children(parent).collect();
+        let child_index = child.and_then(|c| siblings.iter().position(|s| *s == c));
+        let (preceding, following) = match child_index {
+            Some(i) => (&siblings[..i], &siblings[i + 1..]),
+            None => (&siblings[..], &siblings[siblings.len()..]),
+        };
+        let has_other_element = siblings.iter().any(|s| is_element(s) && !is_replaced(s));
+        let doctype_after_child = child.is_some_and(|c| is_doctype(&c) && !is_replaced(&c))
+            || following.iter().any(is_doctype);
+
+        let invalid = match self.node(node).kind {
+            NodeKind::DocumentFragment => {
+                let children: Vec<_> = self.children(node).collect();
+                let elements = children.iter().filter(|c| is_element(c)).count();
+                let has_text = children
+                    .iter()
+                    .any(|c| matches!(self.node(*c).kind, NodeKind::Text(_)));
+                elements > 1
+                    || has_text
+                    || (elements == 1 && (has_other_element || doctype_after_child))
+            }
+            NodeKind::Element(_) => has_other_element || doctype_after_child,
+            NodeKind::DocumentType(_) => {
+                siblings.iter().any(|s| is_doctype(s) && !is_replaced(s))
+                    || match child {
+                        Some(_) => preceding.iter().any(is_element),
+                        None => has_other_element,
+                    }
+            }
+            _ => false,
+        };
+        if invalid {
+            Err(DomException::HierarchyRequestError)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Default for Dom {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 複製するノードの種類。<template> 要素の内容は複製先で新しく作るので引き継がない
+fn copied_kind(node: &Node) -> NodeKind {
+    let mut kind = node.kind.clone();
+    if let NodeKind::Element(ref mut element) = kind {
+        element.template_contents = None;
+    }
+    kind
+}
+
+/// DOM の操作が失敗した理由
+/// https://webidl.spec.whatwg.org/#idl-DOMException-error-names
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DomException {
+    /// 木の構造として正しくない操作 (例: 祖先を子として追加する、文書に2つ目の要素を追加する)
+    HierarchyRequestError,
+    /// 指定したノードが子として見つからない
+    NotFoundError,
+    /// 文字列の構文が正しくない (例: 解析できないセレクタ)
+    SyntaxError,
+    /// 位置や長さが範囲外
+    IndexSizeError,
+    /// 操作の対象にできない種類のノード (例: テキストノード以外の分割)
+    InvalidNodeTypeError,
+}
+
 #[derive(Debug, Clone)]
 pub enum NodeKind {
-    Document,
+    Document(Document),
+    /// https://dom.spec.whatwg.org/#interface-documentfragment
+    DocumentFragment,
+    DocumentType(DocumentType),
     Element(Element),
     Text(String),
+    Comment(String),
 }
 
 impl PartialEq for NodeKind {
     fn eq(&self, other: &Self) -> bool {
         match self {
-            NodeKind::Document => matches!(other, NodeKind::Document),
+            NodeKind::Document(_) => matches!(other, NodeKind::Document(_)),
+            NodeKind::DocumentFragment => matches!(other, NodeKind::DocumentFragment),
             NodeKind::Element(e1) => {
                 if let NodeKind::Element(e2) = other {
                     e1.kind == e2.kind
@@ -120,31 +867,112 @@ impl PartialEq for NodeKind {
                 }
             }
             NodeKind::Text(_) => matches!(other, NodeKind::Text(_)),
+            NodeKind::Comment(_) => matches!(other, NodeKind::Comment(_)),
+            NodeKind::DocumentType(_) => matches!(other, NodeKind::DocumentType(_)),
+        }
+    }
+}
+
+/// Document ノードの情報
+/// https://dom.spec.whatwg.org/#interface-document
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Document {
+    mode: QuirksMode,
+    /// 文書を読み込んだ URL。`None` の場合は about:blank として扱う
+    url: Option<String>,
+}
+
+impl Document {
+    pub fn new() -> Self {
+        Self {
+            mode: QuirksMode::NoQuirks,
+            url: None,
         }
     }
+
+    /// 文書の URL。相対 URL を解決するときの基準になる
+    /// https://dom.spec.whatwg.org/#concept-document-url
+    pub fn url(&self) -> Option<String> {
+        self.url.clone()
+    }
+
+    pub fn set_url(&mut self, url: Option<String>) {
+        self.url = url;
+    }
+
+    /// 文書の互換モード。DOCTYPE をもとにパーサが決定する
+    pub fn mode(&self) -> QuirksMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: QuirksMode) {
+        self.mode = mode;
+    }
+}
+
+/// 文書の互換モード (document mode)
+/// https://dom.spec.whatwg.org/#concept-document-mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QuirksMode {
+    #[default]
+    NoQuirks,
+    LimitedQuirks,
+    Quirks,
+}
+
+/// DOCTYPE を表すノードの情報
+/// https://dom.spec.whatwg.org/#interface-documenttype
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DocumentType {
+    name: String,
+    public_id: String,
+    system_id: String,
+}
+
+impl DocumentType {
+    pub fn new(name: String, public_id: String, system_id: String) -> Self {
+        Self {
+            name,
+            public_id,
+            system_id,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn public_id(&self) -> String {
+        self.public_id.clone()
+    }
+
+    pub fn system_id(&self) -> String {
+        self.system_id.clone()
+    }
 }
 
+/// 文書を表示するウィンドウ。文書の木を保持する
 #[derive(Debug, Clone)]
 pub struct Window {
-    document: Rc<RefCell<Node>>,
+    dom: Dom,
 }
 
 impl Window {
     pub fn new() -> Self {
-        let window = Self {
-            document: Rc::new(RefCell::new(Node::new(NodeKind::Document))),
-        };
+        Self { dom: Dom::new() }
+    }
 
-        window
-            .document
-            .borrow_mut()
-            .set_window(Rc::downgrade(&Rc::new(RefCell::new(window.clone()))));
+    /// 文書の木の根である Document ノード
+    pub fn document(&self) -> NodeId {
+        self.dom.document()
+    }
 
-        window
+    pub fn dom(&self) -> &Dom {
+        &self.dom
     }
 
-    pub fn document(&self) -> Rc<RefCell<Node>> {
-        self.document.clone()
+    pub fn dom_mut(&mut self) -> &mut Dom {
+        &mut self.dom
     }
 }
 
@@ -157,51 +985,1293 @@ impl Default for Window {
 #[derive(Debug, Clone)]
 pub struct Element {
     kind: ElementKind,
+    namespace: Namespace,
     attributes: Vec<Attribute>,
+    /// <template> 要素の内容を保持する DocumentFragment
+    /// `Dom::create_node` で要素を木に加えたときに作る
+    /// https://html.spec.whatwg.org/multipage/scripting.html#template-contents
+    template_contents: Option<NodeId>,
 }
 
 impl Element {
     pub fn new(element_name: &str, attributes: Vec<Attribute>) -> Self {
+        Self::new_with_namespace(element_name, attributes, Namespace::Html)
+    }
+
+    /// 名前空間を指定して要素を作る。SVG や MathML の要素は、HTML の同じ名前の要素と区別するために
+    /// `ElementKind::Unknown` として扱う
+    pub fn new_with_namespace(
+        element_name: &str,
+        attributes: Vec<Attribute>,
+        namespace: Namespace,
+    ) -> Self {
+        let kind = match namespace {
+            Namespace::Html => ElementKind::from_str(element_name).unwrap_or_else(|e| match e {}),
+            _ => ElementKind::Unknown(element_name.to_string()),
+        };
         Self {
-            kind: ElementKind::from_str(element_name)
-                .expect("failed to convert string to ElementKind"),
+            kind,
+            namespace,
             attributes,
+            template_contents: None,
         }
     }
 
-    pub fn kind(&self) -> ElementKind {
-        self.kind
+    pub fn kind(&self) -> &ElementKind {
+        &self.kind
+    }
+
+    pub fn namespace(&self) -> Namespace {
+        self.namespace
+    }
+
+    /// 要素のローカル名。SVG の要素では foreignObject のように大文字を含むことがある
+    pub fn local_name(&self) -> &str {
+        self.kind.as_str()
+    }
+
+    /// id 属性の値。ない場合は空文字列を返す
+    /// https://dom.spec.whatwg.org/#dom-element-id
+    pub fn id(&self) -> String {
+        self.get_attribute("id").unwrap_or_default()
+    }
+
+    /// class 属性の値をクラス名の集合として返す。変更した場合は `set_class_list` で書き戻す
+    /// https://dom.spec.whatwg.org/#dom-element-classlist
+    pub fn class_list(&self) -> DomTokenList {
+        DomTokenList::new(&self.get_attribute("class").unwrap_or_default())
+    }
+
+    pub fn set_class_list(&mut self, class_list: &DomTokenList) {
+        self.set_attribute("class", &class_list.value());
+    }
+
+    /// alt 属性の値。ない場合は空文字列を返す
+    pub fn alt(&self) -> String {
+        self.get_attribute("alt").unwrap_or_default()
+    }
+
+    /// title 属性の値。ない場合は空文字列を返す
+    pub fn title(&self) -> String {
+        self.get_attribute("title").unwrap_or_default()
+    }
+
+    /// hidden 属性があるかどうか
+    /// https://html.spec.whatwg.org/multipage/interaction.html#the-hidden-attribute
+    pub fn hidden(&self) -> bool {
+        self.has_attribute("hidden")
+    }
+
+    pub fn set_hidden(&mut self, hidden: bool) {
+        if hidden {
+            self.set_attribute("hidden", "");
+        } else {
+            self.remove_attribute("hidden");
+        }
+    }
+
+    /// tabindex 属性の値を整数として返す。ないか整数として読めない場合は、要素の種類に応じた
+    /// 既定値 (フォーカスできる要素では 0、それ以外では -1) を返す
+    /// https://html.spec.whatwg.org/multipage/interaction.html#dom-tabindex
+    pub fn tab_index(&self) -> i32 {
+        if let Some(index) = self
+            .get_attribute("tabindex")
+            .and_then(|value| parse_integer(&value))
+        {
+            return index;
+        }
+
+        match self.kind {
+            ElementKind::A
+            | ElementKind::Area
+            | ElementKind::Button
+            | ElementKind::Frame
+            | ElementKind::Iframe
+            | ElementKind::Input
+            | ElementKind::Object
+            | ElementKind::Select
+            | ElementKind::Summary
+            | ElementKind::Textarea
+                if self.namespace == Namespace::Html =>
+            {
+                0
+            }
+            _ => -1,
+        }
+    }
+
+    pub fn set_tab_index(&mut self, index: i32) {
+        self.set_attribute("tabindex", &index.to_string());
+    }
+
+    /// href 属性の値を `base` を基準に解決した URL。`base` が `None` の場合は属性値をそのまま返す
+    /// https://html.spec.whatwg.org/multipage/common-dom-interfaces.html#reflecting-content-attributes-in-idl-attributes
+    pub fn href(&self, base: Option<&Url>) -> Option<String> {
+        self.url_attribute("href", base)
+    }
+
+    /// src 属性の値を `base` を基準に解決した URL
+    pub fn src(&self, base: Option<&Url>) -> Option<String> {
+        self.url_attribute("src", base)
+    }
+
+    fn url_attribute(&self, name: &str, base: Option<&Url>) -> Option<String> {
+        let value = self.get_attribute(name)?;
+        Some(match base {
+            Some(base) => base.join(&value),
+            None => value,
+        })
+    }
+
+    /// 属性を、追加された順に返す
+    pub fn attributes(&self) -> Vec<Attribute> {
+        self.attributes.clone()
+    }
+
+    /// 接頭辞を含めた名前が `name` である最初の属性の値を返す。HTML の要素では、名前の大文字と
+    /// 小文字を区別しない
+    /// https://dom.spec.whatwg.org/#dom-element-getattribute
+    pub fn get_attribute(&self, name: &str) -> Option<String> {
+        self.find_attribute(name)
+            .map(|i| self.attributes[i].value())
+    }
+
+    pub fn has_attribute(&self, name: &str) -> bool {
+        self.find_attribute(name).is_some()
+    }
+
+    /// 属性の値を設定する。同じ名前の属性がなければ、最後に追加する
+    /// https://dom.spec.whatwg.org/#dom-element-setattribute
+    pub fn set_attribute(&mut self, name: &str, value: &str) {
+        match self.find_attribute(name) {
+            Some(i) => self.attributes[i].set_value(value),
+            None => {
+                let name = self.normalize_attribute_name(name);
+                self.attributes
+                    .push(Attribute::new_with_value(&name, value));
+            }
+        }
+    }
+
+    /// 属性を取り除き、取り除いた属性を返す
+    /// https://dom.spec.whatwg.org/#dom-element-removeattribute
+    pub fn remove_attribute(&mut self, name: &str) -> Option<Attribute> {
+        self.find_attribute(name).map(|i| self.attributes.remove(i))
+    }
+
+    /// 名前空間とローカル名が一致する属性の値を返す。HTML の属性の場合、`namespace` は `None`
+    /// https://dom.spec.whatwg.org/#dom-element-getattributens
+    pub fn get_attribute_ns(
+        &self,
+        namespace: Option<Namespace>,
+        local_name: &str,
+    ) -> Option<String> {
+        self.find_attribute_ns(namespace, local_name)
+            .map(|i| self.attributes[i].value())
+    }
+
+    pub fn has_attribute_ns(&self, namespace: Option<Namespace>, local_name: &str) -> bool {
+        self.find_attribute_ns(namespace, local_name).is_some()
+    }
+
+    /// 名前空間付きの属性の値を設定する。`qualified_name` は xlink:href のように接頭辞を含めた
+    /// 名前で、すでに同じ名前空間とローカル名の属性がある場合は値だけを置き換える
+    /// https://dom.spec.whatwg.org/#dom-element-setattributens
+    pub fn set_attribute_ns(
+        &mut self,
+        namespace: Option<Namespace>,
+        qualified_name: &str,
+        value: &str,
+    ) {
+        let (prefix, local_name) = match qualified_name.split_once(':') {
+            Some((prefix, local_name)) => (Some(prefix), local_name),
+            None => (None, qualified_name),
+        };
+
+        match self.find_attribute_ns(namespace, local_name) {
+            Some(i) => self.attributes[i].set_value(value),
+            None => {
+                let mut attribute = Attribute::new_with_value(local_name, value);
+                if let Some(namespace) = namespace {
+                    attribute.set_namespace(prefix, local_name, namespace);
+                }
+                self.attributes.push(attribute);
+            }
+        }
+    }
+
+    /// https://dom.spec.whatwg.org/#dom-element-removeattributens
+    pub fn remove_attribute_ns(
+        &mut self,
+        namespace: Option<Namespace>,
+        local_name: &str,
+    ) -> Option<Attribute> {
+        self.find_attribute_ns(namespace, local_name)
+            .map(|i| self.attributes.remove(i))
+    }
+
+    /// HTML の要素の場合、属性名を小文字にする
+    fn normalize_attribute_name(&self, name: &str) -> String {
+        if self.namespace == Namespace::Html {
+            name.to_ascii_lowercase()
+        } else {
+            name.to_string()
+        }
+    }
+
+    /// https://dom.spec.whatwg.org/#concept-element-attributes-get-by-name
+    fn find_attribute(&self, name: &str) -> Option<usize> {
+        let name = self.normalize_attribute_name(name);
+        self.attributes
+            .iter()
+            .position(|a| a.qualified_name() == name)
+    }
+
+    /// https://dom.spec.whatwg.org/#concept-element-attributes-get-by-namespace
+    fn find_attribute_ns(&self, namespace: Option<Namespace>, local_name: &str) -> Option<usize> {
+        self.attributes
+            .iter()
+            .position(|a| a.namespace() == namespace && a.name() == local_name)
+    }
+
+    /// <template> 要素の場合、その内容を保持する DocumentFragment を返す
+    pub fn template_contents(&self) -> Option<NodeId> {
+        self.template_contents
     }
 }
 
+/// 整数を表す属性値を読む。先頭の空白を読み飛ばし、数字の後ろの文字は無視する
+/// https://html.spec.whatwg.org/multipage/common-microsyntaxes.html#rules-for-parsing-integers
+fn parse_integer(value: &str) -> Option<i32> {
+    let value = value.trim_start_matches(['\t', '\n', '\u{0C}', '\r', ' ']);
+    let (negative, value) = match value.as_bytes().first() {
+        Some(b'-') => (true, &value[1..]),
+        Some(b'+') => (false, &value[1..]),
+        _ => (false, value),
+    };
+    let end = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let digits = &value[..end];
+    if digits.is_empty() {
+        return None;
+    }
+
+    let n: i64 = digits.parse().ok()?;
+    let n = if negative { -n } else { n };
+    i32::try_from(n).ok()
+}
+
+/// 要素や属性の名前空間
+/// https://infra.spec.whatwg.org/#namespaces
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum ElementKind {
+pub enum Namespace {
     Html,
-    Head,
-    Style,
-    Script,
+    MathMl,
+    Svg,
+    XLink,
+    Xml,
+    Xmlns,
+}
+
+impl Namespace {
+    pub fn url(&self) -> &'static str {
+        match self {
+            Namespace::Html => "http://www.w3.org/1999/xhtml",
+            Namespace::MathMl => "http://www.w3.org/1998/Math/MathML",
+            Namespace::Svg => "http://www.w3.org/2000/svg",
+            Namespace::XLink => "http://www.w3.org/1999/xlink",
+            Namespace::Xml => "http://www.w3.org/XML/1998/namespace",
+            Namespace::Xmlns => "http://www.w3.org/2000/xmlns/",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ElementKind {
+    A,
+    Abbr,
+    Acronym,
+    Address,
+    Applet,
+    Area,
+    Article,
+    Aside,
+    Audio,
+    B,
+    Base,
+    Basefont,
+    Bdi,
+    Bdo,
+    Bgsound,
+    Big,
+    Blink,
+    Blockquote,
     Body,
-    P,
+    Br,
+    Button,
+    Canvas,
+    Caption,
+    Center,
+    Cite,
+    Code,
+    Col,
+    Colgroup,
+    Data,
+    Datalist,
+    Dd,
+    Del,
+    Details,
+    Dfn,
+    Dialog,
+    Dir,
+    Div,
+    Dl,
+    Dt,
+    Em,
+    Embed,
+    Fieldset,
+    Figcaption,
+    Figure,
+    Font,
+    Footer,
+    Form,
+    Frame,
+    Frameset,
     H1,
     H2,
-    A,
+    H3,
+    H4,
+    H5,
+    H6,
+    Head,
+    Header,
+    Hgroup,
+    Hr,
+    Html,
+    I,
+    Iframe,
+    Img,
+    Input,
+    Ins,
+    Kbd,
+    Keygen,
+    Label,
+    Legend,
+    Li,
+    Link,
+    Listing,
+    Main,
+    Map,
+    Mark,
+    Marquee,
+    Menu,
+    Meta,
+    Meter,
+    Nav,
+    Nobr,
+    Noembed,
+    Noframes,
+    Noscript,
+    Object,
+    Ol,
+    Optgroup,
+    Option,
+    Output,
+    P,
+    Param,
+    Picture,
+    Plaintext,
+    Pre,
+    Progress,
+    Q,
+    Rb,
+    Rp,
+    Rt,
+    Rtc,
+    Ruby,
+    S,
+    Samp,
+    Script,
+    Search,
+    Section,
+    Select,
+    Slot,
+    Small,
+    Source,
+    Span,
+    Strike,
+    Strong,
+    Style,
+    Sub,
+    Summary,
+    Sup,
+    Table,
+    Tbody,
+    Td,
+    Template,
+    Textarea,
+    Tfoot,
+    Th,
+    Thead,
+    Time,
+    Title,
+    Tr,
+    Track,
+    Tt,
+    U,
+    Ul,
+    Var,
+    Video,
+    Wbr,
+    Xmp,
+    /// 未知の要素やカスタム要素 (例: <my-element>)。タグ名をそのまま保持する
+    Unknown(String),
+}
+
+impl ElementKind {
+    /// タグ名を返す
+    pub fn as_str(&self) -> &str {
+        match self {
+            ElementKind::A => "a",
+            ElementKind::Abbr => "abbr",
+            ElementKind::Acronym => "acronym",
+            ElementKind::Address => "address",
+            ElementKind::Applet => "applet",
+            ElementKind::Area => "area",
+            ElementKind::Article => "article",
+            ElementKind::Aside => "aside",
+            ElementKind::Audio => "audio",
+            ElementKind::B => "b",
+            ElementKind::Base => "base",
+            ElementKind::Basefont => "basefont",
+            ElementKind::Bdi => "bdi",
+            ElementKind::Bdo => "bdo",
+            ElementKind::Bgsound => "bgsound",
+            ElementKind::Big => "big",
+            ElementKind::Blink => "blink",
+            ElementKind::Blockquote => "blockquote",
+            ElementKind::Body => "body",
+            ElementKind::Br => "br",
+            ElementKind::Button => "button",
+            ElementKind::Canvas => "canvas",
+            ElementKind::Caption => "caption",
+            ElementKind::Center => "center",
+            ElementKind::Cite => "cite",
+            ElementKind::Code => "code",
+            ElementKind::Col => "col",
+            ElementKind::Colgroup => "colgroup",
+            ElementKind::Data => "data",
+            ElementKind::Datalist => "datalist",
+            ElementKind::Dd => "dd",
+            ElementKind::Del => "del",
+            ElementKind::Details => "details",
+            ElementKind::Dfn => "dfn",
+            ElementKind::Dialog => "dialog",
+            ElementKind::Dir => "dir",
+            ElementKind::Div => "div",
+            ElementKind::Dl => "dl",
+            ElementKind::Dt => "dt",
+            ElementKind::Em => "em",
+            ElementKind::Embed => "embed",
+            ElementKind::Fieldset => "fieldset",
+            ElementKind::Figcaption => "figcaption",
+            ElementKind::Figure => "figure",
+            ElementKind::Font => "font",
+            ElementKind::Footer => "footer",
+            ElementKind::Form => "form",
+            ElementKind::Frame => "frame",
+            ElementKind::Frameset => "frameset",
+            ElementKind::H1 => "h1",
+            ElementKind::H2 => "h2",
+            ElementKind::H3 => "h3",
+            ElementKind::H4 => "h4",
+            ElementKind::H5 => "h5",
+            ElementKind::H6 => "h6",
+            ElementKind::Head => "head",
+            ElementKind::Header => "header",
+            ElementKind::Hgroup => "hgroup",
+            ElementKind::Hr => "hr",
+            ElementKind::Html => "html",
+            ElementKind::I => "i",
+            ElementKind::Iframe => "iframe",
+            ElementKind::Img => "img",
+            ElementKind::Input => "input",
+            ElementKind::Ins => "ins",
+            ElementKind::Kbd => "kbd",
+            ElementKind::Keygen => "keygen",
+            ElementKind::Label => "label",
+            ElementKind::Legend => "legend",
+            ElementKind::Li => "li",
+            ElementKind::Link => "link",
+            ElementKind::Listing => "listing",
+            ElementKind::Main => "main",
+            ElementKind::Map => "map",
+            ElementKind::Mark => "mark",
+            ElementKind::Marquee => "marquee",
+            ElementKind::Menu => "menu",
+            ElementKind::Meta => "meta",
+            ElementKind::Meter => "meter",
+            ElementKind::Nav => "nav",
+            ElementKind::Nobr => "nobr",
+            ElementKind::Noembed => "noembed",
+            ElementKind::Noframes => "noframes",
+            ElementKind::Noscript => "noscript",
+            ElementKind::Object => "object",
+            ElementKind::Ol => "ol",
+            ElementKind::Optgroup => "optgroup",
+            ElementKind::Option => "option",
+            ElementKind::Output => "output",
+            ElementKind::P => "p",
+            ElementKind::Param => "param",
+            ElementKind::Picture => "picture",
+            ElementKind::Plaintext => "plaintext",
+            ElementKind::Pre => "pre",
+            ElementKind::Progress => "progress",
+            ElementKind::Q => "q",
+            ElementKind::Rb => "rb",
+            ElementKind::Rp => "rp",
+            ElementKind::Rt => "rt",
+            ElementKind::Rtc => "rtc",
+            ElementKind::Ruby => "ruby",
+            ElementKind::S => "s",
+            ElementKind::Samp => "samp",
+            ElementKind::Script => "script",
+            ElementKind::Search => "search",
+            ElementKind::Section => "section",
+            ElementKind::Select => "select",
+            ElementKind::Slot => "slot",
+            ElementKind::Small => "small",
+            ElementKind::Source => "source",
+            ElementKind::Span => "span",
+            ElementKind::Strike => "strike",
+            ElementKind::Strong => "strong",
+            ElementKind::Style => "style",
+            ElementKind::Sub => "sub",
+            ElementKind::Summary => "summary",
+            ElementKind::Sup => "sup",
+            ElementKind::Table => "table",
+            ElementKind::Tbody => "tbody",
+            ElementKind::Td => "td",
+            ElementKind::Template => "template",
+            ElementKind::Textarea => "textarea",
+            ElementKind::Tfoot => "tfoot",
+            ElementKind::Th => "th",
+            ElementKind::Thead => "thead",
+            ElementKind::Time => "time",
+            ElementKind::Title => "title",
+            ElementKind::Tr => "tr",
+            ElementKind::Track => "track",
+            ElementKind::Tt => "tt",
+            ElementKind::U => "u",
+            ElementKind::Ul => "ul",
+            ElementKind::Var => "var",
+            ElementKind::Video => "video",
+            ElementKind::Wbr => "wbr",
+            ElementKind::Xmp => "xmp",
+            ElementKind::Unknown(name) => name,
+        }
+    }
+
+    /// パース時に特別扱いされる要素 (special カテゴリ) かどうかを返す
+    /// https://html.spec.whatwg.org/multipage/parsing.html#special
+    pub fn is_special(&self) -> bool {
+        matches!(
+            self,
+            ElementKind::Address
+                | ElementKind::Applet
+                | ElementKind::Area
+                | ElementKind::Article
+                | ElementKind::Aside
+                | ElementKind::Base
+                | ElementKind::Basefont
+                | ElementKind::Bgsound
+                | ElementKind::Blockquote
+                | ElementKind::Body
+                | ElementKind::Br
+                | ElementKind::Button
+                | ElementKind::Caption
+                | ElementKind::Center
+                | ElementKind::Col
+                | ElementKind::Colgroup
+                | ElementKind::Dd
+                | ElementKind::Details
+                | ElementKind::Dir
+                | ElementKind::Div
+                | ElementKind::Dl
+                | ElementKind::Dt
+                | ElementKind::Embed
+                | ElementKind::Fieldset
+                | ElementKind::Figcaption
+                | ElementKind::Figure
+                | ElementKind::Footer
+                | ElementKind::Form
+                | ElementKind::Frame
+                | ElementKind::Frameset
+                | ElementKind::H1
+                | ElementKind::H2
+                | ElementKind::H3
+                | ElementKind::H4
+                | ElementKind::H5
+                | ElementKind::H6
+                | ElementKind::Head
+                | ElementKind::Header
+                | ElementKind::Hgroup
+                | ElementKind::Hr
+                | ElementKind::Html
+                | ElementKind::Iframe
+                | ElementKind::Img
+                | ElementKind::Input
+                | ElementKind::Keygen
+                | ElementKind::Li
+                | ElementKind::Link
+                | ElementKind::Listing
+                | ElementKind::Main
+                | ElementKind::Marquee
+                | ElementKind::Menu
+                | ElementKind::Meta
+                | ElementKind::Nav
+                | ElementKind::Noembed
+                | ElementKind::Noframes
+                | ElementKind::Noscript
+                | ElementKind::Object
+                | ElementKind::Ol
+                | ElementKind::P
+                | ElementKind::Param
+                | ElementKind::Plaintext
+                | ElementKind::Pre
+                | ElementKind::Script
+                | ElementKind::Search
+                | ElementKind::Section
+                | ElementKind::Select
+                | ElementKind::Source
+                | ElementKind::Style
+                | ElementKind::Summary
+                | ElementKind::Table
+                | ElementKind::Tbody
+                | ElementKind::Td
+                | ElementKind::Template
+                | ElementKind::Textarea
+                | ElementKind::Tfoot
+                | ElementKind::Th
+                | ElementKind::Thead
+                | ElementKind::Title
+                | ElementKind::Tr
+                | ElementKind::Track
+                | ElementKind::Ul
+                | ElementKind::Wbr
+                | ElementKind::Xmp
+        )
+    }
 }
 
 impl FromStr for ElementKind {
-    type Err = String;
+    type Err = Infallible;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "html" => Ok(ElementKind::Html),
-            "head" => Ok(ElementKind::Head),
-            "style" => Ok(ElementKind::Style),
-            "script" => Ok(ElementKind::Script),
-            "body" => Ok(ElementKind::Body),
-            "p" => Ok(ElementKind::P),
-            "h1" => Ok(ElementKind::H1),
-            "h2" => Ok(ElementKind::H2),
-            "a" => Ok(ElementKind::A),
-            _ => Err(format!("unimplemented element name {:?}", s)),
+        let kind = match s {
+            "a" => ElementKind::A,
+            "abbr" => ElementKind::Abbr,
+            "acronym" => ElementKind::Acronym,
+            "address" => ElementKind::Address,
+            "applet" => ElementKind::Applet,
+            "area" => ElementKind::Area,
+            "article" => ElementKind::Article,
+            "aside" => ElementKind::Aside,
+            "audio" => ElementKind::Audio,
+            "b" => ElementKind::B,
+            "base" => ElementKind::Base,
+            "basefont" => ElementKind::Basefont,
+            "bdi" => ElementKind::Bdi,
+            "bdo" => ElementKind::Bdo,
+            "bgsound" => ElementKind::Bgsound,
+            "big" => ElementKind::Big,
+            "blink" => ElementKind::Blink,
+            "blockquote" => ElementKind::Blockquote,
+            "body" => ElementKind::Body,
+            "br" => ElementKind::Br,
+            "button" => ElementKind::Button,
+            "canvas" => ElementKind::Canvas,
+            "caption" => ElementKind::Caption,
+            "center" => ElementKind::Center,
+            "cite" => ElementKind::Cite,
+            "code" => ElementKind::Code,
+            "col" => ElementKind::Col,
+            "colgroup" => ElementKind::Colgroup,
+            "data" => ElementKind::Data,
+            "datalist" => ElementKind::Datalist,
+            "dd" => ElementKind::Dd,
+            "del" => ElementKind::Del,
+            "details" => ElementKind::Details,
+            "dfn" => ElementKind::Dfn,
+            "dialog" => ElementKind::Dialog,
+            "dir" => ElementKind::Dir,
+            "div" => ElementKind::Div,
+            "dl" => ElementKind::Dl,
+            "dt" => ElementKind::Dt,
+            "em" => ElementKind::Em,
+            "embed" => ElementKind::Embed,
+            "fieldset" => ElementKind::Fieldset,
+            "figcaption" => ElementKind::Figcaption,
+            "figure" => ElementKind::Figure,
+            "font" => ElementKind::Font,
+            "footer" => ElementKind::Footer,
+            "form" => ElementKind::Form,
+            "frame" => ElementKind::Frame,
+            "frameset" => ElementKind::Frameset,
+            "h1" => ElementKind::H1,
+            "h2" => ElementKind::H2,
+            "h3" => ElementKind::H3,
+            "h4" => ElementKind::H4,
+            "h5" => ElementKind::H5,
+            "h6" => ElementKind::H6,
+            "head" => ElementKind::Head,
+            "header" => ElementKind::Header,
+            "hgroup" => ElementKind::Hgroup,
+            "hr" => ElementKind::Hr,
+            "html" => ElementKind::Html,
+            "i" => ElementKind::I,
+            "iframe" => ElementKind::Iframe,
+            "img" => ElementKind::Img,
+            "input" => ElementKind::Input,
+            "ins" => ElementKind::Ins,
+            "kbd" => ElementKind::Kbd,
+            "keygen" => ElementKind::Keygen,
+            "label" => ElementKind::Label,
+            "legend" => ElementKind::Legend,
+            "li" => ElementKind::Li,
+            "link" => ElementKind::Link,
+            "listing" => ElementKind::Listing,
+            "main" => ElementKind::Main,
+            "map" => ElementKind::Map,
+            "mark" => ElementKind::Mark,
+            "marquee" => ElementKind::Marquee,
+            "menu" => ElementKind::Menu,
+            "meta" => ElementKind::Meta,
+            "meter" => ElementKind::Meter,
+            "nav" => ElementKind::Nav,
+            "nobr" => ElementKind::Nobr,
+            "noembed" => ElementKind::Noembed,
+            "noframes" => ElementKind::Noframes,
+            "noscript" => ElementKind::Noscript,
+            "object" => ElementKind::Object,
+            "ol" => ElementKind::Ol,
+            "optgroup" => ElementKind::Optgroup,
+            "option" => ElementKind::Option,
+            "output" => ElementKind::Output,
+            "p" => ElementKind::P,
+            "param" => ElementKind::Param,
+            "picture" => ElementKind::Picture,
+            "plaintext" => ElementKind::Plaintext,
+            "pre" => ElementKind::Pre,
+            "progress" => ElementKind::Progress,
+            "q" => ElementKind::Q,
+            "rb" => ElementKind::Rb,
+            "rp" => ElementKind::Rp,
+            "rt" => ElementKind::Rt,
+            "rtc" => ElementKind::Rtc,
+            "ruby" => ElementKind::Ruby,
+            "s" => ElementKind::S,
+            "samp" => ElementKind::Samp,
+            "script" => ElementKind::Script,
+            "search" => ElementKind::Search,
+            "section" => ElementKind::Section,
+            "select" => ElementKind::Select,
+            "slot" => ElementKind::Slot,
+            "small" => ElementKind::Small,
+            "source" => ElementKind::Source,
+            "span" => ElementKind::Span,
+            "strike" => ElementKind::Strike,
+            "strong" => ElementKind::Strong,
+            "style" => ElementKind::Style,
+            "sub" => ElementKind::Sub,
+            "summary" => ElementKind::Summary,
+            "sup" => ElementKind::Sup,
+            "table" => ElementKind::Table,
+            "tbody" => ElementKind::Tbody,
+            "td" => ElementKind::Td,
+            "template" => ElementKind::Template,
+            "textarea" => ElementKind::Textarea,
+            "tfoot" => ElementKind::Tfoot,
+            "th" => ElementKind::Th,
+            "thead" => ElementKind::Thead,
+            "time" => ElementKind::Time,
+            "title" => ElementKind::Title,
+            "tr" => ElementKind::Tr,
+            "track" => ElementKind::Track,
+            "tt" => ElementKind::Tt,
+            "u" => ElementKind::U,
+            "ul" => ElementKind::Ul,
+            "var" => ElementKind::Var,
+            "video" => ElementKind::Video,
+            "wbr" => ElementKind::Wbr,
+            "xmp" => ElementKind::Xmp,
+            _ => ElementKind::Unknown(s.to_string()),
+        };
+        Ok(kind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn attribute(name: &str, value: &str) -> Attribute {
+        Attribute::new_with_value(name, value)
+    }
+
+    #[test]
+    fn test_attributes() {
+        let mut element = Element::new("div", vec![attribute("id", "a"), attribute("class", "b")]);
+        assert_eq!(Some("a".to_string()), element.get_attribute("id"));
+        // HTML の要素では大文字と小文字を区別しない
+        assert_eq!(Some("b".to_string()), element.get_attribute("CLASS"));
+        assert!(!element.has_attribute("title"));
+
+        element.set_attribute("ID", "c");
+        element.set_attribute("Title", "d");
+        let names: Vec<String> = element.attributes().iter().map(|a| a.name()).collect();
+        assert_eq!(vec!["id", "class", "title"], names);
+        assert_eq!(Some("c".to_string()), element.get_attribute("id"));
+
+        assert_eq!(
+            Some("b".to_string()),
+            element.remove_attribute("class").map(|a| a.value())
+        );
+        assert_eq!(None, element.remove_attribute("class"));
+        assert_eq!(2, element.attributes().len());
+    }
+
+    #[test]
+    fn test_namespaced_attributes() {
+        let mut element = Element::new_with_namespace("use", Vec::new(), Namespace::Svg);
+        element.set_attribute("viewBox", "0 0 1 1");
+        // SVG の要素では大文字と小文字を区別する
+        assert!(!element.has_attribute("viewbox"));
+
+        element.set_attribute_ns(Some(Namespace::XLink), "xlink:href", "#a");
+        assert_eq!(
+            Some("#a".to_string()),
+            element.get_attribute_ns(Some(Namespace::XLink), "href")
+        );
+        assert_eq!(Some("#a".to_string()), element.get_attribute("xlink:href"));
+        assert!(!element.has_attribute_ns(None, "href"));
+
+        element.set_attribute_ns(Some(Namespace::XLink), "xlink:href", "#b");
+        assert_eq!(2, element.attributes().len());
+        assert!(element
+            .remove_attribute_ns(Some(Namespace::XLink), "href")
+            .is_some());
+        assert!(!element.has_attribute("xlink:href"));
+    }
+
+    #[test]
+    fn test_id_and_class_list() {
+        let mut element = Element::new(
+            "p",
+            vec![attribute("id", "main"), attribute("class", " a  b a ")],
+        );
+        assert_eq!("main", element.id());
+        assert_eq!("", Element::new("p", Vec::new()).id());
+
+        let mut class_list = element.class_list();
+        assert!(class_list.contains("a"));
+        assert_eq!(2, class_list.len());
+        class_list.toggle("a");
+        class_list.add("c");
+        element.set_class_list(&class_list);
+        assert_eq!(Some("b c".to_string()), element.get_attribute("class"));
+    }
+
+    #[test]
+    fn test_reflected_attributes() {
+        let mut img = Element::new(
+            "img",
+            vec![
+                attribute("src", "images/a.png"),
+                attribute("alt", "A"),
+                attribute("tabindex", " +3px"),
+            ],
+        );
+        assert_eq!("A", img.alt());
+        assert_eq!(3, img.tab_index());
+        assert!(!img.hidden());
+        img.set_hidden(true);
+        assert!(img.hidden());
+        img.set_tab_index(-2);
+        assert_eq!(Some("-2".to_string()), img.get_attribute("tabindex"));
+
+        let base = Url::new("http://example.com/dir/index.html".to_string())
+            .parse()
+            .unwrap();
+        assert_eq!(
+            Some("http://example.com/dir/images/a.png".to_string()),
+            img.src(Some(&base))
+        );
+        assert_eq!(Some("images/a.png".to_string()), img.src(None));
+        assert_eq!(None, img.href(Some(&base)));
+
+        // tabindex が整数として読めない場合は要素の種類に応じた既定値になる
+        assert_eq!(
+            0,
+            Element::new("a", vec![attribute("tabindex", "x")]).tab_index()
+        );
+        assert_eq!(-1, Element::new("div", Vec::new()).tab_index());
+    }
+
+    #[test]
+    fn test_href_resolved_against_document_url() {
+        let mut dom = Dom::new();
+        let document = dom.document();
+        if let Some(d) = dom.node_mut(document).get_document_mut() {
+            d.set_url(Some("http://example.com/a/b.html".to_string()));
+        }
+        let a = dom.create_node(NodeKind::Element(Element::new(
+            "a",
+            vec![attribute("href", "../c.html#x")],
+        )));
+        assert_eq!(Some("http://example.com/c.html#x".to_string()), dom.href(a));
+        assert_eq!(None, dom.src(a));
+    }
+
+    fn element(dom: &mut Dom, name: &str) -> NodeId {
+        dom.create_node(NodeKind::Element(Element::new(name, Vec::new())))
+    }
+
+    fn text(dom: &mut Dom, s: &str) -> NodeId {
+        dom.create_node(NodeKind::Text(s.to_string()))
+    }
+
+    fn names(dom: &Dom, parent: NodeId) -> Vec<String> {
+        dom.children(parent)
+            .map(|n| match dom.node(n).kind {
+                NodeKind::Element(ref e) => e.local_name().to_string(),
+                NodeKind::Text(ref s) => s.clone(),
+                _ => "?".to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_append_and_insert() {
+        let mut dom = Dom::new();
+        let div = element(&mut dom, "div");
+        let a = element(&mut dom, "a");
+        let b = element(&mut dom, "b");
+        let x = text(&mut dom, "x");
+        let i = element(&mut dom, "i");
+        assert_eq!(Ok(a), dom.append_child(div, a));
+        dom.append_child(div, b).unwrap();
+        dom.insert_before(div, x, Some(b)).unwrap();
+        dom.insert_before(div, i, Some(a)).unwrap();
+        assert_eq!(vec!["i", "a", "x", "b"], names(&dom, div));
+        assert_eq!(Some(b), dom.node(div).last_child());
+        assert_eq!(Some(div), dom.node(b).parent());
+
+        // すでに木にあるノードを追加すると、元の位置から移動する
+        dom.append_child(div, a).unwrap();
+        assert_eq!(vec!["i", "x", "b", "a"], names(&dom, div));
+        dom.insert_before(div, b, Some(b)).unwrap();
+        assert_eq!(vec!["i", "x", "b", "a"], names(&dom, div));
+
+        // DocumentFragment を追加すると、その子が移動する
+        let fragment = dom.create_node(NodeKind::DocumentFragment);
+        let p = element(&mut dom, "p");
+        let q = element(&mut dom, "q");
+        dom.append_child(fragment, p).unwrap();
+        dom.append_child(fragment, q).unwrap();
+        dom.insert_before(div, fragment, Some(a)).unwrap();
+        assert_eq!(vec!["i", "x", "b", "p", "q", "a"], names(&dom, div));
+        assert_eq!(None, dom.node(fragment).first_child());
+    }
+
+    #[test]
+    fn test_remove_and_replace() {
+        let mut dom = Dom::new();
+        let div = element(&mut dom, "div");
+        let a = element(&mut dom, "a");
+        let b = element(&mut dom, "b");
+        let c = element(&mut dom, "c");
+        for n in [a, b, c] {
+            dom.append_child(div, n).unwrap();
         }
+
+        assert_eq!(Ok(b), dom.remove_child(div, b));
+        assert_eq!(None, dom.node(b).parent());
+        assert_eq!(vec!["a", "c"], names(&dom, div));
+        assert_eq!(Err(DomException::NotFoundError), dom.remove_child(div, b));
+
+        assert_eq!(Ok(c), dom.replace_child(div, b, c));
+        assert_eq!(vec!["a", "b"], names(&dom, div));
+        assert_eq!(Some(b), dom.node(div).last_child());
+        dom.replace_child(div, b, a).unwrap();
+        assert_eq!(vec!["b"], names(&dom, div));
+    }
+
+    #[test]
+    fn test_pre_insertion_validity() {
+        let mut dom = Dom::new();
+        let div = element(&mut dom, "div");
+        let span = element(&mut dom, "span");
+        dom.append_child(div, span).unwrap();
+        // 祖先を子孫として追加すると循環する
+        assert_eq!(
+            Err(DomException::HierarchyRequestError),
+            dom.append_child(span, div)
+        );
+        assert_eq!(
+            Err(DomException::HierarchyRequestError),
+            dom.append_child(span, span)
+        );
+        // テキストノードは子を持てない
+        let a = text(&mut dom, "a");
+        let b = element(&mut dom, "b");
+        assert_eq!(
+            Err(DomException::HierarchyRequestError),
+            dom.append_child(a, b)
+        );
+        // `child` は `parent` の子でなければならない
+        assert_eq!(
+            Err(DomException::NotFoundError),
+            dom.insert_before(span, b, Some(div))
+        );
+
+        let document = dom.document();
+        let doctype = dom.create_node(NodeKind::DocumentType(DocumentType::new(
+            "html".to_string(),
+            String::new(),
+            String::new(),
+        )));
+        let html = element(&mut dom, "html");
+        dom.append_child(document, html).unwrap();
+        // 文書の要素は1つだけで、DOCTYPE は要素の前に置き、テキストは置けない
+        assert_eq!(
+            Err(DomException::HierarchyRequestError),
+            dom.append_child(document, b)
+        );
+        assert_eq!(
+            Err(DomException::HierarchyRequestError),
+            dom.append_child(document, doctype)
+        );
+        assert_eq!(
+            Err(DomException::HierarchyRequestError),
+            dom.append_child(document, a)
+        );
+        dom.insert_before(document, doctype, Some(html)).unwrap();
+        dom.replace_child(document, b, html).unwrap();
+        assert_eq!(vec![doctype, b], dom.children(document).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_clone_node() {
+        let mut dom = Dom::new();
+        let div = dom.create_node(NodeKind::Element(Element::new(
+            "div",
+            vec![attribute("id", "a")],
+        )));
+        let x = text(&mut dom, "x");
+        let template = element(&mut dom, "template");
+        let p = element(&mut dom, "p");
+        dom.append_child(div, x).unwrap();
+        dom.append_child(div, template).unwrap();
+        let contents = dom.template_contents(template).unwrap();
+        dom.append_child(contents, p).unwrap();
+
+        let shallow = dom.clone_node(div, false);
+        assert_eq!("a", dom.node(shallow).get_element().unwrap().id());
+        assert_eq!(None, dom.node(shallow).first_child());
+
+        let deep = dom.clone_node(div, true);
+        assert_eq!(vec!["x", "template"], names(&dom, deep));
+        let template_copy = dom.node(deep).last_child().unwrap();
+        let contents_copy = dom.template_contents(template_copy).unwrap();
+        // <template> の内容も別のノードとして複製される
+        assert_ne!(contents, contents_copy);
+        assert_eq!(vec!["p"], names(&dom, contents_copy));
+        assert_eq!(vec!["p"], names(&dom, contents));
+
+        // 別の文書に複製する
+        let mut other = Dom::new();
+        let imported = other.import_node(&dom, div, true);
+        assert_eq!(vec!["x", "template"], names(&other, imported));
+        assert_eq!(None, other.node(imported).parent());
+
+        // 深い木を複製してもスタックがあふれない
+        let depth = 100_000;
+        let root = element(&mut dom, "div");
+        let mut parent = root;
+        for _ in 1..depth {
+            let child = element(&mut dom, "div");
+            dom.insert(parent, child, None);
+            parent = child;
+        }
+        let copy = dom.clone_node(root, true);
+        let deepest = dom.descendants(copy).last().unwrap();
+        assert_eq!(depth - 1, dom.ancestors(deepest).count());
+    }
+
+    fn element_with(dom: &mut Dom, name: &str, attributes: &[(&str, &str)]) -> NodeId {
+        let attributes = attributes.iter().map(|(n, v)| attribute(n, v)).collect();
+        dom.create_node(NodeKind::Element(Element::new(name, attributes)))
+    }
+
+    #[test]
+    fn test_get_element_by_id() {
+        let mut dom = Dom::new();
+        let document = dom.document();
+        let body = element(&mut dom, "body");
+        let first = element_with(&mut dom, "div", &[("id", "a")]);
+        let second = element_with(&mut dom, "p", &[("id", "a")]);
+        let child = element_with(&mut dom, "span", &[("id", "b")]);
+        dom.append_child(second, child).unwrap();
+
+        // 文書につながっていない要素は見つからない
+        dom.append_child(body, second).unwrap();
+        assert_eq!(None, dom.get_element_by_id("b"));
+        dom.append_child(document, body).unwrap();
+        assert_eq!(Some(second), dom.get_element_by_id("a"));
+        assert_eq!(Some(child), dom.get_element_by_id("b"));
+
+        // 同じ id の要素が複数ある場合は、木順で最初のものを返す
+        dom.insert_before(body, first, Some(second)).unwrap();
+        assert_eq!(Some(first), dom.get_element_by_id("a"));
+
+        dom.remove_child(body, first).unwrap();
+        assert_eq!(Some(second), dom.get_element_by_id("a"));
+        dom.remove_child(body, second).unwrap();
+        assert_eq!(None, dom.get_element_by_id("a"));
+        assert_eq!(None, dom.get_element_by_id("b"));
+
+        dom.append_child(body, first).unwrap();
+        dom.set_attribute(first, "id", "c");
+        assert_eq!(None, dom.get_element_by_id("a"));
+        assert_eq!(Some(first), dom.get_element_by_id("c"));
+        dom.remove_attribute(first, "id");
+        assert_eq!(None, dom.get_element_by_id("c"));
+    }
+
+    #[test]
+    fn test_is_connected() {
+        let mut dom = Dom::new();
+        let document = dom.document();
+        let html = element(&mut dom, "html");
+        let div = element(&mut dom, "div");
+        let span = element(&mut dom, "span");
+        dom.append_child(div, span).unwrap();
+        assert!(dom.is_connected(document));
+        assert!(!dom.is_connected(span));
+
+        dom.append_child(document, html).unwrap();
+        dom.append_child(html, div).unwrap();
+        assert!(dom.is_connected(div));
+        assert!(dom.is_connected(span));
+
+        dom.remove_child(html, div).unwrap();
+        assert!(!dom.is_connected(div));
+        assert!(!dom.is_connected(span));
+
+        // 深い文書でも、挿入のたびに祖先をたどらずに id を登録できる。`append_child` は挿入できるかを
+        // 祖先をたどって確かめるので、パーサと同じように直接挿入する
+        let mut parent = html;
+        for i in 0..100_000 {
+            let child = element_with(&mut dom, "div", &[("id", &alloc::format!("d{}", i))]);
+            dom.insert(parent, child, None);
+            parent = child;
+        }
+        assert_eq!(Some(parent), dom.get_element_by_id("d99999"));
+        assert!(dom.is_connected(parent));
+    }
+
+    #[test]
+    fn test_get_elements_by() {
+        let mut dom = Dom::new();
+        let document = dom.document();
+        let html = element(&mut dom, "html");
+        let a = element_with(&mut dom, "div", &[("class", "x y")]);
+        let b = element_with(&mut dom, "p", &[("class", "y"), ("name", "n")]);
+        let c = dom.create_node(NodeKind::Element(Element::new_with_namespace(
+            "DIV",
+            vec![attribute("class", "X y"), attribute("name", "n")],
+            Namespace::Svg,
+        )));
+        dom.append_child(document, html).unwrap();
+        for node in [a, b, c] {
+            dom.append_child(html, node).unwrap();
+        }
+
+        assert_eq!(vec![a], dom.get_elements_by_tag_name("div"));
+        // HTML 以外の要素は大文字と小文字を区別する
+        assert_eq!(vec![a, c], dom.get_elements_by_tag_name("DIV"));
+        assert_eq!(vec![html, a, b, c], dom.get_elements_by_tag_name("*"));
+
+        assert_eq!(vec![a, b, c], dom.get_elements_by_class_name(" y "));
+        assert_eq!(vec![a], dom.get_elements_by_class_name("y x"));
+        assert!(dom.get_elements_by_class_name("").is_empty());
+        // 互換モードでは大文字と小文字を区別しない
+        if let Some(d) = dom.node_mut(document).get_document_mut() {
+            d.set_mode(QuirksMode::Quirks);
+        }
+        assert_eq!(vec![a, c], dom.get_elements_by_class_name("x"));
+
+        // name 属性は HTML の要素だけが対象になる
+        assert_eq!(vec![b], dom.get_elements_by_name("n"));
+    }
+
+    #[test]
+    fn test_text_content() {
+        let mut dom = Dom::new();
+        let div = element(&mut dom, "div");
+        let a = text(&mut dom, "a");
+        let b = element(&mut dom, "b");
+        let c = text(&mut dom, "c");
+        let comment = dom.create_node(NodeKind::Comment("d".to_string()));
+        dom.append_child(div, a).unwrap();
+        dom.append_child(div, b).unwrap();
+        dom.append_child(b, c).unwrap();
+        dom.append_child(div, comment).unwrap();
+
+        // コメントの内容は含めない
+        assert_eq!(Some("ac".to_string()), dom.text_content(div));
+        assert_eq!(Some("d".to_string()), dom.text_content(comment));
+        assert_eq!(None, dom.text_content(dom.document()));
+
+        dom.set_text_content(div, "x");
+        assert_eq!(vec!["x"], names(&dom, div));
+        assert_eq!(None, dom.node(b).parent());
+        dom.set_text_content(div, "");
+        assert_eq!(None, dom.node(div).first_child());
+    }
+
+    #[test]
+    fn test_normalize_and_split_text() {
+        let mut dom = Dom::new();
+        let div = element(&mut dom, "div");
+        let a = text(&mut dom, "あい");
+        dom.append_child(div, a).unwrap();
+
+        // 文字の数で数える
+        let b = dom.split_text(a, 1).unwrap();
+        assert_eq!(vec!["あ", "い"], names(&dom, div));
+        assert_eq!(Some(b), dom.node(a).next_sibling());
+        assert_eq!(Err(DomException::IndexSizeError), dom.split_text(b, 2));
+        assert_eq!(
+            Err(DomException::InvalidNodeTypeError),
+            dom.split_text(div, 0)
+        );
+        let empty = dom.split_text(b, 1).unwrap();
+        assert_eq!(vec!["あ", "い", ""], names(&dom, div));
+
+        let span = element(&mut dom, "span");
+        let c = text(&mut dom, "う");
+        let d = text(&mut dom, "");
+        dom.append_child(div, span).unwrap();
+        dom.append_child(div, c).unwrap();
+        dom.append_child(span, d).unwrap();
+
+        dom.normalize(div);
+        assert_eq!(vec!["あい", "span", "う"], names(&dom, div));
+        assert_eq!(None, dom.node(empty).parent());
+        assert_eq!(None, dom.node(span).first_child());
     }
 }
@@ -0,0 +1,99 @@
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+
+/// class 属性のような、空白で区切られたトークンの集合。同じトークンは1回だけ、最初に現れた順に
+/// 保持する
+/// https://dom.spec.whatwg.org/#interface-domtokenlist
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DomTokenList {
+    tokens: Vec<String>,
+}
+
+impl DomTokenList {
+    /// 属性値を ASCII 空白文字で区切ってトークンの集合にする
+    /// https://dom.spec.whatwg.org/#concept-ordered-set-parser
+    pub fn new(value: &str) -> Self {
+        let mut list = Self::default();
+        for token in value.split(is_ascii_whitespace).filter(|t| !t.is_empty()) {
+            list.add(token);
+        }
+        list
+    }
+
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.tokens.iter().map(|t| t.as_str())
+    }
+
+    pub fn contains(&self, token: &str) -> bool {
+        self.tokens.iter().any(|t| t == token)
+    }
+
+    /// トークンを追加する。すでにある場合は何もしない
+    pub fn add(&mut self, token: &str) {
+        if !self.contains(token) {
+            self.tokens.push(token.to_string());
+        }
+    }
+
+    /// トークンを取り除く。なかった場合は何もしない
+    pub fn remove(&mut self, token: &str) {
+        self.tokens.retain(|t| t != token);
+    }
+
+    /// トークンがあれば取り除き、なければ追加する。追加した場合は true を返す
+    pub fn toggle(&mut self, token: &str) -> bool {
+        if self.contains(token) {
+            self.remove(token);
+            false
+        } else {
+            self.add(token);
+            true
+        }
+    }
+
+    /// トークンを空白1つで区切った文字列。属性値に書き戻すときに使う
+    /// https://dom.spec.whatwg.org/#concept-ordered-set-serializer
+    pub fn value(&self) -> String {
+        self.tokens.join(" ")
+    }
+}
+
+/// https://infra.spec.whatwg.org/#ascii-whitespace
+fn is_ascii_whitespace(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\u{0C}' | '\r' | ' ')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_token_list() {
+        let mut list = DomTokenList::new("  a\tb\n a  c ");
+        assert_eq!(vec!["a", "b", "c"], list.iter().collect::<Vec<_>>());
+        assert!(list.contains("b"));
+        assert!(!list.contains("a b"));
+
+        list.add("d");
+        list.add("a");
+        list.remove("b");
+        assert_eq!("a c d", list.value());
+
+        assert!(!list.toggle("c"));
+        assert!(list.toggle("e"));
+        assert_eq!("a d e", list.value());
+        assert_eq!(3, list.len());
+        assert!(DomTokenList::new(" \n").is_empty());
+    }
+}
@@ -0,0 +1,773 @@
+//! DOM ツリーをたどるイテレータと、TreeWalker・NodeIterator
+//! https://dom.spec.whatwg.org/#traversal
+
+use alloc::boxed::Box;
+use core::ops::BitOr;
+
+use crate::renderer::dom::node::{Dom, NodeId, NodeKind};
+
+/// `parent` の子を順に返すイテレータ
+pub struct Children<'a> {
+    dom: &'a Dom,
+    next: Option<NodeId>,
+}
+
+impl<'a> Children<'a> {
+    pub(crate) fn new(dom: &'a Dom, parent: NodeId) -> Self {
+        Self {
+            dom,
+            next: dom.node(parent).first_child(),
+        }
+    }
+}
+
+impl Iterator for Children<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let node = self.next?;
+        self.next = self.dom.node(node).next_sibling();
+        Some(node)
+    }
+}
+
+/// `root` の子孫を、`root` 自身を含めずに木順 (tree order) で返すイテレータ
+/// https://dom.spec.whatwg.org/#concept-tree-order
+pub struct Descendants<'a> {
+    dom: &'a Dom,
+    root: NodeId,
+    next: Option<NodeId>,
+}
+
+impl<'a> Descendants<'a> {
+    pub(crate) fn new(dom: &'a Dom, root: NodeId) -> Self {
+        Self {
+            dom,
+            root,
+            next: dom.node(root).first_child(),
+        }
+    }
+}
+
+impl Iterator for Descendants<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let node = self.next?;
+        self.next = following(self.dom, node, self.root);
+        Some(node)
+    }
+}
+
+/// 親、親の親、と根まで順に返すイテレータ
+pub struct Ancestors<'a> {
+    dom: &'a Dom,
+    next: Option<NodeId>,
+}
+
+impl<'a> Ancestors<'a> {
+    pub(crate) fn new(dom: &'a Dom, node: NodeId) -> Self {
+        Self {
+            dom,
+            next: dom.node(node).parent(),
+        }
+    }
+}
+
+impl Iterator for Ancestors<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let node = self.next?;
+        self.next = self.dom.node(node).parent();
+        Some(node)
+    }
+}
+
+/// 後ろの兄弟を近い順に返すイテレータ
+pub struct FollowingSiblings<'a> {
+    dom: &'a Dom,
+    next: Option<NodeId>,
+}
+
+impl<'a> FollowingSiblings<'a> {
+    pub(crate) fn new(dom: &'a Dom, node: NodeId) -> Self {
+        Self {
+            dom,
+            next: dom.node(node).next_sibling(),
+        }
+    }
+}
+
+impl Iterator for FollowingSiblings<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let node = self.next?;
+        self.next = self.dom.node(node).next_sibling();
+        Some(node)
+    }
+}
+
+/// 木順で前にあるノード (祖先を含む) を、近い順、つまり木順の逆順に返すイテレータ
+/// https://dom.spec.whatwg.org/#concept-tree-preceding
+pub struct Preceding<'a> {
+    dom: &'a Dom,
+    next: Option<NodeId>,
+}
+
+impl<'a> Preceding<'a> {
+    pub(crate) fn new(dom: &'a Dom, node: NodeId) -> Self {
+        Self {
+            dom,
+            next: preceding(dom, node, None),
+        }
+    }
+}
+
+impl Iterator for Preceding<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let node = self.next?;
+        self.next = preceding(self.dom, node, None);
+        Some(node)
+    }
+}
+
+/// `root` の子孫の中で、木順で `node` の次にあるノード
+pub(crate) fn following(dom: &Dom, node: NodeId, root: NodeId) -> Option<NodeId> {
+    if let Some(child) = dom.node(node).first_child() {
+        return Some(child);
+    }
+    let mut node = node;
+    while node != root {
+        if let Some(sibling) = dom.node(node).next_sibling() {
+            return Some(sibling);
+        }
+        node = dom.node(node).parent()?;
+    }
+    None
+}
+
+/// 木順で `node` の直前にあるノード。`root` を指定した場合は、`root` より前には戻らない
+fn preceding(dom: &Dom, node: NodeId, root: Option<NodeId>) -> Option<NodeId> {
+    if Some(node) == root {
+        return None;
+    }
+    let Some(mut node) = dom.node(node).previous_sibling() else {
+        return dom.node(node).parent();
+    };
+    while let Some(child) = dom.node(node).last_child() {
+        node = child;
+    }
+    Some(node)
+}
+
+/// TreeWalker・NodeIterator が対象にするノードの種類。`SHOW_ELEMENT | SHOW_TEXT` のように組み合わせる
+/// https://dom.spec.whatwg.org/#interface-nodefilter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WhatToShow(u32);
+
+impl WhatToShow {
+    pub const SHOW_ALL: Self = Self(0xFFFF_FFFF);
+    pub const SHOW_ELEMENT: Self = Self(0x1);
+    pub const SHOW_TEXT: Self = Self(0x4);
+    pub const SHOW_COMMENT: Self = Self(0x80);
+    pub const SHOW_DOCUMENT: Self = Self(0x100);
+    pub const SHOW_DOCUMENT_TYPE: Self = Self(0x200);
+    pub const SHOW_DOCUMENT_FRAGMENT: Self = Self(0x400);
+
+    /// `kind` のノードを対象にするかどうか
+    fn shows(self, kind: &NodeKind) -> bool {
+        let bit = match kind {
+            NodeKind::Element(_) => Self::SHOW_ELEMENT,
+            NodeKind::Text(_) => Self::SHOW_TEXT,
+            NodeKind::Comment(_) => Self::SHOW_COMMENT,
+            NodeKind::Document(_) => Self::SHOW_DOCUMENT,
+            NodeKind::DocumentType(_) => Self::SHOW_DOCUMENT_TYPE,
+            NodeKind::DocumentFragment => Self::SHOW_DOCUMENT_FRAGMENT,
+        };
+        self.0 & bit.0 != 0
+    }
+}
+
+impl BitOr for WhatToShow {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// フィルタの結果。`Reject` は TreeWalker ではそのノードの子孫もまとめて飛ばし、NodeIterator では
+/// `Skip` と同じように扱う
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterResult {
+    Accept,
+    Reject,
+    Skip,
+}
+
+/// ノードを受け入れるかどうかを決めるコールバック
+pub type NodeFilter<'a> = Box<dyn Fn(&Dom, NodeId) -> FilterResult + 'a>;
+
+/// `what_to_show` と `filter` でノードを絞り込む
+/// https://dom.spec.whatwg.org/#concept-node-filter
+fn filter_node(
+    dom: &Dom,
+    node: NodeId,
+    what_to_show: WhatToShow,
+    filter: &Option<NodeFilter>,
+) -> FilterResult {
+    if !what_to_show.shows(&dom.node(node).kind) {
+        return FilterResult::Skip;
+    }
+    match filter {
+        Some(filter) => filter(dom, node),
+        None => FilterResult::Accept,
+    }
+}
+
+/// `root` の子孫の中を、現在のノードから親・子・兄弟の方向に移動しながらたどる
+/// https://dom.spec.whatwg.org/#interface-treewalker
+pub struct TreeWalker<'a> {
+    dom: &'a Dom,
+    root: NodeId,
+    what_to_show: WhatToShow,
+    filter: Option<NodeFilter<'a>>,
+    current: NodeId,
+}
+
+impl<'a> TreeWalker<'a> {
+    pub fn new(
+        dom: &'a Dom,
+        root: NodeId,
+        what_to_show: WhatToShow,
+        filter: Option<NodeFilter<'a>>,
+    ) -> Self {
+        Self {
+            dom,
+            root,
+            what_to_show,
+            filter,
+            current: root,
+        }
+    }
+
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    pub fn current_node(&self) -> NodeId {
+        self.current
+    }
+
+    pub fn set_current_node(&mut self, node: NodeId) {
+        self.current = node;
+    }
+
+    fn filter(&self, node: NodeId) -> FilterResult {
+        filter_node(self.dom, node, self.what_to_show, &self.filter)
+    }
+
+    /// https://dom.spec.whatwg.org/#dom-treewalker-parentnode
+    pub fn parent_node(&mut self) -> Option<NodeId> {
+        let mut node = Some(self.current);
+        while let Some(n) = node {
+            if n == self.root {
+                break;
+            }
+            node = self.dom.node(n).parent();
+            if let Some(parent) = node {
+                if self.filter(parent) == FilterResult::Accept {
+                    self.current = parent;
+                    return Some(parent);
+                }
+            }
+        }
+        None
+    }
+
+    /// https://dom.spec.whatwg.org/#dom-treewalker-firstchild
+    pub fn first_child(&mut self) -> Option<NodeId> {
+        self.traverse_children(true)
+    }
+
+    /// https://dom.spec.whatwg.org/#dom-treewalker-lastchild
+    pub fn last_child(&mut self) -> Option<NodeId> {
+        self.traverse_children(false)
+    }
+
+    /// https://dom.spec.whatwg.org/#dom-treewalker-previoussibling
+    pub fn previous_sibling(&mut self) -> Option<NodeId> {
+        self.traverse_siblings(false)
+    }
+
+    /// https://dom.spec.whatwg.org/#dom-treewalker-nextsibling
+    pub fn next_sibling(&mut self) -> Option<NodeId> {
+        self.traverse_siblings(true)
+    }
+
+    /// 木順で前にある、受け入れられたノードに移動する
+    /// https://dom.spec.whatwg.org/#dom-treewalker-previousnode
+    pub fn previous_node(&mut self) -> Option<NodeId> {
+        let dom = self.dom;
+        let mut node = self.current;
+        while node != self.root {
+            let mut sibling = dom.node(node).previous_sibling();
+            while let Some(s) = sibling {
+                node = s;
+                let mut result = self.filter(node);
+                while result != FilterResult::Reject {
+                    let Some(child) = dom.node(node).last_child() else {
+                        break;
+                    };
+                    node = child;
+                    result = self.filter(node);
+                }
+                if result == FilterResult::Accept {
+                    self.current = node;
+                    return Some(node);
+                }
+                sibling = dom.node(node).previous_sibling();
+            }
+
+            node = dom.node(node).parent()?;
+            if self.filter(node) == FilterResult::Accept {
+                self.current = node;
+                return Some(node);
+            }
+        }
+        None
+    }
+
+    /// 木順で次にある、受け入れられたノードに移動する
+    /// https://dom.spec.whatwg.org/#dom-treewalker-nextnode
+    pub fn next_node(&mut self) -> Option<NodeId> {
+        let dom = self.dom;
+        let mut node = self.current;
+        let mut result = FilterResult::Accept;
+        loop {
+            while result != FilterResult::Reject {
+                let Some(child) = dom.node(node).first_child() else {
+                    break;
+                };
+                node = child;
+                result = self.filter(node);
+                if result == FilterResult::Accept {
+                    self.current = node;
+                    return Some(node);
+                }
+            }
+
+            // 子孫を見終えたので、自分か祖先の次の兄弟に進む
+            let mut temporary = node;
+            loop {
+                if temporary == self.root {
+                    return None;
+                }
+                if let Some(sibling) = dom.node(temporary).next_sibling() {
+                    node = sibling;
+                    break;
+                }
+                temporary = dom.node(temporary).parent()?;
+            }
+
+            result = self.filter(node);
+            if result == FilterResult::Accept {
+                self.current = node;
+                return Some(node);
+            }
+        }
+    }
+
+    /// `first` が真なら最初の子、偽なら最後の子の方向に探す
+    /// https://dom.spec.whatwg.org/#concept-traverse-children
+    fn traverse_children(&mut self, first: bool) -> Option<NodeId> {
+        let dom = self.dom;
+        let first_child = |n: NodeId| {
+            if first {
+                dom.node(n).first_child()
+            } else {
+                dom.node(n).last_child()
+            }
+        };
+        let next_sibling = |n: NodeId| {
+            if first {
+                dom.node(n).next_sibling()
+            } else {
+                dom.node(n).previous_sibling()
+            }
+        };
+
+        let mut node = first_child(self.current);
+        while let Some(mut n) = node {
+            match self.filter(n) {
+                FilterResult::Accept => {
+                    self.current = n;
+                    return Some(n);
+                }
+                // 飛ばしたノードの子は候補になる
+                FilterResult::Skip => {
+                    if let Some(child) = first_child(n) {
+                        node = Some(child);
+                        continue;
+                    }
+                }
+                FilterResult::Reject => {}
+            }
+
+            node = loop {
+                if let Some(sibling) = next_sibling(n) {
+                    break Some(sibling);
+                }
+                let parent = dom.node(n).parent()?;
+                if parent == self.root || parent == self.current {
+                    return None;
+                }
+                n = parent;
+            };
+        }
+        None
+    }
+
+    /// `next` が真なら次の兄弟、偽なら前の兄弟の方向に探す
+    /// https://dom.spec.whatwg.org/#concept-traverse-siblings
+    fn traverse_siblings(&mut self, next: bool) -> Option<NodeId> {
+        let dom = self.dom;
+        let sibling_of = |n: NodeId| {
+            if next {
+                dom.node(n).next_sibling()
+            } else {
+                dom.node(n).previous_sibling()
+            }
+        };
+        let child_of = |n: NodeId| {
+            if next {
+                dom.node(n).first_child()
+            } else {
+                dom.node(n).last_child()
+            }
+        };
+
+        let mut node = self.current;
+        if node == self.root {
+            return None;
+        }
+        loop {
+            let mut sibling = sibling_of(node);
+            while let Some(s) = sibling {
+                node = s;
+                let result = self.filter(node);
+                if result == FilterResult::Accept {
+                    self.current = node;
+                    return Some(node);
+                }
+                sibling = child_of(node);
+                if result == FilterResult::Reject || sibling.is_none() {
+                    sibling = sibling_of(node);
+                }
+            }
+
+            node = dom.node(node).parent()?;
+            if node == self.root || self.filter(node) == FilterResult::Accept {
+                return None;
+            }
+        }
+    }
+}
+
+/// `root` とその子孫を、木順に前後へたどる
+/// https://dom.spec.whatwg.org/#interface-nodeiterator
+pub struct NodeIterator<'a> {
+    dom: &'a Dom,
+    root: NodeId,
+    what_to_show: WhatToShow,
+    filter: Option<NodeFilter<'a>>,
+    reference: NodeId,
+    pointer_before_reference: bool,
+}
+
+impl<'a> NodeIterator<'a> {
+    pub fn new(
+        dom: &'a Dom,
+        root: NodeId,
+        what_to_show: WhatToShow,
+        filter: Option<NodeFilter<'a>>,
+    ) -> Self {
+        Self {
+            dom,
+            root,
+            what_to_show,
+            filter,
+            reference: root,
+            pointer_before_reference: true,
+        }
+    }
+
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    pub fn reference_node(&self) -> NodeId {
+        self.reference
+    }
+
+    /// https://dom.spec.whatwg.org/#dom-nodeiterator-nextnode
+    pub fn next_node(&mut self) -> Option<NodeId> {
+        self.traverse(true)
+    }
+
+    /// https://dom.spec.whatwg.org/#dom-nodeiterator-previousnode
+    pub fn previous_node(&mut self) -> Option<NodeId> {
+        self.traverse(false)
+    }
+
+    /// https://dom.spec.whatwg.org/#concept-nodeiterator-traverse
+    fn traverse(&mut self, next: bool) -> Option<NodeId> {
+        let mut node = self.reference;
+        let mut before_node = self.pointer_before_reference;
+        loop {
+            if next {
+                if !before_node {
+                    node = following(self.dom, node, self.root)?;
+                }
+                before_node = false;
+            } else {
+                if before_node {
+                    node = preceding(self.dom, node, Some(self.root))?;
+                }
+                before_node = true;
+            }
+
+            if filter_node(self.dom, node, self.what_to_show, &self.filter) == FilterResult::Accept
+            {
+                break;
+            }
+        }
+        self.reference = node;
+        self.pointer_before_reference = before_node;
+        Some(node)
+    }
+}
+
+impl Iterator for NodeIterator<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        self.next_node()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::{
+        dom::node::Window,
+        html::{parser::HtmlParser, token::HtmlTokenizer},
+    };
+    use alloc::{
+        string::{String, ToString},
+        vec,
+        vec::Vec,
+    };
+
+    fn parse(html: &str) -> Window {
+        HtmlParser::new(HtmlTokenizer::new(html.to_string())).construct_tree()
+    }
+
+    /// 要素はタグ名、テキストは内容で表す
+    fn name(dom: &Dom, node: NodeId) -> String {
+        match dom.node(node).kind {
+            NodeKind::Element(ref e) => e.local_name().to_string(),
+            NodeKind::Text(ref s) => s.clone(),
+            NodeKind::Comment(ref s) => "!".to_string() + s,
+            NodeKind::Document(_) => "#document".to_string(),
+            _ => "?".to_string(),
+        }
+    }
+
+    fn names(dom: &Dom, nodes: impl Iterator<Item = NodeId>) -> Vec<String> {
+        nodes.map(|n| name(dom, n)).collect()
+    }
+
+    /// <html><head></head><body><div><p>a</p><!--c--><p>b</p></div><span>d</span></body></html>
+    const HTML: &str = "<div><p>a</p><!--c--><p>b</p></div><span>d</span>";
+
+    fn find(dom: &Dom, tag: &str) -> NodeId {
+        dom.descendants(dom.document())
+            .find(|n| name(dom, *n) == tag)
+            .expect("failed to find an element")
+    }
+
+    #[test]
+    fn test_iterators() {
+        let window = parse(HTML);
+        let dom = window.dom();
+        let body = find(dom, "body");
+        let div = find(dom, "div");
+
+        assert_eq!(vec!["div", "span"], names(dom, dom.children(body)));
+        assert_eq!(
+            vec!["div", "p", "a", "!c", "p", "b", "span", "d"],
+            names(dom, dom.descendants(body))
+        );
+
+        let b = dom.descendants(div).find(|n| name(dom, *n) == "b").unwrap();
+        assert_eq!(
+            vec!["p", "div", "body", "html", "#document"],
+            names(dom, dom.ancestors(b))
+        );
+        assert_eq!(
+            vec!["!c", "p"],
+            names(
+                dom,
+                dom.following_siblings(dom.node(div).first_child().unwrap())
+            )
+        );
+        assert_eq!(
+            vec![
+                "p",
+                "!c",
+                "a",
+                "p",
+                "div",
+                "body",
+                "head",
+                "html",
+                "#document"
+            ],
+            names(dom, dom.preceding(b))
+        );
+    }
+
+    #[test]
+    fn test_tree_walker() {
+        let window = parse(HTML);
+        let dom = window.dom();
+        let body = find(dom, "body");
+
+        // 要素だけをたどる
+        let mut walker = TreeWalker::new(dom, body, WhatToShow::SHOW_ELEMENT, None);
+        let mut visited = Vec::new();
+        while let Some(node) = walker.next_node() {
+            visited.push(name(dom, node));
+        }
+        assert_eq!(vec!["div", "p", "p", "span"], visited);
+        assert_eq!(
+            Some("p"),
+            walker.previous_node().map(|n| name(dom, n)).as_deref()
+        );
+        assert_eq!(
+            Some("div"),
+            walker.parent_node().map(|n| name(dom, n)).as_deref()
+        );
+        assert_eq!(
+            Some("span"),
+            walker.next_sibling().map(|n| name(dom, n)).as_deref()
+        );
+        assert_eq!(None, walker.first_child());
+        // 根より上には移動しない
+        assert_eq!(
+            Some("body"),
+            walker.parent_node().map(|n| name(dom, n)).as_deref()
+        );
+        assert_eq!(None, walker.parent_node());
+        assert_eq!(None, walker.next_sibling());
+
+        // <div> を飛ばすと、その子の <p> が <body> の子のように扱われる
+        let skip_div: NodeFilter = Box::new(|dom, n| {
+            if name(dom, n) == "div" {
+                FilterResult::Skip
+            } else {
+                FilterResult::Accept
+            }
+        });
+        let mut walker = TreeWalker::new(dom, body, WhatToShow::SHOW_ELEMENT, Some(skip_div));
+        assert_eq!(
+            Some("p"),
+            walker.first_child().map(|n| name(dom, n)).as_deref()
+        );
+        assert_eq!(
+            Some("p"),
+            walker.next_sibling().map(|n| name(dom, n)).as_deref()
+        );
+        assert_eq!(
+            Some("span"),
+            walker.next_sibling().map(|n| name(dom, n)).as_deref()
+        );
+        assert_eq!(
+            Some("body"),
+            walker.parent_node().map(|n| name(dom, n)).as_deref()
+        );
+
+        // <div> を拒否すると、その子孫も対象にならない
+        let reject_div: NodeFilter = Box::new(|dom, n| {
+            if name(dom, n) == "div" {
+                FilterResult::Reject
+            } else {
+                FilterResult::Accept
+            }
+        });
+        let mut walker = TreeWalker::new(
+            dom,
+            body,
+            WhatToShow::SHOW_ELEMENT | WhatToShow::SHOW_TEXT,
+            Some(reject_div),
+        );
+        assert_eq!(
+            Some("span"),
+            walker.next_node().map(|n| name(dom, n)).as_deref()
+        );
+        assert_eq!(
+            Some("d"),
+            walker.last_child().map(|n| name(dom, n)).as_deref()
+        );
+        assert_eq!(
+            Some("span"),
+            walker.previous_node().map(|n| name(dom, n)).as_deref()
+        );
+        assert_eq!(
+            Some("body"),
+            walker.previous_node().map(|n| name(dom, n)).as_deref()
+        );
+        assert_eq!(None, walker.previous_node());
+    }
+
+    #[test]
+    fn test_node_iterator() {
+        let window = parse(HTML);
+        let dom = window.dom();
+        let div = find(dom, "div");
+
+        let iterator = NodeIterator::new(dom, div, WhatToShow::SHOW_ALL, None);
+        assert_eq!(vec!["div", "p", "a", "!c", "p", "b"], names(dom, iterator));
+
+        let mut iterator = NodeIterator::new(
+            dom,
+            div,
+            WhatToShow::SHOW_TEXT | WhatToShow::SHOW_COMMENT,
+            None,
+        );
+        assert_eq!(
+            Some("a"),
+            iterator.next_node().map(|n| name(dom, n)).as_deref()
+        );
+        assert_eq!(
+            Some("!c"),
+            iterator.next_node().map(|n| name(dom, n)).as_deref()
+        );
+        // 向きを変えると、直前に返したノードをもう一度返す
+        assert_eq!(
+            Some("!c"),
+            iterator.previous_node().map(|n| name(dom, n)).as_deref()
+        );
+        assert_eq!(
+            Some("a"),
+            iterator.previous_node().map(|n| name(dom, n)).as_deref()
+        );
+        assert_eq!(None, iterator.previous_node());
+        assert_eq!(div, iterator.root());
+    }
+}
@@ -1,3 +1,7 @@
 pub mod attribute;
+pub mod character_reference;
+pub mod encoding_sniffing;
+pub mod parse_error;
 pub mod parser;
+pub mod serializer;
 pub mod token;
@@ -1,9 +1,15 @@
-use alloc::string::String;
+use alloc::string::{String, ToString};
+
+use crate::renderer::dom::node::Namespace;
 
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub struct Attribute {
     name: String,
     value: String,
+    /// xlink:href などの名前空間付きの属性の名前空間。HTML の属性では `None`
+    namespace: Option<Namespace>,
+    /// xlink:href の "xlink" のような接頭辞
+    prefix: Option<String>,
 }
 
 impl Attribute {
@@ -11,6 +17,18 @@ impl Attribute {
         Self {
             name: String::new(),
             value: String::new(),
+            namespace: None,
+            prefix: None,
+        }
+    }
+
+    /// 名前と値を指定して属性を作る
+    pub fn new_with_value(name: &str, value: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            value: value.to_string(),
+            namespace: None,
+            prefix: None,
         }
     }
 
@@ -26,7 +44,46 @@ impl Attribute {
         self.name.clone()
     }
 
+    /// 名前を複製せずに参照する。属性を何度も比べる処理ではこちらを使う
+    pub fn name_str(&self) -> &str {
+        &self.name
+    }
+
     pub fn value(&self) -> String {
         self.value.clone()
     }
+
+    pub fn set_value(&mut self, value: &str) {
+        self.value = value.to_string();
+    }
+
+    /// 接頭辞を含めた属性の名前 (例: xlink:href)
+    /// https://dom.spec.whatwg.org/#concept-attribute-qualified-name
+    pub fn qualified_name(&self) -> String {
+        match self.prefix {
+            Some(ref prefix) => prefix.clone() + ":" + &self.name,
+            None => self.name.clone(),
+        }
+    }
+
+    pub fn namespace(&self) -> Option<Namespace> {
+        self.namespace
+    }
+
+    pub fn prefix(&self) -> Option<String> {
+        self.prefix.clone()
+    }
+
+    /// 属性名を置き換える。SVG や MathML の属性名の大文字小文字を調整するために使う
+    pub fn set_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+
+    /// 属性を名前空間付きの属性にする。`name` はローカル名になる
+    /// https://html.spec.whatwg.org/multipage/parsing.html#adjust-foreign-attributes
+    pub fn set_namespace(&mut self, prefix: Option<&str>, name: &str, namespace: Namespace) {
+        self.prefix = prefix.map(|p| p.to_string());
+        self.name = name.to_string();
+        self.namespace = Some(namespace);
+    }
 }
@@ -0,0 +1,380 @@
+//! HTML のバイト列の文字コードを決め、文字列に変換する
+//! https://html.spec.whatwg.org/multipage/parsing.html#encoding-sniffing-algorithm
+
+use alloc::{string::String, vec::Vec};
+
+use crate::encoding::{Decoder, Encoding};
+
+/// meta 要素を探すために先読みするバイト数
+const PRESCAN_LENGTH: usize = 1024;
+
+/// 文字コードの指定がどこにもないときに使う文字コード
+const DEFAULT_ENCODING: Encoding = Encoding::Utf8;
+
+/// バイト列の先頭の BOM から文字コードを求める。BOM の長さも返す
+pub fn sniff_bom(bytes: &[u8]) -> Option<(Encoding, usize)> {
+    if bytes.starts_with(&[0xEF, 0xBB, 0xBF]) {
+        Some((Encoding::Utf8, 3))
+    } else if bytes.starts_with(&[0xFE, 0xFF]) {
+        Some((Encoding::Utf16Be, 2))
+    } else if bytes.starts_with(&[0xFF, 0xFE]) {
+        Some((Encoding::Utf16Le, 2))
+    } else {
+        None
+    }
+}
+
+/// Content-Type ヘッダの値 (例えば `text/html; charset=Shift_JIS`) の charset パラメータから文字コードを
+/// 求める
+pub fn charset_from_content_type(content_type: &str) -> Option<Encoding> {
+    let (_, parameters) = content_type.split_once(';')?;
+    extract_charset(parameters)
+}
+
+/// 文字列中の `charset=...` から文字コードを求める。meta 要素の content 属性にも使う
+/// https://html.spec.whatwg.org/multipage/urls-and-fetching.html#algorithm-for-extracting-a-character-encoding-from-a-meta-element
+fn extract_charset(s: &str) -> Option<Encoding> {
+    let bytes = s.as_bytes();
+    let mut pos = 0;
+    loop {
+        let found = s[pos..]
+            .as_bytes()
+            .windows(7)
+            .position(|w| w.eq_ignore_ascii_case(b"charset"))?;
+        pos += found + 7;
+
+        while bytes.get(pos).is_some_and(|b| is_whitespace(*b)) {
+            pos += 1;
+        }
+        if bytes.get(pos) != Some(&b'=') {
+            continue;
+        }
+        pos += 1;
+        while bytes.get(pos).is_some_and(|b| is_whitespace(*b)) {
+            pos += 1;
+        }
+
+        let rest = &s[pos..];
+        let label = match rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let (label, _) = rest[1..].split_once(quote)?;
+                label
+            }
+            Some(_) => rest
+                .split(|c: char| c == ';' || c.is_ascii_whitespace())
+                .next()
+                .unwrap_or_default(),
+            None => return None,
+        };
+        return Encoding::for_label(label);
+    }
+}
+
+/// 先頭の 1024 バイトから meta 要素による文字コードの指定を探す
+/// https://html.spec.whatwg.org/multipage/parsing.html#prescan-a-byte-stream-to-determine-its-encoding
+pub fn prescan(bytes: &[u8]) -> Option<Encoding> {
+    let bytes = &bytes[..bytes.len().min(PRESCAN_LENGTH)];
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        let rest = &bytes[pos..];
+        if rest.starts_with(b"<!--") {
+            // コメントは読み飛ばす。"<!-->" の "-->" は開始の "--" と重なってよい
+            pos += 2 + find(&rest[2..], b"-->")? + 3;
+        } else if rest.len() >= 6
+            && rest[..5].eq_ignore_ascii_case(b"<meta")
+            && (is_whitespace(rest[5]) || rest[5] == b'/')
+        {
+            pos += 6;
+            if let Some(encoding) = prescan_meta(bytes, &mut pos)? {
+                return Some(encoding);
+            }
+        } else if rest.starts_with(b"<")
+            && (rest.get(1).is_some_and(u8::is_ascii_alphabetic)
+                || (rest.get(1) == Some(&b'/') && rest.get(2).is_some_and(u8::is_ascii_alphabetic)))
+        {
+            // meta 以外のタグは属性ごと読み飛ばす
+            while pos < bytes.len() && !is_whitespace(bytes[pos]) && bytes[pos] != b'>' {
+                pos += 1;
+            }
+            while get_attribute(bytes, &mut pos)?.is_some() {}
+        } else if rest.starts_with(b"<!") || rest.starts_with(b"</") || rest.starts_with(b"<?") {
+            pos += find(rest, b">")? + 1;
+        } else {
+            pos += 1;
+        }
+    }
+
+    None
+}
+
+/// meta 要素の属性を読み、文字コードが指定されていれば返す。入力が途中で終わった場合は None を返す
+fn prescan_meta(bytes: &[u8], pos: &mut usize) -> Option<Option<Encoding>> {
+    let mut names: Vec<String> = Vec::new();
+    let mut got_pragma = false;
+    let mut need_pragma = None;
+    let mut charset = None;
+
+    while let Some((name, value)) = get_attribute(bytes, pos)? {
+        if names.contains(&name) {
+            continue;
+        }
+        match name.as_str() {
+            "http-equiv" => got_pragma |= value == "content-type",
+            "content" if charset.is_none() => {
+                if let Some(encoding) = extract_charset(&value) {
+                    charset = Some(encoding);
+                    need_pragma = Some(true);
+                }
+            }
+            "charset" => {
+                charset = Encoding::for_label(&value);
+                need_pragma = Some(false);
+            }
+            _ => {}
+        }
+        names.push(name);
+    }
+
+    let encoding = match need_pragma {
+        Some(true) if !got_pragma => None,
+        Some(_) => charset,
+        None => None,
+    };
+    // バイト列から UTF-16 だと判断することはできないので UTF-8 として扱う
+    Some(encoding.map(|encoding| match encoding {
+        Encoding::Utf16Be | Encoding::Utf16Le => Encoding::Utf8,
+        encoding => encoding,
+    }))
+}
+
+/// 属性を 1つ読む。タグの終わりに達した場合は `Some(None)` を、入力が途中で終わった場合は None を返す。
+/// 名前と値は小文字に変換する
+/// https://html.spec.whatwg.org/multipage/parsing.html#concept-get-attributes-when-sniffing
+fn get_attribute(bytes: &[u8], pos: &mut usize) -> Option<Option<(String, String)>> {
+    while is_whitespace(*bytes.get(*pos)?) || bytes[*pos] == b'/' {
+        *pos += 1;
+    }
+    if bytes[*pos] == b'>' {
+        return Some(None);
+    }
+
+    let mut name = String::new();
+    let mut value = String::new();
+    loop {
+        let b = *bytes.get(*pos)?;
+        if b == b'=' && !name.is_empty() {
+            *pos += 1;
+            break;
+        }
+        if is_whitespace(b) {
+            while is_whitespace(*bytes.get(*pos)?) {
+                *pos += 1;
+            }
+            if bytes[*pos] != b'=' {
+                return Some(Some((name, value)));
+            }
+            *pos += 1;
+            break;
+        }
+        if b == b'/' || b == b'>' {
+            return Some(Some((name, value)));
+        }
+        name.push(b.to_ascii_lowercase() as char);
+        *pos += 1;
+    }
+
+    while is_whitespace(*bytes.get(*pos)?) {
+        *pos += 1;
+    }
+    match bytes[*pos] {
+        quote @ (b'"' | b'\'') => {
+            *pos += 1;
+            loop {
+                let b = *bytes.get(*pos)?;
+                *pos += 1;
+                if b == quote {
+                    return Some(Some((name, value)));
+                }
+                value.push(b.to_ascii_lowercase() as char);
+            }
+        }
+        b'>' => Some(Some((name, value))),
+        _ => {
+            while !is_whitespace(*bytes.get(*pos)?) && bytes[*pos] != b'>' {
+                value.push(bytes[*pos].to_ascii_lowercase() as char);
+                *pos += 1;
+            }
+            Some(Some((name, value)))
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn is_whitespace(b: u8) -> bool {
+    matches!(b, b'\t' | b'\n' | b'\x0C' | b'\r' | b' ')
+}
+
+/// 受信した HTML のバイト列を文字コードを判定しながら文字列に変換する。文字コードは BOM、Content-Type
+/// ヘッダ、meta 要素の順に優先し、どれもなければ UTF-8 とする
+#[derive(Debug, Clone)]
+pub struct HtmlByteDecoder {
+    /// Content-Type ヘッダで指定された文字コード
+    content_type_encoding: Option<Encoding>,
+    /// 文字コードが決まるまで受信したバイト列を溜めておく
+    buffer: Vec<u8>,
+    decoder: Option<Decoder>,
+}
+
+impl HtmlByteDecoder {
+    /// `content_type` には Content-Type ヘッダの値を渡す
+    pub fn new(content_type: Option<&str>) -> Self {
+        Self {
+            content_type_encoding: content_type.and_then(charset_from_content_type),
+            buffer: Vec::new(),
+            decoder: None,
+        }
+    }
+
+    /// 使っている文字コード。まだ決まっていない場合は None を返す
+    pub fn encoding(&self) -> Option<Encoding> {
+        self.decoder.as_ref().map(Decoder::encoding)
+    }
+
+    /// 受信したバイト列を渡し、変換できた文字列を返す。文字コードが決まるまでは空文字列を返す
+    pub fn feed(&mut self, bytes: &[u8]) -> String {
+        if let Some(decoder) = self.decoder.as_mut() {
+            return decoder.decode(bytes);
+        }
+
+        self.buffer.extend_from_slice(bytes);
+        self.try_start(false)
+    }
+
+    /// 入力の終わりを処理し、残りの文字列を返す
+    pub fn finish(&mut self) -> String {
+        let mut s = if self.decoder.is_none() {
+            self.try_start(true)
+        } else {
+            String::new()
+        };
+        if let Some(decoder) = self.decoder.as_mut() {
+            s.push_str(&decoder.finish());
+        }
+        s
+    }
+
+    /// 文字コードを決められるだけのバイト列が揃っていればデコーダを作り、溜めていたバイト列を変換する
+    fn try_start(&mut self, last: bool) -> String {
+        // BOM は 3バイトまで読まないと判断できない
+        let may_be_bom = self
+            .buffer
+            .first()
+            .map_or(true, |b| [0xEF, 0xFE, 0xFF].contains(b));
+        if self.buffer.len() < 3 && !last && may_be_bom {
+            return String::new();
+        }
+
+        let (encoding, bom_length) = if let Some(bom) = sniff_bom(&self.buffer) {
+            bom
+        } else if let Some(encoding) = self.content_type_encoding {
+            (encoding, 0)
+        } else if let Some(encoding) = prescan(&self.buffer) {
+            (encoding, 0)
+        } else if self.buffer.len() < PRESCAN_LENGTH && !last {
+            return String::new();
+        } else {
+            (DEFAULT_ENCODING, 0)
+        };
+
+        let mut decoder = encoding.new_decoder();
+        let s = decoder.decode(&self.buffer[bom_length..]);
+        self.decoder = Some(decoder);
+        self.buffer = Vec::new();
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff_bom() {
+        assert_eq!(Some((Encoding::Utf8, 3)), sniff_bom(b"\xEF\xBB\xBF<html>"));
+        assert_eq!(Some((Encoding::Utf16Le, 2)), sniff_bom(b"\xFF\xFE<\x00"));
+        assert_eq!(None, sniff_bom(b"<html>"));
+    }
+
+    #[test]
+    fn test_charset_from_content_type() {
+        assert_eq!(
+            Some(Encoding::ShiftJis),
+            charset_from_content_type("text/html; charset=Shift_JIS")
+        );
+        assert_eq!(
+            Some(Encoding::EucJp),
+            charset_from_content_type("text/html;charset=\"EUC-JP\"")
+        );
+        assert_eq!(None, charset_from_content_type("text/html"));
+    }
+
+    #[test]
+    fn test_prescan() {
+        assert_eq!(
+            Some(Encoding::ShiftJis),
+            prescan(b"<!doctype html><html><head><meta charset=\"shift_jis\">")
+        );
+        assert_eq!(
+            Some(Encoding::EucJp),
+            prescan(b"<meta http-equiv=\"Content-Type\" content=\"text/html; charset=euc-jp\">")
+        );
+        // http-equiv のない content 属性は無視する
+        assert_eq!(
+            None,
+            prescan(b"<meta content=\"text/html; charset=euc-jp\">")
+        );
+        // コメントや他の要素の属性の中にあるものは無視する
+        assert_eq!(
+            None,
+            prescan(b"<!-- <meta charset=euc-jp> --><div title=\"<meta charset=euc-jp>\">")
+        );
+        // UTF-16 の指定は UTF-8 として扱う
+        assert_eq!(Some(Encoding::Utf8), prescan(b"<meta charset=utf-16>"));
+        // 1024 バイトより後ろにあるものは無視する
+        let mut html = [b' '; 1100].to_vec();
+        html.extend_from_slice(b"<meta charset=euc-jp>");
+        assert_eq!(None, prescan(&html));
+    }
+
+    #[test]
+    fn test_html_byte_decoder() {
+        let mut decoder = HtmlByteDecoder::new(None);
+        assert_eq!(None, decoder.encoding());
+        let mut s = decoder.feed(b"<meta charset=shift_jis>\x93");
+        assert_eq!(Some(Encoding::ShiftJis), decoder.encoding());
+        s.push_str(&decoder.finish());
+        assert_eq!(Some(Encoding::ShiftJis), decoder.encoding());
+        assert_eq!("<meta charset=shift_jis>\u{FFFD}", s);
+
+        // BOM は Content-Type より優先する
+        let mut decoder = HtmlByteDecoder::new(Some("text/html; charset=euc-jp"));
+        let mut s = decoder.feed(b"\xEF\xBB");
+        assert_eq!(None, decoder.encoding());
+        s.push_str(&decoder.feed(b"\xBF"));
+        s.push_str(&decoder.feed("日本".as_bytes()));
+        s.push_str(&decoder.finish());
+        assert_eq!(Some(Encoding::Utf8), decoder.encoding());
+        assert_eq!("日本", s);
+
+        let mut decoder = HtmlByteDecoder::new(Some("text/html; charset=euc-jp"));
+        let mut s = decoder.feed(b"<p>\xC6");
+        assert_eq!(Some(Encoding::EucJp), decoder.encoding());
+        s.push_str(&decoder.feed(b"\xFC</p>"));
+        s.push_str(&decoder.finish());
+        assert_eq!("<p>日</p>", s);
+    }
+}
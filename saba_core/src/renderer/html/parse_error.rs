@@ -0,0 +1,228 @@
+use core::fmt;
+
+/// 入力中の位置。行と列はどちらも 1 から数え、列は文字単位で数える
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SourcePosition {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl SourcePosition {
+    pub fn new(line: usize, column: usize) -> Self {
+        Self { line, column }
+    }
+}
+
+impl Default for SourcePosition {
+    fn default() -> Self {
+        Self::new(1, 1)
+    }
+}
+
+impl fmt::Display for SourcePosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// トークナイザやパーサが検出したパースの失敗
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseError {
+    kind: ParseErrorKind,
+    position: SourcePosition,
+}
+
+impl ParseError {
+    pub fn new(kind: ParseErrorKind, position: SourcePosition) -> Self {
+        Self { kind, position }
+    }
+
+    pub fn kind(&self) -> ParseErrorKind {
+        self.kind
+    }
+
+    pub fn position(&self) -> SourcePosition {
+        self.position
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.position, self.kind.as_str())
+    }
+}
+
+/// パースの失敗の種類。トークナイザが検出するものは仕様で定められた名前を持つ
+/// https://html.spec.whatwg.org/multipage/parsing.html#parse-errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    AbruptClosingOfEmptyComment,
+    AbruptDoctypePublicIdentifier,
+    AbruptDoctypeSystemIdentifier,
+    AbsenceOfDigitsInNumericCharacterReference,
+    CdataInHtmlContent,
+    CharacterReferenceOutsideUnicodeRange,
+    ControlCharacterReference,
+    DuplicateAttribute,
+    EndTagWithAttributes,
+    EndTagWithTrailingSolidus,
+    EofBeforeTagName,
+    EofInCdata,
+    EofInComment,
+    EofInDoctype,
+    EofInScriptHtmlCommentLikeText,
+    EofInTag,
+    IncorrectlyClosedComment,
+    IncorrectlyOpenedComment,
+    InvalidCharacterSequenceAfterDoctypeName,
+    InvalidFirstCharacterOfTagName,
+    MissingAttributeValue,
+    MissingDoctypeName,
+    MissingDoctypePublicIdentifier,
+    MissingDoctypeSystemIdentifier,
+    MissingEndTagName,
+    MissingQuoteBeforeDoctypePublicIdentifier,
+    MissingQuoteBeforeDoctypeSystemIdentifier,
+    MissingSemicolonAfterCharacterReference,
+    MissingWhitespaceAfterDoctypePublicKeyword,
+    MissingWhitespaceAfterDoctypeSystemKeyword,
+    MissingWhitespaceBeforeDoctypeName,
+    MissingWhitespaceBetweenAttributes,
+    MissingWhitespaceBetweenDoctypePublicAndSystemIdentifiers,
+    NestedComment,
+    NonVoidHtmlElementStartTagWithTrailingSolidus,
+    NoncharacterCharacterReference,
+    NullCharacterReference,
+    SurrogateCharacterReference,
+    UnexpectedCharacterAfterDoctypeSystemIdentifier,
+    UnexpectedCharacterInAttributeName,
+    UnexpectedCharacterInUnquotedAttributeValue,
+    UnexpectedEqualsSignBeforeAttributeName,
+    UnexpectedNullCharacter,
+    UnexpectedQuestionMarkInsteadOfTagName,
+    UnexpectedSolidusInTag,
+    UnknownNamedCharacterReference,
+
+    // ここから下は木構築の段階で検出するもの。仕様には名前がないので独自の名前をつけている
+    /// 文書の先頭に DOCTYPE がない
+    MissingDoctype,
+    /// html 以外の名前や識別子を持つ DOCTYPE
+    NonConformingDoctype,
+    /// 文書の途中に現れた DOCTYPE
+    UnexpectedDoctype,
+    /// 現在の挿入モードでは許されない開始タグ
+    UnexpectedStartTag,
+    /// 現在の挿入モードでは許されない終了タグ
+    UnexpectedEndTag,
+    /// 対応する要素がスコープ内に開かれていない終了タグ
+    EndTagWithoutMatchingOpenElement,
+    /// 閉じられていない要素を暗黙に閉じる終了タグ
+    EndTagWithUnclosedElements,
+    /// 誤って入れ子になった書式要素 (adoption agency algorithm で組み替えたもの)
+    MisnestedFormattingElement,
+    /// 現在の挿入モードでは許されない文字 (テーブルの外に出す文字など)
+    UnexpectedCharacter,
+    /// 閉じられていない要素が残ったままの入力の終わり
+    EofWithOpenElements,
+}
+
+impl ParseErrorKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ParseErrorKind::AbruptClosingOfEmptyComment => "abrupt-closing-of-empty-comment",
+            ParseErrorKind::AbruptDoctypePublicIdentifier => "abrupt-doctype-public-identifier",
+            ParseErrorKind::AbruptDoctypeSystemIdentifier => "abrupt-doctype-system-identifier",
+            ParseErrorKind::AbsenceOfDigitsInNumericCharacterReference => {
+                "absence-of-digits-in-numeric-character-reference"
+            }
+            ParseErrorKind::CdataInHtmlContent => "cdata-in-html-content",
+            ParseErrorKind::CharacterReferenceOutsideUnicodeRange => {
+                "character-reference-outside-unicode-range"
+            }
+            ParseErrorKind::ControlCharacterReference => "control-character-reference",
+            ParseErrorKind::DuplicateAttribute => "duplicate-attribute",
+            ParseErrorKind::EndTagWithAttributes => "end-tag-with-attributes",
+            ParseErrorKind::EndTagWithTrailingSolidus => "end-tag-with-trailing-solidus",
+            ParseErrorKind::EofBeforeTagName => "eof-before-tag-name",
+            ParseErrorKind::EofInCdata => "eof-in-cdata",
+            ParseErrorKind::EofInComment => "eof-in-comment",
+            ParseErrorKind::EofInDoctype => "eof-in-doctype",
+            ParseErrorKind::EofInScriptHtmlCommentLikeText => {
+                "eof-in-script-html-comment-like-text"
+            }
+            ParseErrorKind::EofInTag => "eof-in-tag",
+            ParseErrorKind::IncorrectlyClosedComment => "incorrectly-closed-comment",
+            ParseErrorKind::IncorrectlyOpenedComment => "incorrectly-opened-comment",
+            ParseErrorKind::InvalidCharacterSequenceAfterDoctypeName => {
+                "invalid-character-sequence-after-doctype-name"
+            }
+            ParseErrorKind::InvalidFirstCharacterOfTagName => "invalid-first-character-of-tag-name",
+            ParseErrorKind::MissingAttributeValue => "missing-attribute-value",
+            ParseErrorKind::MissingDoctypeName => "missing-doctype-name",
+            ParseErrorKind::MissingDoctypePublicIdentifier => "missing-doctype-public-identifier",
+            ParseErrorKind::MissingDoctypeSystemIdentifier => "missing-doctype-system-identifier",
+            ParseErrorKind::MissingEndTagName => "missing-end-tag-name",
+            ParseErrorKind::MissingQuoteBeforeDoctypePublicIdentifier => {
+                "missing-quote-before-doctype-public-identifier"
+            }
+            ParseErrorKind::MissingQuoteBeforeDoctypeSystemIdentifier => {
+                "missing-quote-before-doctype-system-identifier"
+            }
+            ParseErrorKind::MissingSemicolonAfterCharacterReference => {
+                "missing-semicolon-after-character-reference"
+            }
+            ParseErrorKind::MissingWhitespaceAfterDoctypePublicKeyword => {
+                "missing-whitespace-after-doctype-public-keyword"
+            }
+            ParseErrorKind::MissingWhitespaceAfterDoctypeSystemKeyword => {
+                "missing-whitespace-after-doctype-system-keyword"
+            }
+            ParseErrorKind::MissingWhitespaceBeforeDoctypeName => {
+                "missing-whitespace-before-doctype-name"
+            }
+            ParseErrorKind::MissingWhitespaceBetweenAttributes => {
+                "missing-whitespace-between-attributes"
+            }
+            ParseErrorKind::MissingWhitespaceBetweenDoctypePublicAndSystemIdentifiers => {
+                "missing-whitespace-between-doctype-public-and-system-identifiers"
+            }
+            ParseErrorKind::NestedComment => "nested-comment",
+            ParseErrorKind::NonVoidHtmlElementStartTagWithTrailingSolidus => {
+                "non-void-html-element-start-tag-with-trailing-solidus"
+            }
+            ParseErrorKind::NoncharacterCharacterReference => "noncharacter-character-reference",
+            ParseErrorKind::NullCharacterReference => "null-character-reference",
+            ParseErrorKind::SurrogateCharacterReference => "surrogate-character-reference",
+            ParseErrorKind::UnexpectedCharacterAfterDoctypeSystemIdentifier => {
+                "unexpected-character-after-doctype-system-identifier"
+            }
+            ParseErrorKind::UnexpectedCharacterInAttributeName => {
+                "unexpected-character-in-attribute-name"
+            }
+            ParseErrorKind::UnexpectedCharacterInUnquotedAttributeValue => {
+                "unexpected-character-in-unquoted-attribute-value"
+            }
+            ParseErrorKind::UnexpectedEqualsSignBeforeAttributeName => {
+                "unexpected-equals-sign-before-attribute-name"
+            }
+            ParseErrorKind::UnexpectedNullCharacter => "unexpected-null-character",
+            ParseErrorKind::UnexpectedQuestionMarkInsteadOfTagName => {
+                "unexpected-question-mark-instead-of-tag-name"
+            }
+            ParseErrorKind::UnexpectedSolidusInTag => "unexpected-solidus-in-tag",
+            ParseErrorKind::UnknownNamedCharacterReference => "unknown-named-character-reference",
+            ParseErrorKind::MissingDoctype => "missing-doctype",
+            ParseErrorKind::NonConformingDoctype => "non-conforming-doctype",
+            ParseErrorKind::UnexpectedDoctype => "unexpected-doctype",
+            ParseErrorKind::UnexpectedStartTag => "unexpected-start-tag",
+            ParseErrorKind::UnexpectedEndTag => "unexpected-end-tag",
+            ParseErrorKind::EndTagWithoutMatchingOpenElement => {
+                "end-tag-without-matching-open-element"
+            }
+            ParseErrorKind::EndTagWithUnclosedElements => "end-tag-with-unclosed-elements",
+            ParseErrorKind::MisnestedFormattingElement => "misnested-formatting-element",
+            ParseErrorKind::UnexpectedCharacter => "unexpected-character",
+            ParseErrorKind::EofWithOpenElements => "eof-with-open-elements",
+        }
+    }
+}
@@ -1,612 +1,4364 @@
-use alloc::{rc::Rc, string::String, vec::Vec};
-use core::{cell::RefCell, str::FromStr};
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{ops::Deref, str::FromStr};
 
 use crate::renderer::{
-    dom::node::{Element, ElementKind, Node, NodeKind, Window},
-    html::token::HtmlTokenizer,
+    dom::node::{
+        DocumentType, Dom, Element, ElementKind, Namespace, Node, NodeId, NodeKind, QuirksMode,
+        Window,
+    },
+    html::{
+        parse_error::{ParseError, ParseErrorKind, SourcePosition},
+        token::{HtmlTokenizer, State, TextSpan},
+    },
 };
 
 use super::{attribute::Attribute, token::HtmlToken};
 
 #[derive(Debug, Clone)]
 pub struct HtmlParser {
-    window: Rc<RefCell<Window>>,
+    window: Window,
     mode: InsertionMode,
     original_insertion_mode: InsertionMode,
-    stack_of_open_elements: Vec<Rc<RefCell<Node>>>,
+    stack_of_open_elements: StackOfOpenElements,
+    /// 閉じられていない書式要素のリスト (list of active formatting elements)
+    active_formatting_elements: Vec<ActiveFormattingElement>,
+    /// <template> の中で使う挿入モードのスタック (stack of template insertion modes)
+    stack_of_template_insertion_modes: Vec<InsertionMode>,
+    /// <head> 要素 (head element pointer)
+    head_element: Option<NodeId>,
+    /// 現在開いている <form> 要素 (form element pointer)
+    form_element: Option<NodeId>,
+    /// <frameset> を挿入してもよいかどうか (frameset-ok flag)
+    frameset_ok: bool,
+    /// 誤った位置にある要素をテーブルの前に挿入するかどうか (foster parenting)
+    foster_parenting: bool,
+    /// InTableText モードで保留している文字 (pending table character tokens)
+    pending_table_characters: Vec<char>,
+    /// 次のトークンが改行文字だった場合に無視するかどうか。<pre> などの直後の改行を取り除くために使う
+    ignore_next_line_feed: bool,
+    /// トークナイザとパーサが検出したパースの失敗
+    errors: Vec<ParseError>,
+    /// ファイルの終了まで処理したかどうか
+    stopped: bool,
+    /// HTML の断片をパースする場合の、断片を子として持つ要素 (context element)
+    context_element: Option<NodeId>,
     t: HtmlTokenizer,
 }
 
 impl HtmlParser {
     pub fn new(t: HtmlTokenizer) -> Self {
         Self {
-            window: Rc::new(RefCell::new(Window::new())),
+            window: Window::new(),
             mode: InsertionMode::Initial,
             original_insertion_mode: InsertionMode::Initial,
-            stack_of_open_elements: Vec::new(),
+            stack_of_open_elements: StackOfOpenElements::default(),
+            active_formatting_elements: Vec::new(),
+            stack_of_template_insertion_modes: Vec::new(),
+            head_element: None,
+            form_element: None,
+            frameset_ok: true,
+            foster_parenting: false,
+            pending_table_characters: Vec::new(),
+            ignore_next_line_feed: false,
+            errors: Vec::new(),
+            stopped: false,
+            context_element: None,
             t,
         }
     }
 
-    pub fn construct_tree(&mut self) -> Rc<RefCell<Window>> {
-        let mut cur = self.t.next();
+    /// 入力をすべて処理して DOM ツリーを構築する
+    pub fn construct_tree(&mut self) -> Window {
+        self.finish()
+    }
 
-        while let Some(ref token) = cur {
-            match self.mode {
-                InsertionMode::Initial => {
-                    // 本書では、DOCTYPE トークンをサポートしていないため、
-                    // <!doctype html> のようなトークンは文字トークンとして表される
-                    // 文字トークンは無視する
-                    if let HtmlToken::Char(_) = token {
-                        cur = self.t.next();
-                        continue;
-                    }
+    /// HTML の断片 `markup` を `dom` の `context` 要素の子としてパースし、できたノードを返す。
+    /// innerHTML の設定のように、既存の要素の内容を置き換えるときに使う。返すノードは `dom` の中に
+    /// 作られるが親を持たないので、そのまま `context` などに追加できる
+    /// https://html.spec.whatwg.org/multipage/parsing.html#parsing-html-fragments
+    pub fn parse_fragment(dom: &mut Dom, context: NodeId, markup: &str) -> Vec<NodeId> {
+        let mut t = HtmlTokenizer::new(markup.to_string());
+        let context_element = dom.node(context).get_element();
+        if let Some(ref element) = context_element {
+            // <textarea> などの内容は、その要素の開始タグの直後と同じステートから読み込む
+            if element.namespace() == Namespace::Html {
+                let state = match element.kind() {
+                    ElementKind::Title | ElementKind::Textarea => Some(State::RcData),
+                    ElementKind::Style
+                    | ElementKind::Xmp
+                    | ElementKind::Iframe
+                    | ElementKind::Noembed
+                    | ElementKind::Noframes
+                    | ElementKind::Noscript => Some(State::RawText),
+                    ElementKind::Script => Some(State::ScriptData),
+                    ElementKind::Plaintext => Some(State::Plaintext),
+                    _ => None,
+                };
+                if let Some(state) = state {
+                    t.set_state(state);
+                }
+            }
+            // 終了タグ </textarea> などを認識させる
+            t.set_last_start_tag(element.local_name());
+            t.set_in_foreign_content(element.namespace() != Namespace::Html);
+        }
 
-                    self.mode = InsertionMode::BeforeHtml;
-                    continue;
+        let mut parser = Self::new(t);
+        let mode = dom
+            .node(dom.document())
+            .get_document()
+            .map(|d| d.mode())
+            .unwrap_or_default();
+        parser.set_quirks_mode(mode);
+
+        let root = parser
+            .dom_mut()
+            .create_node(NodeKind::Element(Element::new("html", Vec::new())));
+        let document = parser.window.document();
+        parser.dom_mut().insert(document, root, None);
+        parser
+            .stack_of_open_elements
+            .push(parser.window.dom(), root);
+        if context_element.is_some_and(|e| *e.kind() == ElementKind::Template) {
+            parser
+                .stack_of_template_insertion_modes
+                .push(InsertionMode::InTemplate);
+        }
+        // 断片は新しい文書の中でパースするので、context 要素は複製して参照する
+        parser.context_element = Some(parser.dom_mut().import_node(dom, context, false));
+        parser.reset_insertion_mode_appropriately();
+
+        // context 要素またはその祖先の <form> を、開いている <form> とする
+        let mut node = Some(context);
+        while let Some(n) = node {
+            if dom.node(n).element_kind() == Some(&ElementKind::Form) {
+                parser.form_element = Some(parser.dom_mut().import_node(dom, n, false));
+                break;
+            }
+            node = dom.node(n).parent();
+        }
+
+        parser.process_available_tokens();
+
+        // できたノードを `dom` に移す
+        parser
+            .dom()
+            .children(root)
+            .map(|child| dom.import_node(parser.dom(), child, true))
+            .collect()
+    }
+
+    /// 入力の続きを追加し、そこまでに完成したトークンから DOM ツリーを構築する。構築途中の
+    /// DOM ツリーは `window` で参照できる
+    pub fn feed(&mut self, chunk: &str) {
+        self.t.feed(chunk);
+        self.process_available_tokens();
+    }
+
+    /// 入力がこれ以上ないことを伝え、残りのトークンを処理して DOM ツリーを完成させる。完成した
+    /// DOM ツリーを持つウィンドウはパーサから取り出して返す
+    pub fn finish(&mut self) -> Window {
+        if !self.t.is_finished() {
+            self.t.finish();
+        }
+        self.process_available_tokens();
+
+        core::mem::take(&mut self.window)
+    }
+
+    /// 構築中の DOM ツリーを持つウィンドウを返す
+    pub fn window(&self) -> &Window {
+        &self.window
+    }
+
+    /// 読み込む文書の URL を設定する。相対 URL を解決するときの基準になる
+    pub fn set_document_url(&mut self, url: Option<String>) {
+        let document = self.window.document();
+        if let Some(d) = self.dom_mut().node_mut(document).get_document_mut() {
+            d.set_url(url);
+        }
+    }
+
+    fn dom(&self) -> &Dom {
+        self.window.dom()
+    }
+
+    fn dom_mut(&mut self) -> &mut Dom {
+        self.window.dom_mut()
+    }
+
+    fn node(&self, id: NodeId) -> &Node {
+        self.window.dom().node(id)
+    }
+
+    /// トークナイザが返せるトークンをすべて処理する。入力の終わりに達していれば、ファイルの終了
+    /// として処理する
+    fn process_available_tokens(&mut self) {
+        if self.stopped {
+            return;
+        }
+
+        while let Some(token) = self.t.next() {
+            self.errors.append(&mut self.t.take_errors());
+            self.process_token(&token);
+        }
+        self.errors.append(&mut self.t.take_errors());
+
+        if self.t.is_finished() {
+            self.process_token(&HtmlToken::Eof);
+            self.stopped = true;
+        }
+    }
+
+    /// これまでに検出したパースの失敗を、見つかった順に返す
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
+    /// 現在のトークンでパースの失敗を検出したことを記録する
+    fn parse_error(&mut self, kind: ParseErrorKind) {
+        self.errors
+            .push(ParseError::new(kind, self.t.token_position()));
+    }
+
+    /// 現在の挿入モードでは処理できないトークンを、トークンの種類に応じたパースの失敗として記録する
+    fn parse_error_for_token(&mut self, token: &HtmlToken) {
+        let kind = match *token {
+            HtmlToken::StartTag { .. } => ParseErrorKind::UnexpectedStartTag,
+            HtmlToken::EndTag { .. } => ParseErrorKind::UnexpectedEndTag,
+            HtmlToken::Char('\0') => ParseErrorKind::UnexpectedNullCharacter,
+            HtmlToken::Char(_) | HtmlToken::Text(_) | HtmlToken::Comment(_) => {
+                ParseErrorKind::UnexpectedCharacter
+            }
+            HtmlToken::Doctype { .. } => ParseErrorKind::UnexpectedDoctype,
+            HtmlToken::Eof => ParseErrorKind::EofWithOpenElements,
+        };
+        self.parse_error(kind);
+    }
+
+    /// https://html.spec.whatwg.org/multipage/parsing.html#tree-construction-dispatcher
+    fn process_token(&mut self, token: &HtmlToken) {
+        if let HtmlToken::Text(span) = *token {
+            self.process_text(span);
+            return;
+        }
+
+        if core::mem::take(&mut self.ignore_next_line_feed) && *token == HtmlToken::Char('\n') {
+            return;
+        }
+
+        loop {
+            let result = if self.should_use_html_rules(token) {
+                self.process_using_rules_for(self.mode, token)
+            } else {
+                self.handle_in_foreign_content(token)
+            };
+            if result == ProcessResult::Next {
+                break;
+            }
+        }
+
+        // SVG や MathML の中でのみ CDATA セクションを認識させる
+        let in_foreign_content = self
+            .adjusted_current_node()
+            .and_then(|n| self.node(n).element())
+            .is_some_and(|e| e.namespace() != Namespace::Html);
+        self.t.set_in_foreign_content(in_foreign_content);
+    }
+
+    /// 連続した文字のトークンを処理する。<body> の中やテキストだけを持つ要素の中ではまとめて挿入し、
+    /// それ以外の挿入モードでは 1文字ずつ処理する
+    fn process_text(&mut self, mut span: TextSpan) {
+        if core::mem::take(&mut self.ignore_next_line_feed) && self.t.text(span).starts_with('\n') {
+            span.start += 1;
+        }
+        if span.is_empty() {
+            return;
+        }
+
+        let text = self.t.text(span);
+        let can_insert_at_once = self.should_use_html_rules(&HtmlToken::Text(span))
+            && match self.mode {
+                InsertionMode::Text => true,
+                InsertionMode::InBody => !text.contains('\0'),
+                _ => false,
+            };
+        if !can_insert_at_once {
+            let text = String::from(text);
+            for c in text.chars() {
+                self.process_token(&HtmlToken::Char(c));
+            }
+            return;
+        }
+
+        if self.mode == InsertionMode::InBody {
+            if !text.chars().all(is_whitespace) {
+                self.frameset_ok = false;
+            }
+            self.reconstruct_active_formatting_elements();
+        }
+        if let Some(location) = self.text_insertion_location() {
+            let position = self.t.token_position();
+            insert_text_at(
+                self.window.dom_mut(),
+                &location,
+                self.t.text(span),
+                position,
+            );
+        }
+    }
+
+    /// 現在の挿入モードの規則でトークンを処理するか、"in foreign content" の規則で処理するかを返す
+    fn should_use_html_rules(&self, token: &HtmlToken) -> bool {
+        let Some(element) = self
+            .adjusted_current_node()
+            .and_then(|n| self.node(n).element())
+        else {
+            return true;
+        };
+
+        if element.namespace() == Namespace::Html {
+            return true;
+        }
+
+        match *token {
+            HtmlToken::StartTag { ref tag, .. } => {
+                (is_mathml_text_integration_point(element)
+                    && tag != "mglyph"
+                    && tag != "malignmark")
+                    || (element.namespace() == Namespace::MathMl
+                        && element.local_name() == "annotation-xml"
+                        && tag == "svg")
+                    || is_html_integration_point(element)
+            }
+            HtmlToken::Char(_) | HtmlToken::Text(_) => {
+                is_mathml_text_integration_point(element) || is_html_integration_point(element)
+            }
+            HtmlToken::Eof => true,
+            _ => false,
+        }
+    }
+
+    /// 断片をパースしていて、スタックに <html> 要素しかない場合は context 要素を返す
+    /// https://html.spec.whatwg.org/multipage/parsing.html#adjusted-current-node
+    fn adjusted_current_node(&self) -> Option<NodeId> {
+        match self.context_element {
+            Some(ref context) if self.stack_of_open_elements.len() == 1 => Some(*context),
+            _ => self.current_node(),
+        }
+    }
+
+    /// 指定した挿入モードの規則に従ってトークンを処理する
+    fn process_using_rules_for(&mut self, mode: InsertionMode, token: &HtmlToken) -> ProcessResult {
+        match mode {
+            InsertionMode::Initial => self.handle_initial(token),
+            InsertionMode::BeforeHtml => self.handle_before_html(token),
+            InsertionMode::BeforeHead => self.handle_before_head(token),
+            InsertionMode::InHead => self.handle_in_head(token),
+            InsertionMode::AfterHead => self.handle_after_head(token),
+            InsertionMode::InBody => self.handle_in_body(token),
+            InsertionMode::Text => self.handle_text(token),
+            InsertionMode::InTable => self.handle_in_table(token),
+            InsertionMode::InTableText => self.handle_in_table_text(token),
+            InsertionMode::InCaption => self.handle_in_caption(token),
+            InsertionMode::InColumnGroup => self.handle_in_column_group(token),
+            InsertionMode::InTableBody => self.handle_in_table_body(token),
+            InsertionMode::InRow => self.handle_in_row(token),
+            InsertionMode::InCell => self.handle_in_cell(token),
+            InsertionMode::InSelect => self.handle_in_select(token),
+            InsertionMode::InSelectInTable => self.handle_in_select_in_table(token),
+            InsertionMode::InTemplate => self.handle_in_template(token),
+            InsertionMode::AfterBody => self.handle_after_body(token),
+            InsertionMode::AfterAfterBody => self.handle_after_after_body(token),
+            InsertionMode::InFrameset => self.handle_in_frameset(token),
+            InsertionMode::AfterFrameset => self.handle_after_frameset(token),
+            InsertionMode::AfterAfterFrameset => self.handle_after_after_frameset(token),
+        }
+    }
+
+    /// https://html.spec.whatwg.org/multipage/parsing.html#the-initial-insertion-mode
+    fn handle_initial(&mut self, token: &HtmlToken) -> ProcessResult {
+        match *token {
+            HtmlToken::Char(c) if is_whitespace(c) => return ProcessResult::Next,
+            HtmlToken::Comment(ref data) => {
+                let document = self.window.document();
+                let comment = self.create_comment(data);
+                self.dom_mut().insert(document, comment, None);
+                return ProcessResult::Next;
+            }
+            HtmlToken::Doctype {
+                ref name,
+                ref public_id,
+                ref system_id,
+                force_quirks,
+            } => {
+                // name が "html" 以外、または識別子が指定されている DOCTYPE はパースの失敗
+                // だが、そのまま処理を続ける
+                if name.as_deref() != Some("html")
+                    || public_id.is_some()
+                    || system_id
+                        .as_deref()
+                        .is_some_and(|id| id != "about:legacy-compat")
+                {
+                    self.parse_error(ParseErrorKind::NonConformingDoctype);
                 }
-                InsertionMode::BeforeHtml => {
-                    match *token {
-                        HtmlToken::Char(c) => {
-                            if c == ' ' || c == '\n' {
-                                cur = self.t.next();
-                                continue;
-                            }
-                        }
-                        HtmlToken::StartTag {
-                            ref tag,
-                            ref attributes,
-                            ..
-                        } => {
-                            if tag == "html" {
-                                self.insert_element(tag, attributes.to_vec());
-                                self.mode = InsertionMode::BeforeHead;
-                                cur = self.t.next();
-                                continue;
-                            }
-                        }
-                        HtmlToken::Eof => {
-                            return self.window.clone();
-                        }
-                        _ => {}
-                    }
-                    self.insert_element("html", Vec::new());
-                    self.mode = InsertionMode::BeforeHead;
-                    continue;
+                self.set_quirks_mode(quirks_mode_from_doctype(
+                    name,
+                    public_id,
+                    system_id,
+                    force_quirks,
+                ));
+
+                let document = self.window.document();
+                let doctype = DocumentType::new(
+                    name.clone().unwrap_or_default(),
+                    public_id.clone().unwrap_or_default(),
+                    system_id.clone().unwrap_or_default(),
+                );
+                let doctype = self.create_node(NodeKind::DocumentType(doctype));
+                self.dom_mut().insert(document, doctype, None);
+                self.mode = InsertionMode::BeforeHtml;
+                return ProcessResult::Next;
+            }
+            _ => {}
+        }
+
+        self.parse_error(ParseErrorKind::MissingDoctype);
+        // DOCTYPE がない文書は後方互換モードとして扱う
+        self.set_quirks_mode(QuirksMode::Quirks);
+        self.mode = InsertionMode::BeforeHtml;
+        ProcessResult::Reprocess
+    }
+
+    /// https://html.spec.whatwg.org/multipage/parsing.html#the-before-html-insertion-mode
+    fn handle_before_html(&mut self, token: &HtmlToken) -> ProcessResult {
+        match *token {
+            HtmlToken::Doctype { .. } => {
+                self.parse_error(ParseErrorKind::UnexpectedDoctype);
+                return ProcessResult::Next;
+            }
+            HtmlToken::Comment(ref data) => {
+                let document = self.window.document();
+                let comment = self.create_comment(data);
+                self.dom_mut().insert(document, comment, None);
+                return ProcessResult::Next;
+            }
+            HtmlToken::Char(c) if is_whitespace(c) => return ProcessResult::Next,
+            HtmlToken::StartTag {
+                ref tag,
+                ref attributes,
+                ..
+            } if tag == "html" => {
+                self.insert_element(tag, attributes.to_vec());
+                self.mode = InsertionMode::BeforeHead;
+                return ProcessResult::Next;
+            }
+            HtmlToken::EndTag { ref tag } => {
+                if !matches!(tag.as_str(), "head" | "body" | "html" | "br") {
+                    self.parse_error(ParseErrorKind::UnexpectedEndTag);
+                    return ProcessResult::Next;
                 }
-                InsertionMode::BeforeHead => {
-                    match *token {
-                        HtmlToken::Char(c) => {
-                            if c == ' ' || c == '\n' {
-                                cur = self.t.next();
-                                continue;
-                            }
-                        }
-                        HtmlToken::StartTag {
-                            ref tag,
-                            ref attributes,
-                            ..
-                        } => {
-                            if tag == "head" {
-                                self.insert_element(tag, attributes.to_vec());
-                                self.mode = InsertionMode::InHead;
-                                cur = self.t.next();
-                                continue;
-                            }
-                        }
-                        HtmlToken::Eof => return self.window.clone(),
-                        _ => {}
-                    }
-                    self.insert_element("head", Vec::new());
+            }
+            _ => {}
+        }
+
+        self.insert_element("html", Vec::new());
+        self.mode = InsertionMode::BeforeHead;
+        ProcessResult::Reprocess
+    }
+
+    /// https://html.spec.whatwg.org/multipage/parsing.html#the-before-head-insertion-mode
+    fn handle_before_head(&mut self, token: &HtmlToken) -> ProcessResult {
+        match *token {
+            HtmlToken::Char(c) if is_whitespace(c) => return ProcessResult::Next,
+            HtmlToken::Comment(ref data) => {
+                self.insert_comment(data);
+                return ProcessResult::Next;
+            }
+            HtmlToken::Doctype { .. } => {
+                self.parse_error(ParseErrorKind::UnexpectedDoctype);
+                return ProcessResult::Next;
+            }
+            HtmlToken::StartTag {
+                ref tag,
+                ref attributes,
+                ..
+            } => match tag.as_str() {
+                "html" => return self.handle_in_body(token),
+                "head" => {
+                    self.insert_element(tag, attributes.to_vec());
+                    self.head_element = self.current_node();
                     self.mode = InsertionMode::InHead;
-                    continue;
+                    return ProcessResult::Next;
                 }
-                InsertionMode::InHead => {
-                    match *token {
-                        HtmlToken::Char(c) => {
-                            if c == ' ' || c == '\n' {
-                                self.insert_char(c);
-                                cur = self.t.next();
-                                continue;
-                            }
-                        }
-                        HtmlToken::StartTag {
-                            ref tag,
-                            ref attributes,
-                            ..
-                        } => {
-                            if tag == "style" || tag == "script" {
-                                self.insert_element(tag, attributes.to_vec());
-                                self.original_insertion_mode = self.mode;
-                                self.mode = InsertionMode::Text;
-                                cur = self.t.next();
-                                continue;
-                            }
-                            // 仕様書には定められていないが、このブラウザは仕様をすべて実装している
-                            // わけではないので、<head> が省略されている HTML 文書を扱うために
-                            // 必要。これがないと <head> が省略されている HTML 文書で無限ループが
-                            // 発生
-                            if tag == "body" {
-                                self.pop_until(ElementKind::Head);
-                                self.mode = InsertionMode::AfterHead;
-                                continue;
-                            }
-                            if let Ok(_element_kind) = ElementKind::from_str(tag) {
-                                self.pop_until(ElementKind::Head);
-                                self.mode = InsertionMode::AfterHead;
-                                continue;
-                            }
-                        }
-                        HtmlToken::EndTag { ref tag } => {
-                            if tag == "head" {
-                                self.mode = InsertionMode::AfterHead;
-                                cur = self.t.next();
-                                self.pop_until(ElementKind::Head);
-                                continue;
-                            }
-                        }
-                        HtmlToken::Eof => {
-                            return self.window.clone();
-                        }
-                    }
-                    // <meta> や <title> などのサポートしていないタグは無視する
-                    cur = self.t.next();
-                    continue;
+                _ => {}
+            },
+            HtmlToken::EndTag { ref tag } => {
+                if !matches!(tag.as_str(), "head" | "body" | "html" | "br") {
+                    self.parse_error(ParseErrorKind::UnexpectedEndTag);
+                    return ProcessResult::Next;
                 }
-                InsertionMode::AfterHead => {
-                    match *token {
-                        HtmlToken::Char(c) => {
-                            if c == ' ' || c == '\n' {
-                                self.insert_char(c);
-                                cur = self.t.next();
-                                continue;
-                            }
-                        }
-                        HtmlToken::StartTag {
-                            ref tag,
-                            ref attributes,
-                            ..
-                        } => {
-                            if tag == "body" {
-                                self.insert_element(tag, attributes.to_vec());
-                                cur = self.t.next();
-                                self.mode = InsertionMode::InBody;
-                                continue;
-                            }
-                        }
-                        HtmlToken::Eof => return self.window.clone(),
-                        _ => {}
-                    }
-                    self.insert_element("body", Vec::new());
-                    self.mode = InsertionMode::InBody;
-                    continue;
+            }
+            _ => {}
+        }
+
+        self.insert_element("head", Vec::new());
+        self.head_element = self.current_node();
+        self.mode = InsertionMode::InHead;
+        ProcessResult::Reprocess
+    }
+
+    /// https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inhead
+    fn handle_in_head(&mut self, token: &HtmlToken) -> ProcessResult {
+        match *token {
+            HtmlToken::Char(c) if is_whitespace(c) => {
+                self.insert_char(c);
+                return ProcessResult::Next;
+            }
+            HtmlToken::Comment(ref data) => {
+                self.insert_comment(data);
+                return ProcessResult::Next;
+            }
+            HtmlToken::Doctype { .. } => {
+                self.parse_error(ParseErrorKind::UnexpectedDoctype);
+                return ProcessResult::Next;
+            }
+            HtmlToken::StartTag {
+                ref tag,
+                ref attributes,
+                ..
+            } => match tag.as_str() {
+                "html" => return self.handle_in_body(token),
+                "base" | "basefont" | "bgsound" | "link" | "meta" => {
+                    // 空要素なので、挿入したらすぐにスタックから取り除く
+                    self.insert_element(tag, attributes.to_vec());
+                    self.stack_of_open_elements.pop();
+                    return ProcessResult::Next;
                 }
-                InsertionMode::InBody => {
-                    match *token {
-                        HtmlToken::StartTag {
-                            ref tag,
-                            ref attributes,
-                            ..
-                        } => match tag.as_str() {
-                            "p" => {
-                                self.insert_element(tag, attributes.to_vec());
-                                cur = self.t.next();
-                                continue;
-                            }
-                            "h1" | "h2" => {
-                                self.insert_element(tag, attributes.to_vec());
-                                cur = self.t.next();
-                                continue;
-                            }
-                            "a" => {
-                                self.insert_element(tag, attributes.to_vec());
-                                cur = self.t.next();
-                                continue;
-                            }
-                            _ => {}
-                        },
-                        HtmlToken::EndTag { ref tag } => {
-                            match tag.as_str() {
-                                "body" => {
-                                    self.mode = InsertionMode::AfterBody;
-                                    cur = self.t.next();
-                                    if !self.contain_in_stack(ElementKind::Body) {
-                                        // パースの失敗。トークンを無視する
-                                        continue;
-                                    }
-                                    self.pop_until(ElementKind::Body);
-                                    continue;
-                                }
-                                "html" => {
-                                    if self.pop_current_node(ElementKind::Body) {
-                                        self.mode = InsertionMode::AfterBody;
-                                        assert!(self.pop_current_node(ElementKind::Html));
-                                    } else {
-                                        cur = self.t.next();
-                                    }
-                                    continue;
-                                }
-                                "p" => {
-                                    let element_kind = ElementKind::from_str(tag)
-                                        .expect("failed to convert string to ElementKind");
-                                    cur = self.t.next();
-                                    self.pop_until(element_kind);
-                                    continue;
-                                }
-                                "h1" | "h2" => {
-                                    let element_kind = ElementKind::from_str(tag)
-                                        .expect("failed to convert string to ElementKind");
-                                    cur = self.t.next();
-                                    self.pop_until(element_kind);
-                                    continue;
-                                }
-                                "a" => {
-                                    let element_kind = ElementKind::from_str(tag)
-                                        .expect("failed to convert string to ElementKind");
-                                    cur = self.t.next();
-                                    self.pop_until(element_kind);
-                                    continue;
-                                }
-                                _ => cur = self.t.next(),
-                            }
-                        }
-                        HtmlToken::Char(c) => {
-                            self.insert_char(c);
-                            cur = self.t.next();
-                            continue;
-                        }
-                        HtmlToken::Eof => return self.window.clone(),
-                    }
+                "title" => {
+                    self.parse_text_element(tag, attributes.to_vec(), State::RcData);
+                    return ProcessResult::Next;
                 }
-                InsertionMode::AfterBody => {
-                    match *token {
-                        HtmlToken::Char(_) => {
-                            cur = self.t.next();
-                            continue;
-                        }
-                        HtmlToken::EndTag { ref tag } => {
-                            if tag == "html" {
-                                self.mode = InsertionMode::AfterAfterBody;
-                                cur = self.t.next();
-                                continue;
-                            }
-                        }
-                        HtmlToken::Eof => return self.window.clone(),
-                        _ => {}
+                // このブラウザではスクリプトを実行しないが、<noscript> の内容は仕様のスクリプトが
+                // 有効な場合と同じように RAWTEXT として扱う
+                "noscript" | "noframes" | "style" => {
+                    self.parse_text_element(tag, attributes.to_vec(), State::RawText);
+                    return ProcessResult::Next;
+                }
+                "script" => {
+                    self.parse_text_element(tag, attributes.to_vec(), State::ScriptData);
+                    return ProcessResult::Next;
+                }
+                "template" => {
+                    self.insert_element(tag, attributes.to_vec());
+                    self.active_formatting_elements
+                        .push(ActiveFormattingElement::Marker);
+                    self.frameset_ok = false;
+                    self.mode = InsertionMode::InTemplate;
+                    self.stack_of_template_insertion_modes
+                        .push(InsertionMode::InTemplate);
+                    return ProcessResult::Next;
+                }
+                "head" => {
+                    self.parse_error(ParseErrorKind::UnexpectedStartTag);
+                    return ProcessResult::Next;
+                }
+                _ => {}
+            },
+            HtmlToken::EndTag { ref tag } => match tag.as_str() {
+                "head" => {
+                    self.stack_of_open_elements.pop();
+                    self.mode = InsertionMode::AfterHead;
+                    return ProcessResult::Next;
+                }
+                "template" => {
+                    if !self.contain_in_stack(ElementKind::Template) {
+                        self.parse_error(ParseErrorKind::EndTagWithoutMatchingOpenElement);
+                        return ProcessResult::Next;
                     }
+                    self.generate_all_implied_end_tags_thoroughly();
+                    self.pop_until(ElementKind::Template);
+                    self.clear_active_formatting_elements_to_last_marker();
+                    self.stack_of_template_insertion_modes.pop();
+                    self.reset_insertion_mode_appropriately();
+                    return ProcessResult::Next;
+                }
+                "body" | "html" | "br" => {}
+                _ => {
+                    self.parse_error(ParseErrorKind::UnexpectedEndTag);
+                    return ProcessResult::Next;
+                }
+            },
+            _ => {}
+        }
+
+        // <head> が省略されている HTML 文書では、ここで <head> を閉じて AfterHead モードで
+        // トークンを再処理する
+        self.stack_of_open_elements.pop();
+        self.mode = InsertionMode::AfterHead;
+        ProcessResult::Reprocess
+    }
 
+    /// https://html.spec.whatwg.org/multipage/parsing.html#the-after-head-insertion-mode
+    fn handle_after_head(&mut self, token: &HtmlToken) -> ProcessResult {
+        match *token {
+            HtmlToken::Char(c) if is_whitespace(c) => {
+                self.insert_char(c);
+                return ProcessResult::Next;
+            }
+            HtmlToken::Comment(ref data) => {
+                self.insert_comment(data);
+                return ProcessResult::Next;
+            }
+            HtmlToken::Doctype { .. } => {
+                self.parse_error(ParseErrorKind::UnexpectedDoctype);
+                return ProcessResult::Next;
+            }
+            HtmlToken::StartTag {
+                ref tag,
+                ref attributes,
+                ..
+            } => match tag.as_str() {
+                "html" => return self.handle_in_body(token),
+                "body" => {
+                    self.insert_element(tag, attributes.to_vec());
+                    self.frameset_ok = false;
                     self.mode = InsertionMode::InBody;
+                    return ProcessResult::Next;
                 }
-                InsertionMode::AfterAfterBody => {
-                    match *token {
-                        HtmlToken::Char(_) => {
-                            cur = self.t.next();
-                            continue;
-                        }
-                        HtmlToken::Eof => {
-                            return self.window.clone();
-                        }
-                        _ => {}
+                "frameset" => {
+                    self.insert_element(tag, attributes.to_vec());
+                    self.mode = InsertionMode::InFrameset;
+                    return ProcessResult::Next;
+                }
+                "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script"
+                | "style" | "template" | "title" => {
+                    self.parse_error(ParseErrorKind::UnexpectedStartTag);
+                    // <head> 要素をスタックに戻して InHead モードの規則で処理する
+                    let Some(head) = self.head_element else {
+                        return ProcessResult::Next;
+                    };
+                    self.stack_of_open_elements.push(self.window.dom(), head);
+                    let result = self.handle_in_head(token);
+                    self.remove_from_stack(head);
+                    return result;
+                }
+                "head" => {
+                    self.parse_error(ParseErrorKind::UnexpectedStartTag);
+                    return ProcessResult::Next;
+                }
+                _ => {}
+            },
+            HtmlToken::EndTag { ref tag } => match tag.as_str() {
+                "template" => return self.handle_in_head(token),
+                "body" | "html" | "br" => {}
+                _ => {
+                    self.parse_error(ParseErrorKind::UnexpectedEndTag);
+                    return ProcessResult::Next;
+                }
+            },
+            _ => {}
+        }
+
+        self.insert_element("body", Vec::new());
+        self.mode = InsertionMode::InBody;
+        ProcessResult::Reprocess
+    }
+
+    /// https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inbody
+    fn handle_in_body(&mut self, token: &HtmlToken) -> ProcessResult {
+        match *token {
+            HtmlToken::Text(_) => unreachable!("text runs should be processed by `process_text`"),
+            HtmlToken::Char(c) => {
+                if c == '\0' {
+                    self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                    return ProcessResult::Next;
+                }
+
+                self.reconstruct_active_formatting_elements();
+                self.insert_char(c);
+                if !is_whitespace(c) {
+                    self.frameset_ok = false;
+                }
+                ProcessResult::Next
+            }
+            HtmlToken::Comment(ref data) => {
+                self.insert_comment(data);
+                ProcessResult::Next
+            }
+            HtmlToken::Doctype { .. } => {
+                self.parse_error(ParseErrorKind::UnexpectedDoctype);
+                ProcessResult::Next
+            }
+            HtmlToken::StartTag {
+                ref tag,
+                ref attributes,
+                ..
+            } => self.handle_in_body_start_tag(token, tag, attributes),
+            HtmlToken::EndTag { ref tag } => self.handle_in_body_end_tag(tag),
+            HtmlToken::Eof => {
+                if !self.stack_of_template_insertion_modes.is_empty() {
+                    return self.handle_in_template(token);
+                }
+                // スタックに閉じられていない要素が残っている場合はパースの失敗だが、そのまま終了する
+                let has_open_elements = self.stack_of_open_elements.iter().any(|n| {
+                    !self.node(*n).element_kind().is_some_and(|k| {
+                        has_implied_end_tag(k)
+                            || matches!(
+                                k,
+                                ElementKind::Tbody
+                                    | ElementKind::Td
+                                    | ElementKind::Tfoot
+                                    | ElementKind::Th
+                                    | ElementKind::Thead
+                                    | ElementKind::Tr
+                                    | ElementKind::Body
+                                    | ElementKind::Html
+                            )
+                    })
+                });
+                if has_open_elements {
+                    self.parse_error(ParseErrorKind::EofWithOpenElements);
+                }
+                ProcessResult::Next
+            }
+        }
+    }
+
+    fn handle_in_body_start_tag(
+        &mut self,
+        token: &HtmlToken,
+        tag: &str,
+        attributes: &[Attribute],
+    ) -> ProcessResult {
+        match tag {
+            "html" => {
+                self.parse_error(ParseErrorKind::UnexpectedStartTag);
+                if !self.contain_in_stack(ElementKind::Template) {
+                    let html = self.stack_of_open_elements[0];
+                    add_missing_attributes(self.dom_mut(), html, attributes);
+                }
+            }
+            "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script" | "style"
+            | "template" | "title" => return self.handle_in_head(token),
+            "body" => {
+                self.parse_error(ParseErrorKind::UnexpectedStartTag);
+                if self.second_element_is_body() && !self.contain_in_stack(ElementKind::Template) {
+                    self.frameset_ok = false;
+                    let body = self.stack_of_open_elements[1];
+                    add_missing_attributes(self.dom_mut(), body, attributes);
+                }
+            }
+            "frameset" => {
+                self.parse_error(ParseErrorKind::UnexpectedStartTag);
+                if !self.second_element_is_body() || !self.frameset_ok {
+                    return ProcessResult::Next;
+                }
+
+                // <body> を取り除いて <frameset> に置き換える
+                let body = self.stack_of_open_elements[1];
+                self.dom_mut().remove(body);
+                self.stack_of_open_elements.truncate(1);
+                self.insert_element(tag, attributes.to_vec());
+                self.mode = InsertionMode::InFrameset;
+            }
+            "address" | "article" | "aside" | "blockquote" | "center" | "details" | "dialog"
+            | "dir" | "div" | "dl" | "fieldset" | "figcaption" | "figure" | "footer" | "header"
+            | "hgroup" | "main" | "menu" | "nav" | "ol" | "p" | "search" | "section"
+            | "summary" | "ul" => {
+                self.close_p_element_in_button_scope();
+                self.insert_element(tag, attributes.to_vec());
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.close_p_element_in_button_scope();
+                if self
+                    .current_node()
+                    .is_some_and(|n| self.node(n).element_kind().is_some_and(is_heading))
+                {
+                    self.parse_error(ParseErrorKind::UnexpectedStartTag);
+                    // 見出しは入れ子にできないので、現在の見出しを閉じる
+                    self.stack_of_open_elements.pop();
+                }
+                self.insert_element(tag, attributes.to_vec());
+            }
+            "pre" | "listing" => {
+                self.close_p_element_in_button_scope();
+                self.insert_element(tag, attributes.to_vec());
+                // 開始タグ直後の改行は無視する
+                self.ignore_next_line_feed = true;
+                self.frameset_ok = false;
+            }
+            "form" => {
+                let in_template = self.contain_in_stack(ElementKind::Template);
+                if self.form_element.is_some() && !in_template {
+                    self.parse_error(ParseErrorKind::UnexpectedStartTag);
+                    return ProcessResult::Next;
+                }
+                self.close_p_element_in_button_scope();
+                self.insert_element(tag, attributes.to_vec());
+                if !in_template {
+                    self.form_element = self.current_node();
+                }
+            }
+            "math" | "svg" => {
+                self.reconstruct_active_formatting_elements();
+                let namespace = if tag == "math" {
+                    Namespace::MathMl
+                } else {
+                    Namespace::Svg
+                };
+                let attributes = adjust_attributes(attributes, namespace);
+                self.insert_foreign_element(tag, attributes, namespace);
+                if let HtmlToken::StartTag {
+                    self_closing: true, ..
+                } = *token
+                {
+                    self.stack_of_open_elements.pop();
+                }
+            }
+            "select" => {
+                self.reconstruct_active_formatting_elements();
+                self.insert_element(tag, attributes.to_vec());
+                self.frameset_ok = false;
+                self.mode = match self.mode {
+                    InsertionMode::InTable
+                    | InsertionMode::InCaption
+                    | InsertionMode::InTableBody
+                    | InsertionMode::InRow
+                    | InsertionMode::InCell => InsertionMode::InSelectInTable,
+                    _ => InsertionMode::InSelect,
+                };
+            }
+            "li" | "dd" | "dt" => {
+                self.frameset_ok = false;
+
+                // 閉じられていない同じ種類のリスト項目があれば閉じる
+                let closing: &[ElementKind] = if tag == "li" {
+                    &[ElementKind::Li]
+                } else {
+                    &[ElementKind::Dd, ElementKind::Dt]
+                };
+                for i in (0..self.stack_of_open_elements.len()).rev() {
+                    let Some(element) = self.node(self.stack_of_open_elements[i]).element() else {
+                        continue;
+                    };
+                    let kind = element.kind();
+
+                    if closing.contains(kind) {
+                        let kind = kind.clone();
+                        self.generate_implied_end_tags(Some(&kind));
+                        self.pop_until(kind);
+                        break;
                     }
 
-                    // パースの失敗
-                    self.mode = InsertionMode::InBody;
-                }
-                _ => {}
-            }
-        }
+                    if is_special_element(element)
+                        && !matches!(
+                            kind,
+                            ElementKind::Address | ElementKind::Div | ElementKind::P
+                        )
+                    {
+                        break;
+                    }
+                }
+
+                self.close_p_element_in_button_scope();
+                self.insert_element(tag, attributes.to_vec());
+            }
+            "plaintext" => {
+                self.close_p_element_in_button_scope();
+                self.insert_element(tag, attributes.to_vec());
+                // 以降の入力はすべて文字として扱われる
+                self.t.set_state(State::Plaintext);
+            }
+            "button" => {
+                if self.has_element_in_scope(ElementKind::Button) {
+                    self.parse_error(ParseErrorKind::UnexpectedStartTag);
+                    // 開いている <button> を閉じる
+                    self.generate_implied_end_tags(None);
+                    self.pop_until(ElementKind::Button);
+                }
+                self.reconstruct_active_formatting_elements();
+                self.insert_element(tag, attributes.to_vec());
+                self.frameset_ok = false;
+            }
+            "a" => {
+                let a = self
+                    .active_formatting_elements_after_last_marker()
+                    .find(|node| self.node(*node).element_kind() == Some(&ElementKind::A));
+                if let Some(a) = a {
+                    self.parse_error(ParseErrorKind::UnexpectedStartTag);
+                    // 開いている <a> を閉じる
+                    self.run_adoption_agency_algorithm(tag);
+                    self.remove_from_active_formatting_elements(&a);
+                    self.remove_from_stack(a);
+                }
+                self.reconstruct_active_formatting_elements();
+                self.insert_element(tag, attributes.to_vec());
+                self.push_active_formatting_element();
+            }
+            "b" | "big" | "code" | "em" | "font" | "i" | "s" | "small" | "strike" | "strong"
+            | "tt" | "u" => {
+                self.reconstruct_active_formatting_elements();
+                self.insert_element(tag, attributes.to_vec());
+                self.push_active_formatting_element();
+            }
+            "nobr" => {
+                self.reconstruct_active_formatting_elements();
+                if self.has_element_in_scope(ElementKind::Nobr) {
+                    self.parse_error(ParseErrorKind::UnexpectedStartTag);
+                    // 開いている <nobr> を閉じる
+                    self.run_adoption_agency_algorithm(tag);
+                    self.reconstruct_active_formatting_elements();
+                }
+                self.insert_element(tag, attributes.to_vec());
+                self.push_active_formatting_element();
+            }
+            "table" => {
+                if self.document_mode() != QuirksMode::Quirks {
+                    self.close_p_element_in_button_scope();
+                }
+                self.insert_element(tag, attributes.to_vec());
+                self.frameset_ok = false;
+                self.mode = InsertionMode::InTable;
+            }
+            "applet" | "marquee" | "object" => {
+                self.reconstruct_active_formatting_elements();
+                self.insert_element(tag, attributes.to_vec());
+                self.active_formatting_elements
+                    .push(ActiveFormattingElement::Marker);
+                self.frameset_ok = false;
+            }
+            "area" | "br" | "embed" | "img" | "keygen" | "wbr" => {
+                self.reconstruct_active_formatting_elements();
+                self.insert_element(tag, attributes.to_vec());
+                self.stack_of_open_elements.pop();
+                self.frameset_ok = false;
+            }
+            "input" => {
+                self.reconstruct_active_formatting_elements();
+                self.insert_element(tag, attributes.to_vec());
+                self.stack_of_open_elements.pop();
+                let is_hidden = attributes.iter().any(|attr| {
+                    attr.name() == "type" && attr.value().eq_ignore_ascii_case("hidden")
+                });
+                if !is_hidden {
+                    self.frameset_ok = false;
+                }
+            }
+            "param" | "source" | "track" => {
+                self.insert_element(tag, attributes.to_vec());
+                self.stack_of_open_elements.pop();
+            }
+            "hr" => {
+                self.close_p_element_in_button_scope();
+                self.insert_element(tag, attributes.to_vec());
+                self.stack_of_open_elements.pop();
+                self.frameset_ok = false;
+            }
+            "image" => {
+                self.parse_error(ParseErrorKind::UnexpectedStartTag);
+                // <img> として処理する
+                return self.handle_in_body(&HtmlToken::StartTag {
+                    tag: "img".to_string(),
+                    self_closing: false,
+                    attributes: attributes.to_vec(),
+                });
+            }
+            "textarea" => {
+                self.parse_text_element(tag, attributes.to_vec(), State::RcData);
+                // 開始タグ直後の改行は無視する
+                self.ignore_next_line_feed = true;
+                self.frameset_ok = false;
+            }
+            "xmp" => {
+                self.close_p_element_in_button_scope();
+                self.reconstruct_active_formatting_elements();
+                self.frameset_ok = false;
+                self.parse_text_element(tag, attributes.to_vec(), State::RawText);
+            }
+            "iframe" => {
+                self.frameset_ok = false;
+                self.parse_text_element(tag, attributes.to_vec(), State::RawText);
+            }
+            "noembed" | "noscript" => {
+                self.parse_text_element(tag, attributes.to_vec(), State::RawText);
+            }
+            "optgroup" | "option" => {
+                if self.current_node_is(ElementKind::Option) {
+                    self.stack_of_open_elements.pop();
+                }
+                self.reconstruct_active_formatting_elements();
+                self.insert_element(tag, attributes.to_vec());
+            }
+            "rb" | "rtc" => {
+                if self.has_element_in_scope(ElementKind::Ruby) {
+                    self.generate_implied_end_tags(None);
+                }
+                self.insert_element(tag, attributes.to_vec());
+            }
+            "rp" | "rt" => {
+                if self.has_element_in_scope(ElementKind::Ruby) {
+                    self.generate_implied_end_tags(Some(&ElementKind::Rtc));
+                }
+                self.insert_element(tag, attributes.to_vec());
+            }
+            "caption" | "col" | "colgroup" | "frame" | "head" | "tbody" | "td" | "tfoot" | "th"
+            | "thead" | "tr" => {
+                self.parse_error(ParseErrorKind::UnexpectedStartTag);
+            }
+            _ => {
+                // その他の開始タグ
+                self.reconstruct_active_formatting_elements();
+                self.insert_element(tag, attributes.to_vec());
+            }
+        }
+
+        ProcessResult::Next
+    }
+
+    fn handle_in_body_end_tag(&mut self, tag: &str) -> ProcessResult {
+        match tag {
+            "body" => {
+                if !self.has_element_in_scope(ElementKind::Body) {
+                    self.parse_error(ParseErrorKind::EndTagWithoutMatchingOpenElement);
+                    return ProcessResult::Next;
+                }
+                self.mode = InsertionMode::AfterBody;
+            }
+            "html" => {
+                if !self.has_element_in_scope(ElementKind::Body) {
+                    self.parse_error(ParseErrorKind::EndTagWithoutMatchingOpenElement);
+                    return ProcessResult::Next;
+                }
+                self.mode = InsertionMode::AfterBody;
+                return ProcessResult::Reprocess;
+            }
+            "address" | "article" | "aside" | "blockquote" | "button" | "center" | "details"
+            | "dialog" | "dir" | "div" | "dl" | "fieldset" | "figcaption" | "figure" | "footer"
+            | "header" | "hgroup" | "listing" | "main" | "menu" | "nav" | "ol" | "pre"
+            | "search" | "section" | "summary" | "ul" => {
+                let element_kind = ElementKind::from_str(tag).unwrap_or_else(|e| match e {});
+                if !self.has_element_in_scope(element_kind.clone()) {
+                    self.parse_error(ParseErrorKind::EndTagWithoutMatchingOpenElement);
+                    return ProcessResult::Next;
+                }
+                self.generate_implied_end_tags(None);
+                self.pop_until(element_kind);
+            }
+            "a" | "b" | "big" | "code" | "em" | "font" | "i" | "nobr" | "s" | "small"
+            | "strike" | "strong" | "tt" | "u" => {
+                if !self.run_adoption_agency_algorithm(tag) {
+                    self.close_element_for_end_tag(tag);
+                }
+            }
+            "applet" | "marquee" | "object" => {
+                let element_kind = ElementKind::from_str(tag).unwrap_or_else(|e| match e {});
+                if !self.has_element_in_scope(element_kind.clone()) {
+                    self.parse_error(ParseErrorKind::EndTagWithoutMatchingOpenElement);
+                    return ProcessResult::Next;
+                }
+                self.generate_implied_end_tags(None);
+                self.pop_until(element_kind);
+                self.clear_active_formatting_elements_to_last_marker();
+            }
+            "form" if self.contain_in_stack(ElementKind::Template) => {
+                if !self.has_element_in_scope(ElementKind::Form) {
+                    self.parse_error(ParseErrorKind::EndTagWithoutMatchingOpenElement);
+                    return ProcessResult::Next;
+                }
+                self.generate_implied_end_tags(None);
+                self.pop_until(ElementKind::Form);
+            }
+            "form" => {
+                let Some(form) = self.form_element.take() else {
+                    self.parse_error(ParseErrorKind::EndTagWithoutMatchingOpenElement);
+                    return ProcessResult::Next;
+                };
+                if !self.has_node_in_scope(&form) {
+                    self.parse_error(ParseErrorKind::EndTagWithoutMatchingOpenElement);
+                    return ProcessResult::Next;
+                }
+                self.generate_implied_end_tags(None);
+                self.remove_from_stack(form);
+            }
+            "p" => {
+                if !self.has_element_in_button_scope(ElementKind::P) {
+                    self.parse_error(ParseErrorKind::EndTagWithoutMatchingOpenElement);
+                    // 空の <p> 要素を挿入してから閉じる
+                    self.insert_element("p", Vec::new());
+                }
+                self.close_p_element();
+            }
+            "li" => {
+                if !self.has_element_in_list_item_scope(ElementKind::Li) {
+                    self.parse_error(ParseErrorKind::EndTagWithoutMatchingOpenElement);
+                    return ProcessResult::Next;
+                }
+                self.generate_implied_end_tags(Some(&ElementKind::Li));
+                self.pop_until(ElementKind::Li);
+            }
+            "dd" | "dt" => {
+                let element_kind = ElementKind::from_str(tag).unwrap_or_else(|e| match e {});
+                if !self.has_element_in_scope(element_kind.clone()) {
+                    self.parse_error(ParseErrorKind::EndTagWithoutMatchingOpenElement);
+                    return ProcessResult::Next;
+                }
+                self.generate_implied_end_tags(Some(&element_kind));
+                self.pop_until(element_kind);
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let in_scope = self.has_element_in_specific_scope(
+                    |node| node.element_kind().is_some_and(is_heading),
+                    Scope::Default,
+                );
+                if !in_scope {
+                    self.parse_error(ParseErrorKind::EndTagWithoutMatchingOpenElement);
+                    return ProcessResult::Next;
+                }
+                self.generate_implied_end_tags(None);
+                // 終了タグと異なる見出しでも閉じる
+                while let Some(node) = self.stack_of_open_elements.pop() {
+                    if self.node(node).element_kind().is_some_and(is_heading) {
+                        break;
+                    }
+                }
+            }
+            "template" => {
+                return self.handle_in_head(&HtmlToken::EndTag {
+                    tag: tag.to_string(),
+                })
+            }
+            "br" => {
+                self.parse_error(ParseErrorKind::UnexpectedEndTag);
+                // <br> 開始タグとして処理する
+                return self.handle_in_body(&HtmlToken::StartTag {
+                    tag: "br".to_string(),
+                    self_closing: false,
+                    attributes: Vec::new(),
+                });
+            }
+            _ => {
+                // その他の終了タグ
+                self.close_element_for_end_tag(tag);
+            }
+        }
+
+        ProcessResult::Next
+    }
+
+    /// https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-incdata
+    fn handle_text(&mut self, token: &HtmlToken) -> ProcessResult {
+        match *token {
+            HtmlToken::Char(c) => {
+                self.insert_char(c);
+                ProcessResult::Next
+            }
+            HtmlToken::Eof => {
+                self.parse_error(ParseErrorKind::EofWithOpenElements);
+                // 現在のノードを取り除いて元のモードで再処理する
+                self.stack_of_open_elements.pop();
+                self.mode = self.original_insertion_mode;
+                ProcessResult::Reprocess
+            }
+            HtmlToken::EndTag { .. } => {
+                self.stack_of_open_elements.pop();
+                self.mode = self.original_insertion_mode;
+                ProcessResult::Next
+            }
+            // トークナイザが RCDATA などのステートにある間は、それ以外のトークンは出力されない
+            _ => ProcessResult::Next,
+        }
+    }
+
+    /// https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-intable
+    fn handle_in_table(&mut self, token: &HtmlToken) -> ProcessResult {
+        match *token {
+            HtmlToken::Text(_) => unreachable!("text runs should be processed by `process_text`"),
+            HtmlToken::Char(_) => {
+                let in_table_context = self.current_node().is_some_and(|n| {
+                    self.node(n).element_kind().is_some_and(|k| {
+                        matches!(
+                            k,
+                            ElementKind::Table
+                                | ElementKind::Tbody
+                                | ElementKind::Template
+                                | ElementKind::Tfoot
+                                | ElementKind::Thead
+                                | ElementKind::Tr
+                        )
+                    })
+                });
+                if in_table_context {
+                    self.pending_table_characters.clear();
+                    self.original_insertion_mode = self.mode;
+                    self.mode = InsertionMode::InTableText;
+                    return ProcessResult::Reprocess;
+                }
+            }
+            HtmlToken::Comment(ref data) => {
+                self.insert_comment(data);
+                return ProcessResult::Next;
+            }
+            HtmlToken::Doctype { .. } => {
+                self.parse_error(ParseErrorKind::UnexpectedDoctype);
+                return ProcessResult::Next;
+            }
+            HtmlToken::StartTag {
+                ref tag,
+                ref attributes,
+                ..
+            } => match tag.as_str() {
+                "caption" => {
+                    self.clear_stack_back_to_table_context();
+                    self.active_formatting_elements
+                        .push(ActiveFormattingElement::Marker);
+                    self.insert_element(tag, attributes.to_vec());
+                    self.mode = InsertionMode::InCaption;
+                    return ProcessResult::Next;
+                }
+                "colgroup" => {
+                    self.clear_stack_back_to_table_context();
+                    self.insert_element(tag, attributes.to_vec());
+                    self.mode = InsertionMode::InColumnGroup;
+                    return ProcessResult::Next;
+                }
+                "col" => {
+                    self.clear_stack_back_to_table_context();
+                    self.insert_element("colgroup", Vec::new());
+                    self.mode = InsertionMode::InColumnGroup;
+                    return ProcessResult::Reprocess;
+                }
+                "tbody" | "tfoot" | "thead" => {
+                    self.clear_stack_back_to_table_context();
+                    self.insert_element(tag, attributes.to_vec());
+                    self.mode = InsertionMode::InTableBody;
+                    return ProcessResult::Next;
+                }
+                "td" | "th" | "tr" => {
+                    self.clear_stack_back_to_table_context();
+                    self.insert_element("tbody", Vec::new());
+                    self.mode = InsertionMode::InTableBody;
+                    return ProcessResult::Reprocess;
+                }
+                "table" => {
+                    self.parse_error(ParseErrorKind::UnexpectedStartTag);
+                    // 開いている <table> を閉じて再処理する
+                    if !self.has_element_in_table_scope(ElementKind::Table) {
+                        return ProcessResult::Next;
+                    }
+                    self.pop_until(ElementKind::Table);
+                    self.reset_insertion_mode_appropriately();
+                    return ProcessResult::Reprocess;
+                }
+                "style" | "script" | "template" => return self.handle_in_head(token),
+                "input" => {
+                    let is_hidden = attributes.iter().any(|attr| {
+                        attr.name() == "type" && attr.value().eq_ignore_ascii_case("hidden")
+                    });
+                    if is_hidden {
+                        self.parse_error(ParseErrorKind::UnexpectedStartTag);
+                        // テーブルの中にそのまま挿入する
+                        self.insert_element(tag, attributes.to_vec());
+                        self.stack_of_open_elements.pop();
+                        return ProcessResult::Next;
+                    }
+                }
+                "form" => {
+                    self.parse_error(ParseErrorKind::UnexpectedStartTag);
+                    if self.form_element.is_some() || self.contain_in_stack(ElementKind::Template) {
+                        return ProcessResult::Next;
+                    }
+                    self.insert_element(tag, attributes.to_vec());
+                    self.form_element = self.current_node();
+                    self.stack_of_open_elements.pop();
+                    return ProcessResult::Next;
+                }
+                _ => {}
+            },
+            HtmlToken::EndTag { ref tag } => match tag.as_str() {
+                "table" => {
+                    if !self.has_element_in_table_scope(ElementKind::Table) {
+                        self.parse_error(ParseErrorKind::EndTagWithoutMatchingOpenElement);
+                        return ProcessResult::Next;
+                    }
+                    self.pop_until(ElementKind::Table);
+                    self.reset_insertion_mode_appropriately();
+                    return ProcessResult::Next;
+                }
+                "body" | "caption" | "col" | "colgroup" | "html" | "tbody" | "td" | "tfoot"
+                | "th" | "thead" | "tr" => {
+                    self.parse_error(ParseErrorKind::UnexpectedEndTag);
+                    return ProcessResult::Next;
+                }
+                "template" => return self.handle_in_head(token),
+                _ => {}
+            },
+            HtmlToken::Eof => return self.handle_in_body(token),
+        }
+
+        self.parse_error_for_token(token);
+        // テーブルの外に出すべき内容は、テーブルの直前に挿入する
+        self.foster_parenting = true;
+        let result = self.handle_in_body(token);
+        self.foster_parenting = false;
+        result
+    }
+
+    /// https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-intabletext
+    fn handle_in_table_text(&mut self, token: &HtmlToken) -> ProcessResult {
+        if let HtmlToken::Char(c) = *token {
+            if c == '\0' {
+                self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+            } else {
+                self.pending_table_characters.push(c);
+            }
+            return ProcessResult::Next;
+        }
+
+        let characters = core::mem::take(&mut self.pending_table_characters);
+        if characters.iter().all(|c| is_whitespace(*c)) {
+            for c in characters {
+                self.insert_char(c);
+            }
+        } else {
+            self.parse_error(ParseErrorKind::UnexpectedCharacter);
+            // 空白文字以外を含む場合は、テーブルの直前に挿入する
+            self.foster_parenting = true;
+            for c in characters {
+                self.handle_in_body(&HtmlToken::Char(c));
+            }
+            self.foster_parenting = false;
+        }
+
+        self.mode = self.original_insertion_mode;
+        ProcessResult::Reprocess
+    }
+
+    /// https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-incaption
+    fn handle_in_caption(&mut self, token: &HtmlToken) -> ProcessResult {
+        let reprocess = match *token {
+            HtmlToken::EndTag { ref tag } if tag == "caption" => false,
+            HtmlToken::StartTag { ref tag, .. }
+                if matches!(
+                    tag.as_str(),
+                    "caption"
+                        | "col"
+                        | "colgroup"
+                        | "tbody"
+                        | "td"
+                        | "tfoot"
+                        | "th"
+                        | "thead"
+                        | "tr"
+                ) =>
+            {
+                true
+            }
+            HtmlToken::EndTag { ref tag } if tag == "table" => true,
+            HtmlToken::EndTag { ref tag }
+                if matches!(
+                    tag.as_str(),
+                    "body"
+                        | "col"
+                        | "colgroup"
+                        | "html"
+                        | "tbody"
+                        | "td"
+                        | "tfoot"
+                        | "th"
+                        | "thead"
+                        | "tr"
+                ) =>
+            {
+                self.parse_error(ParseErrorKind::UnexpectedEndTag);
+                return ProcessResult::Next;
+            }
+            _ => return self.handle_in_body(token),
+        };
+
+        if !self.has_element_in_table_scope(ElementKind::Caption) {
+            self.parse_error_for_token(token);
+            return ProcessResult::Next;
+        }
+        self.generate_implied_end_tags(None);
+        self.pop_until(ElementKind::Caption);
+        self.clear_active_formatting_elements_to_last_marker();
+        self.mode = InsertionMode::InTable;
+
+        if reprocess {
+            ProcessResult::Reprocess
+        } else {
+            ProcessResult::Next
+        }
+    }
+
+    /// https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-incolgroup
+    fn handle_in_column_group(&mut self, token: &HtmlToken) -> ProcessResult {
+        match *token {
+            HtmlToken::Char(c) if is_whitespace(c) => {
+                self.insert_char(c);
+                return ProcessResult::Next;
+            }
+            HtmlToken::Comment(ref data) => {
+                self.insert_comment(data);
+                return ProcessResult::Next;
+            }
+            HtmlToken::Doctype { .. } => {
+                self.parse_error(ParseErrorKind::UnexpectedDoctype);
+                return ProcessResult::Next;
+            }
+            HtmlToken::StartTag {
+                ref tag,
+                ref attributes,
+                ..
+            } => match tag.as_str() {
+                "html" => return self.handle_in_body(token),
+                "col" => {
+                    self.insert_element(tag, attributes.to_vec());
+                    self.stack_of_open_elements.pop();
+                    return ProcessResult::Next;
+                }
+                "template" => return self.handle_in_head(token),
+                _ => {}
+            },
+            HtmlToken::EndTag { ref tag } => match tag.as_str() {
+                "colgroup" => {
+                    if !self.current_node_is(ElementKind::Colgroup) {
+                        self.parse_error(ParseErrorKind::EndTagWithoutMatchingOpenElement);
+                        return ProcessResult::Next;
+                    }
+                    self.stack_of_open_elements.pop();
+                    self.mode = InsertionMode::InTable;
+                    return ProcessResult::Next;
+                }
+                "col" => {
+                    self.parse_error(ParseErrorKind::UnexpectedEndTag);
+                    return ProcessResult::Next;
+                }
+                "template" => return self.handle_in_head(token),
+                _ => {}
+            },
+            HtmlToken::Eof => return self.handle_in_body(token),
+            _ => {}
+        }
+
+        if !self.current_node_is(ElementKind::Colgroup) {
+            self.parse_error_for_token(token);
+            return ProcessResult::Next;
+        }
+        self.stack_of_open_elements.pop();
+        self.mode = InsertionMode::InTable;
+        ProcessResult::Reprocess
+    }
+
+    /// https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-intbody
+    fn handle_in_table_body(&mut self, token: &HtmlToken) -> ProcessResult {
+        match *token {
+            HtmlToken::StartTag {
+                ref tag,
+                ref attributes,
+                ..
+            } => match tag.as_str() {
+                "tr" => {
+                    self.clear_stack_back_to_table_body_context();
+                    self.insert_element(tag, attributes.to_vec());
+                    self.mode = InsertionMode::InRow;
+                    return ProcessResult::Next;
+                }
+                "th" | "td" => {
+                    self.parse_error(ParseErrorKind::UnexpectedStartTag);
+                    // <tr> を補って再処理する
+                    self.clear_stack_back_to_table_body_context();
+                    self.insert_element("tr", Vec::new());
+                    self.mode = InsertionMode::InRow;
+                    return ProcessResult::Reprocess;
+                }
+                "caption" | "col" | "colgroup" | "tbody" | "tfoot" | "thead" => {
+                    return self.close_table_body()
+                }
+                _ => {}
+            },
+            HtmlToken::EndTag { ref tag } => match tag.as_str() {
+                "tbody" | "tfoot" | "thead" => {
+                    let element_kind = ElementKind::from_str(tag).unwrap_or_else(|e| match e {});
+                    if !self.has_element_in_table_scope(element_kind) {
+                        self.parse_error(ParseErrorKind::EndTagWithoutMatchingOpenElement);
+                        return ProcessResult::Next;
+                    }
+                    self.clear_stack_back_to_table_body_context();
+                    self.stack_of_open_elements.pop();
+                    self.mode = InsertionMode::InTable;
+                    return ProcessResult::Next;
+                }
+                "table" => return self.close_table_body(),
+                "body" | "caption" | "col" | "colgroup" | "html" | "td" | "th" | "tr" => {
+                    self.parse_error(ParseErrorKind::UnexpectedEndTag);
+                    return ProcessResult::Next;
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+
+        self.handle_in_table(token)
+    }
+
+    /// 開いている <tbody>、<thead>、<tfoot> を閉じて、InTable モードで再処理する
+    fn close_table_body(&mut self) -> ProcessResult {
+        if !self.has_element_in_table_scope(ElementKind::Tbody)
+            && !self.has_element_in_table_scope(ElementKind::Thead)
+            && !self.has_element_in_table_scope(ElementKind::Tfoot)
+        {
+            self.parse_error(ParseErrorKind::EndTagWithoutMatchingOpenElement);
+            return ProcessResult::Next;
+        }
+        self.clear_stack_back_to_table_body_context();
+        self.stack_of_open_elements.pop();
+        self.mode = InsertionMode::InTable;
+        ProcessResult::Reprocess
+    }
+
+    /// https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-intr
+    fn handle_in_row(&mut self, token: &HtmlToken) -> ProcessResult {
+        match *token {
+            HtmlToken::StartTag {
+                ref tag,
+                ref attributes,
+                ..
+            } => match tag.as_str() {
+                "th" | "td" => {
+                    self.clear_stack_back_to_table_row_context();
+                    self.insert_element(tag, attributes.to_vec());
+                    self.mode = InsertionMode::InCell;
+                    self.active_formatting_elements
+                        .push(ActiveFormattingElement::Marker);
+                    return ProcessResult::Next;
+                }
+                "caption" | "col" | "colgroup" | "tbody" | "tfoot" | "thead" | "tr" => {
+                    return self.close_table_row(true)
+                }
+                _ => {}
+            },
+            HtmlToken::EndTag { ref tag } => match tag.as_str() {
+                "tr" => return self.close_table_row(false),
+                "table" => return self.close_table_row(true),
+                "tbody" | "tfoot" | "thead" => {
+                    let element_kind = ElementKind::from_str(tag).unwrap_or_else(|e| match e {});
+                    if !self.has_element_in_table_scope(element_kind) {
+                        self.parse_error(ParseErrorKind::EndTagWithoutMatchingOpenElement);
+                        return ProcessResult::Next;
+                    }
+                    return self.close_table_row(true);
+                }
+                "body" | "caption" | "col" | "colgroup" | "html" | "td" | "th" => {
+                    self.parse_error(ParseErrorKind::UnexpectedEndTag);
+                    return ProcessResult::Next;
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+
+        self.handle_in_table(token)
+    }
+
+    /// 開いている <tr> を閉じて InTableBody モードに移る。`reprocess` が真の場合はトークンを
+    /// 再処理する
+    fn close_table_row(&mut self, reprocess: bool) -> ProcessResult {
+        if !self.has_element_in_table_scope(ElementKind::Tr) {
+            self.parse_error(ParseErrorKind::EndTagWithoutMatchingOpenElement);
+            return ProcessResult::Next;
+        }
+        self.clear_stack_back_to_table_row_context();
+        self.stack_of_open_elements.pop();
+        self.mode = InsertionMode::InTableBody;
+
+        if reprocess {
+            ProcessResult::Reprocess
+        } else {
+            ProcessResult::Next
+        }
+    }
+
+    /// https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-intd
+    fn handle_in_cell(&mut self, token: &HtmlToken) -> ProcessResult {
+        match *token {
+            HtmlToken::EndTag { ref tag } => match tag.as_str() {
+                "td" | "th" => {
+                    let element_kind = ElementKind::from_str(tag).unwrap_or_else(|e| match e {});
+                    if !self.has_element_in_table_scope(element_kind.clone()) {
+                        self.parse_error(ParseErrorKind::EndTagWithoutMatchingOpenElement);
+                        return ProcessResult::Next;
+                    }
+                    self.generate_implied_end_tags(None);
+                    self.pop_until(element_kind);
+                    self.clear_active_formatting_elements_to_last_marker();
+                    self.mode = InsertionMode::InRow;
+                    return ProcessResult::Next;
+                }
+                "body" | "caption" | "col" | "colgroup" | "html" => {
+                    self.parse_error(ParseErrorKind::UnexpectedEndTag);
+                    return ProcessResult::Next;
+                }
+                "table" | "tbody" | "tfoot" | "thead" | "tr" => {
+                    let element_kind = ElementKind::from_str(tag).unwrap_or_else(|e| match e {});
+                    if !self.has_element_in_table_scope(element_kind) {
+                        self.parse_error(ParseErrorKind::EndTagWithoutMatchingOpenElement);
+                        return ProcessResult::Next;
+                    }
+                    self.close_cell();
+                    return ProcessResult::Reprocess;
+                }
+                _ => {}
+            },
+            HtmlToken::StartTag { ref tag, .. } => {
+                if matches!(
+                    tag.as_str(),
+                    "caption"
+                        | "col"
+                        | "colgroup"
+                        | "tbody"
+                        | "td"
+                        | "tfoot"
+                        | "th"
+                        | "thead"
+                        | "tr"
+                ) {
+                    if !self.has_element_in_table_scope(ElementKind::Td)
+                        && !self.has_element_in_table_scope(ElementKind::Th)
+                    {
+                        self.parse_error(ParseErrorKind::EndTagWithoutMatchingOpenElement);
+                        return ProcessResult::Next;
+                    }
+                    self.close_cell();
+                    return ProcessResult::Reprocess;
+                }
+            }
+            _ => {}
+        }
+
+        self.handle_in_body(token)
+    }
+
+    /// https://html.spec.whatwg.org/multipage/parsing.html#close-the-cell
+    fn close_cell(&mut self) {
+        self.generate_implied_end_tags(None);
+        while let Some(node) = self.stack_of_open_elements.pop() {
+            let kind = self.node(node).element_kind();
+            if matches!(kind, Some(ElementKind::Td) | Some(ElementKind::Th)) {
+                break;
+            }
+        }
+        self.clear_active_formatting_elements_to_last_marker();
+        self.mode = InsertionMode::InRow;
+    }
+
+    /// https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inselect
+    fn handle_in_select(&mut self, token: &HtmlToken) -> ProcessResult {
+        match *token {
+            HtmlToken::Text(_) => unreachable!("text runs should be processed by `process_text`"),
+            HtmlToken::Char(c) => {
+                if c == '\0' {
+                    self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                } else {
+                    self.insert_char(c);
+                }
+            }
+            HtmlToken::Comment(ref data) => self.insert_comment(data),
+            HtmlToken::Doctype { .. } => {
+                self.parse_error(ParseErrorKind::UnexpectedDoctype);
+            }
+            HtmlToken::StartTag {
+                ref tag,
+                ref attributes,
+                ..
+            } => match tag.as_str() {
+                "html" => return self.handle_in_body(token),
+                "option" => {
+                    if self.current_node_is(ElementKind::Option) {
+                        self.stack_of_open_elements.pop();
+                    }
+                    self.insert_element(tag, attributes.to_vec());
+                }
+                "optgroup" => {
+                    if self.current_node_is(ElementKind::Option) {
+                        self.stack_of_open_elements.pop();
+                    }
+                    if self.current_node_is(ElementKind::Optgroup) {
+                        self.stack_of_open_elements.pop();
+                    }
+                    self.insert_element(tag, attributes.to_vec());
+                }
+                "hr" => {
+                    if self.current_node_is(ElementKind::Option) {
+                        self.stack_of_open_elements.pop();
+                    }
+                    if self.current_node_is(ElementKind::Optgroup) {
+                        self.stack_of_open_elements.pop();
+                    }
+                    self.insert_element(tag, attributes.to_vec());
+                    self.stack_of_open_elements.pop();
+                }
+                "select" => {
+                    self.parse_error(ParseErrorKind::UnexpectedStartTag);
+                    // <select> の終了タグとして扱う
+                    self.close_select();
+                }
+                "input" | "keygen" | "textarea" => {
+                    self.parse_error(ParseErrorKind::UnexpectedStartTag);
+                    // <select> を閉じて再処理する
+                    if !self.has_element_in_select_scope(ElementKind::Select) {
+                        return ProcessResult::Next;
+                    }
+                    self.close_select();
+                    return ProcessResult::Reprocess;
+                }
+                "script" | "template" => return self.handle_in_head(token),
+                _ => {
+                    self.parse_error(ParseErrorKind::UnexpectedStartTag);
+                }
+            },
+            HtmlToken::EndTag { ref tag } => match tag.as_str() {
+                "optgroup" => {
+                    let len = self.stack_of_open_elements.len();
+                    if self.current_node_is(ElementKind::Option)
+                        && len >= 2
+                        && self
+                            .node(self.stack_of_open_elements[len - 2])
+                            .element_kind()
+                            == Some(&ElementKind::Optgroup)
+                    {
+                        self.stack_of_open_elements.pop();
+                    }
+                    if self.current_node_is(ElementKind::Optgroup) {
+                        self.stack_of_open_elements.pop();
+                    }
+                }
+                "option" => {
+                    if self.current_node_is(ElementKind::Option) {
+                        self.stack_of_open_elements.pop();
+                    }
+                }
+                "select" => self.close_select(),
+                "template" => return self.handle_in_head(token),
+                _ => {
+                    self.parse_error(ParseErrorKind::UnexpectedEndTag);
+                }
+            },
+            HtmlToken::Eof => return self.handle_in_body(token),
+        }
+
+        ProcessResult::Next
+    }
+
+    /// 開いている <select> を閉じる。<select> がない場合は何もしない
+    fn close_select(&mut self) {
+        if !self.has_element_in_select_scope(ElementKind::Select) {
+            self.parse_error(ParseErrorKind::EndTagWithoutMatchingOpenElement);
+            return;
+        }
+        self.pop_until(ElementKind::Select);
+        self.reset_insertion_mode_appropriately();
+    }
+
+    /// https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inselectintable
+    fn handle_in_select_in_table(&mut self, token: &HtmlToken) -> ProcessResult {
+        let (tag, is_end_tag) = match *token {
+            HtmlToken::StartTag { ref tag, .. } => (tag, false),
+            HtmlToken::EndTag { ref tag } => (tag, true),
+            _ => return self.handle_in_select(token),
+        };
+        if !matches!(
+            tag.as_str(),
+            "caption" | "table" | "tbody" | "tfoot" | "thead" | "tr" | "td" | "th"
+        ) {
+            return self.handle_in_select(token);
+        }
+
+        self.parse_error_for_token(token);
+        // <select> を閉じて再処理する
+        if is_end_tag {
+            let element_kind = ElementKind::from_str(tag).unwrap_or_else(|e| match e {});
+            if !self.has_element_in_table_scope(element_kind) {
+                return ProcessResult::Next;
+            }
+        }
+        self.pop_until(ElementKind::Select);
+        self.reset_insertion_mode_appropriately();
+        ProcessResult::Reprocess
+    }
+
+    /// https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-intemplate
+    fn handle_in_template(&mut self, token: &HtmlToken) -> ProcessResult {
+        match *token {
+            HtmlToken::Text(_) => unreachable!("text runs should be processed by `process_text`"),
+            HtmlToken::Char(_) | HtmlToken::Comment(_) | HtmlToken::Doctype { .. } => {
+                self.handle_in_body(token)
+            }
+            HtmlToken::StartTag { ref tag, .. } => {
+                let mode = match tag.as_str() {
+                    "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script"
+                    | "style" | "template" | "title" => return self.handle_in_head(token),
+                    "caption" | "colgroup" | "tbody" | "tfoot" | "thead" => InsertionMode::InTable,
+                    "col" => InsertionMode::InColumnGroup,
+                    "tr" => InsertionMode::InTableBody,
+                    "td" | "th" => InsertionMode::InRow,
+                    _ => InsertionMode::InBody,
+                };
+
+                // 最初の開始タグで <template> の内容に合った挿入モードを決める
+                self.stack_of_template_insertion_modes.pop();
+                self.stack_of_template_insertion_modes.push(mode);
+                self.mode = mode;
+                ProcessResult::Reprocess
+            }
+            HtmlToken::EndTag { ref tag } => {
+                if tag == "template" {
+                    return self.handle_in_head(token);
+                }
+                self.parse_error(ParseErrorKind::UnexpectedEndTag);
+                ProcessResult::Next
+            }
+            HtmlToken::Eof => {
+                if !self.contain_in_stack(ElementKind::Template) {
+                    return ProcessResult::Next;
+                }
+
+                self.parse_error(ParseErrorKind::EofWithOpenElements);
+                // 閉じられていない <template> を閉じて再処理する
+                self.pop_until(ElementKind::Template);
+                self.clear_active_formatting_elements_to_last_marker();
+                self.stack_of_template_insertion_modes.pop();
+                self.reset_insertion_mode_appropriately();
+                ProcessResult::Reprocess
+            }
+        }
+    }
+
+    /// https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-afterbody
+    fn handle_after_body(&mut self, token: &HtmlToken) -> ProcessResult {
+        match *token {
+            HtmlToken::Char(c) if is_whitespace(c) => return self.handle_in_body(token),
+            HtmlToken::Comment(ref data) => {
+                // コメントは <html> 要素の最後の子として挿入する
+                if let Some(html) = self.stack_of_open_elements.first().cloned() {
+                    let comment = self.create_comment(data);
+                    self.dom_mut().insert(html, comment, None);
+                }
+                return ProcessResult::Next;
+            }
+            HtmlToken::Doctype { .. } => {
+                self.parse_error(ParseErrorKind::UnexpectedDoctype);
+                return ProcessResult::Next;
+            }
+            HtmlToken::StartTag { ref tag, .. } if tag == "html" => {
+                return self.handle_in_body(token)
+            }
+            HtmlToken::EndTag { ref tag } if tag == "html" => {
+                // 断片をパースしている場合は無視する
+                if self.context_element.is_some() {
+                    self.parse_error(ParseErrorKind::UnexpectedEndTag);
+                    return ProcessResult::Next;
+                }
+                self.mode = InsertionMode::AfterAfterBody;
+                return ProcessResult::Next;
+            }
+            HtmlToken::Eof => return ProcessResult::Next,
+            _ => {}
+        }
+
+        self.parse_error_for_token(token);
+        self.mode = InsertionMode::InBody;
+        ProcessResult::Reprocess
+    }
+
+    /// https://html.spec.whatwg.org/multipage/parsing.html#the-after-after-body-insertion-mode
+    fn handle_after_after_body(&mut self, token: &HtmlToken) -> ProcessResult {
+        match *token {
+            HtmlToken::Comment(ref data) => {
+                let document = self.window.document();
+                let comment = self.create_comment(data);
+                self.dom_mut().insert(document, comment, None);
+                return ProcessResult::Next;
+            }
+            HtmlToken::Doctype { .. } => return self.handle_in_body(token),
+            HtmlToken::Char(c) if is_whitespace(c) => return self.handle_in_body(token),
+            HtmlToken::StartTag { ref tag, .. } if tag == "html" => {
+                return self.handle_in_body(token)
+            }
+            HtmlToken::Eof => return ProcessResult::Next,
+            _ => {}
+        }
+
+        self.parse_error_for_token(token);
+        self.mode = InsertionMode::InBody;
+        ProcessResult::Reprocess
+    }
+
+    fn document_mode(&self) -> QuirksMode {
+        let document = self.window.document();
+        let mode = self.node(document).get_document().map(|d| d.mode());
+        mode.unwrap_or_default()
+    }
+
+    /// https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inframeset
+    fn handle_in_frameset(&mut self, token: &HtmlToken) -> ProcessResult {
+        match *token {
+            HtmlToken::Char(c) if is_whitespace(c) => self.insert_char(c),
+            HtmlToken::Comment(ref data) => self.insert_comment(data),
+            HtmlToken::StartTag {
+                ref tag,
+                ref attributes,
+                ..
+            } => match tag.as_str() {
+                "html" => return self.handle_in_body(token),
+                "frameset" => self.insert_element(tag, attributes.to_vec()),
+                "frame" => {
+                    self.insert_element(tag, attributes.to_vec());
+                    self.stack_of_open_elements.pop();
+                }
+                "noframes" => return self.handle_in_head(token),
+                _ => {
+                    self.parse_error(ParseErrorKind::UnexpectedStartTag);
+                }
+            },
+            HtmlToken::EndTag { ref tag } if tag == "frameset" => {
+                if self.current_node_is(ElementKind::Html) {
+                    self.parse_error(ParseErrorKind::EndTagWithoutMatchingOpenElement);
+                    return ProcessResult::Next;
+                }
+                self.stack_of_open_elements.pop();
+                if !self.current_node_is(ElementKind::Frameset) {
+                    self.mode = InsertionMode::AfterFrameset;
+                }
+            }
+            _ => {
+                self.parse_error_for_token(token);
+            }
+        }
+
+        ProcessResult::Next
+    }
+
+    /// https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-afterframeset
+    fn handle_after_frameset(&mut self, token: &HtmlToken) -> ProcessResult {
+        match *token {
+            HtmlToken::Char(c) if is_whitespace(c) => self.insert_char(c),
+            HtmlToken::Comment(ref data) => self.insert_comment(data),
+            HtmlToken::StartTag { ref tag, .. } if tag == "html" => {
+                return self.handle_in_body(token)
+            }
+            HtmlToken::StartTag { ref tag, .. } if tag == "noframes" => {
+                return self.handle_in_head(token)
+            }
+            HtmlToken::EndTag { ref tag } if tag == "html" => {
+                self.mode = InsertionMode::AfterAfterFrameset;
+            }
+            _ => {
+                self.parse_error_for_token(token);
+            }
+        }
+
+        ProcessResult::Next
+    }
+
+    /// https://html.spec.whatwg.org/multipage/parsing.html#the-after-after-frameset-insertion-mode
+    fn handle_after_after_frameset(&mut self, token: &HtmlToken) -> ProcessResult {
+        match *token {
+            HtmlToken::Comment(ref data) => {
+                let document = self.window.document();
+                let comment = self.create_comment(data);
+                self.dom_mut().insert(document, comment, None);
+                ProcessResult::Next
+            }
+            HtmlToken::Doctype { .. } => self.handle_in_body(token),
+            HtmlToken::Char(c) if is_whitespace(c) => self.handle_in_body(token),
+            HtmlToken::StartTag { ref tag, .. } if tag == "html" => self.handle_in_body(token),
+            HtmlToken::StartTag { ref tag, .. } if tag == "noframes" => self.handle_in_head(token),
+            _ => {
+                self.parse_error_for_token(token);
+                ProcessResult::Next
+            }
+        }
+    }
+
+    /// スタックの 2 番目の要素が <body> かどうかを返す
+    fn second_element_is_body(&self) -> bool {
+        self.stack_of_open_elements
+            .get(1)
+            .is_some_and(|n| self.node(*n).element_kind() == Some(&ElementKind::Body))
+    }
+
+    /// https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inforeign
+    fn handle_in_foreign_content(&mut self, token: &HtmlToken) -> ProcessResult {
+        match *token {
+            HtmlToken::Text(_) => unreachable!("text runs should be processed by `process_text`"),
+            HtmlToken::Char(c) => {
+                // NULL 文字は U+FFFD に置き換える
+                let c = if c == '\0' {
+                    self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                    '\u{FFFD}'
+                } else {
+                    c
+                };
+                self.insert_char(c);
+                if !is_whitespace(c) {
+                    self.frameset_ok = false;
+                }
+            }
+            HtmlToken::Comment(ref data) => self.insert_comment(data),
+            HtmlToken::Doctype { .. } => {
+                self.parse_error(ParseErrorKind::UnexpectedDoctype);
+            }
+            HtmlToken::StartTag {
+                ref tag,
+                self_closing,
+                ref attributes,
+            } => {
+                let breaks_out = matches!(
+                    tag.as_str(),
+                    "b" | "big"
+                        | "blockquote"
+                        | "body"
+                        | "br"
+                        | "center"
+                        | "code"
+                        | "dd"
+                        | "div"
+                        | "dl"
+                        | "dt"
+                        | "em"
+                        | "embed"
+                        | "h1"
+                        | "h2"
+                        | "h3"
+                        | "h4"
+                        | "h5"
+                        | "h6"
+                        | "head"
+                        | "hr"
+                        | "i"
+                        | "img"
+                        | "li"
+                        | "listing"
+                        | "menu"
+                        | "meta"
+                        | "nobr"
+                        | "ol"
+                        | "p"
+                        | "pre"
+                        | "ruby"
+                        | "s"
+                        | "small"
+                        | "span"
+                        | "strong"
+                        | "strike"
+                        | "sub"
+                        | "sup"
+                        | "table"
+                        | "tt"
+                        | "u"
+                        | "ul"
+                        | "var"
+                ) || (tag == "font"
+                    && attributes
+                        .iter()
+                        .any(|attr| matches!(attr.name().as_str(), "color" | "face" | "size")));
+                if breaks_out {
+                    return self.break_out_of_foreign_content(token);
+                }
+
+                let Some(namespace) = self
+                    .adjusted_current_node()
+                    .and_then(|n| self.node(n).element())
+                    .map(|e| e.namespace())
+                else {
+                    return ProcessResult::Next;
+                };
+                let tag = if namespace == Namespace::Svg {
+                    adjust_svg_tag_name(tag)
+                } else {
+                    tag
+                };
+                let attributes = adjust_attributes(attributes, namespace);
+                self.insert_foreign_element(tag, attributes, namespace);
+                if self_closing {
+                    self.stack_of_open_elements.pop();
+                }
+            }
+            HtmlToken::EndTag { ref tag } => {
+                if tag == "br" || tag == "p" {
+                    return self.break_out_of_foreign_content(token);
+                }
+
+                // 対応する要素を探して閉じる。HTML の要素に達した場合は現在の挿入モードで処理する
+                for i in (1..self.stack_of_open_elements.len()).rev() {
+                    let Some(element) = self.node(self.stack_of_open_elements[i]).element() else {
+                        continue;
+                    };
+
+                    if i != self.stack_of_open_elements.len() - 1
+                        && element.namespace() == Namespace::Html
+                    {
+                        return self.process_using_rules_for(self.mode, token);
+                    }
+
+                    if element.local_name().to_ascii_lowercase() == *tag {
+                        self.stack_of_open_elements.truncate(i);
+                        return ProcessResult::Next;
+                    }
+                }
+            }
+            HtmlToken::Eof => return self.process_using_rules_for(self.mode, token),
+        }
+
+        ProcessResult::Next
+    }
+
+    /// SVG や MathML の中に HTML の要素が現れた場合に、外側の HTML の要素まで戻って処理し直す
+    fn break_out_of_foreign_content(&mut self, token: &HtmlToken) -> ProcessResult {
+        self.parse_error(ParseErrorKind::UnexpectedStartTag);
+        while let Some(element) = self.current_node().and_then(|n| self.node(n).element()) {
+            if element.namespace() == Namespace::Html
+                || is_mathml_text_integration_point(element)
+                || is_html_integration_point(element)
+            {
+                break;
+            }
+            self.stack_of_open_elements.pop();
+        }
+
+        self.process_using_rules_for(self.mode, token)
+    }
+
+    fn set_quirks_mode(&mut self, mode: QuirksMode) {
+        let document = self.window.document();
+        if let Some(d) = self.dom_mut().node_mut(document).get_document_mut() {
+            d.set_mode(mode);
+        }
+    }
+
+    /// RCDATA や RAWTEXT などの内容を持つ要素を挿入し、終了タグまでを Text モードで処理する
+    /// https://html.spec.whatwg.org/multipage/parsing.html#generic-raw-text-element-parsing-algorithm
+    fn parse_text_element(&mut self, tag: &str, attributes: Vec<Attribute>, state: State) {
+        self.insert_element(tag, attributes);
+        self.t.set_state(state);
+        self.original_insertion_mode = self.mode;
+        self.mode = InsertionMode::Text;
+    }
+
+    /// 現在のトークンの位置を持つノードを作る
+    fn create_node(&mut self, kind: NodeKind) -> NodeId {
+        let position = self.t.token_position();
+        let node = self.dom_mut().create_node(kind);
+        self.dom_mut().node_mut(node).set_position(Some(position));
+        node
+    }
+
+    /// `original` と同じ名前と属性を持つ要素を作り直す。位置は `original` のものを引き継ぐ
+    fn recreate_element(&mut self, original: NodeId) -> Option<NodeId> {
+        let NodeKind::Element(_) = self.node(original).kind else {
+            return None;
+        };
+        let position = self.node(original).position();
+        let node = self.dom_mut().clone_node(original, false);
+        self.dom_mut().node_mut(node).set_position(position);
+        Some(node)
+    }
+
+    fn insert_element(&mut self, tag: &str, attributes: Vec<Attribute>) {
+        self.insert_foreign_element(tag, attributes, Namespace::Html);
+    }
+
+    /// https://html.spec.whatwg.org/multipage/parsing.html#insert-a-foreign-element
+    fn insert_foreign_element(
+        &mut self,
+        tag: &str,
+        attributes: Vec<Attribute>,
+        namespace: Namespace,
+    ) {
+        let location = self.appropriate_place_for_inserting_node(None);
+
+        let node = self.create_node(NodeKind::Element(Element::new_with_namespace(
+            tag, attributes, namespace,
+        )));
+        self.insert_node_at(&location, node);
+
+        self.stack_of_open_elements.push(self.window.dom(), node);
+    }
+
+    fn create_comment(&mut self, data: &str) -> NodeId {
+        self.create_node(NodeKind::Comment(data.to_string()))
+    }
+
+    /// コメントをノードを挿入するのに適切な位置に挿入する
+    fn insert_comment(&mut self, data: &str) {
+        let location = self.appropriate_place_for_inserting_node(None);
+        let comment = self.create_comment(data);
+        self.insert_node_at(&location, comment);
+    }
+
+    /// ノードを挿入するのに適切な位置を返す。`override_target` を指定しない場合は現在のノードを
+    /// 対象とする
+    /// https://html.spec.whatwg.org/multipage/parsing.html#appropriate-place-for-inserting-a-node
+    fn appropriate_place_for_inserting_node(
+        &self,
+        override_target: Option<NodeId>,
+    ) -> InsertionLocation {
+        let location = self.adjusted_insertion_location(override_target);
+
+        // <template> の中に挿入する場合は、その内容を保持する DocumentFragment に挿入する
+        let template_contents = self.dom().template_contents(location.parent);
+        match template_contents {
+            Some(contents) => InsertionLocation {
+                parent: contents,
+                before: None,
+            },
+            None => location,
+        }
+    }
+
+    fn adjusted_insertion_location(&self, override_target: Option<NodeId>) -> InsertionLocation {
+        let target = match override_target.or_else(|| self.current_node()) {
+            Some(n) => n,
+            None => {
+                return InsertionLocation {
+                    parent: self.window.document(),
+                    before: None,
+                }
+            }
+        };
+
+        let is_table_related = self.node(target).element_kind().is_some_and(|k| {
+            matches!(
+                k,
+                ElementKind::Table
+                    | ElementKind::Tbody
+                    | ElementKind::Tfoot
+                    | ElementKind::Thead
+                    | ElementKind::Tr
+            )
+        });
+        if !(self.foster_parenting && is_table_related) {
+            return InsertionLocation {
+                parent: target,
+                before: None,
+            };
+        }
+
+        // テーブルの中に置けない要素は、テーブルの直前に挿入する (foster parenting)
+        let last_table = self
+            .stack_of_open_elements
+            .iter()
+            .rposition(|n| self.node(*n).element_kind() == Some(&ElementKind::Table));
+        let last_template = self
+            .stack_of_open_elements
+            .iter()
+            .rposition(|n| self.node(*n).element_kind() == Some(&ElementKind::Template));
+
+        if let Some(template) = last_template {
+            if last_table.map_or(true, |table| template > table) {
+                return InsertionLocation {
+                    parent: self.stack_of_open_elements[template],
+                    before: None,
+                };
+            }
+        }
+
+        let Some(last_table) = last_table else {
+            return InsertionLocation {
+                parent: self.stack_of_open_elements[0],
+                before: None,
+            };
+        };
+
+        let table = self.stack_of_open_elements[last_table];
+        if let Some(parent) = self.node(table).parent() {
+            return InsertionLocation {
+                parent,
+                before: Some(table),
+            };
+        }
+
+        InsertionLocation {
+            parent: self.stack_of_open_elements[last_table - 1],
+            before: None,
+        }
+    }
+
+    fn insert_node_at(&mut self, location: &InsertionLocation, node: NodeId) {
+        self.dom_mut()
+            .insert(location.parent, node, location.before);
+    }
+
+    fn current_node(&self) -> Option<NodeId> {
+        self.stack_of_open_elements.last().cloned()
+    }
+
+    fn current_node_is(&self, element_kind: ElementKind) -> bool {
+        self.stack_of_open_elements
+            .last()
+            .is_some_and(|n| self.node(*n).element_kind() == Some(&element_kind))
+    }
+
+    fn contain_in_stack(&mut self, element_kind: ElementKind) -> bool {
+        self.stack_of_open_elements.contains_kind(&element_kind)
+    }
+
+    /// 要素をスタックから取り出す。要素がスタックにない場合は何もしない
+    fn pop_until(&mut self, element_kind: ElementKind) {
+        if !self.contain_in_stack(element_kind.clone()) {
+            return;
+        }
+
+        loop {
+            let current = match self.stack_of_open_elements.pop() {
+                Some(n) => n,
+                None => return,
+            };
+
+            if self.node(current).element_kind() == Some(&element_kind) {
+                return;
+            }
+        }
+    }
+
+    /// `node` をスタックから取り除く。`node` より上にある要素はそのまま残す
+    fn remove_from_stack(&mut self, node: NodeId) {
+        self.stack_of_open_elements.remove_node(node);
+    }
+
+    /// 仕様の "any other end tag" に従って、終了タグに対応する要素をスタックから取り除く
+    /// https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inbody
+    fn close_element_for_end_tag(&mut self, tag: &str) {
+        let element_kind = ElementKind::from_str(tag).unwrap_or_else(|e| match e {});
+
+        for i in (0..self.stack_of_open_elements.len()).rev() {
+            let Some(element) = self.node(self.stack_of_open_elements[i]).element() else {
+                continue;
+            };
+
+            if element.namespace() == Namespace::Html && *element.kind() == element_kind {
+                self.generate_implied_end_tags(Some(&element_kind));
+                self.stack_of_open_elements.truncate(i);
+                return;
+            }
+
+            if is_special_element(element) {
+                self.parse_error(ParseErrorKind::EndTagWithoutMatchingOpenElement);
+                return;
+            }
+        }
+    }
+
+    /// スタックの上から `target` を満たす要素を探し、`scope` の境界となる要素より先に見つかるかを返す
+    /// https://html.spec.whatwg.org/multipage/parsing.html#has-an-element-in-the-specific-scope
+    fn has_element_in_specific_scope(&self, target: impl Fn(&Node) -> bool, scope: Scope) -> bool {
+        for node in self.stack_of_open_elements.iter().rev() {
+            let node = self.node(*node);
+            if target(node) {
+                return true;
+            }
+
+            let Some(element) = node.element() else {
+                continue;
+            };
+            if scope.is_boundary(element) {
+                return false;
+            }
+        }
+
+        false
+    }
+
+    /// `element_kind` の要素がスコープ内にあるかどうかを返す。その種類の要素がスタックにまったくない
+    /// 場合は、スタックをたどらずに false を返す
+    fn has_element_kind_in_specific_scope(&self, element_kind: &ElementKind, scope: Scope) -> bool {
+        self.stack_of_open_elements.contains_kind(element_kind)
+            && self.has_element_in_specific_scope(
+                |node| node.element_kind() == Some(element_kind),
+                scope,
+            )
+    }
+
+    fn has_element_in_scope(&self, element_kind: ElementKind) -> bool {
+        self.has_element_kind_in_specific_scope(&element_kind, Scope::Default)
+    }
+
+    fn has_element_in_button_scope(&self, element_kind: ElementKind) -> bool {
+        self.has_element_kind_in_specific_scope(&element_kind, Scope::Button)
+    }
+
+    fn has_element_in_list_item_scope(&self, element_kind: ElementKind) -> bool {
+        self.has_element_kind_in_specific_scope(&element_kind, Scope::ListItem)
+    }
+
+    fn has_element_in_select_scope(&self, element_kind: ElementKind) -> bool {
+        self.has_element_kind_in_specific_scope(&element_kind, Scope::Select)
+    }
+
+    fn has_element_in_table_scope(&self, element_kind: ElementKind) -> bool {
+        self.has_element_kind_in_specific_scope(&element_kind, Scope::Table)
+    }
+
+    /// `node` そのものがスコープ内にあるかどうかを返す
+    fn has_node_in_scope(&self, node: &NodeId) -> bool {
+        for n in self.stack_of_open_elements.iter().rev() {
+            if n == node {
+                return true;
+            }
+
+            let Some(element) = self.node(*n).element() else {
+                continue;
+            };
+            if Scope::Default.is_boundary(element) {
+                return false;
+            }
+        }
+
+        false
+    }
+
+    /// 終了タグが省略可能な要素を閉じる。`exception` で指定した要素は閉じない
+    /// https://html.spec.whatwg.org/multipage/parsing.html#generate-implied-end-tags
+    fn generate_implied_end_tags(&mut self, exception: Option<&ElementKind>) {
+        while let Some(kind) = self
+            .current_node()
+            .and_then(|n| self.node(n).element_kind())
+        {
+            if Some(kind) == exception || !has_implied_end_tag(kind) {
+                return;
+            }
+            self.stack_of_open_elements.pop();
+        }
+    }
+
+    /// 現在のノードが `kinds` のいずれかか <html> になるまで要素を取り出す
+    /// https://html.spec.whatwg.org/multipage/parsing.html#clear-the-stack-back-to-a-table-context
+    fn clear_stack_back_to(&mut self, kinds: &[ElementKind]) {
+        while let Some(kind) = self
+            .current_node()
+            .and_then(|n| self.node(n).element_kind())
+        {
+            if *kind == ElementKind::Html || *kind == ElementKind::Template || kinds.contains(kind)
+            {
+                return;
+            }
+            self.stack_of_open_elements.pop();
+        }
+    }
+
+    fn clear_stack_back_to_table_context(&mut self) {
+        self.clear_stack_back_to(&[ElementKind::Table]);
+    }
+
+    fn clear_stack_back_to_table_body_context(&mut self) {
+        self.clear_stack_back_to(&[ElementKind::Tbody, ElementKind::Tfoot, ElementKind::Thead]);
+    }
+
+    fn clear_stack_back_to_table_row_context(&mut self) {
+        self.clear_stack_back_to(&[ElementKind::Tr]);
+    }
+
+    /// スタックの状態から挿入モードを決め直す
+    /// https://html.spec.whatwg.org/multipage/parsing.html#reset-the-insertion-mode-appropriately
+    fn reset_insertion_mode_appropriately(&mut self) {
+        for (i, node) in self.stack_of_open_elements.iter().enumerate().rev() {
+            let last = i == 0;
+            // 断片をパースしている場合、スタックの最初の要素の代わりに context 要素を使う
+            let node = match self.context_element {
+                Some(ref context) if last => context,
+                _ => node,
+            };
+            let Some(kind) = self.node(*node).element_kind() else {
+                continue;
+            };
+
+            self.mode = match kind {
+                ElementKind::Select => {
+                    // <select> がテーブルの中にあるかどうかを調べる
+                    let in_table = self.stack_of_open_elements[..i]
+                        .iter()
+                        .rev()
+                        .map_while(|n| {
+                            self.node(*n)
+                                .element_kind()
+                                .filter(|k| **k != ElementKind::Template)
+                        })
+                        .any(|k| *k == ElementKind::Table);
+                    if in_table {
+                        InsertionMode::InSelectInTable
+                    } else {
+                        InsertionMode::InSelect
+                    }
+                }
+                ElementKind::Td | ElementKind::Th if !last => InsertionMode::InCell,
+                ElementKind::Tr => InsertionMode::InRow,
+                ElementKind::Tbody | ElementKind::Thead | ElementKind::Tfoot => {
+                    InsertionMode::InTableBody
+                }
+                ElementKind::Caption => InsertionMode::InCaption,
+                ElementKind::Colgroup => InsertionMode::InColumnGroup,
+                ElementKind::Table => InsertionMode::InTable,
+                ElementKind::Template => match self.stack_of_template_insertion_modes.last() {
+                    Some(mode) => *mode,
+                    None => InsertionMode::InBody,
+                },
+                ElementKind::Head if !last => InsertionMode::InHead,
+                ElementKind::Body => InsertionMode::InBody,
+                ElementKind::Frameset => InsertionMode::InFrameset,
+                ElementKind::Html => {
+                    if self.head_element.is_none() {
+                        InsertionMode::BeforeHead
+                    } else {
+                        InsertionMode::AfterHead
+                    }
+                }
+                _ if last => InsertionMode::InBody,
+                _ => continue,
+            };
+            return;
+        }
+
+        self.mode = InsertionMode::InBody;
+    }
+
+    /// 表に関する要素を含め、終了タグを省略できる要素をすべて閉じる
+    /// https://html.spec.whatwg.org/multipage/parsing.html#generate-all-implied-end-tags-thoroughly
+    fn generate_all_implied_end_tags_thoroughly(&mut self) {
+        while let Some(kind) = self
+            .current_node()
+            .and_then(|n| self.node(n).element_kind())
+        {
+            let is_table_related = matches!(
+                kind,
+                ElementKind::Caption
+                    | ElementKind::Colgroup
+                    | ElementKind::Tbody
+                    | ElementKind::Td
+                    | ElementKind::Tfoot
+                    | ElementKind::Th
+                    | ElementKind::Thead
+                    | ElementKind::Tr
+            );
+            if !has_implied_end_tag(kind) && !is_table_related {
+                return;
+            }
+            self.stack_of_open_elements.pop();
+        }
+    }
+
+    /// https://html.spec.whatwg.org/multipage/parsing.html#close-a-p-element
+    fn close_p_element(&mut self) {
+        self.generate_implied_end_tags(Some(&ElementKind::P));
+        if !self.current_node_is(ElementKind::P) {
+            self.parse_error(ParseErrorKind::EndTagWithUnclosedElements);
+        }
+        self.pop_until(ElementKind::P);
+    }
+
+    /// ボタンスコープ内に <p> 要素があれば閉じる。ブロック要素の開始タグは開いている <p> を閉じる
+    fn close_p_element_in_button_scope(&mut self) {
+        if self.has_element_in_button_scope(ElementKind::P) {
+            self.close_p_element();
+        }
+    }
+
+    /// 書式要素のリストのうち、最後のマーカーより後にある要素を末尾から順に返す
+    fn active_formatting_elements_after_last_marker(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.active_formatting_elements
+            .iter()
+            .rev()
+            .map_while(|entry| match entry {
+                ActiveFormattingElement::Marker => None,
+                ActiveFormattingElement::Element(node) => Some(*node),
+            })
+    }
+
+    fn position_in_active_formatting_elements(&self, node: &NodeId) -> Option<usize> {
+        self.active_formatting_elements
+            .iter()
+            .position(|entry| entry.is(node))
+    }
+
+    fn remove_from_active_formatting_elements(&mut self, node: &NodeId) {
+        self.active_formatting_elements
+            .retain(|entry| !entry.is(node));
+    }
+
+    fn is_in_stack(&self, node: &NodeId) -> bool {
+        self.stack_of_open_elements.iter().any(|n| n == node)
+    }
+
+    /// 現在のノードを書式要素のリストに追加する
+    /// https://html.spec.whatwg.org/multipage/parsing.html#push-onto-the-list-of-active-formatting-elements
+    fn push_active_formatting_element(&mut self) {
+        let Some(node) = self.current_node() else {
+            return;
+        };
+        let Some(element) = self.node(node).element() else {
+            return;
+        };
+
+        // Noah's Ark clause: 同じタグ名と属性を持つ要素が既に 3 つある場合は、最も古いものを取り除く
+        let same: Vec<NodeId> = self
+            .active_formatting_elements_after_last_marker()
+            .filter(|n| {
+                self.node(*n)
+                    .element()
+                    .is_some_and(|e| is_same_element(e, element))
+            })
+            .collect();
+        if same.len() >= 3 {
+            self.remove_from_active_formatting_elements(&same[same.len() - 1]);
+        }
+
+        self.active_formatting_elements
+            .push(ActiveFormattingElement::Element(node));
+    }
+
+    /// 閉じられてしまった書式要素を再び開く
+    /// https://html.spec.whatwg.org/multipage/parsing.html#reconstruct-the-active-formatting-elements
+    fn reconstruct_active_formatting_elements(&mut self) {
+        let is_open_or_marker = |parser: &Self, entry: &ActiveFormattingElement| match entry {
+            ActiveFormattingElement::Marker => true,
+            ActiveFormattingElement::Element(node) => parser.is_in_stack(node),
+        };
+
+        let Some(last) = self.active_formatting_elements.last() else {
+            return;
+        };
+        if is_open_or_marker(self, last) {
+            return;
+        }
+
+        // 最後のマーカーまたは開いている要素の直後まで巻き戻す
+        let mut entry = self.active_formatting_elements.len() - 1;
+        while entry > 0 && !is_open_or_marker(self, &self.active_formatting_elements[entry - 1]) {
+            entry -= 1;
+        }
+
+        for i in entry..self.active_formatting_elements.len() {
+            let ActiveFormattingElement::Element(node) = self.active_formatting_elements[i].clone()
+            else {
+                continue;
+            };
+            let Some(element) = self.node(node).get_element() else {
+                continue;
+            };
+
+            self.insert_element(element.kind().as_str(), element.attributes());
+            if let Some(new_node) = self.current_node() {
+                self.active_formatting_elements[i] = ActiveFormattingElement::Element(new_node);
+            }
+        }
+    }
+
+    /// https://html.spec.whatwg.org/multipage/parsing.html#clear-the-list-of-active-formatting-elements-up-to-the-last-marker
+    fn clear_active_formatting_elements_to_last_marker(&mut self) {
+        while let Some(entry) = self.active_formatting_elements.pop() {
+            if let ActiveFormattingElement::Marker = entry {
+                return;
+            }
+        }
+    }
+
+    /// 入れ子が正しくない書式要素の終了タグを処理する。`false` を返した場合、呼び出し元は
+    /// "any other end tag" として処理する必要がある
+    /// https://html.spec.whatwg.org/multipage/parsing.html#adoption-agency-algorithm
+    fn run_adoption_agency_algorithm(&mut self, subject: &str) -> bool {
+        let subject = ElementKind::from_str(subject).unwrap_or_else(|e| match e {});
+
+        if let Some(current) = self.current_node() {
+            if self.node(current).element_kind() == Some(&subject)
+                && self
+                    .position_in_active_formatting_elements(&current)
+                    .is_none()
+            {
+                self.stack_of_open_elements.pop();
+                return true;
+            }
+        }
+
+        for _ in 0..8 {
+            let Some(formatting_element) = self
+                .active_formatting_elements_after_last_marker()
+                .find(|n| self.node(*n).element_kind() == Some(&subject))
+            else {
+                return false;
+            };
+
+            let Some(formatting_index) = self
+                .stack_of_open_elements
+                .iter()
+                .position(|n| *n == formatting_element)
+            else {
+                self.parse_error(ParseErrorKind::EndTagWithoutMatchingOpenElement);
+                // 書式要素のリストから取り除く
+                self.remove_from_active_formatting_elements(&formatting_element);
+                return true;
+            };
+
+            if !self.has_node_in_scope(&formatting_element) {
+                self.parse_error(ParseErrorKind::EndTagWithoutMatchingOpenElement);
+                return true;
+            }
+
+            if formatting_index != self.stack_of_open_elements.len() - 1 {
+                self.parse_error(ParseErrorKind::MisnestedFormattingElement);
+            }
+
+            // 書式要素より上にある最初の特別な要素 (furthest block) を探す
+            let Some(furthest_block_index) = self.stack_of_open_elements[formatting_index + 1..]
+                .iter()
+                .position(|n| self.node(*n).element().is_some_and(is_special_element))
+                .map(|i| formatting_index + 1 + i)
+            else {
+                self.stack_of_open_elements.truncate(formatting_index);
+                self.remove_from_active_formatting_elements(&formatting_element);
+                return true;
+            };
+            let furthest_block = self.stack_of_open_elements[furthest_block_index];
+
+            let common_ancestor = self.stack_of_open_elements[formatting_index - 1];
+            let mut bookmark = self
+                .position_in_active_formatting_elements(&formatting_element)
+                .unwrap_or(0);
+
+            let mut node_index = furthest_block_index;
+            let mut last_node = furthest_block;
+            let mut inner_loop_counter = 0;
+            loop {
+                inner_loop_counter += 1;
+                node_index -= 1;
+                let node = self.stack_of_open_elements[node_index];
+                if node == formatting_element {
+                    break;
+                }
+
+                let mut position = self.position_in_active_formatting_elements(&node);
+                if inner_loop_counter > 3 {
+                    if let Some(i) = position.take() {
+                        self.active_formatting_elements.remove(i);
+                        if i < bookmark {
+                            bookmark -= 1;
+                        }
+                    }
+                }
+
+                let Some(position) = position else {
+                    self.stack_of_open_elements.remove(node_index);
+                    continue;
+                };
+
+                // 要素を作り直して、リストとスタックの両方で置き換える
+                let Some(new_node) = self.recreate_element(node) else {
+                    continue;
+                };
+                self.active_formatting_elements[position] =
+                    ActiveFormattingElement::Element(new_node);
+                self.stack_of_open_elements
+                    .replace(self.window.dom(), node_index, new_node);
+
+                if last_node == furthest_block {
+                    bookmark = position + 1;
+                }
+
+                self.dom_mut().insert(new_node, last_node, None);
+                last_node = new_node;
+            }
+
+            self.dom_mut().remove(last_node);
+            let location = self.appropriate_place_for_inserting_node(Some(common_ancestor));
+            self.insert_node_at(&location, last_node);
+
+            // furthest block の子を、書式要素を作り直した要素に移す
+            let Some(new_element) = self.recreate_element(formatting_element) else {
+                return true;
+            };
+            loop {
+                let Some(child) = self.node(furthest_block).first_child() else {
+                    break;
+                };
+                self.dom_mut().insert(new_element, child, None);
+            }
+            self.dom_mut().insert(furthest_block, new_element, None);
+
+            if let Some(i) = self.position_in_active_formatting_elements(&formatting_element) {
+                self.active_formatting_elements.remove(i);
+                if i < bookmark {
+                    bookmark -= 1;
+                }
+            }
+            self.active_formatting_elements.insert(
+                bookmark.min(self.active_formatting_elements.len()),
+                ActiveFormattingElement::Element(new_element),
+            );
+
+            self.remove_from_stack(formatting_element);
+            let furthest_block_index = self
+                .stack_of_open_elements
+                .iter()
+                .position(|n| *n == furthest_block)
+                .unwrap_or(self.stack_of_open_elements.len() - 1);
+            self.stack_of_open_elements.insert(
+                self.window.dom(),
+                furthest_block_index + 1,
+                new_element,
+            );
+        }
+
+        true
+    }
+
+    fn insert_char(&mut self, c: char) {
+        self.insert_text(c.encode_utf8(&mut [0; 4]));
+    }
+
+    fn insert_text(&mut self, text: &str) {
+        let Some(location) = self.text_insertion_location() else {
+            return;
+        };
+        let position = self.t.token_position();
+        insert_text_at(self.window.dom_mut(), &location, text, position);
+    }
+
+    /// 文字を挿入する位置を返す。文書の直下には文字を挿入しないので `None` を返す
+    fn text_insertion_location(&self) -> Option<InsertionLocation> {
+        if self.stack_of_open_elements.is_empty() {
+            return None;
+        }
+        let location = self.appropriate_place_for_inserting_node(None);
+        if let NodeKind::Document(_) = self.node(location.parent).kind {
+            return None;
+        }
+        Some(location)
+    }
+}
+
+/// `location` に文字を挿入する。パーサのトークナイザが持つ文字列をそのまま挿入できるように、
+/// パーサのメソッドではなく DOM ツリーだけを受け取る
+fn insert_text_at(
+    dom: &mut Dom,
+    location: &InsertionLocation,
+    text: &str,
+    position: SourcePosition,
+) {
+    // 挿入位置の直前のノードがテキストノードの場合、そのノードに文字を追加する
+    let previous = match location.before {
+        Some(before) => dom.node(before).previous_sibling(),
+        None => dom.node(location.parent).last_child(),
+    };
+    if let Some(previous) = previous {
+        if let NodeKind::Text(ref mut s) = dom.node_mut(previous).kind {
+            s.push_str(text);
+            return;
+        }
+    }
+
+    if text.is_empty() {
+        return;
+    }
+
+    let node = dom.create_node(NodeKind::Text(text.to_string()));
+    dom.node_mut(node).set_position(Some(position));
+    dom.insert(location.parent, node, location.before);
+}
+
+/// 仕様の特別な要素 (special) かどうかを返す
+/// https://html.spec.whatwg.org/multipage/parsing.html#special
+fn is_special_element(element: &Element) -> bool {
+    match element.namespace() {
+        Namespace::Html => element.kind().is_special(),
+        Namespace::MathMl => matches!(
+            element.local_name(),
+            "mi" | "mo" | "mn" | "ms" | "mtext" | "annotation-xml"
+        ),
+        Namespace::Svg => matches!(element.local_name(), "foreignObject" | "desc" | "title"),
+        _ => false,
+    }
+}
+
+/// https://html.spec.whatwg.org/multipage/parsing.html#mathml-text-integration-point
+fn is_mathml_text_integration_point(element: &Element) -> bool {
+    element.namespace() == Namespace::MathMl
+        && matches!(element.local_name(), "mi" | "mo" | "mn" | "ms" | "mtext")
+}
+
+/// https://html.spec.whatwg.org/multipage/parsing.html#html-integration-point
+fn is_html_integration_point(element: &Element) -> bool {
+    match element.namespace() {
+        Namespace::MathMl => {
+            element.local_name() == "annotation-xml"
+                && element.attributes().iter().any(|attr| {
+                    attr.name() == "encoding"
+                        && (attr.value().eq_ignore_ascii_case("text/html")
+                            || attr.value().eq_ignore_ascii_case("application/xhtml+xml"))
+                })
+        }
+        Namespace::Svg => matches!(element.local_name(), "foreignObject" | "desc" | "title"),
+        _ => false,
+    }
+}
+
+/// SVG の要素名を正しい大文字小文字に直す
+fn adjust_svg_tag_name(tag: &str) -> &str {
+    SVG_TAG_NAME_ADJUSTMENTS
+        .iter()
+        .find(|(lower, _)| *lower == tag)
+        .map_or(tag, |(_, adjusted)| adjusted)
+}
+
+/// SVG や MathML の要素の属性名と名前空間を調整する
+fn adjust_attributes(attributes: &[Attribute], namespace: Namespace) -> Vec<Attribute> {
+    let mut attributes = attributes.to_vec();
+    for attr in attributes.iter_mut() {
+        let name = attr.name();
+        match namespace {
+            // https://html.spec.whatwg.org/multipage/parsing.html#adjust-mathml-attributes
+            Namespace::MathMl if name == "definitionurl" => attr.set_name("definitionURL"),
+            Namespace::Svg => {
+                if let Some((_, adjusted)) = SVG_ATTRIBUTE_ADJUSTMENTS
+                    .iter()
+                    .find(|(lower, _)| *lower == name)
+                {
+                    attr.set_name(adjusted);
+                }
+            }
+            _ => {}
+        }
+
+        if let Some((_, prefix, local_name, namespace)) = FOREIGN_ATTRIBUTE_ADJUSTMENTS
+            .iter()
+            .find(|(qualified_name, ..)| *qualified_name == name)
+        {
+            attr.set_namespace(*prefix, local_name, *namespace);
+        }
+    }
+    attributes
+}
+
+/// HTML における空白文字 (タブ、改行、フォームフィード、キャリッジリターン、スペース) かどうかを返す
+fn is_whitespace(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\x0C' | '\r' | ' ')
+}
+
+/// 2 つの要素のタグ名と属性がすべて同じかどうかを返す。属性の順番は問わない
+fn is_same_element(a: &Element, b: &Element) -> bool {
+    let a_attributes = a.attributes();
+    let b_attributes = b.attributes();
+    a.kind() == b.kind()
+        && a_attributes.len() == b_attributes.len()
+        && a_attributes.iter().all(|attr| b_attributes.contains(attr))
+}
+
+fn is_heading(kind: &ElementKind) -> bool {
+    matches!(
+        kind,
+        ElementKind::H1
+            | ElementKind::H2
+            | ElementKind::H3
+            | ElementKind::H4
+            | ElementKind::H5
+            | ElementKind::H6
+    )
+}
+
+/// 終了タグを省略できる要素かどうかを返す
+fn has_implied_end_tag(kind: &ElementKind) -> bool {
+    matches!(
+        kind,
+        ElementKind::Dd
+            | ElementKind::Dt
+            | ElementKind::Li
+            | ElementKind::Optgroup
+            | ElementKind::Option
+            | ElementKind::P
+            | ElementKind::Rb
+            | ElementKind::Rp
+            | ElementKind::Rt
+            | ElementKind::Rtc
+    )
+}
+
+/// SVG の要素名の大文字小文字の調整表
+/// https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inforeign
+const SVG_TAG_NAME_ADJUSTMENTS: [(&str, &str); 37] = [
+    ("altglyph", "altGlyph"),
+    ("altglyphdef", "altGlyphDef"),
+    ("altglyphitem", "altGlyphItem"),
+    ("animatecolor", "animateColor"),
+    ("animatemotion", "animateMotion"),
+    ("animatetransform", "animateTransform"),
+    ("clippath", "clipPath"),
+    ("feblend", "feBlend"),
+    ("fecolormatrix", "feColorMatrix"),
+    ("fecomponenttransfer", "feComponentTransfer"),
+    ("fecomposite", "feComposite"),
+    ("feconvolvematrix", "feConvolveMatrix"),
+    ("fediffuselighting", "feDiffuseLighting"),
+    ("fedisplacementmap", "feDisplacementMap"),
+    ("fedistantlight", "feDistantLight"),
+    ("fedropshadow", "feDropShadow"),
+    ("feflood", "feFlood"),
+    ("fefunca", "feFuncA"),
+    ("fefuncb", "feFuncB"),
+    ("fefuncg", "feFuncG"),
+    ("fefuncr", "feFuncR"),
+    ("fegaussianblur", "feGaussianBlur"),
+    ("feimage", "feImage"),
+    ("femerge", "feMerge"),
+    ("femergenode", "feMergeNode"),
+    ("femorphology", "feMorphology"),
+    ("feoffset", "feOffset"),
+    ("fepointlight", "fePointLight"),
+    ("fespecularlighting", "feSpecularLighting"),
+    ("fespotlight", "feSpotLight"),
+    ("fetile", "feTile"),
+    ("feturbulence", "feTurbulence"),
+    ("foreignobject", "foreignObject"),
+    ("glyphref", "glyphRef"),
+    ("lineargradient", "linearGradient"),
+    ("radialgradient", "radialGradient"),
+    ("textpath", "textPath"),
+];
+
+/// SVG の属性名の大文字小文字の調整表
+/// https://html.spec.whatwg.org/multipage/parsing.html#adjust-svg-attributes
+const SVG_ATTRIBUTE_ADJUSTMENTS: [(&str, &str); 58] = [
+    ("attributename", "attributeName"),
+    ("attributetype", "attributeType"),
+    ("basefrequency", "baseFrequency"),
+    ("baseprofile", "baseProfile"),
+    ("calcmode", "calcMode"),
+    ("clippathunits", "clipPathUnits"),
+    ("diffuseconstant", "diffuseConstant"),
+    ("edgemode", "edgeMode"),
+    ("filterunits", "filterUnits"),
+    ("glyphref", "glyphRef"),
+    ("gradienttransform", "gradientTransform"),
+    ("gradientunits", "gradientUnits"),
+    ("kernelmatrix", "kernelMatrix"),
+    ("kernelunitlength", "kernelUnitLength"),
+    ("keypoints", "keyPoints"),
+    ("keysplines", "keySplines"),
+    ("keytimes", "keyTimes"),
+    ("lengthadjust", "lengthAdjust"),
+    ("limitingconeangle", "limitingConeAngle"),
+    ("markerheight", "markerHeight"),
+    ("markerunits", "markerUnits"),
+    ("markerwidth", "markerWidth"),
+    ("maskcontentunits", "maskContentUnits"),
+    ("maskunits", "maskUnits"),
+    ("numoctaves", "numOctaves"),
+    ("pathlength", "pathLength"),
+    ("patterncontentunits", "patternContentUnits"),
+    ("patterntransform", "patternTransform"),
+    ("patternunits", "patternUnits"),
+    ("pointsatx", "pointsAtX"),
+    ("pointsaty", "pointsAtY"),
+    ("pointsatz", "pointsAtZ"),
+    ("preservealpha", "preserveAlpha"),
+    ("preserveaspectratio", "preserveAspectRatio"),
+    ("primitiveunits", "primitiveUnits"),
+    ("refx", "refX"),
+    ("refy", "refY"),
+    ("repeatcount", "repeatCount"),
+    ("repeatdur", "repeatDur"),
+    ("requiredextensions", "requiredExtensions"),
+    ("requiredfeatures", "requiredFeatures"),
+    ("specularconstant", "specularConstant"),
+    ("specularexponent", "specularExponent"),
+    ("spreadmethod", "spreadMethod"),
+    ("startoffset", "startOffset"),
+    ("stddeviation", "stdDeviation"),
+    ("stitchtiles", "stitchTiles"),
+    ("surfacescale", "surfaceScale"),
+    ("systemlanguage", "systemLanguage"),
+    ("tablevalues", "tableValues"),
+    ("targetx", "targetX"),
+    ("targety", "targetY"),
+    ("textlength", "textLength"),
+    ("viewbox", "viewBox"),
+    ("viewtarget", "viewTarget"),
+    ("xchannelselector", "xChannelSelector"),
+    ("ychannelselector", "yChannelSelector"),
+    ("zoomandpan", "zoomAndPan"),
+];
+
+/// 名前空間付きの属性の調整表。(属性名, 接頭辞, ローカル名, 名前空間)
+/// https://html.spec.whatwg.org/multipage/parsing.html#adjust-foreign-attributes
+const FOREIGN_ATTRIBUTE_ADJUSTMENTS: [(&str, Option<&str>, &str, Namespace); 11] = [
+    ("xlink:actuate", Some("xlink"), "actuate", Namespace::XLink),
+    ("xlink:arcrole", Some("xlink"), "arcrole", Namespace::XLink),
+    ("xlink:href", Some("xlink"), "href", Namespace::XLink),
+    ("xlink:role", Some("xlink"), "role", Namespace::XLink),
+    ("xlink:show", Some("xlink"), "show", Namespace::XLink),
+    ("xlink:title", Some("xlink"), "title", Namespace::XLink),
+    ("xlink:type", Some("xlink"), "type", Namespace::XLink),
+    ("xml:lang", Some("xml"), "lang", Namespace::Xml),
+    ("xml:space", Some("xml"), "space", Namespace::Xml),
+    ("xmlns", None, "xmlns", Namespace::Xmlns),
+    ("xmlns:xlink", Some("xmlns"), "xlink", Namespace::Xmlns),
+];
+
+/// 後方互換モードになる公開識別子の接頭辞
+const QUIRKS_PUBLIC_ID_PREFIXES: [&str; 55] = [
+    "+//Silmaril//dtd html Pro v0r11 19970101//",
+    "-//AS//DTD HTML 3.0 asWedit + extensions//",
+    "-//AdvaSoft Ltd//DTD HTML 3.0 asWedit + extensions//",
+    "-//IETF//DTD HTML 2.0 Level 1//",
+    "-//IETF//DTD HTML 2.0 Level 2//",
+    "-//IETF//DTD HTML 2.0 Strict Level 1//",
+    "-//IETF//DTD HTML 2.0 Strict Level 2//",
+    "-//IETF//DTD HTML 2.0 Strict//",
+    "-//IETF//DTD HTML 2.0//",
+    "-//IETF//DTD HTML 2.1E//",
+    "-//IETF//DTD HTML 3.0//",
+    "-//IETF//DTD HTML 3.2 Final//",
+    "-//IETF//DTD HTML 3.2//",
+    "-//IETF//DTD HTML 3//",
+    "-//IETF//DTD HTML Level 0//",
+    "-//IETF//DTD HTML Level 1//",
+    "-//IETF//DTD HTML Level 2//",
+    "-//IETF//DTD HTML Level 3//",
+    "-//IETF//DTD HTML Strict Level 0//",
+    "-//IETF//DTD HTML Strict Level 1//",
+    "-//IETF//DTD HTML Strict Level 2//",
+    "-//IETF//DTD HTML Strict Level 3//",
+    "-//IETF//DTD HTML Strict//",
+    "-//IETF//DTD HTML//",
+    "-//Metrius//DTD Metrius Presentational//",
+    "-//Microsoft//DTD Internet Explorer 2.0 HTML Strict//",
+    "-//Microsoft//DTD Internet Explorer 2.0 HTML//",
+    "-//Microsoft//DTD Internet Explorer 2.0 Tables//",
+    "-//Microsoft//DTD Internet Explorer 3.0 HTML Strict//",
+    "-//Microsoft//DTD Internet Explorer 3.0 HTML//",
+    "-//Microsoft//DTD Internet Explorer 3.0 Tables//",
+    "-//Netscape Comm. Corp.//DTD HTML//",
+    "-//Netscape Comm. Corp.//DTD Strict HTML//",
+    "-//O'Reilly and Associates//DTD HTML 2.0//",
+    "-//O'Reilly and Associates//DTD HTML Extended 1.0//",
+    "-//O'Reilly and Associates//DTD HTML Extended Relaxed 1.0//",
+    "-//SQ//DTD HTML 2.0 HoTMetaL + extensions//",
+    "-//SoftQuad Software//DTD HoTMetaL PRO 6.0::19990601::extensions to HTML 4.0//",
+    "-//SoftQuad//DTD HoTMetaL PRO 4.0::19970916::extensions to HTML 4.0//",
+    "-//Spyglass//DTD HTML 2.0 Extended//",
+    "-//Sun Microsystems Corp.//DTD HotJava HTML//",
+    "-//Sun Microsystems Corp.//DTD HotJava Strict HTML//",
+    "-//W3C//DTD HTML 3 1995-03-24//",
+    "-//W3C//DTD HTML 3.2 Draft//",
+    "-//W3C//DTD HTML 3.2 Final//",
+    "-//W3C//DTD HTML 3.2//",
+    "-//W3C//DTD HTML 3.2S Draft//",
+    "-//W3C//DTD HTML 4.0 Frameset//",
+    "-//W3C//DTD HTML 4.0 Transitional//",
+    "-//W3C//DTD HTML Experimental 19960712//",
+    "-//W3C//DTD HTML Experimental 970421//",
+    "-//W3C//DTD W3 HTML//",
+    "-//W3O//DTD W3 HTML 3.0//",
+    "-//WebTechs//DTD Mozilla HTML 2.0//",
+    "-//WebTechs//DTD Mozilla HTML//",
+];
+
+/// 後方互換モードになる公開識別子
+const QUIRKS_PUBLIC_IDS: [&str; 3] = [
+    "-//W3O//DTD W3 HTML Strict 3.0//EN//",
+    "-/W3C/DTD HTML 4.0 Transitional/EN",
+    "HTML",
+];
+
+/// システム識別子がないときに後方互換モード、あるときに限定後方互換モードになる公開識別子の接頭辞
+const HTML4_PUBLIC_ID_PREFIXES: [&str; 2] = [
+    "-//W3C//DTD HTML 4.01 Frameset//",
+    "-//W3C//DTD HTML 4.01 Transitional//",
+];
+
+/// 限定後方互換モードになる公開識別子の接頭辞
+const LIMITED_QUIRKS_PUBLIC_ID_PREFIXES: [&str; 2] = [
+    "-//W3C//DTD XHTML 1.0 Frameset//",
+    "-//W3C//DTD XHTML 1.0 Transitional//",
+];
+
+fn starts_with_ignore_ascii_case(s: &str, prefix: &str) -> bool {
+    s.len() >= prefix.len() && s.as_bytes()[..prefix.len()].eq_ignore_ascii_case(prefix.as_bytes())
+}
+
+/// 2つ目の <html> や <body> の開始タグの属性のうち、`element` にまだないものを追加する
+fn add_missing_attributes(dom: &mut Dom, element: NodeId, attributes: &[Attribute]) {
+    for attribute in attributes {
+        let missing = dom
+            .node(element)
+            .element()
+            .is_some_and(|e| !e.has_attribute(&attribute.name()));
+        if missing {
+            dom.set_attribute(element, &attribute.name(), &attribute.value());
+        }
+    }
+}
+
+/// DOCTYPE トークンから文書の互換モードを決める
+/// https://html.spec.whatwg.org/multipage/parsing.html#the-initial-insertion-mode
+fn quirks_mode_from_doctype(
+    name: &Option<String>,
+    public_id: &Option<String>,
+    system_id: &Option<String>,
+    force_quirks: bool,
+) -> QuirksMode {
+    let public_id = public_id.as_deref();
+    let system_id = system_id.as_deref();
+    let public_id_starts_with = |prefixes: &[&str]| {
+        public_id.is_some_and(|id| {
+            prefixes
+                .iter()
+                .any(|prefix| starts_with_ignore_ascii_case(id, prefix))
+        })
+    };
+
+    if force_quirks
+        || name.as_deref() != Some("html")
+        || public_id.is_some_and(|id| {
+            QUIRKS_PUBLIC_IDS
+                .iter()
+                .any(|quirks_id| id.eq_ignore_ascii_case(quirks_id))
+        })
+        || system_id.is_some_and(|id| {
+            id.eq_ignore_ascii_case("http://www.ibm.com/data/dtd/v11/ibmxhtml1-transitional.dtd")
+        })
+        || public_id_starts_with(&QUIRKS_PUBLIC_ID_PREFIXES)
+        || (system_id.is_none() && public_id_starts_with(&HTML4_PUBLIC_ID_PREFIXES))
+    {
+        return QuirksMode::Quirks;
+    }
+
+    if public_id_starts_with(&LIMITED_QUIRKS_PUBLIC_ID_PREFIXES)
+        || (system_id.is_some() && public_id_starts_with(&HTML4_PUBLIC_ID_PREFIXES))
+    {
+        return QuirksMode::LimitedQuirks;
+    }
+
+    QuirksMode::NoQuirks
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertionMode {
+    Initial,
+    BeforeHtml,
+    BeforeHead,
+    InHead,
+    AfterHead,
+    InBody,
+    Text,
+    InTable,
+    InTableText,
+    InCaption,
+    InColumnGroup,
+    InTableBody,
+    InRow,
+    InCell,
+    InSelect,
+    InSelectInTable,
+    InTemplate,
+    AfterBody,
+    InFrameset,
+    AfterFrameset,
+    AfterAfterBody,
+    AfterAfterFrameset,
+}
+
+/// ノードを挿入する位置。`parent` の子として、`before` の直前 (`None` の場合は最後) に挿入する
+#[derive(Debug, Clone)]
+struct InsertionLocation {
+    parent: NodeId,
+    before: Option<NodeId>,
+}
+
+/// スタック上の要素 (stack of open elements)。要素の種類ごとの数も数えておき、スタックにない種類の
+/// 要素を探すときにスタック全体をたどらずに済むようにする。深く入れ子になった文書では、開始タグの
+/// たびに <p> がボタンスコープ内にあるかを調べるので、毎回たどるとパースに入れ子の深さの 2 乗の
+/// 時間がかかる
+/// https://html.spec.whatwg.org/multipage/parsing.html#the-stack-of-open-elements
+#[derive(Debug, Clone, Default)]
+struct StackOfOpenElements {
+    nodes: Vec<NodeId>,
+    /// `nodes` の各要素の種類
+    kinds: Vec<Option<ElementKind>>,
+    counts: BTreeMap<ElementKind, usize>,
+}
+
+impl StackOfOpenElements {
+    fn push(&mut self, dom: &Dom, node: NodeId) {
+        self.insert(dom, self.nodes.len(), node);
+    }
+
+    fn insert(&mut self, dom: &Dom, index: usize, node: NodeId) {
+        let kind = dom.node(node).element_kind().cloned();
+        if let Some(ref kind) = kind {
+            *self.counts.entry(kind.clone()).or_default() += 1;
+        }
+        self.nodes.insert(index, node);
+        self.kinds.insert(index, kind);
+    }
+
+    /// `index` の位置の要素を `node` に置き換える
+    fn replace(&mut self, dom: &Dom, index: usize, node: NodeId) {
+        self.remove(index);
+        self.insert(dom, index, node);
+    }
+
+    fn pop(&mut self) -> Option<NodeId> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        Some(self.remove(self.nodes.len() - 1))
+    }
+
+    fn remove(&mut self, index: usize) -> NodeId {
+        if let Some(kind) = self.kinds.remove(index) {
+            if let Some(count) = self.counts.get_mut(&kind) {
+                *count -= 1;
+                if *count == 0 {
+                    self.counts.remove(&kind);
+                }
+            }
+        }
+        self.nodes.remove(index)
+    }
+
+    fn truncate(&mut self, len: usize) {
+        while self.nodes.len() > len {
+            self.pop();
+        }
+    }
+
+    /// `node` をスタックから取り除く。スタックにない場合は何もしない
+    fn remove_node(&mut self, node: NodeId) {
+        if let Some(index) = self.nodes.iter().position(|n| *n == node) {
+            self.remove(index);
+        }
+    }
+
+    /// `kind` の要素がスタックにあるかどうか
+    fn contains_kind(&self, kind: &ElementKind) -> bool {
+        self.counts.contains_key(kind)
+    }
+}
+
+impl Deref for StackOfOpenElements {
+    type Target = [NodeId];
+
+    fn deref(&self) -> &Self::Target {
+        &self.nodes
+    }
+}
+
+/// 書式要素のリストの項目
+/// https://html.spec.whatwg.org/multipage/parsing.html#list-of-active-formatting-elements
+#[derive(Debug, Clone)]
+enum ActiveFormattingElement {
+    /// <applet> などの開始時に挿入され、それより前の書式要素が再構築されないようにする
+    Marker,
+    Element(NodeId),
+}
+
+impl ActiveFormattingElement {
+    fn is(&self, node: &NodeId) -> bool {
+        match self {
+            ActiveFormattingElement::Marker => false,
+            ActiveFormattingElement::Element(n) => n == node,
+        }
+    }
+}
+
+/// 挿入モードの規則でトークンを処理した結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProcessResult {
+    /// 次のトークンに進む
+    Next,
+    /// 同じトークンを現在の挿入モードで再処理する
+    Reprocess,
+}
+
+/// 要素がスコープ内にあるかを判定する際に、探索を打ち切る要素の集合
+/// https://html.spec.whatwg.org/multipage/parsing.html#has-an-element-in-scope
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scope {
+    Default,
+    ListItem,
+    Button,
+    Table,
+    Select,
+}
+
+impl Scope {
+    fn is_boundary(&self, element: &Element) -> bool {
+        let kind = element.kind();
+        let is_default_boundary = match element.namespace() {
+            Namespace::Html => matches!(
+                kind,
+                ElementKind::Applet
+                    | ElementKind::Caption
+                    | ElementKind::Html
+                    | ElementKind::Table
+                    | ElementKind::Td
+                    | ElementKind::Th
+                    | ElementKind::Marquee
+                    | ElementKind::Object
+                    | ElementKind::Template
+            ),
+            Namespace::MathMl => matches!(
+                element.local_name(),
+                "mi" | "mo" | "mn" | "ms" | "mtext" | "annotation-xml"
+            ),
+            Namespace::Svg => matches!(element.local_name(), "foreignObject" | "desc" | "title"),
+            _ => false,
+        };
+        let is_html = element.namespace() == Namespace::Html;
+
+        match self {
+            Scope::Default => is_default_boundary,
+            Scope::ListItem => {
+                is_default_boundary
+                    || (is_html && matches!(kind, ElementKind::Ol | ElementKind::Ul))
+            }
+            Scope::Button => is_default_boundary || (is_html && *kind == ElementKind::Button),
+            Scope::Table => {
+                is_html
+                    && matches!(
+                        kind,
+                        ElementKind::Html | ElementKind::Table | ElementKind::Template
+                    )
+            }
+            // <optgroup> と <option> 以外のすべての要素が境界になる
+            Scope::Select => {
+                !(is_html && matches!(kind, ElementKind::Optgroup | ElementKind::Option))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{string::ToString, vec};
+
+    use super::*;
+    use crate::renderer::{dom::node::Document, html::parse_error::SourcePosition};
+
+    #[test]
+    fn test_empty() {
+        let html = "".to_string();
+        let t = HtmlTokenizer::new(html);
+        let window = HtmlParser::new(t).construct_tree();
+        let expected = NodeKind::Document(Document::new());
+
+        assert_eq!(expected, window.dom().node(window.document()).kind());
+    }
+
+    #[test]
+    fn test_body() {
+        let html = "<html><head></head><body></body></html>".to_string();
+        let t = HtmlTokenizer::new(html);
+        let window = HtmlParser::new(t).construct_tree();
+        let dom = window.dom();
+        let document = window.document();
+        assert_eq!(
+            NodeKind::Document(Document::new()),
+            dom.node(document).kind(),
+        );
+
+        let html = dom
+            .node(document)
+            .first_child()
+            .expect("failed to get a first child of document");
+        assert_eq!(
+            NodeKind::Element(Element::new("html", Vec::new())),
+            dom.node(html).kind(),
+        );
+
+        let head = dom
+            .node(html)
+            .first_child()
+            .expect("failed to get a first child of html");
+        assert_eq!(
+            NodeKind::Element(Element::new("head", Vec::new())),
+            dom.node(head).kind(),
+        );
+
+        let body = dom
+            .node(head)
+            .next_sibling()
+            .expect("failed to get a next sibling of head");
+        assert_eq!(
+            NodeKind::Element(Element::new("body", Vec::new())),
+            dom.node(body).kind(),
+        );
+    }
+
+    #[test]
+    fn test_text() {
+        let html = "<html><head></head><body>text</body></html>".to_string();
+        let t = HtmlTokenizer::new(html);
+        let window = HtmlParser::new(t).construct_tree();
+        let dom = window.dom();
+        let document = window.document();
+        assert_eq!(
+            NodeKind::Document(Document::new()),
+            dom.node(document).kind(),
+        );
+
+        let html = dom
+            .node(document)
+            .first_child()
+            .expect("failed to get a first child of document");
+        assert_eq!(
+            NodeKind::Element(Element::new("html", Vec::new())),
+            dom.node(html).kind(),
+        );
+
+        let head = dom
+            .node(html)
+            .first_child()
+            .expect("failed to get a first child of document");
+        let body = dom
+            .node(head)
+            .next_sibling()
+            .expect("failed to get a next sibling of head");
+        assert_eq!(
+            NodeKind::Element(Element::new("body", Vec::new())),
+            dom.node(body).kind(),
+        );
+
+        let text = dom
+            .node(body)
+            .first_child()
+            .expect("failed to get a first child of document");
+        assert_eq!(NodeKind::Text("text".to_string()), dom.node(text).kind());
+    }
+
+    #[test]
+    fn test_multiple_nodes() {
+        let html = "<html><head></head><body><p><a foo=bar>text</a></p></body></html>".to_string();
+        let t = HtmlTokenizer::new(html);
+        let window = HtmlParser::new(t).construct_tree();
+        let dom = window.dom();
+
+        let body = first_body(dom, window.document());
+        assert_eq!(
+            NodeKind::Element(Element::new("body", Vec::new())),
+            dom.node(body).kind(),
+        );
+
+        let p = dom
+            .node(body)
+            .first_child()
+            .expect("failed to get a first child of body");
+        assert_eq!(
+            NodeKind::Element(Element::new("p", Vec::new())),
+            dom.node(p).kind(),
+        );
+
+        let mut attr = Attribute::new();
+        attr.add_char('f', true);
+        attr.add_char('o', true);
+        attr.add_char('o', true);
+        attr.add_char('b', false);
+        attr.add_char('a', false);
+        attr.add_char('r', false);
+        let a = dom
+            .node(p)
+            .first_child()
+            .expect("failed to get a first child of p");
+        assert_eq!(
+            NodeKind::Element(Element::new("a", vec![attr])),
+            dom.node(a).kind(),
+        );
+
+        let text = dom
+            .node(a)
+            .first_child()
+            .expect("failed to get a first child of a");
+        assert_eq!(NodeKind::Text("text".to_string()), dom.node(text).kind());
+    }
+
+    #[test]
+    fn test_unknown_tags() {
+        let html =
+            "<html><head><meta charset=utf-8></head><body><div><span>text</span><my-element></my-element></div></p></body></html>"
+                .to_string();
+        let t = HtmlTokenizer::new(html);
+        let window = HtmlParser::new(t).construct_tree();
+        let dom = window.dom();
+
+        let body = first_body(dom, window.document());
+        assert_eq!(
+            NodeKind::Element(Element::new("body", Vec::new())),
+            dom.node(body).kind(),
+        );
+
+        let div = dom
+            .node(body)
+            .first_child()
+            .expect("failed to get a first child of body");
+        assert_eq!(
+            NodeKind::Element(Element::new("div", Vec::new())),
+            dom.node(div).kind(),
+        );
+
+        let span = dom
+            .node(div)
+            .first_child()
+            .expect("failed to get a first child of div");
+        assert_eq!(
+            NodeKind::Element(Element::new("span", Vec::new())),
+            dom.node(span).kind(),
+        );
 
-        self.window.clone()
+        let custom = dom
+            .node(span)
+            .next_sibling()
+            .expect("failed to get a next sibling of span");
+        assert_eq!(
+            Some(&ElementKind::Unknown("my-element".to_string())),
+            dom.node(custom).element_kind(),
+        );
     }
 
-    fn create_element(&self, tag: &str, attributes: Vec<Attribute>) -> Node {
-        Node::new(NodeKind::Element(Element::new(tag, attributes)))
+    /// 文書の最初の子 (<html>) の、2番目の子 (<body>) を返す
+    fn first_body(dom: &Dom, document: NodeId) -> NodeId {
+        let html = dom
+            .node(document)
+            .first_child()
+            .expect("failed to get a first child of document");
+        let head = dom
+            .node(html)
+            .first_child()
+            .expect("failed to get a frist child of document");
+        dom.node(head)
+            .next_sibling()
+            .expect("failed to get a next sibling of head")
     }
 
-    fn insert_element(&mut self, tag: &str, attributes: Vec<Attribute>) {
-        let window = self.window.borrow();
-        let current = match self.stack_of_open_elements.last() {
-            Some(n) => n.clone(),
-            None => window.document(),
-        };
+    #[test]
+    fn test_doctype_and_comments() {
+        let html =
+            "<!DOCTYPE html><!-- a --><html><body><!-- b --></body></html><!-- c -->".to_string();
+        let t = HtmlTokenizer::new(html);
+        let window = HtmlParser::new(t).construct_tree();
+        let dom = window.dom();
+        let document = window.document();
 
-        let node = Rc::new(RefCell::new(self.create_element(tag, attributes)));
+        let doctype = dom
+            .node(document)
+            .first_child()
+            .expect("failed to get a first child of document");
+        assert_eq!(
+            NodeKind::DocumentType(DocumentType::new(
+                "html".to_string(),
+                String::new(),
+                String::new()
+            )),
+            dom.node(doctype).kind(),
+        );
 
-        if current.borrow().first_child().is_some() {
-            let mut last_sibling = current.borrow().first_child().unwrap();
-            loop {
-                let Some(next) = last_sibling.borrow().next_sibling() else {
-                    break;
-                };
-                last_sibling = next;
-            }
+        let comment = dom
+            .node(doctype)
+            .next_sibling()
+            .expect("failed to get a next sibling of doctype");
+        assert_eq!(
+            NodeKind::Comment(" a ".to_string()),
+            dom.node(comment).kind(),
+        );
 
-            last_sibling
-                .borrow_mut()
-                .set_next_sibling(Some(node.clone()));
-            node.borrow_mut()
-                .set_previous_sibling(Rc::downgrade(&last_sibling));
-        } else {
-            current.borrow_mut().set_first_child(Some(node.clone()));
-        }
+        let html = dom
+            .node(comment)
+            .next_sibling()
+            .expect("failed to get a next sibling of comment");
+        assert_eq!(
+            NodeKind::Element(Element::new("html", Vec::new())),
+            dom.node(html).kind(),
+        );
 
-        current.borrow_mut().set_last_child(Rc::downgrade(&node));
-        node.borrow_mut().set_parent(Rc::downgrade(&current));
+        let body = dom
+            .node(html)
+            .last_child()
+            .expect("failed to get a last child of html");
+        let body_comment = dom
+            .node(body)
+            .first_child()
+            .expect("failed to get a first child of body");
+        assert_eq!(
+            NodeKind::Comment(" b ".to_string()),
+            dom.node(body_comment).kind(),
+        );
 
-        self.stack_of_open_elements.push(node);
+        let last = dom
+            .node(document)
+            .last_child()
+            .expect("failed to get a last child of document");
+        assert_eq!(NodeKind::Comment(" c ".to_string()), dom.node(last).kind());
     }
 
-    fn pop_current_node(&mut self, element_kind: ElementKind) -> bool {
-        let current = match self.stack_of_open_elements.last() {
-            Some(n) => n,
-            None => return false,
-        };
+    #[test]
+    fn test_quirks_mode() {
+        let cases = [
+            ("<!DOCTYPE html><p>", QuirksMode::NoQuirks),
+            ("<p>", QuirksMode::Quirks),
+            (
+                "<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01 Transitional//EN\">",
+                QuirksMode::Quirks,
+            ),
+            (
+                "<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01 Transitional//EN\" \"http://www.w3.org/TR/html4/loose.dtd\">",
+                QuirksMode::LimitedQuirks,
+            ),
+            (
+                "<!DOCTYPE html PUBLIC \"-//W3C//DTD XHTML 1.0 Strict//EN\" \"http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd\">",
+                QuirksMode::NoQuirks,
+            ),
+            ("<!DOCTYPE svg>", QuirksMode::Quirks),
+        ];
 
-        if current.borrow().element_kind() == Some(element_kind) {
-            self.stack_of_open_elements.pop();
-            true
-        } else {
-            false
+        for (html, expected) in cases {
+            let t = HtmlTokenizer::new(html.to_string());
+            let window = HtmlParser::new(t).construct_tree();
+            let document = window
+                .dom()
+                .node(window.document())
+                .get_document()
+                .expect("failed to get a document");
+            assert_eq!(expected, document.mode(), "{}", html);
         }
     }
 
-    fn contain_in_stack(&mut self, element_kind: ElementKind) -> bool {
-        self.stack_of_open_elements
+    #[test]
+    fn test_raw_text_elements() {
+        let html =
+            "<html><head><title>a<b>&amp;</title><style>p>a{}</style></head></html>".to_string();
+        let t = HtmlTokenizer::new(html);
+        let window = HtmlParser::new(t).construct_tree();
+        let dom = window.dom();
+
+        let html = dom
+            .node(window.document())
+            .first_child()
+            .expect("failed to get a first child of document");
+        let head = dom
+            .node(html)
+            .first_child()
+            .expect("failed to get a first child of html");
+        let title = dom
+            .node(head)
+            .first_child()
+            .expect("failed to get a first child of head");
+        assert_eq!(Some(&ElementKind::Title), dom.node(title).element_kind());
+
+        let text = dom
+            .node(title)
+            .first_child()
+            .expect("failed to get a first child of title");
+        assert!(matches!(dom.node(text).kind(), NodeKind::Text(ref s) if s == "a<b>&"));
+
+        let style = dom
+            .node(title)
+            .next_sibling()
+            .expect("failed to get a next sibling of title");
+        assert_eq!(Some(&ElementKind::Style), dom.node(style).element_kind());
+        let text = dom
+            .node(style)
+            .first_child()
+            .expect("failed to get a first child of style");
+        assert!(matches!(dom.node(text).kind(), NodeKind::Text(ref s) if s == "p>a{}"));
+    }
+
+    /// `node` の子ノードを順に集める
+    fn children(dom: &Dom, node: NodeId) -> Vec<NodeId> {
+        dom.children(node).collect()
+    }
+
+    /// `html` をパースし、文書と <body> 要素を返す
+    fn parse_body(html: &str) -> (Window, NodeId) {
+        let t = HtmlTokenizer::new(html.to_string());
+        let window = HtmlParser::new(t).construct_tree();
+        let dom = window.dom();
+        let html = dom
+            .node(window.document())
+            .first_child()
+            .expect("failed to get a first child of document");
+        let body = children(dom, html)
+            .into_iter()
+            .find(|n| dom.node(*n).element_kind() == Some(&ElementKind::Body))
+            .expect("failed to get a body");
+        (window, body)
+    }
+
+    fn kinds(dom: &Dom, node: NodeId) -> Vec<Option<ElementKind>> {
+        children(dom, node)
             .iter()
-            .any(|node| node.borrow().element_kind() == Some(element_kind))
+            .map(|n| dom.node(*n).element_kind().cloned())
+            .collect()
     }
 
-    fn pop_until(&mut self, element_kind: ElementKind) {
-        assert!(
-            self.contain_in_stack(element_kind),
-            "stack doesn't have an element {:?}",
-            element_kind
+    #[test]
+    fn test_implied_p_end_tag() {
+        let (window, body) = parse_body("<p>a<div>b</div><p>c<p>d");
+        let dom = window.dom();
+        assert_eq!(
+            vec![
+                Some(ElementKind::P),
+                Some(ElementKind::Div),
+                Some(ElementKind::P),
+                Some(ElementKind::P),
+            ],
+            kinds(dom, body),
         );
 
-        loop {
-            let current = match self.stack_of_open_elements.pop() {
-                Some(n) => n,
-                None => return,
-            };
+        let p = children(dom, body)[0];
+        let text = dom.node(p).first_child().expect("failed to get a text");
+        assert!(matches!(dom.node(text).kind(), NodeKind::Text(ref s) if s == "a"));
+    }
 
-            if current.borrow().element_kind() == Some(element_kind) {
-                return;
-            }
-        }
+    #[test]
+    fn test_stray_p_end_tag() {
+        let (window, body) = parse_body("a</p>b");
+        let dom = window.dom();
+        let nodes = children(dom, body);
+        assert_eq!(3, nodes.len());
+        assert!(matches!(dom.node(nodes[0]).kind(), NodeKind::Text(ref s) if s == "a"));
+        assert_eq!(Some(&ElementKind::P), dom.node(nodes[1]).element_kind());
+        assert!(dom.node(nodes[1]).first_child().is_none());
+        assert!(matches!(dom.node(nodes[2]).kind(), NodeKind::Text(ref s) if s == "b"));
     }
 
-    fn create_char(&self, c: char) -> Node {
-        let mut s = String::new();
-        s.push(c);
-        Node::new(NodeKind::Text(s))
+    #[test]
+    fn test_list_items() {
+        let (window, body) =
+            parse_body("<ul><li>a<li>b<ul><li>c</ul><li>d</ul><dl><dt>e<dd>f<dt>g</dl>");
+        let dom = window.dom();
+        let lists = children(dom, body);
+        assert_eq!(2, lists.len());
+
+        assert_eq!(vec![Some(ElementKind::Li); 3], kinds(dom, lists[0]));
+        let nested = children(dom, children(dom, lists[0])[1])[1];
+        assert_eq!(Some(&ElementKind::Ul), dom.node(nested).element_kind());
+        assert_eq!(vec![Some(ElementKind::Li)], kinds(dom, nested));
+
+        assert_eq!(
+            vec![
+                Some(ElementKind::Dt),
+                Some(ElementKind::Dd),
+                Some(ElementKind::Dt),
+            ],
+            kinds(dom, lists[1]),
+        );
     }
 
-    fn insert_char(&mut self, c: char) {
-        let current = match self.stack_of_open_elements.last() {
-            Some(n) => n,
-            None => return,
-        };
+    #[test]
+    fn test_nested_headings() {
+        let (window, body) = parse_body("<h1>a<h2>b</h1>c");
+        let dom = window.dom();
+        let nodes = children(dom, body);
+        assert_eq!(3, nodes.len());
+        assert_eq!(Some(&ElementKind::H1), dom.node(nodes[0]).element_kind());
+        assert_eq!(Some(&ElementKind::H2), dom.node(nodes[1]).element_kind());
+        assert!(matches!(dom.node(nodes[2]).kind(), NodeKind::Text(ref s) if s == "c"));
+    }
 
-        // 現在参照しているノードがテキストノードの場合、そのノードに文字を追加する
-        if let NodeKind::Text(ref mut s) = current.borrow_mut().kind {
-            s.push(c);
-            return;
-        }
+    #[test]
+    fn test_pre_leading_newline_and_void_elements() {
+        let (window, body) = parse_body("<pre>\na</pre><image><br></br><hr>");
+        let dom = window.dom();
+        let pre = children(dom, body)[0];
+        let text = dom.node(pre).first_child().expect("failed to get a text");
+        assert!(matches!(dom.node(text).kind(), NodeKind::Text(ref s) if s == "a"));
 
-        // 改行文字や空白文字の時はテキストノードを追加しない
-        if c == '\n' || c == ' ' {
-            return;
-        }
+        assert_eq!(
+            vec![
+                Some(ElementKind::Pre),
+                Some(ElementKind::Img),
+                Some(ElementKind::Br),
+                Some(ElementKind::Br),
+                Some(ElementKind::Hr),
+            ],
+            kinds(dom, body),
+        );
+    }
 
-        let node = Rc::new(RefCell::new(self.create_char(c)));
+    #[test]
+    fn test_unmatched_end_tags() {
+        let (window, body) = parse_body("<div><span>a</div></span></form></li>b");
+        let dom = window.dom();
+        let nodes = children(dom, body);
+        assert_eq!(2, nodes.len());
+        assert_eq!(Some(&ElementKind::Div), dom.node(nodes[0]).element_kind());
+        assert!(matches!(dom.node(nodes[1]).kind(), NodeKind::Text(ref s) if s == "b"));
+    }
 
-        let first_child = current.borrow().first_child();
-        if first_child.is_some() {
-            let first_child = first_child.unwrap();
-            first_child
-                .borrow_mut()
-                .set_next_sibling(Some(node.clone()));
-            node.borrow_mut()
-                .set_previous_sibling(Rc::downgrade(&first_child))
-        } else {
-            current.borrow_mut().set_first_child(Some(node.clone()));
+    fn text(dom: &Dom, node: NodeId) -> String {
+        match dom.node(node).kind() {
+            NodeKind::Text(s) => s,
+            _ => panic!("{:?} is not a text node", dom.node(node).kind()),
         }
+    }
+
+    #[test]
+    fn test_misnested_formatting_elements() {
+        // <b><i>x</i></b><i>y</i>
+        let (window, body) = parse_body("<b><i>x</b>y</i>");
+        let dom = window.dom();
+        assert_eq!(
+            vec![Some(ElementKind::B), Some(ElementKind::I)],
+            kinds(dom, body)
+        );
 
-        current.borrow_mut().set_last_child(Rc::downgrade(&node));
-        node.borrow_mut().set_parent(Rc::downgrade(current));
+        let b = children(dom, body)[0];
+        let i = children(dom, b)[0];
+        assert_eq!(Some(&ElementKind::I), dom.node(i).element_kind());
+        assert_eq!("x", text(dom, children(dom, i)[0]));
 
-        self.stack_of_open_elements.push(node);
+        let i = children(dom, body)[1];
+        assert_eq!("y", text(dom, children(dom, i)[0]));
     }
-}
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum InsertionMode {
-    Initial,
-    BeforeHtml,
-    BeforeHead,
-    InHead,
-    AfterHead,
-    InBody,
-    Text,
-    AfterBody,
-    AfterAfterBody,
-}
+    #[test]
+    fn test_adoption_agency_with_furthest_block() {
+        // <b>1</b><p><b>2</b>3</p>
+        let (window, body) = parse_body("<b>1<p>2</b>3</p>");
+        let dom = window.dom();
+        assert_eq!(
+            vec![Some(ElementKind::B), Some(ElementKind::P)],
+            kinds(dom, body)
+        );
 
-#[cfg(test)]
-mod tests {
-    use alloc::{string::ToString, vec};
+        let p = children(dom, body)[1];
+        let nodes = children(dom, p);
+        assert_eq!(2, nodes.len());
+        assert_eq!(Some(&ElementKind::B), dom.node(nodes[0]).element_kind());
+        assert_eq!("2", text(dom, children(dom, nodes[0])[0]));
+        assert_eq!("3", text(dom, nodes[1]));
+    }
 
-    use super::*;
+    #[test]
+    fn test_reconstruct_active_formatting_elements() {
+        // <p><b>a</b></p><p><b>b</b></p>
+        let (window, body) = parse_body("<p><b>a<p>b");
+        let dom = window.dom();
+        let ps = children(dom, body);
+        assert_eq!(2, ps.len());
+        for (p, expected) in ps.iter().zip(["a", "b"]) {
+            let b = children(dom, *p)[0];
+            assert_eq!(Some(&ElementKind::B), dom.node(b).element_kind());
+            assert_eq!(expected, text(dom, children(dom, b)[0]));
+        }
+    }
 
     #[test]
-    fn test_empty() {
-        let html = "".to_string();
-        let t = HtmlTokenizer::new(html);
-        let window = HtmlParser::new(t).construct_tree();
-        let expected = Rc::new(RefCell::new(Node::new(NodeKind::Document)));
+    fn test_noahs_ark_clause() {
+        // 同じ書式要素は 3 つまでしか再構築されない
+        let (window, body) = parse_body("<p><b><b><b><b>x<p>y");
+        let dom = window.dom();
+        let p = children(dom, body)[1];
+        let mut depth = 0;
+        let mut node = p;
+        loop {
+            let child = children(dom, node)[0];
+            if dom.node(child).element_kind() != Some(&ElementKind::B) {
+                assert_eq!("y", text(dom, child));
+                break;
+            }
+            depth += 1;
+            node = child;
+        }
+        assert_eq!(3, depth);
+    }
 
-        assert_eq!(expected, window.borrow().document());
+    #[test]
+    fn test_nested_anchor() {
+        // <a>1</a><a>2</a>
+        let (window, body) = parse_body("<a>1<a>2</a>");
+        let dom = window.dom();
+        assert_eq!(
+            vec![Some(ElementKind::A), Some(ElementKind::A)],
+            kinds(dom, body)
+        );
     }
 
     #[test]
-    fn test_body() {
-        let html = "<html><head></head><body></body></html>".to_string();
-        let t = HtmlTokenizer::new(html);
-        let window = HtmlParser::new(t).construct_tree();
-        let document = window.borrow().document();
+    fn test_table_implied_elements() {
+        let (window, body) = parse_body("<table><tr><td>a<td>b</table>");
+        let dom = window.dom();
+        let table = children(dom, body)[0];
+        assert_eq!(Some(&ElementKind::Table), dom.node(table).element_kind());
+        assert_eq!(vec![Some(ElementKind::Tbody)], kinds(dom, table));
+
+        let tbody = children(dom, table)[0];
+        assert_eq!(vec![Some(ElementKind::Tr)], kinds(dom, tbody));
+
+        let tr = children(dom, tbody)[0];
+        let cells = children(dom, tr);
         assert_eq!(
-            Rc::new(RefCell::new(Node::new(NodeKind::Document))),
-            document,
+            vec![Some(ElementKind::Td), Some(ElementKind::Td)],
+            kinds(dom, tr)
         );
+        assert_eq!("a", text(dom, children(dom, cells[0])[0]));
+        assert_eq!("b", text(dom, children(dom, cells[1])[0]));
+    }
 
-        let html = document
-            .borrow()
-            .first_child()
-            .expect("failed to get a first child of document");
+    #[test]
+    fn test_table_caption_and_column_group() {
+        let (window, body) = parse_body("<table><caption>c<col><thead><tr><th>h</table>");
+        let dom = window.dom();
+        let table = children(dom, body)[0];
         assert_eq!(
-            Rc::new(RefCell::new(Node::new(NodeKind::Element(Element::new(
-                "html",
-                Vec::new(),
-            ))))),
-            html,
+            vec![
+                Some(ElementKind::Caption),
+                Some(ElementKind::Colgroup),
+                Some(ElementKind::Thead),
+            ],
+            kinds(dom, table),
         );
+        let colgroup = children(dom, table)[1];
+        assert_eq!(vec![Some(ElementKind::Col)], kinds(dom, colgroup));
+    }
 
-        let head = html
-            .borrow()
-            .first_child()
-            .expect("failed to get a first child of html");
+    #[test]
+    fn test_foster_parenting() {
+        // テーブルの中に直接書かれた文字と要素は、テーブルの前に移される
+        let (window, body) = parse_body("<table>a<div>b</div><tr><td>c</td></tr></table>");
+        let dom = window.dom();
+        let nodes = children(dom, body);
+        assert_eq!(3, nodes.len());
+        assert_eq!("a", text(dom, nodes[0]));
+        assert_eq!(Some(&ElementKind::Div), dom.node(nodes[1]).element_kind());
+        assert_eq!("b", text(dom, children(dom, nodes[1])[0]));
+        assert_eq!(Some(&ElementKind::Table), dom.node(nodes[2]).element_kind());
+    }
+
+    #[test]
+    fn test_nested_tables() {
+        let (window, body) = parse_body("<table><td><table><td>a</table>b</table>");
+        let dom = window.dom();
+        let outer = children(dom, body)[0];
+        let td = children(dom, children(dom, children(dom, outer)[0])[0])[0];
+        assert_eq!(Some(&ElementKind::Td), dom.node(td).element_kind());
+
+        let nodes = children(dom, td);
+        assert_eq!(2, nodes.len());
+        assert_eq!(Some(&ElementKind::Table), dom.node(nodes[0]).element_kind());
+        assert_eq!("b", text(dom, nodes[1]));
+    }
+
+    #[test]
+    fn test_select() {
+        let (window, body) =
+            parse_body("<select><option>a<option>b<optgroup><option>c</select><p>d");
+        let dom = window.dom();
         assert_eq!(
-            Rc::new(RefCell::new(Node::new(NodeKind::Element(Element::new(
-                "head",
-                Vec::new(),
-            ))))),
-            head,
+            vec![Some(ElementKind::Select), Some(ElementKind::P)],
+            kinds(dom, body)
         );
 
-        let body = head
-            .borrow()
-            .next_sibling()
-            .expect("failed to get a next sibling of head");
+        let select = children(dom, body)[0];
         assert_eq!(
-            Rc::new(RefCell::new(Node::new(NodeKind::Element(Element::new(
-                "body",
-                Vec::new(),
-            ))))),
-            body,
+            vec![
+                Some(ElementKind::Option),
+                Some(ElementKind::Option),
+                Some(ElementKind::Optgroup),
+            ],
+            kinds(dom, select),
         );
+        let optgroup = children(dom, select)[2];
+        assert_eq!(vec![Some(ElementKind::Option)], kinds(dom, optgroup));
     }
 
     #[test]
-    fn test_text() {
-        let html = "<html><head></head><body>text</body></html>".to_string();
-        let t = HtmlTokenizer::new(html);
-        let window = HtmlParser::new(t).construct_tree();
-        let document = window.borrow().document();
+    fn test_select_in_table() {
+        let (window, body) = parse_body("<table><tr><td><select><option>a</td><td>b</table>");
+        let dom = window.dom();
+        let tr = children(dom, children(dom, children(dom, body)[0])[0])[0];
+        let cells = children(dom, tr);
+        assert_eq!(2, cells.len());
+        assert_eq!(vec![Some(ElementKind::Select)], kinds(dom, cells[0]));
+        assert_eq!("b", text(dom, children(dom, cells[1])[0]));
+    }
+
+    #[test]
+    fn test_template_contents() {
+        let (window, body) = parse_body("<body><template><tr><td>a</td></tr></template><p>b");
+        let dom = window.dom();
+        let template = children(dom, body)[0];
+        assert_eq!(
+            Some(&ElementKind::Template),
+            dom.node(template).element_kind()
+        );
+        // 内容は <template> 要素の子ではなく、DocumentFragment の子になる
+        assert!(dom.node(template).first_child().is_none());
+
+        let contents = dom
+            .template_contents(template)
+            .expect("failed to get template contents");
+        assert_eq!(NodeKind::DocumentFragment, dom.node(contents).kind());
+        assert_eq!(vec![Some(ElementKind::Tr)], kinds(dom, contents));
+
         assert_eq!(
-            Rc::new(RefCell::new(Node::new(NodeKind::Document))),
-            document,
+            Some(&ElementKind::P),
+            dom.node(children(dom, body)[1]).element_kind()
         );
+    }
 
-        let html = document
-            .borrow()
+    #[test]
+    fn test_frameset() {
+        let html = "<html><head></head><frameset><frame><frameset><frame></frameset></frameset><!--c--></html>".to_string();
+        let t = HtmlTokenizer::new(html);
+        let window = HtmlParser::new(t).construct_tree();
+        let dom = window.dom();
+        let html = dom
+            .node(window.document())
             .first_child()
             .expect("failed to get a first child of document");
         assert_eq!(
-            Rc::new(RefCell::new(Node::new(NodeKind::Element(Element::new(
-                "html",
-                Vec::new(),
-            ))))),
-            html,
+            vec![Some(ElementKind::Head), Some(ElementKind::Frameset), None],
+            kinds(dom, html),
         );
 
-        let body = html
-            .borrow()
-            .first_child()
-            .expect("failed to get a first child of document")
-            .borrow()
-            .next_sibling()
-            .expect("failed to get a next sibling of head");
+        let frameset = children(dom, html)[1];
         assert_eq!(
-            Rc::new(RefCell::new(Node::new(NodeKind::Element(Element::new(
-                "body",
-                Vec::new(),
-            ))))),
-            body,
+            vec![Some(ElementKind::Frame), Some(ElementKind::Frameset)],
+            kinds(dom, frameset),
         );
+    }
 
-        let text = body
-            .borrow()
-            .first_child()
-            .expect("failed to get a first child of document");
+    fn element(dom: &Dom, node: NodeId) -> Element {
+        dom.node(node)
+            .get_element()
+            .expect("failed to get an element")
+    }
+
+    #[test]
+    fn test_svg() {
+        let (window, body) = parse_body(
+            "<svg viewbox='0 0 1 1'><lineargradient/><use xlink:href='#a'/><foreignobject><p>a</p></foreignobject></svg><p>b",
+        );
+        let dom = window.dom();
+        let nodes = children(dom, body);
+        assert_eq!(2, nodes.len());
+
+        let svg = element(dom, nodes[0]);
+        assert_eq!(Namespace::Svg, svg.namespace());
+        assert_eq!("svg", svg.local_name());
+        assert_eq!("viewBox", svg.attributes()[0].name());
+
+        let svg_children = children(dom, nodes[0]);
+        assert_eq!(3, svg_children.len());
+        assert_eq!("linearGradient", element(dom, svg_children[0]).local_name());
+        assert!(dom.node(svg_children[0]).first_child().is_none());
+
+        let href = element(dom, svg_children[1]).attributes()[0].clone();
+        assert_eq!("href", href.name());
+        assert_eq!(Some("xlink".to_string()), href.prefix());
+        assert_eq!(Some(Namespace::XLink), href.namespace());
+
+        // <foreignObject> の中は HTML として扱われる
+        let foreign_object = element(dom, svg_children[2]);
+        assert_eq!("foreignObject", foreign_object.local_name());
+        let p = children(dom, svg_children[2])[0];
+        assert_eq!(Namespace::Html, element(dom, p).namespace());
+        assert_eq!(&ElementKind::P, element(dom, p).kind());
+
+        assert_eq!(Some(&ElementKind::P), dom.node(nodes[1]).element_kind());
+    }
+
+    #[test]
+    fn test_break_out_of_foreign_content() {
+        let (window, body) = parse_body("<svg><g><p>a</p></g></svg>");
+        let dom = window.dom();
+        let nodes = children(dom, body);
+        assert_eq!(2, nodes.len());
+        assert_eq!(Namespace::Svg, element(dom, nodes[0]).namespace());
+        assert_eq!(Some(&ElementKind::P), dom.node(nodes[1]).element_kind());
+    }
+
+    #[test]
+    fn test_mathml_and_cdata() {
+        let (window, body) = parse_body("<math><mi>x<b>y</b></mi><ms><![CDATA[<z>]]></ms></math>");
+        let dom = window.dom();
+        let math = children(dom, body)[0];
+        assert_eq!(Namespace::MathMl, element(dom, math).namespace());
+
+        let nodes = children(dom, math);
+        assert_eq!(2, nodes.len());
+
+        // <mi> の中の要素は HTML の要素になる
+        let mi_children = children(dom, nodes[0]);
+        assert_eq!("x", text(dom, mi_children[0]));
+        assert_eq!(Namespace::Html, element(dom, mi_children[1]).namespace());
+
+        assert_eq!("<z>", text(dom, children(dom, nodes[1])[0]));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let t = HtmlTokenizer::new("<p>a</div>\n<b><i>b</b></i>".to_string());
+        let mut parser = HtmlParser::new(t);
+        parser.construct_tree();
+        let errors: Vec<_> = parser
+            .errors()
+            .iter()
+            .map(|e| (e.kind().as_str(), e.position()))
+            .collect();
         assert_eq!(
-            Rc::new(RefCell::new(Node::new(NodeKind::Text("text".to_string())))),
-            text,
+            vec![
+                ("missing-doctype", SourcePosition::new(1, 1)),
+                (
+                    "end-tag-without-matching-open-element",
+                    SourcePosition::new(1, 5)
+                ),
+                ("misnested-formatting-element", SourcePosition::new(2, 8)),
+                (
+                    "end-tag-without-matching-open-element",
+                    SourcePosition::new(2, 12)
+                ),
+            ],
+            errors
         );
     }
 
     #[test]
-    fn test_multiple_nodes() {
-        let html = "<html><head></head><body><p><a foo=bar>text</a></p></body></html>".to_string();
-        let t = HtmlTokenizer::new(html);
+    fn test_merge_html_and_body_attributes() {
+        let t = HtmlTokenizer::new(
+            "<html lang=ja><body class=a><html lang=en dir=ltr><body class=b id=c>".to_string(),
+        );
         let window = HtmlParser::new(t).construct_tree();
-        let document = window.borrow().document();
+        let dom = window.dom();
+        let html = children(dom, window.document())[0];
+        let body = children(dom, html)[1];
 
-        let body = document
-            .borrow()
-            .first_child()
-            .expect("failed to get a first child of document")
-            .borrow()
-            .first_child()
-            .expect("failed to get a frist child of document")
-            .borrow()
-            .next_sibling()
-            .expect("failed to get a next sibling of head");
+        // すでにある属性は上書きせず、ない属性だけを追加する
+        let attributes = |node: NodeId| -> Vec<(String, String)> {
+            dom.node(node)
+                .get_element()
+                .unwrap()
+                .attributes()
+                .iter()
+                .map(|a| (a.name(), a.value()))
+                .collect()
+        };
         assert_eq!(
-            Rc::new(RefCell::new(Node::new(NodeKind::Element(Element::new(
-                "body",
-                Vec::new()
-            ))))),
-            body,
+            vec![
+                ("lang".to_string(), "ja".to_string()),
+                ("dir".to_string(), "ltr".to_string()),
+            ],
+            attributes(html)
         );
-
-        let p = body
-            .borrow()
-            .first_child()
-            .expect("failed to get a first child of body");
         assert_eq!(
-            Rc::new(RefCell::new(Node::new(NodeKind::Element(Element::new(
-                "p",
-                Vec::new()
-            ))))),
-            p,
+            vec![
+                ("class".to_string(), "a".to_string()),
+                ("id".to_string(), "c".to_string()),
+            ],
+            attributes(body)
         );
+    }
 
-        let mut attr = Attribute::new();
-        attr.add_char('f', true);
-        attr.add_char('o', true);
-        attr.add_char('o', true);
-        attr.add_char('b', false);
-        attr.add_char('a', false);
-        attr.add_char('r', false);
-        let a = p
-            .borrow()
-            .first_child()
-            .expect("failed to get a first child of p");
+    #[test]
+    fn test_node_position() {
+        let (window, body) = parse_body("<body>\n<p>a\n<b>b</b>");
+        let dom = window.dom();
+        assert_eq!(Some(SourcePosition::new(1, 1)), dom.node(body).position());
+        // 空白文字だけのテキストノードも残る
+        let newline = children(dom, body)[0];
+        assert_eq!("\n", text(dom, newline));
+        assert_eq!(
+            Some(SourcePosition::new(1, 7)),
+            dom.node(newline).position()
+        );
+        let p = children(dom, body)[1];
+        assert_eq!(Some(SourcePosition::new(2, 1)), dom.node(p).position());
+        let nodes = children(dom, p);
         assert_eq!(
-            Rc::new(RefCell::new(Node::new(NodeKind::Element(Element::new(
-                "a",
-                vec![attr]
-            ))))),
-            a,
+            Some(SourcePosition::new(2, 4)),
+            dom.node(nodes[0]).position()
         );
+        assert_eq!(
+            Some(SourcePosition::new(3, 1)),
+            dom.node(nodes[1]).position()
+        );
+    }
 
-        let text = a
-            .borrow()
-            .first_child()
-            .expect("failed to get a first child of a");
+    #[test]
+    fn test_incremental_parsing() {
+        let mut parser = HtmlParser::new(HtmlTokenizer::new_streaming());
+        parser.feed("<ul><li>a<li");
+        let dom = parser.window().dom();
+        let html = children(dom, parser.window().document())[0];
+        let body = children(dom, html)[1];
+        let ul = children(dom, body)[0];
+        // 完成したトークンまでは DOM ツリーに反映されている
+        assert_eq!(vec![Some(ElementKind::Li)], kinds(dom, ul));
+
+        parser.feed(">b</ul>");
+        let window = parser.finish();
+        let dom = window.dom();
         assert_eq!(
-            Rc::new(RefCell::new(Node::new(NodeKind::Text("text".to_string())))),
-            text,
+            vec![Some(ElementKind::Li), Some(ElementKind::Li)],
+            kinds(dom, ul)
+        );
+        assert_eq!("b", text(dom, children(dom, children(dom, ul)[1])[0]));
+    }
+
+    #[test]
+    fn test_parse_fragment() {
+        let mut window = Window::new();
+        let dom = window.dom_mut();
+        let mut element =
+            |name: &str| dom.create_node(NodeKind::Element(Element::new(name, Vec::new())));
+        let td = element("td");
+        let textarea = element("textarea");
+
+        // <td> の中では、<tr> などの表の構造を作らずにそのまま要素が入る
+        let nodes = HtmlParser::parse_fragment(dom, td, "<div>a</div>b");
+        assert_eq!(2, nodes.len());
+        assert_eq!(Some(&ElementKind::Div), dom.node(nodes[0]).element_kind());
+        assert!(dom.node(nodes[0]).parent().is_none());
+        assert_eq!("a", text(dom, children(dom, nodes[0])[0]));
+        assert_eq!("b", text(dom, nodes[1]));
+
+        // <textarea> の中身はタグもテキストとして扱う
+        let nodes = HtmlParser::parse_fragment(dom, textarea, "<b>x</b>");
+        assert_eq!(1, nodes.len());
+        assert_eq!("<b>x</b>", text(dom, nodes[0]));
+
+        // 深い断片でも、この文書に取り込むときにスタックがあふれない
+        let depth = 100_000;
+        let nodes = HtmlParser::parse_fragment(dom, td, &"<span>".repeat(depth));
+        assert_eq!(1, nodes.len());
+        let deepest = dom
+            .descendants(nodes[0])
+            .last()
+            .expect("failed to get a span");
+        assert_eq!(depth - 1, dom.ancestors(deepest).count());
+    }
+
+    #[test]
+    fn test_deep_nesting() {
+        extern crate std;
+
+        // 開始タグのたびにスタック全体をたどると、この深さでは数十秒かかる
+        let depth = 30000;
+        let start = std::time::Instant::now();
+        let (window, body) = parse_body(&"<div>".repeat(depth));
+        let elapsed = start.elapsed();
+        assert!(
+            elapsed < core::time::Duration::from_secs(5),
+            "parsing {} nested elements took {:?}",
+            depth,
+            elapsed
         );
+
+        let dom = window.dom();
+        let deepest = dom.descendants(body).last().expect("failed to get a div");
+        assert_eq!(depth, dom.ancestors(deepest).count() - 2);
     }
 }
@@ -0,0 +1,281 @@
+//! DOM ツリーを HTML の文字列に戻す
+//! https://html.spec.whatwg.org/multipage/parsing.html#serialising-html-fragments
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use crate::renderer::{
+    dom::node::{Dom, Element, ElementKind, Namespace, NodeId, NodeKind},
+    html::attribute::Attribute,
+};
+
+/// `node` の子孫を HTML として出力する。innerHTML の取得に相当する
+/// https://html.spec.whatwg.org/multipage/parsing.html#html-fragment-serialisation-algorithm
+pub fn serialize_children(dom: &Dom, node: NodeId) -> String {
+    let mut serializer = Serializer::new(dom);
+    serializer.push_children(node);
+    serializer.run()
+}
+
+/// `node` 自身を含めて HTML として出力する。outerHTML の取得に相当する。Document の場合は文書全体を
+/// 出力する
+pub fn serialize(dom: &Dom, node: NodeId) -> String {
+    let mut serializer = Serializer::new(dom);
+    // 親がないものとして扱うので、テキストは常にエスケープする
+    serializer.stack.push(Step::Node(node, false));
+    serializer.run()
+}
+
+/// これから出力するもの。深い木でもスタックが溢れないように、再帰せずに `Serializer::stack` に
+/// 積んで順に処理する
+enum Step<'a> {
+    /// ノードを出力する。真の場合、テキストをエスケープせずにそのまま出力する
+    Node(NodeId, bool),
+    /// 子をすべて出力した後に、この名前の終了タグを出力する
+    EndTag(&'a str),
+}
+
+struct Serializer<'a> {
+    dom: &'a Dom,
+    stack: Vec<Step<'a>>,
+    result: String,
+}
+
+impl<'a> Serializer<'a> {
+    fn new(dom: &'a Dom) -> Self {
+        Self {
+            dom,
+            stack: Vec::new(),
+            result: String::new(),
+        }
+    }
+
+    fn run(mut self) -> String {
+        while let Some(step) = self.stack.pop() {
+            match step {
+                Step::Node(node, raw_text) => self.push_node(node, raw_text),
+                Step::EndTag(name) => {
+                    self.result.push_str("</");
+                    self.result.push_str(name);
+                    self.result.push('>');
+                }
+            }
+        }
+        self.result
+    }
+
+    /// `node` の子を、先頭の子から出力されるようにスタックに積む
+    fn push_children(&mut self, node: NodeId) {
+        let dom = self.dom;
+        let (parent, raw_text) = match dom.node(node).kind {
+            NodeKind::Element(ref element) => {
+                if serializes_as_void(element) {
+                    return;
+                }
+                // <template> 要素の子は、要素自身ではなく内容の DocumentFragment にある
+                match element.template_contents() {
+                    Some(contents) => (contents, false),
+                    None => (node, is_raw_text_parent(element)),
+                }
+            }
+            _ => (node, false),
+        };
+
+        let start = self.stack.len();
+        self.stack.extend(
+            dom.children(parent)
+                .map(|child| Step::Node(child, raw_text)),
+        );
+        self.stack[start..].reverse();
+    }
+
+    fn push_node(&mut self, node: NodeId, raw_text: bool) {
+        let dom = self.dom;
+        let result = &mut self.result;
+        match dom.node(node).kind {
+            NodeKind::Element(ref element) => {
+                let name = element.local_name();
+                result.push('<');
+                result.push_str(name);
+                for attribute in element.attributes() {
+                    result.push(' ');
+                    result.push_str(&attribute_name(&attribute));
+                    result.push_str("=\"");
+                    escape(&attribute.value(), true, result);
+                    result.push('"');
+                }
+                result.push('>');
+
+                if serializes_as_void(element) {
+                    return;
+                }
+                self.stack.push(Step::EndTag(name));
+                self.push_children(node);
+            }
+            NodeKind::Text(ref text) => {
+                if raw_text {
+                    result.push_str(text);
+                } else {
+                    escape(text, false, result);
+                }
+            }
+            NodeKind::Comment(ref comment) => {
+                result.push_str("<!--");
+                result.push_str(comment);
+                result.push_str("-->");
+            }
+            NodeKind::DocumentType(ref doctype) => {
+                result.push_str("<!DOCTYPE ");
+                result.push_str(&doctype.name());
+                result.push('>');
+            }
+            NodeKind::Document(_) | NodeKind::DocumentFragment => self.push_children(node),
+        }
+    }
+}
+
+/// 終了タグや子を出力しない要素かどうか
+fn serializes_as_void(element: &Element) -> bool {
+    element.namespace() == Namespace::Html
+        && matches!(
+            element.kind(),
+            ElementKind::Area
+                | ElementKind::Base
+                | ElementKind::Basefont
+                | ElementKind::Bgsound
+                | ElementKind::Br
+                | ElementKind::Col
+                | ElementKind::Embed
+                | ElementKind::Frame
+                | ElementKind::Hr
+                | ElementKind::Img
+                | ElementKind::Input
+                | ElementKind::Keygen
+                | ElementKind::Link
+                | ElementKind::Meta
+                | ElementKind::Param
+                | ElementKind::Source
+                | ElementKind::Track
+                | ElementKind::Wbr
+        )
+}
+
+/// 子のテキストをエスケープせずに出力する要素かどうか。パーサはスクリプトが有効なものとして
+/// <noscript> の内容をテキストとして読むので、ここでもそれに合わせる
+fn is_raw_text_parent(element: &Element) -> bool {
+    element.namespace() == Namespace::Html
+        && matches!(
+            element.kind(),
+            ElementKind::Style
+                | ElementKind::Script
+                | ElementKind::Xmp
+                | ElementKind::Iframe
+                | ElementKind::Noembed
+                | ElementKind::Noframes
+                | ElementKind::Plaintext
+                | ElementKind::Noscript
+        )
+}
+
+/// 属性の名前を、名前空間に応じた接頭辞を付けて返す
+fn attribute_name(attribute: &Attribute) -> String {
+    let name = attribute.name();
+    match attribute.namespace() {
+        None => name,
+        Some(Namespace::Xml) => "xml:".to_string() + &name,
+        Some(Namespace::Xmlns) if name == "xmlns" => name,
+        Some(Namespace::Xmlns) => "xmlns:".to_string() + &name,
+        Some(Namespace::XLink) => "xlink:".to_string() + &name,
+        Some(_) => match attribute.prefix() {
+            Some(prefix) => prefix + ":" + &name,
+            None => name,
+        },
+    }
+}
+
+/// 文字列をエスケープして `result` に追加する。属性値の場合は `"` もエスケープする
+/// https://html.spec.whatwg.org/multipage/parsing.html#escapingString
+fn escape(s: &str, attribute_mode: bool, result: &mut String) {
+    for c in s.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '\u{a0}' => result.push_str("&nbsp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' if attribute_mode => result.push_str("&quot;"),
+            _ => result.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::{
+        dom::node::Window,
+        html::{parser::HtmlParser, token::HtmlTokenizer},
+    };
+
+    fn parse(html: &str) -> Window {
+        HtmlParser::new(HtmlTokenizer::new(html.to_string())).construct_tree()
+    }
+
+    fn serialize_document(html: &str) -> String {
+        let window = parse(html);
+        serialize(window.dom(), window.document())
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let html = "<!DOCTYPE html><html><head><title>a &amp; b</title></head><body>\
+                    <p class=\"x\" id=\"y\">text<br><img src=\"a.png\"></p><!-- comment --></body></html>";
+        assert_eq!(html, serialize_document(html));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(
+            "<html><head></head><body><p title=\"&quot;&lt;&amp;&nbsp;\">&lt;a&gt; &amp; &nbsp;</p></body></html>",
+            serialize_document("<p title='&quot;&lt;&amp;&nbsp;'>&lt;a&gt; &amp; &nbsp;</p>")
+        );
+    }
+
+    #[test]
+    fn test_raw_text() {
+        let html = "<html><head><style>a > b { }</style><script>if (a < b && c) {}</script></head>\
+                    <body><textarea>&lt;b&gt;</textarea></body></html>";
+        assert_eq!(html, serialize_document(html));
+    }
+
+    #[test]
+    fn test_template_and_foreign_elements() {
+        let html = "<html><head><template><p>a</p></template></head><body>\
+                    <svg viewBox=\"0 0 1 1\"><foreignObject><a href=\"#x\"></a></foreignObject>\
+                    <use xlink:href=\"#x\"></use></svg><math><mi>x</mi></math></body></html>";
+        assert_eq!(html, serialize_document(html));
+    }
+
+    #[test]
+    fn test_serialize_children() {
+        let window = parse("<div><p>a</p>b</div>");
+        let dom = window.dom();
+        let html = dom.node(window.document()).first_child().unwrap();
+        let body = dom.node(html).last_child().unwrap();
+        let div = dom.node(body).first_child().unwrap();
+        assert_eq!("<p>a</p>b", serialize_children(dom, div));
+        assert_eq!("<div><p>a</p>b</div>", serialize(dom, div));
+    }
+
+    #[test]
+    fn test_deep_nesting() {
+        let depth = 30000;
+        let html = "<div>".repeat(depth);
+        let window = parse(&html);
+        let dom = window.dom();
+        let body = dom.get_elements_by_tag_name("body")[0];
+        let expected = "<div>".repeat(depth) + &"</div>".repeat(depth);
+        assert_eq!(expected, serialize_children(dom, body));
+    }
+}
@@ -1,40 +1,292 @@
-use alloc::{string::String, vec::Vec};
+use core::cell::Cell;
 
-use crate::renderer::html::attribute::Attribute;
+use alloc::{
+    collections::{BTreeSet, VecDeque},
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use crate::renderer::html::{
+    attribute::Attribute,
+    character_reference,
+    parse_error::{ParseError, ParseErrorKind, SourcePosition},
+};
+
+/// タグの属性がこの数以上になったら、属性名の重複を集合で調べる
+const ATTRIBUTE_NAME_SET_THRESHOLD: usize = 16;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct HtmlTokenizer {
     state: State,
+    /// 文字参照の処理が終わった後に戻るステート
+    return_state: State,
     pos: usize,
     reconsume: bool,
     latest_token: Option<HtmlToken>,
-    input: Vec<char>,
+    /// 読み込み中の属性が同じタグの既存の属性と名前が重複していて、取り除いたかどうか。
+    /// 真の間は、属性値を読み込んでも捨てる
+    ignore_attribute: bool,
+    /// 読み込み中のタグの属性名。属性が多いタグで重複を探すときに、すべての属性と比べずに済むように
+    /// する。属性が `ATTRIBUTE_NAME_SET_THRESHOLD` 個以上になってから作る
+    attribute_names: BTreeSet<String>,
+    /// 改行文字を正規化した入力。`pos` などの位置はこの文字列のバイト単位の位置で表す。
+    /// `HtmlToken::Text` はこの文字列の範囲を指すので、読み終えた部分も取り除かない
+    input: String,
     buf: String,
+    /// 数値文字参照で読み込んだコードポイント
+    character_reference_code: u32,
+    /// 1回の状態遷移で複数のトークンを出力したときに、まだ返していないトークン
+    pending_tokens: VecDeque<HtmlToken>,
+    /// 最後に出力した開始タグの名前
+    last_start_tag_name: Option<String>,
+    /// パーサの調整された現在のノードが HTML 名前空間以外の要素かどうか。CDATA セクションは
+    /// SVG や MathML の中でのみ認識される
+    in_foreign_content: bool,
+    /// 各行の先頭の文字の位置。文字の位置から行と列を求めるために使う
+    line_starts: Vec<usize>,
+    /// トークンの開始位置を求めるためのカーソル。開始位置は前にしか進まないので、前回の位置から
+    /// 文字数を数え足すだけで行と列を求められる
+    token_cursor: Cell<(usize, SourcePosition)>,
+    /// パースの失敗と現在の位置を求めるためのカーソル
+    error_cursor: Cell<(usize, SourcePosition)>,
+    /// 次に出力するトークンの最初の文字の位置
+    token_start: usize,
+    /// 最後に出力したトークンの開始位置
+    token_position: SourcePosition,
+    /// 検出したパースの失敗
+    errors: Vec<ParseError>,
+    /// 直前に追加した入力の最後の文字が CR だったかどうか。CRLF が 2つのチャンクに分かれた
+    /// 場合に、LF を取り除くために使う
+    last_char_was_cr: bool,
+    /// 入力がすべて追加されたかどうか。false の間は、入力の終わりに達しても続きを待つ
+    finished: bool,
 }
 
 impl HtmlTokenizer {
+    /// 入力全体を受け取ってトークナイザを作る
     pub fn new(html: String) -> Self {
+        let mut t = Self::new_streaming();
+        if html.contains('\r') {
+            t.feed(&html);
+        } else {
+            // 正規化する必要がなければ、受け取った文字列をそのまま入力として使う
+            t.push_input_lines(0, &html);
+            t.input = html;
+        }
+        t.finish();
+        t
+    }
+
+    /// 入力を少しずつ受け取るトークナイザを作る。入力は `feed` で追加し、最後に `finish` を呼ぶ
+    pub fn new_streaming() -> Self {
         Self {
             state: State::Data,
+            return_state: State::Data,
             pos: 0,
             reconsume: false,
             latest_token: None,
-            input: html.chars().collect(),
+            ignore_attribute: false,
+            attribute_names: BTreeSet::new(),
+            input: String::new(),
             buf: String::new(),
+            character_reference_code: 0,
+            pending_tokens: VecDeque::new(),
+            last_start_tag_name: None,
+            in_foreign_content: false,
+            line_starts: Vec::from([0]),
+            token_cursor: Cell::new((0, SourcePosition::default())),
+            error_cursor: Cell::new((0, SourcePosition::default())),
+            token_start: 0,
+            token_position: SourcePosition::default(),
+            errors: Vec::new(),
+            last_char_was_cr: false,
+            finished: false,
+        }
+    }
+
+    /// 入力の続きを追加する。追加した入力から作れるトークンは、次の `next` で返す
+    pub fn feed(&mut self, chunk: &str) {
+        assert!(!self.finished, "cannot feed input after finish");
+
+        self.input.reserve(chunk.len());
+        // 改行文字を正規化する (CRLF と CR を LF に置き換える)
+        // https://html.spec.whatwg.org/multipage/parsing.html#preprocessing-the-input-stream
+        for (i, line) in chunk.split('\r').enumerate() {
+            let follows_cr = if i == 0 { self.last_char_was_cr } else { true };
+            if i > 0 {
+                self.push_input("\n");
+            }
+            let line = match line.strip_prefix('\n') {
+                Some(rest) if follows_cr => rest,
+                _ => line,
+            };
+            self.push_input(line);
         }
+        if !chunk.is_empty() {
+            self.last_char_was_cr = chunk.ends_with('\r');
+        }
+    }
+
+    /// 正規化済みの文字列を入力の末尾に追加する
+    fn push_input(&mut self, s: &str) {
+        self.push_input_lines(self.input.len(), s);
+        self.input.push_str(s);
+    }
+
+    /// 入力の `offset` バイト目から `s` が続くものとして、各行の先頭の位置を記録する
+    fn push_input_lines(&mut self, offset: usize, s: &str) {
+        self.line_starts.extend(
+            s.bytes()
+                .enumerate()
+                .filter(|(_, b)| *b == b'\n')
+                .map(|(i, _)| offset + i + 1),
+        );
+    }
+
+    /// `HtmlToken::Text` が指す文字列を返す
+    pub fn text(&self, span: TextSpan) -> &str {
+        &self.input[span.start..span.end]
+    }
+
+    /// 入力がこれ以上ないことを伝える。これ以降、入力の終わりはファイルの終了として扱う
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    /// `finish` が呼ばれたかどうかを返す。`next` が None を返したとき、true であれば入力の終わりに
+    /// 達しており、false であれば入力の続きを待っている
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// 最後に出力したトークンの開始位置を返す
+    pub fn token_position(&self) -> SourcePosition {
+        self.token_position
+    }
+
+    /// 現在読み込んでいる位置を返す
+    pub fn current_position(&self) -> SourcePosition {
+        self.position_at(&self.error_cursor, self.pos)
+    }
+
+    /// これまでに検出したパースの失敗を取り出す
+    pub fn take_errors(&mut self) -> Vec<ParseError> {
+        core::mem::take(&mut self.errors)
+    }
+
+    /// トークナイザのステートを切り替える。パーサが <title> や <style> などの要素を挿入したときに、
+    /// その内容を RCDATA や RAWTEXT として読み込ませるために使う
+    pub fn set_state(&mut self, state: State) {
+        self.state = state;
+    }
+
+    /// 最後に出力した開始タグの名前を設定する。RCDATA などのステートから読み始める場合に、
+    /// 適切な終了タグを判定するために使う
+    pub fn set_last_start_tag(&mut self, tag: &str) {
+        self.last_start_tag_name = Some(tag.to_string());
+    }
+
+    /// パーサの調整された現在のノードが SVG や MathML の要素かどうかを設定する
+    pub fn set_in_foreign_content(&mut self, in_foreign_content: bool) {
+        self.in_foreign_content = in_foreign_content;
     }
 
+    /// 次の文字を読み込む。入力の終わりに達している場合は '\0' を返し、`is_eof` が true になる
     fn consume_next_input(&mut self) -> char {
-        let c = self.input[self.pos];
-        self.pos += 1;
-        c
+        match self.input.get(self.pos..).and_then(|s| s.chars().next()) {
+            Some(c) => {
+                self.pos += c.len_utf8();
+                c
+            }
+            None => {
+                self.pos = self.input.len() + 1;
+                '\0'
+            }
+        }
+    }
+
+    /// 最後に読み込んだ文字 (現在の文字) の位置を返す。入力の終わりに達している場合は入力の長さを返す
+    fn current_char_start(&self) -> usize {
+        match self
+            .input
+            .get(..self.pos)
+            .and_then(|s| s.chars().next_back())
+        {
+            Some(c) => self.pos - c.len_utf8(),
+            None => self.input.len().min(self.pos),
+        }
+    }
+
+    /// Data などのステートで、特別な意味を持たない文字の並びをまとめて読み込む。ひとつずつ状態遷移を
+    /// 行う代わりに、入力の範囲として文字トークンを出力する
+    fn consume_text_run(&mut self) -> Option<TextSpan> {
+        let is_special: fn(&u8) -> bool = match self.state {
+            State::Data | State::RcData => |b| matches!(b, b'<' | b'&' | b'\0'),
+            State::RawText | State::ScriptData => |b| matches!(b, b'<' | b'\0'),
+            State::Plaintext => |b| *b == b'\0',
+            State::CdataSection => |b| *b == b']',
+            _ => return None,
+        };
+
+        let rest = self.input.as_bytes().get(self.pos..)?;
+        // 特別な文字はどれも ASCII なので、その直前は必ず文字の境界になる
+        let len = rest.iter().position(is_special).unwrap_or(rest.len());
+        if len == 0 {
+            return None;
+        }
+
+        let start = self.pos;
+        self.pos += len;
+        Some(TextSpan::new(start, self.pos))
     }
 
     fn is_eof(&self) -> bool {
         self.pos > self.input.len()
     }
 
+    /// `start` の位置から `len` 文字を先読みしたいが、まだ入力が追加されていない場合に true を返す
+    fn needs_more_input(&self, start: usize, len: usize) -> bool {
+        !self.finished && self.input.len() < start + len
+    }
+
+    /// 入力の `index` バイト目の文字の行と列を返し、`cursor` をその位置へ動かす。数える文字数は
+    /// `cursor` から `index` までの距離に比例するので、前に進むだけであれば入力全体で線形に収まる
+    fn position_at(&self, cursor: &Cell<(usize, SourcePosition)>, index: usize) -> SourcePosition {
+        let index = index.min(self.input.len());
+        let (cursor_index, cursor_position) = cursor.get();
+        let line = if index >= cursor_index {
+            // カーソルより後ろの行の先頭だけを探す
+            let after = &self.line_starts[cursor_position.line..];
+            cursor_position.line + after.partition_point(|&start| start <= index)
+        } else {
+            self.line_starts[..cursor_position.line].partition_point(|&start| start <= index)
+        };
+        let line_start = self.line_starts[line - 1];
+
+        let position = if line != cursor_position.line {
+            let count = self.input[line_start..index].chars().count();
+            SourcePosition::new(line, count + 1)
+        } else if index >= cursor_index {
+            let count = self.input[cursor_index..index].chars().count();
+            SourcePosition::new(line, cursor_position.column + count)
+        } else {
+            // 同じ行で戻る場合 (先読みのやり直しなど) も、戻った距離だけを数える
+            let count = self.input[index..cursor_index].chars().count();
+            SourcePosition::new(line, cursor_position.column - count)
+        };
+        cursor.set((index, position));
+        position
+    }
+
+    /// 現在の文字でパースの失敗を検出したことを記録する
+    fn parse_error(&mut self, kind: ParseErrorKind) {
+        let position = self.position_at(&self.error_cursor, self.current_char_start());
+        self.errors.push(ParseError::new(kind, position));
+    }
+
     fn create_tag(&mut self, start_tag_token: bool) {
+        self.ignore_attribute = false;
+        self.attribute_names.clear();
         if start_tag_token {
             self.latest_token = Some(HtmlToken::StartTag {
                 tag: String::new(),
@@ -48,7 +300,10 @@ impl HtmlTokenizer {
 
     fn reconsume_input(&mut self) -> char {
         self.reconsume = false;
-        self.input[self.pos - 1]
+        self.input
+            .get(..self.pos)
+            .and_then(|s| s.chars().next_back())
+            .unwrap_or('\0')
     }
 
     fn append_tag_name(&mut self, c: char) {
@@ -63,6 +318,110 @@ impl HtmlTokenizer {
             }
         }
     }
+    fn create_comment(&mut self) {
+        self.latest_token = Some(HtmlToken::Comment(String::new()));
+    }
+
+    fn append_comment(&mut self, c: char) {
+        assert!(self.latest_token.is_some());
+
+        if let Some(t) = self.latest_token.as_mut() {
+            match t {
+                HtmlToken::Comment(ref mut data) => data.push(c),
+                _ => panic!("`latest_token` should be Comment"),
+            }
+        }
+    }
+
+    fn create_doctype(&mut self) {
+        self.latest_token = Some(HtmlToken::Doctype {
+            name: None,
+            public_id: None,
+            system_id: None,
+            force_quirks: false,
+        });
+    }
+
+    fn append_doctype_name(&mut self, c: char) {
+        assert!(self.latest_token.is_some());
+
+        if let Some(t) = self.latest_token.as_mut() {
+            match t {
+                HtmlToken::Doctype { ref mut name, .. } => {
+                    name.get_or_insert_with(String::new).push(c)
+                }
+                _ => panic!("`latest_token` should be Doctype"),
+            }
+        }
+    }
+
+    /// DOCTYPE の公開識別子 (`is_public` が true のとき) またはシステム識別子を空文字列にする
+    fn start_doctype_identifier(&mut self, is_public: bool) {
+        assert!(self.latest_token.is_some());
+
+        if let Some(t) = self.latest_token.as_mut() {
+            match t {
+                HtmlToken::Doctype {
+                    ref mut public_id,
+                    ref mut system_id,
+                    ..
+                } => {
+                    if is_public {
+                        *public_id = Some(String::new());
+                    } else {
+                        *system_id = Some(String::new());
+                    }
+                }
+                _ => panic!("`latest_token` should be Doctype"),
+            }
+        }
+    }
+
+    fn append_doctype_identifier(&mut self, c: char, is_public: bool) {
+        assert!(self.latest_token.is_some());
+
+        if let Some(t) = self.latest_token.as_mut() {
+            match t {
+                HtmlToken::Doctype {
+                    ref mut public_id,
+                    ref mut system_id,
+                    ..
+                } => {
+                    let id = if is_public { public_id } else { system_id };
+                    id.get_or_insert_with(String::new).push(c);
+                }
+                _ => panic!("`latest_token` should be Doctype"),
+            }
+        }
+    }
+
+    fn set_force_quirks_flag(&mut self) {
+        assert!(self.latest_token.is_some());
+
+        if let Some(t) = self.latest_token.as_mut() {
+            match t {
+                HtmlToken::Doctype {
+                    ref mut force_quirks,
+                    ..
+                } => *force_quirks = true,
+                _ => panic!("`latest_token` should be Doctype"),
+            }
+        }
+    }
+
+    /// `start` の位置から入力が `s` と一致するかどうかを返す。`s` は ASCII 文字のみを含む
+    fn input_matches(&self, start: usize, s: &str, case_insensitive: bool) -> bool {
+        let Some(input) = self.input.as_bytes().get(start..start + s.len()) else {
+            return false;
+        };
+
+        if case_insensitive {
+            input.eq_ignore_ascii_case(s.as_bytes())
+        } else {
+            input == s.as_bytes()
+        }
+    }
+
     fn take_latest_token(&mut self) -> Option<HtmlToken> {
         assert!(self.latest_token.is_some());
 
@@ -70,24 +429,51 @@ impl HtmlTokenizer {
         core::mem::swap(&mut self.latest_token, &mut t);
         assert!(self.latest_token.is_none());
 
+        if let Some(HtmlToken::StartTag { ref tag, .. }) = t {
+            self.last_start_tag_name = Some(tag.clone());
+        }
+
         t
     }
 
+    /// 現在の終了タグが、最後に出力した開始タグに対応する終了タグ (appropriate end tag) かどうかを返す
+    /// https://html.spec.whatwg.org/multipage/parsing.html#appropriate-end-tag-token
+    fn is_appropriate_end_tag_token(&self) -> bool {
+        match (&self.latest_token, &self.last_start_tag_name) {
+            (Some(HtmlToken::EndTag { tag }), Some(last)) => tag == last,
+            _ => false,
+        }
+    }
+
+    fn is_end_tag(&self) -> bool {
+        matches!(self.latest_token, Some(HtmlToken::EndTag { .. }))
+    }
+
     fn start_new_attribute(&mut self) {
         assert!(self.latest_token.is_some());
+        self.ignore_attribute = false;
+
+        if self.is_end_tag() {
+            // 終了タグの属性は無視する
+            self.parse_error(ParseErrorKind::EndTagWithAttributes);
+            return;
+        }
 
         if let Some(t) = self.latest_token.as_mut() {
             match t {
                 HtmlToken::StartTag {
                     ref mut attributes, ..
                 } => attributes.push(Attribute::new()),
-                _ => panic!("`latest_token` should be either StartTag"),
+                _ => panic!("`latest_token` should be either StartTag or EndTag"),
             }
         }
     }
 
     fn append_attribute(&mut self, c: char, is_name: bool) {
         assert!(self.latest_token.is_some());
+        if self.ignore_attribute {
+            return;
+        }
 
         if let Some(t) = self.latest_token.as_mut() {
             match t {
@@ -99,34 +485,224 @@ impl HtmlTokenizer {
 
                     attributes[len - 1].add_char(c, is_name);
                 }
-                _ => panic!("`latest_token` should be either StartTag"),
+                // 終了タグの属性は無視する
+                HtmlToken::EndTag { .. } => {}
+                _ => panic!("`latest_token` should be either StartTag or EndTag"),
+            }
+        }
+    }
+
+    /// 属性名を読み終えたときに呼ぶ。同じ名前の属性がすでにある場合は、後の属性を取り除く
+    /// https://html.spec.whatwg.org/multipage/parsing.html#attribute-name-state
+    fn check_duplicate_attribute(&mut self) {
+        let duplicate = match self.latest_token {
+            Some(HtmlToken::StartTag {
+                ref mut attributes, ..
+            }) => {
+                let Some((last, rest)) = attributes.split_last() else {
+                    return;
+                };
+                let name = last.name_str();
+                let duplicate = if rest.len() < ATTRIBUTE_NAME_SET_THRESHOLD {
+                    rest.iter().any(|a| a.name_str() == name)
+                } else {
+                    if self.attribute_names.is_empty() {
+                        self.attribute_names
+                            .extend(rest.iter().map(|a| a.name_str().to_string()));
+                    }
+                    !self.attribute_names.insert(name.to_string())
+                };
+                if duplicate {
+                    attributes.pop();
+                }
+                duplicate
             }
+            // 終了タグの属性はもともと捨てている
+            _ => false,
+        };
+
+        if duplicate {
+            self.parse_error(ParseErrorKind::DuplicateAttribute);
+            self.ignore_attribute = true;
         }
     }
 
     fn set_self_closing_flag(&mut self) {
         assert!(self.latest_token.is_some());
 
+        if self.is_end_tag() {
+            // 終了タグの自己終了フラグは無視する
+            self.parse_error(ParseErrorKind::EndTagWithTrailingSolidus);
+            return;
+        }
+
         if let Some(t) = self.latest_token.as_mut() {
             match t {
                 HtmlToken::StartTag {
                     ref mut self_closing,
                     ..
                 } => *self_closing = true,
-                _ => panic!("`latest_token` should be either StartTag"),
+                _ => panic!("`latest_token` should be either StartTag or EndTag"),
+            }
+        }
+    }
+
+    fn is_return_state_attribute_value(&self) -> bool {
+        matches!(
+            self.return_state,
+            State::AttributeValueDoubleQuoted
+                | State::AttributeValueSingleQuoted
+                | State::AttributeValueUnquoted
+        )
+    }
+
+    /// 文字参照として読み込んだ文字 (一時的なバッファの中身) を、戻り先のステートに応じて
+    /// 属性値に追加するか文字トークンとして出力する
+    fn flush_code_points_consumed_as_character_reference(&mut self) {
+        let buf = core::mem::take(&mut self.buf);
+        if self.is_return_state_attribute_value() {
+            for c in buf.chars() {
+                self.append_attribute(c, false);
+            }
+        } else {
+            for c in buf.chars() {
+                self.pending_tokens.push_back(HtmlToken::Char(c));
+            }
+        }
+    }
+
+    /// 現在の位置から、名前付き文字参照の表に含まれる最も長い名前を探す
+    fn match_named_character_reference(&self, start: usize) -> Option<(usize, &'static str)> {
+        let mut name = String::new();
+        for b in self.input[start..]
+            .bytes()
+            .take(character_reference::LONGEST_NAME_LENGTH)
+        {
+            if b.is_ascii_alphanumeric() {
+                name.push(b as char);
+                continue;
+            }
+            if b == b';' {
+                name.push(';');
             }
+            break;
         }
+
+        (1..=name.len())
+            .rev()
+            .find_map(|len| character_reference::lookup(&name[..len]).map(|chars| (len, chars)))
+    }
+
+    /// 数値文字参照の終わりの処理 (numeric character reference end state)
+    /// https://html.spec.whatwg.org/multipage/parsing.html#numeric-character-reference-end-state
+    fn end_numeric_character_reference(&mut self) {
+        let code = match self.character_reference_code {
+            0x00 => {
+                self.parse_error(ParseErrorKind::NullCharacterReference);
+                0xFFFD
+            }
+            0x110000.. => {
+                self.parse_error(ParseErrorKind::CharacterReferenceOutsideUnicodeRange);
+                0xFFFD
+            }
+            0xD800..=0xDFFF => {
+                self.parse_error(ParseErrorKind::SurrogateCharacterReference);
+                0xFFFD
+            }
+            code if is_noncharacter(code) => {
+                self.parse_error(ParseErrorKind::NoncharacterCharacterReference);
+                code
+            }
+            // C1 制御文字は windows-1252 として解釈する
+            code @ 0x80..=0x9F => {
+                self.parse_error(ParseErrorKind::ControlCharacterReference);
+                match C1_REPLACEMENTS[(code - 0x80) as usize] {
+                    0 => code,
+                    replacement => replacement,
+                }
+            }
+            // 空白文字以外の制御文字 (CR を含む)
+            code @ (0x01..=0x08 | 0x0B | 0x0D..=0x1F | 0x7F) => {
+                self.parse_error(ParseErrorKind::ControlCharacterReference);
+                code
+            }
+            code => code,
+        };
+
+        self.buf = String::new();
+        self.buf
+            .push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+        self.flush_code_points_consumed_as_character_reference();
+        self.state = self.return_state.clone();
     }
 }
 
+/// Unicode の非文字 (noncharacter) かどうかを返す
+/// https://infra.spec.whatwg.org/#noncharacter
+fn is_noncharacter(code: u32) -> bool {
+    matches!(code, 0xFDD0..=0xFDEF) || (code & 0xFFFE == 0xFFFE && code <= 0x10FFFF)
+}
+
+/// HTML における空白文字 (タブ、改行、フォームフィード、スペース) かどうかを返す
+fn is_whitespace(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\x0C' | ' ')
+}
+
+/// 数値文字参照で 0x80 から 0x9F を指定したときに置き換える文字。0 は置き換えないことを表す
+/// https://html.spec.whatwg.org/multipage/parsing.html#numeric-character-reference-end-state
+const C1_REPLACEMENTS: [u32; 32] = [
+    0x20AC, 0, 0x201A, 0x0192, 0x201E, 0x2026, 0x2020, 0x2021, 0x02C6, 0x2030, 0x0160, 0x2039,
+    0x0152, 0, 0x017D, 0, 0, 0x2018, 0x2019, 0x201C, 0x201D, 0x2022, 0x2013, 0x2014, 0x02DC,
+    0x2122, 0x0161, 0x203A, 0x0153, 0, 0x017E, 0x0178,
+];
+
 impl Iterator for HtmlTokenizer {
     type Item = HtmlToken;
     fn next(&mut self) -> Option<Self::Item> {
-        if self.pos >= self.input.len() {
+        let token = self.next_token();
+        if token.is_none() && !self.finished {
+            // 入力の続きを待っている
             return None;
         }
 
+        self.token_position = self.position_at(&self.token_cursor, self.token_start);
+        // 1回の状態遷移で出力したトークンは、同じ位置から始まるものとして扱う
+        if self.pending_tokens.is_empty() {
+            self.token_start = if self.reconsume {
+                self.current_char_start()
+            } else {
+                self.pos
+            };
+        }
+
+        token
+    }
+}
+
+impl HtmlTokenizer {
+    /// 状態遷移を繰り返し、次のトークンを返す
+    /// https://html.spec.whatwg.org/multipage/parsing.html#tokenization
+    fn next_token(&mut self) -> Option<HtmlToken> {
         loop {
+            if let Some(t) = self.pending_tokens.pop_front() {
+                return Some(t);
+            }
+
+            if self.is_eof() && !self.reconsume {
+                return None;
+            }
+
+            if !self.reconsume && !self.finished && self.pos >= self.input.len() {
+                // 入力の続きが追加されるまで待つ
+                return None;
+            }
+
+            if !self.reconsume {
+                if let Some(span) = self.consume_text_run() {
+                    return Some(HtmlToken::Text(span));
+                }
+            }
+
             let c = if self.reconsume {
                 self.reconsume_input()
             } else {
@@ -135,16 +711,25 @@ impl Iterator for HtmlTokenizer {
 
             match self.state {
                 State::Data => {
+                    if self.is_eof() {
+                        return None;
+                    }
+                    if c == '&' {
+                        self.return_state = State::Data;
+                        self.state = State::CharacterReference;
+                        continue;
+                    }
                     if c == '<' {
                         self.state = State::TagOpen;
                         continue;
                     }
-                    if self.is_eof() {
-                        return Some(HtmlToken::Eof);
-                    }
                     return Some(HtmlToken::Char(c));
                 }
                 State::TagOpen => {
+                    if c == '!' {
+                        self.state = State::MarkupDeclarationOpen;
+                        continue;
+                    }
                     if c == '/' {
                         self.state = State::EndTagOpen;
                         continue;
@@ -155,15 +740,27 @@ impl Iterator for HtmlTokenizer {
                         self.create_tag(true);
                         continue;
                     }
+                    if c == '?' {
+                        self.parse_error(ParseErrorKind::UnexpectedQuestionMarkInsteadOfTagName);
+                        self.create_comment();
+                        self.reconsume = true;
+                        self.state = State::BogusComment;
+                        continue;
+                    }
                     if self.is_eof() {
-                        return Some(HtmlToken::Eof);
+                        self.parse_error(ParseErrorKind::EofBeforeTagName);
+                        return Some(HtmlToken::Char('<'));
                     }
+                    self.parse_error(ParseErrorKind::InvalidFirstCharacterOfTagName);
                     self.reconsume = true;
                     self.state = State::Data;
+                    return Some(HtmlToken::Char('<'));
                 }
                 State::EndTagOpen => {
                     if self.is_eof() {
-                        return Some(HtmlToken::Eof);
+                        self.parse_error(ParseErrorKind::EofBeforeTagName);
+                        self.pending_tokens.push_back(HtmlToken::Char('/'));
+                        return Some(HtmlToken::Char('<'));
                     }
 
                     if c.is_ascii_alphabetic() {
@@ -172,9 +769,20 @@ impl Iterator for HtmlTokenizer {
                         self.create_tag(false);
                         continue;
                     }
+
+                    if c == '>' {
+                        self.parse_error(ParseErrorKind::MissingEndTagName);
+                        self.state = State::Data;
+                        continue;
+                    }
+
+                    self.parse_error(ParseErrorKind::InvalidFirstCharacterOfTagName);
+                    self.create_comment();
+                    self.reconsume = true;
+                    self.state = State::BogusComment;
                 }
                 State::TagName => {
-                    if c == ' ' {
+                    if is_whitespace(c) {
                         self.state = State::BeforeAttributeName;
                         continue;
                     }
@@ -189,50 +797,73 @@ impl Iterator for HtmlTokenizer {
                         return self.take_latest_token();
                     }
 
-                    if c.is_ascii_uppercase() {
-                        self.append_tag_name(c.to_ascii_lowercase());
-                        continue;
+                    if self.is_eof() {
+                        // タグは出力しない
+                        self.parse_error(ParseErrorKind::EofInTag);
+                        return None;
                     }
 
-                    if self.is_eof() {
-                        return Some(HtmlToken::Eof);
+                    if c == '\0' {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.append_tag_name('\u{FFFD}');
+                        continue;
                     }
 
-                    self.append_tag_name(c);
+                    self.append_tag_name(c.to_ascii_lowercase());
                 }
                 State::BeforeAttributeName => {
+                    if is_whitespace(c) {
+                        // 空白文字は無視
+                        continue;
+                    }
+
                     if c == '/' || c == '>' || self.is_eof() {
                         self.reconsume = true;
                         self.state = State::AfterAttributeName;
                         continue;
                     }
 
+                    if c == '=' {
+                        self.parse_error(ParseErrorKind::UnexpectedEqualsSignBeforeAttributeName);
+                        self.start_new_attribute();
+                        self.append_attribute(c, true);
+                        self.state = State::AttributeName;
+                        continue;
+                    }
+
                     self.reconsume = true;
                     self.state = State::AttributeName;
 
                     self.start_new_attribute();
                 }
                 State::AttributeName => {
-                    if c == ' ' || c == '/' || c == '>' || self.is_eof() {
+                    if is_whitespace(c) || c == '/' || c == '>' || self.is_eof() {
+                        self.check_duplicate_attribute();
                         self.reconsume = true;
                         self.state = State::AfterAttributeName;
                         continue;
                     }
 
                     if c == '=' {
+                        self.check_duplicate_attribute();
                         self.state = State::BeforeAttributeValue;
                         continue;
                     }
 
-                    if c.is_ascii_uppercase() {
-                        self.append_attribute(c, true);
+                    if c == '\0' {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.append_attribute('\u{FFFD}', true);
                         continue;
                     }
 
-                    self.append_attribute(c, true);
+                    if c == '"' || c == '\'' || c == '<' {
+                        // そのまま属性名に追加する
+                        self.parse_error(ParseErrorKind::UnexpectedCharacterInAttributeName);
+                    }
+                    self.append_attribute(c.to_ascii_lowercase(), true);
                 }
                 State::AfterAttributeName => {
-                    if c == ' ' {
+                    if is_whitespace(c) {
                         // 空白文字は無視
                         continue;
                     }
@@ -252,7 +883,9 @@ impl Iterator for HtmlTokenizer {
                     }
 
                     if self.is_eof() {
-                        return Some(HtmlToken::Eof);
+                        // タグは出力しない
+                        self.parse_error(ParseErrorKind::EofInTag);
+                        return None;
                     }
 
                     self.reconsume = true;
@@ -260,7 +893,7 @@ impl Iterator for HtmlTokenizer {
                     self.start_new_attribute();
                 }
                 State::BeforeAttributeValue => {
-                    if c == ' ' {
+                    if is_whitespace(c) {
                         // 空白文字は無視
                         continue;
                     }
@@ -275,52 +908,85 @@ impl Iterator for HtmlTokenizer {
                         continue;
                     }
 
+                    if c == '>' {
+                        self.parse_error(ParseErrorKind::MissingAttributeValue);
+                        self.state = State::Data;
+                        return self.take_latest_token();
+                    }
+
                     self.reconsume = true;
                     self.state = State::AttributeValueUnquoted;
                 }
-                State::AttributeValueDoubleQuoted => {
-                    if c == '"' {
+                State::AttributeValueDoubleQuoted | State::AttributeValueSingleQuoted => {
+                    let quote = if self.state == State::AttributeValueDoubleQuoted {
+                        '"'
+                    } else {
+                        '\''
+                    };
+                    if c == quote {
                         self.state = State::AfterAttributeValueQuoted;
                         continue;
                     }
 
-                    if self.is_eof() {
-                        return Some(HtmlToken::Eof);
-                    }
-
-                    self.append_attribute(c, false);
-                }
-                State::AttributeValueSingleQuoted => {
-                    if c == '\'' {
-                        self.state = State::AfterAttributeValueQuoted;
+                    if c == '&' {
+                        self.return_state = self.state.clone();
+                        self.state = State::CharacterReference;
                         continue;
                     }
 
                     if self.is_eof() {
-                        return Some(HtmlToken::Eof);
+                        // タグは出力しない
+                        self.parse_error(ParseErrorKind::EofInTag);
+                        return None;
+                    }
+
+                    if c == '\0' {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.append_attribute('\u{FFFD}', false);
+                        continue;
                     }
 
                     self.append_attribute(c, false);
                 }
                 State::AttributeValueUnquoted => {
-                    if c == ' ' {
+                    if is_whitespace(c) {
                         self.state = State::BeforeAttributeName;
                         continue;
                     }
 
+                    if c == '&' {
+                        self.return_state = State::AttributeValueUnquoted;
+                        self.state = State::CharacterReference;
+                        continue;
+                    }
+
                     if c == '>' {
                         self.state = State::Data;
                         return self.take_latest_token();
                     }
 
                     if self.is_eof() {
-                        return Some(HtmlToken::Eof);
+                        // タグは出力しない
+                        self.parse_error(ParseErrorKind::EofInTag);
+                        return None;
+                    }
+
+                    if c == '\0' {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.append_attribute('\u{FFFD}', false);
+                        continue;
                     }
 
+                    if matches!(c, '"' | '\'' | '<' | '=' | '`') {
+                        // そのまま属性値に追加する
+                        self.parse_error(
+                            ParseErrorKind::UnexpectedCharacterInUnquotedAttributeValue,
+                        );
+                    }
                     self.append_attribute(c, false);
                 }
                 State::AfterAttributeValueQuoted => {
-                    if c == ' ' {
+                    if is_whitespace(c) {
                         self.state = State::BeforeAttributeName;
                         continue;
                     }
@@ -335,11 +1001,14 @@ impl Iterator for HtmlTokenizer {
                     }
 
                     if self.is_eof() {
-                        return Some(HtmlToken::Eof);
+                        // タグは出力しない
+                        self.parse_error(ParseErrorKind::EofInTag);
+                        return None;
                     }
 
+                    self.parse_error(ParseErrorKind::MissingWhitespaceBetweenAttributes);
                     self.reconsume = true;
-                    self.state = State::BeforeAttributeValue;
+                    self.state = State::BeforeAttributeName;
                 }
                 State::SelfClosingStartTag => {
                     if c == '>' {
@@ -349,53 +1018,860 @@ impl Iterator for HtmlTokenizer {
                     }
 
                     if self.is_eof() {
-                        // invalid parse error.
-                        return Some(HtmlToken::Eof);
+                        // タグは出力しない
+                        self.parse_error(ParseErrorKind::EofInTag);
+                        return None;
                     }
+
+                    self.parse_error(ParseErrorKind::UnexpectedSolidusInTag);
+                    self.reconsume = true;
+                    self.state = State::BeforeAttributeName;
                 }
-                State::ScriptData => {
-                    if c == '<' {
-                        self.state = State::ScriptDataLessThanSign;
+                State::MarkupDeclarationOpen => {
+                    // 現在の文字から始まる文字列を調べる
+                    let start = self.current_char_start();
+                    if self.needs_more_input(start, "[CDATA[".len()) {
+                        // 現在の文字は入力が追加されてから読み直す
+                        self.reconsume = true;
+                        return None;
+                    }
+
+                    if self.input_matches(start, "--", false) {
+                        self.pos = start + 2;
+                        self.create_comment();
+                        self.state = State::CommentStart;
                         continue;
                     }
 
-                    if self.is_eof() {
-                        return Some(HtmlToken::Eof);
+                    if self.input_matches(start, "DOCTYPE", true) {
+                        self.pos = start + 7;
+                        self.state = State::Doctype;
+                        continue;
                     }
 
-                    return Some(HtmlToken::Char(c));
-                }
-                State::ScriptDataLessThanSign => {
-                    if c == '/' {
-                        // 一時的なバッファを空文字でリセットする
-                        self.buf = String::new();
-                        self.state = State::ScriptDataEndTagOpen;
+                    if self.input_matches(start, "[CDATA[", false) {
+                        self.pos = start + 7;
+                        if self.in_foreign_content {
+                            self.state = State::CdataSection;
+                            continue;
+                        }
+
+                        // コメントとして扱う
+                        self.parse_error(ParseErrorKind::CdataInHtmlContent);
+                        self.create_comment();
+                        for c in "[CDATA[".chars() {
+                            self.append_comment(c);
+                        }
+                        self.state = State::BogusComment;
                         continue;
                     }
 
-                    self.reconsume = true;
-                    self.state = State::ScriptData;
-                    return Some(HtmlToken::Char('<'));
+                    // 現在の文字は次に読み直す
+                    self.parse_error(ParseErrorKind::IncorrectlyOpenedComment);
+                    self.pos = start;
+                    self.create_comment();
+                    self.state = State::BogusComment;
                 }
-                State::ScriptDataEndTagOpen => {
+                State::CommentStart => {
+                    if c == '-' {
+                        self.state = State::CommentStartDash;
+                        continue;
+                    }
+
+                    if c == '>' {
+                        self.parse_error(ParseErrorKind::AbruptClosingOfEmptyComment);
+                        self.state = State::Data;
+                        return self.take_latest_token();
+                    }
+
+                    self.reconsume = true;
+                    self.state = State::Comment;
+                }
+                State::CommentStartDash => {
+                    if c == '-' {
+                        self.state = State::CommentEnd;
+                        continue;
+                    }
+
+                    if c == '>' {
+                        self.parse_error(ParseErrorKind::AbruptClosingOfEmptyComment);
+                        self.state = State::Data;
+                        return self.take_latest_token();
+                    }
+
+                    if self.is_eof() {
+                        self.parse_error(ParseErrorKind::EofInComment);
+                        return self.take_latest_token();
+                    }
+
+                    self.append_comment('-');
+                    self.reconsume = true;
+                    self.state = State::Comment;
+                }
+                State::Comment => {
+                    if self.is_eof() {
+                        self.parse_error(ParseErrorKind::EofInComment);
+                        return self.take_latest_token();
+                    }
+
+                    if c == '<' {
+                        self.append_comment(c);
+                        self.state = State::CommentLessThanSign;
+                        continue;
+                    }
+
+                    if c == '-' {
+                        self.state = State::CommentEndDash;
+                        continue;
+                    }
+
+                    if c == '\0' {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.append_comment(char::REPLACEMENT_CHARACTER);
+                        continue;
+                    }
+
+                    self.append_comment(c);
+                }
+                State::CommentLessThanSign => {
+                    if c == '!' {
+                        self.append_comment(c);
+                        self.state = State::CommentLessThanSignBang;
+                        continue;
+                    }
+
+                    if c == '<' {
+                        self.append_comment(c);
+                        continue;
+                    }
+
+                    self.reconsume = true;
+                    self.state = State::Comment;
+                }
+                State::CommentLessThanSignBang => {
+                    if c == '-' {
+                        self.state = State::CommentLessThanSignBangDash;
+                        continue;
+                    }
+
+                    self.reconsume = true;
+                    self.state = State::Comment;
+                }
+                State::CommentLessThanSignBangDash => {
+                    if c == '-' {
+                        self.state = State::CommentLessThanSignBangDashDash;
+                        continue;
+                    }
+
+                    self.reconsume = true;
+                    self.state = State::CommentEndDash;
+                }
+                State::CommentLessThanSignBangDashDash => {
+                    if c != '>' && !self.is_eof() {
+                        self.parse_error(ParseErrorKind::NestedComment);
+                    }
+                    self.reconsume = true;
+                    self.state = State::CommentEnd;
+                }
+                State::CommentEndDash => {
+                    if c == '-' {
+                        self.state = State::CommentEnd;
+                        continue;
+                    }
+
+                    if self.is_eof() {
+                        self.parse_error(ParseErrorKind::EofInComment);
+                        return self.take_latest_token();
+                    }
+
+                    self.append_comment('-');
+                    self.reconsume = true;
+                    self.state = State::Comment;
+                }
+                State::CommentEnd => {
+                    if c == '>' {
+                        self.state = State::Data;
+                        return self.take_latest_token();
+                    }
+
+                    if c == '!' {
+                        self.state = State::CommentEndBang;
+                        continue;
+                    }
+
+                    if c == '-' {
+                        self.append_comment('-');
+                        continue;
+                    }
+
+                    if self.is_eof() {
+                        self.parse_error(ParseErrorKind::EofInComment);
+                        return self.take_latest_token();
+                    }
+
+                    self.append_comment('-');
+                    self.append_comment('-');
+                    self.reconsume = true;
+                    self.state = State::Comment;
+                }
+                State::CommentEndBang => {
+                    if c == '-' {
+                        self.append_comment('-');
+                        self.append_comment('-');
+                        self.append_comment('!');
+                        self.state = State::CommentEndDash;
+                        continue;
+                    }
+
+                    if c == '>' {
+                        self.parse_error(ParseErrorKind::IncorrectlyClosedComment);
+                        self.state = State::Data;
+                        return self.take_latest_token();
+                    }
+
+                    if self.is_eof() {
+                        self.parse_error(ParseErrorKind::EofInComment);
+                        return self.take_latest_token();
+                    }
+
+                    self.append_comment('-');
+                    self.append_comment('-');
+                    self.append_comment('!');
+                    self.reconsume = true;
+                    self.state = State::Comment;
+                }
+                State::BogusComment => {
+                    if c == '>' {
+                        self.state = State::Data;
+                        return self.take_latest_token();
+                    }
+
+                    if self.is_eof() {
+                        return self.take_latest_token();
+                    }
+
+                    if c == '\0' {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.append_comment(char::REPLACEMENT_CHARACTER);
+                        continue;
+                    }
+
+                    self.append_comment(c);
+                }
+                State::Doctype => {
+                    if is_whitespace(c) {
+                        self.state = State::BeforeDoctypeName;
+                        continue;
+                    }
+
+                    if c == '>' {
+                        self.reconsume = true;
+                        self.state = State::BeforeDoctypeName;
+                        continue;
+                    }
+
+                    if self.is_eof() {
+                        self.parse_error(ParseErrorKind::EofInDoctype);
+                        self.create_doctype();
+                        self.set_force_quirks_flag();
+                        return self.take_latest_token();
+                    }
+
+                    self.parse_error(ParseErrorKind::MissingWhitespaceBeforeDoctypeName);
+                    self.reconsume = true;
+                    self.state = State::BeforeDoctypeName;
+                }
+                State::BeforeDoctypeName => {
+                    if is_whitespace(c) {
+                        // 空白文字は無視
+                        continue;
+                    }
+
+                    if self.is_eof() {
+                        self.parse_error(ParseErrorKind::EofInDoctype);
+                        self.create_doctype();
+                        self.set_force_quirks_flag();
+                        return self.take_latest_token();
+                    }
+
+                    if c == '>' {
+                        self.parse_error(ParseErrorKind::MissingDoctypeName);
+                        self.create_doctype();
+                        self.set_force_quirks_flag();
+                        self.state = State::Data;
+                        return self.take_latest_token();
+                    }
+
+                    self.create_doctype();
+                    self.reconsume = true;
+                    self.state = State::DoctypeName;
+                }
+                State::DoctypeName => {
+                    if is_whitespace(c) {
+                        self.state = State::AfterDoctypeName;
+                        continue;
+                    }
+
+                    if c == '>' {
+                        self.state = State::Data;
+                        return self.take_latest_token();
+                    }
+
+                    if self.is_eof() {
+                        self.parse_error(ParseErrorKind::EofInDoctype);
+                        self.set_force_quirks_flag();
+                        return self.take_latest_token();
+                    }
+
+                    if c == '\0' {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.append_doctype_name(char::REPLACEMENT_CHARACTER);
+                        continue;
+                    }
+
+                    self.append_doctype_name(c.to_ascii_lowercase());
+                }
+                State::AfterDoctypeName => {
+                    if is_whitespace(c) {
+                        // 空白文字は無視
+                        continue;
+                    }
+
+                    if c == '>' {
+                        self.state = State::Data;
+                        return self.take_latest_token();
+                    }
+
+                    if self.is_eof() {
+                        self.parse_error(ParseErrorKind::EofInDoctype);
+                        self.set_force_quirks_flag();
+                        return self.take_latest_token();
+                    }
+
+                    // 現在の文字から始まる文字列を調べる
+                    let start = self.current_char_start();
+                    if self.needs_more_input(start, "PUBLIC".len()) {
+                        // 現在の文字は入力が追加されてから読み直す
+                        self.reconsume = true;
+                        return None;
+                    }
+
+                    if self.input_matches(start, "PUBLIC", true) {
+                        self.pos = start + 6;
+                        self.state = State::AfterDoctypePublicKeyword;
+                        continue;
+                    }
+
+                    if self.input_matches(start, "SYSTEM", true) {
+                        self.pos = start + 6;
+                        self.state = State::AfterDoctypeSystemKeyword;
+                        continue;
+                    }
+
+                    self.parse_error(ParseErrorKind::InvalidCharacterSequenceAfterDoctypeName);
+                    self.set_force_quirks_flag();
+                    self.reconsume = true;
+                    self.state = State::BogusDoctype;
+                }
+                State::AfterDoctypePublicKeyword | State::AfterDoctypeSystemKeyword => {
+                    let is_public = self.state == State::AfterDoctypePublicKeyword;
+
+                    if is_whitespace(c) {
+                        self.state = if is_public {
+                            State::BeforeDoctypePublicIdentifier
+                        } else {
+                            State::BeforeDoctypeSystemIdentifier
+                        };
+                        continue;
+                    }
+
+                    // キーワードの直後に識別子が続く場合は、空白文字があるものとして扱う
+                    if c == '"' || c == '\'' {
+                        self.parse_error(if is_public {
+                            ParseErrorKind::MissingWhitespaceAfterDoctypePublicKeyword
+                        } else {
+                            ParseErrorKind::MissingWhitespaceAfterDoctypeSystemKeyword
+                        });
+                    }
+                    self.reconsume = true;
+                    self.state = if is_public {
+                        State::BeforeDoctypePublicIdentifier
+                    } else {
+                        State::BeforeDoctypeSystemIdentifier
+                    };
+                }
+                State::BeforeDoctypePublicIdentifier | State::BeforeDoctypeSystemIdentifier => {
+                    let is_public = self.state == State::BeforeDoctypePublicIdentifier;
+
+                    if is_whitespace(c) {
+                        // 空白文字は無視
+                        continue;
+                    }
+
+                    if c == '"' {
+                        self.start_doctype_identifier(is_public);
+                        self.state = if is_public {
+                            State::DoctypePublicIdentifierDoubleQuoted
+                        } else {
+                            State::DoctypeSystemIdentifierDoubleQuoted
+                        };
+                        continue;
+                    }
+
+                    if c == '\'' {
+                        self.start_doctype_identifier(is_public);
+                        self.state = if is_public {
+                            State::DoctypePublicIdentifierSingleQuoted
+                        } else {
+                            State::DoctypeSystemIdentifierSingleQuoted
+                        };
+                        continue;
+                    }
+
+                    if c == '>' {
+                        self.parse_error(if is_public {
+                            ParseErrorKind::MissingDoctypePublicIdentifier
+                        } else {
+                            ParseErrorKind::MissingDoctypeSystemIdentifier
+                        });
+                        self.set_force_quirks_flag();
+                        self.state = State::Data;
+                        return self.take_latest_token();
+                    }
+
+                    if self.is_eof() {
+                        self.parse_error(ParseErrorKind::EofInDoctype);
+                        self.set_force_quirks_flag();
+                        return self.take_latest_token();
+                    }
+
+                    self.parse_error(if is_public {
+                        ParseErrorKind::MissingQuoteBeforeDoctypePublicIdentifier
+                    } else {
+                        ParseErrorKind::MissingQuoteBeforeDoctypeSystemIdentifier
+                    });
+                    self.set_force_quirks_flag();
+                    self.reconsume = true;
+                    self.state = State::BogusDoctype;
+                }
+                State::DoctypePublicIdentifierDoubleQuoted
+                | State::DoctypePublicIdentifierSingleQuoted
+                | State::DoctypeSystemIdentifierDoubleQuoted
+                | State::DoctypeSystemIdentifierSingleQuoted => {
+                    let (is_public, quote) = match self.state {
+                        State::DoctypePublicIdentifierDoubleQuoted => (true, '"'),
+                        State::DoctypePublicIdentifierSingleQuoted => (true, '\''),
+                        State::DoctypeSystemIdentifierDoubleQuoted => (false, '"'),
+                        _ => (false, '\''),
+                    };
+
+                    if c == quote {
+                        self.state = if is_public {
+                            State::AfterDoctypePublicIdentifier
+                        } else {
+                            State::AfterDoctypeSystemIdentifier
+                        };
+                        continue;
+                    }
+
+                    if c == '>' {
+                        self.parse_error(if is_public {
+                            ParseErrorKind::AbruptDoctypePublicIdentifier
+                        } else {
+                            ParseErrorKind::AbruptDoctypeSystemIdentifier
+                        });
+                        self.set_force_quirks_flag();
+                        self.state = State::Data;
+                        return self.take_latest_token();
+                    }
+
+                    if self.is_eof() {
+                        self.parse_error(ParseErrorKind::EofInDoctype);
+                        self.set_force_quirks_flag();
+                        return self.take_latest_token();
+                    }
+
+                    if c == '\0' {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        self.append_doctype_identifier(char::REPLACEMENT_CHARACTER, is_public);
+                        continue;
+                    }
+
+                    self.append_doctype_identifier(c, is_public);
+                }
+                State::AfterDoctypePublicIdentifier
+                | State::BetweenDoctypePublicAndSystemIdentifiers => {
+                    if is_whitespace(c) {
+                        self.state = State::BetweenDoctypePublicAndSystemIdentifiers;
+                        continue;
+                    }
+
+                    if c == '>' {
+                        self.state = State::Data;
+                        return self.take_latest_token();
+                    }
+
+                    if c == '"' || c == '\'' {
+                        // 公開識別子の直後にシステム識別子が続く場合はパースの失敗
+                        if self.state == State::AfterDoctypePublicIdentifier {
+                            self.parse_error(
+                                ParseErrorKind::MissingWhitespaceBetweenDoctypePublicAndSystemIdentifiers,
+                            );
+                        }
+                        self.start_doctype_identifier(false);
+                        self.state = if c == '"' {
+                            State::DoctypeSystemIdentifierDoubleQuoted
+                        } else {
+                            State::DoctypeSystemIdentifierSingleQuoted
+                        };
+                        continue;
+                    }
+
+                    if self.is_eof() {
+                        self.parse_error(ParseErrorKind::EofInDoctype);
+                        self.set_force_quirks_flag();
+                        return self.take_latest_token();
+                    }
+
+                    self.parse_error(ParseErrorKind::MissingQuoteBeforeDoctypeSystemIdentifier);
+                    self.set_force_quirks_flag();
+                    self.reconsume = true;
+                    self.state = State::BogusDoctype;
+                }
+                State::AfterDoctypeSystemIdentifier => {
+                    if is_whitespace(c) {
+                        // 空白文字は無視
+                        continue;
+                    }
+
+                    if c == '>' {
+                        self.state = State::Data;
+                        return self.take_latest_token();
+                    }
+
+                    if self.is_eof() {
+                        self.parse_error(ParseErrorKind::EofInDoctype);
+                        self.set_force_quirks_flag();
+                        return self.take_latest_token();
+                    }
+
+                    self.parse_error(
+                        ParseErrorKind::UnexpectedCharacterAfterDoctypeSystemIdentifier,
+                    );
+                    // force-quirks フラグは設定しない
+                    self.reconsume = true;
+                    self.state = State::BogusDoctype;
+                }
+                State::BogusDoctype => {
+                    if c == '>' {
+                        self.state = State::Data;
+                        return self.take_latest_token();
+                    }
+
+                    if self.is_eof() {
+                        return self.take_latest_token();
+                    }
+
+                    // その他の文字は無視
+                }
+                State::CdataSection => {
+                    if c == ']' {
+                        self.state = State::CdataSectionBracket;
+                        continue;
+                    }
+
+                    if self.is_eof() {
+                        self.parse_error(ParseErrorKind::EofInCdata);
+                        return None;
+                    }
+
+                    return Some(HtmlToken::Char(c));
+                }
+                State::CdataSectionBracket => {
+                    if c == ']' {
+                        self.state = State::CdataSectionEnd;
+                        continue;
+                    }
+
+                    self.reconsume = true;
+                    self.state = State::CdataSection;
+                    return Some(HtmlToken::Char(']'));
+                }
+                State::CdataSectionEnd => {
+                    if c == ']' {
+                        return Some(HtmlToken::Char(']'));
+                    }
+
+                    if c == '>' {
+                        self.state = State::Data;
+                        continue;
+                    }
+
+                    self.reconsume = true;
+                    self.state = State::CdataSection;
+                    self.pending_tokens.push_back(HtmlToken::Char(']'));
+                    return Some(HtmlToken::Char(']'));
+                }
+                State::CharacterReference => {
+                    self.buf = String::from("&");
+
+                    if c.is_ascii_alphanumeric() {
+                        self.reconsume = true;
+                        self.state = State::NamedCharacterReference;
+                        continue;
+                    }
+
+                    if c == '#' {
+                        self.buf.push(c);
+                        self.state = State::NumericCharacterReference;
+                        continue;
+                    }
+
+                    self.flush_code_points_consumed_as_character_reference();
+                    self.reconsume = true;
+                    self.state = self.return_state.clone();
+                }
+                State::NamedCharacterReference => {
+                    // 現在の文字から、名前付き文字参照の表に含まれる最も長い名前を読み込む
+                    let start = self.current_char_start();
+                    // 名前の終わりと、その次の 1文字まで読めるようになるまで待つ
+                    let name_is_complete = self.input[start..]
+                        .bytes()
+                        .take(character_reference::LONGEST_NAME_LENGTH)
+                        .position(|b| !b.is_ascii_alphanumeric())
+                        .is_some_and(|i| !self.needs_more_input(start, i + 2));
+                    if !name_is_complete
+                        && self
+                            .needs_more_input(start, character_reference::LONGEST_NAME_LENGTH + 1)
+                    {
+                        self.reconsume = true;
+                        return None;
+                    }
+                    let Some((len, chars)) = self.match_named_character_reference(start) else {
+                        // 読み込んだ文字はないので、現在の文字を次に読み直す
+                        self.pos = start;
+                        self.flush_code_points_consumed_as_character_reference();
+                        self.state = State::AmbiguousAmpersand;
+                        continue;
+                    };
+
+                    self.pos = start + len;
+                    self.buf.push_str(&self.input[start..self.pos]);
+
+                    let last_is_semicolon = self.input.as_bytes()[self.pos - 1] == b';';
+                    let next = self.input.as_bytes().get(self.pos);
+                    if self.is_return_state_attribute_value()
+                        && !last_is_semicolon
+                        && next.is_some_and(|b| *b == b'=' || b.is_ascii_alphanumeric())
+                    {
+                        // 歴史的な理由により、属性値の中ではセミコロンのない文字参照を解釈しない
+                        self.flush_code_points_consumed_as_character_reference();
+                        self.state = self.return_state.clone();
+                        continue;
+                    }
+
+                    if !last_is_semicolon {
+                        self.parse_error(ParseErrorKind::MissingSemicolonAfterCharacterReference);
+                    }
+                    self.buf = String::from(chars);
+                    self.flush_code_points_consumed_as_character_reference();
+                    self.state = self.return_state.clone();
+                }
+                State::AmbiguousAmpersand => {
+                    if c.is_ascii_alphanumeric() {
+                        if self.is_return_state_attribute_value() {
+                            self.append_attribute(c, false);
+                        } else {
+                            return Some(HtmlToken::Char(c));
+                        }
+                        continue;
+                    }
+
+                    if c == ';' {
+                        self.parse_error(ParseErrorKind::UnknownNamedCharacterReference);
+                    }
+                    self.reconsume = true;
+                    self.state = self.return_state.clone();
+                }
+                State::NumericCharacterReference => {
+                    self.character_reference_code = 0;
+
+                    if c == 'x' || c == 'X' {
+                        self.buf.push(c);
+                        self.state = State::HexadecimalCharacterReferenceStart;
+                        continue;
+                    }
+
+                    self.reconsume = true;
+                    self.state = State::DecimalCharacterReferenceStart;
+                }
+                State::HexadecimalCharacterReferenceStart => {
+                    if c.is_ascii_hexdigit() {
+                        self.reconsume = true;
+                        self.state = State::HexadecimalCharacterReference;
+                        continue;
+                    }
+
+                    self.parse_error(ParseErrorKind::AbsenceOfDigitsInNumericCharacterReference);
+                    self.flush_code_points_consumed_as_character_reference();
+                    self.reconsume = true;
+                    self.state = self.return_state.clone();
+                }
+                State::DecimalCharacterReferenceStart => {
+                    if c.is_ascii_digit() {
+                        self.reconsume = true;
+                        self.state = State::DecimalCharacterReference;
+                        continue;
+                    }
+
+                    self.parse_error(ParseErrorKind::AbsenceOfDigitsInNumericCharacterReference);
+                    self.flush_code_points_consumed_as_character_reference();
+                    self.reconsume = true;
+                    self.state = self.return_state.clone();
+                }
+                State::HexadecimalCharacterReference | State::DecimalCharacterReference => {
+                    let radix = if self.state == State::HexadecimalCharacterReference {
+                        16
+                    } else {
+                        10
+                    };
+
+                    if let Some(digit) = c.to_digit(radix) {
+                        // 大きすぎる値は範囲外として扱われるので、オーバーフローしないように丸める
+                        self.character_reference_code = self
+                            .character_reference_code
+                            .saturating_mul(radix)
+                            .saturating_add(digit)
+                            .min(0x110000);
+                        continue;
+                    }
+
+                    if c == ';' {
+                        self.end_numeric_character_reference();
+                        continue;
+                    }
+
+                    self.parse_error(ParseErrorKind::MissingSemicolonAfterCharacterReference);
+                    self.end_numeric_character_reference();
+                    self.reconsume = true;
+                }
+                State::RcData | State::RawText | State::ScriptData | State::Plaintext => {
+                    if self.is_eof() {
+                        return None;
+                    }
+
+                    if c == '&' && self.state == State::RcData {
+                        self.return_state = State::RcData;
+                        self.state = State::CharacterReference;
+                        continue;
+                    }
+
+                    if c == '<' {
+                        match self.state {
+                            State::RcData => self.state = State::RcDataLessThanSign,
+                            State::RawText => self.state = State::RawTextLessThanSign,
+                            State::ScriptData => self.state = State::ScriptDataLessThanSign,
+                            _ => return Some(HtmlToken::Char(c)),
+                        }
+                        continue;
+                    }
+
+                    if c == '\0' {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        return Some(HtmlToken::Char(char::REPLACEMENT_CHARACTER));
+                    }
+
+                    return Some(HtmlToken::Char(c));
+                }
+                State::RcDataLessThanSign | State::RawTextLessThanSign => {
+                    let (text_state, end_tag_open_state) =
+                        if self.state == State::RcDataLessThanSign {
+                            (State::RcData, State::RcDataEndTagOpen)
+                        } else {
+                            (State::RawText, State::RawTextEndTagOpen)
+                        };
+
+                    if c == '/' {
+                        // 一時的なバッファを空文字でリセットする
+                        self.buf = String::new();
+                        self.state = end_tag_open_state;
+                        continue;
+                    }
+
+                    self.reconsume = true;
+                    self.state = text_state;
+                    return Some(HtmlToken::Char('<'));
+                }
+                State::ScriptDataLessThanSign => {
+                    if c == '/' {
+                        // 一時的なバッファを空文字でリセットする
+                        self.buf = String::new();
+                        self.state = State::ScriptDataEndTagOpen;
+                        continue;
+                    }
+
+                    if c == '!' {
+                        self.state = State::ScriptDataEscapeStart;
+                        self.pending_tokens.push_back(HtmlToken::Char('!'));
+                        return Some(HtmlToken::Char('<'));
+                    }
+
+                    self.reconsume = true;
+                    self.state = State::ScriptData;
+                    return Some(HtmlToken::Char('<'));
+                }
+                State::RcDataEndTagOpen
+                | State::RawTextEndTagOpen
+                | State::ScriptDataEndTagOpen
+                | State::ScriptDataEscapedEndTagOpen => {
+                    let (text_state, end_tag_name_state) = match self.state {
+                        State::RcDataEndTagOpen => (State::RcData, State::RcDataEndTagName),
+                        State::RawTextEndTagOpen => (State::RawText, State::RawTextEndTagName),
+                        State::ScriptDataEndTagOpen => {
+                            (State::ScriptData, State::ScriptDataEndTagName)
+                        }
+                        _ => (State::ScriptDataEscaped, State::ScriptDataEscapedEndTagName),
+                    };
+
                     if c.is_ascii_alphabetic() {
                         self.reconsume = true;
-                        self.state = State::ScriptDataEndTagName;
+                        self.state = end_tag_name_state;
                         self.create_tag(false);
                         continue;
                     }
 
                     self.reconsume = true;
-                    self.state = State::ScriptData;
-                    // 仕様では、"<" と "/" の2つの文字トークンを返すとなっているが、
-                    // 私たちの実装では next メソッドからは一つのトークンしか返せないため、
-                    // "<" のトークンのみを返す
+                    self.state = text_state;
+                    self.pending_tokens.push_back(HtmlToken::Char('/'));
                     return Some(HtmlToken::Char('<'));
                 }
-                State::ScriptDataEndTagName => {
-                    if c == '>' {
-                        self.state = State::Data;
-                        return self.take_latest_token();
+                State::RcDataEndTagName
+                | State::RawTextEndTagName
+                | State::ScriptDataEndTagName
+                | State::ScriptDataEscapedEndTagName => {
+                    let text_state = match self.state {
+                        State::RcDataEndTagName => State::RcData,
+                        State::RawTextEndTagName => State::RawText,
+                        State::ScriptDataEndTagName => State::ScriptData,
+                        _ => State::ScriptDataEscaped,
+                    };
+
+                    // 終了タグが直前の開始タグと対応している場合のみ、タグとして扱う
+                    if self.is_appropriate_end_tag_token() {
+                        if is_whitespace(c) {
+                            self.state = State::BeforeAttributeName;
+                            continue;
+                        }
+
+                        if c == '/' {
+                            self.state = State::SelfClosingStartTag;
+                            continue;
+                        }
+
+                        if c == '>' {
+                            self.state = State::Data;
+                            return self.take_latest_token();
+                        }
                     }
 
                     if c.is_ascii_alphabetic() {
@@ -404,27 +1880,157 @@ impl Iterator for HtmlTokenizer {
                         continue;
                     }
 
-                    self.state = State::TemporaryBuffer;
-                    self.buf = String::from("</") + &self.buf;
-                    self.buf.push(c);
+                    // 終了タグではなかったので、読み込んだ文字を文字トークンとして出力する
+                    self.latest_token = None;
+                    self.pending_tokens.push_back(HtmlToken::Char('<'));
+                    self.pending_tokens.push_back(HtmlToken::Char('/'));
+                    for c in core::mem::take(&mut self.buf).chars() {
+                        self.pending_tokens.push_back(HtmlToken::Char(c));
+                    }
+                    self.reconsume = true;
+                    self.state = text_state;
                 }
-                State::TemporaryBuffer => {
+                State::ScriptDataEscapeStart | State::ScriptDataEscapeStartDash => {
+                    if c == '-' {
+                        self.state = if self.state == State::ScriptDataEscapeStart {
+                            State::ScriptDataEscapeStartDash
+                        } else {
+                            State::ScriptDataEscapedDashDash
+                        };
+                        return Some(HtmlToken::Char('-'));
+                    }
+
                     self.reconsume = true;
+                    self.state = State::ScriptData;
+                }
+                State::ScriptDataEscaped
+                | State::ScriptDataEscapedDash
+                | State::ScriptDataEscapedDashDash => {
+                    if self.is_eof() {
+                        self.parse_error(ParseErrorKind::EofInScriptHtmlCommentLikeText);
+                        return None;
+                    }
+
+                    if c == '-' {
+                        self.state = match self.state {
+                            State::ScriptDataEscaped => State::ScriptDataEscapedDash,
+                            _ => State::ScriptDataEscapedDashDash,
+                        };
+                        return Some(HtmlToken::Char('-'));
+                    }
+
+                    if c == '<' {
+                        self.state = State::ScriptDataEscapedLessThanSign;
+                        continue;
+                    }
 
-                    if self.buf.chars().count() == 0 {
+                    if c == '>' && self.state == State::ScriptDataEscapedDashDash {
                         self.state = State::ScriptData;
+                        return Some(HtmlToken::Char('>'));
+                    }
+
+                    self.state = State::ScriptDataEscaped;
+
+                    if c == '\0' {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        return Some(HtmlToken::Char(char::REPLACEMENT_CHARACTER));
+                    }
+
+                    return Some(HtmlToken::Char(c));
+                }
+                State::ScriptDataEscapedLessThanSign => {
+                    if c == '/' {
+                        // 一時的なバッファを空文字でリセットする
+                        self.buf = String::new();
+                        self.state = State::ScriptDataEscapedEndTagOpen;
                         continue;
                     }
 
-                    // 最初の1文字を削除する
-                    let c = self
-                        .buf
-                        .chars()
-                        .nth(0)
-                        .expect("self.buf should have at least 1 char");
-                    self.buf.remove(0);
+                    if c.is_ascii_alphabetic() {
+                        self.buf = String::new();
+                        self.reconsume = true;
+                        self.state = State::ScriptDataDoubleEscapeStart;
+                        return Some(HtmlToken::Char('<'));
+                    }
+
+                    self.reconsume = true;
+                    self.state = State::ScriptDataEscaped;
+                    return Some(HtmlToken::Char('<'));
+                }
+                State::ScriptDataDoubleEscapeStart | State::ScriptDataDoubleEscapeEnd => {
+                    let (state_if_script, state_otherwise) =
+                        if self.state == State::ScriptDataDoubleEscapeStart {
+                            (State::ScriptDataDoubleEscaped, State::ScriptDataEscaped)
+                        } else {
+                            (State::ScriptDataEscaped, State::ScriptDataDoubleEscaped)
+                        };
+
+                    if is_whitespace(c) || c == '/' || c == '>' {
+                        self.state = if self.buf == "script" {
+                            state_if_script
+                        } else {
+                            state_otherwise
+                        };
+                        return Some(HtmlToken::Char(c));
+                    }
+
+                    if c.is_ascii_alphabetic() {
+                        self.buf.push(c.to_ascii_lowercase());
+                        return Some(HtmlToken::Char(c));
+                    }
+
+                    self.reconsume = true;
+                    self.state = if self.state == State::ScriptDataDoubleEscapeStart {
+                        State::ScriptDataEscaped
+                    } else {
+                        State::ScriptDataDoubleEscaped
+                    };
+                }
+                State::ScriptDataDoubleEscaped
+                | State::ScriptDataDoubleEscapedDash
+                | State::ScriptDataDoubleEscapedDashDash => {
+                    if self.is_eof() {
+                        self.parse_error(ParseErrorKind::EofInScriptHtmlCommentLikeText);
+                        return None;
+                    }
+
+                    if c == '-' {
+                        self.state = match self.state {
+                            State::ScriptDataDoubleEscaped => State::ScriptDataDoubleEscapedDash,
+                            _ => State::ScriptDataDoubleEscapedDashDash,
+                        };
+                        return Some(HtmlToken::Char('-'));
+                    }
+
+                    if c == '<' {
+                        self.state = State::ScriptDataDoubleEscapedLessThanSign;
+                        return Some(HtmlToken::Char('<'));
+                    }
+
+                    if c == '>' && self.state == State::ScriptDataDoubleEscapedDashDash {
+                        self.state = State::ScriptData;
+                        return Some(HtmlToken::Char('>'));
+                    }
+
+                    self.state = State::ScriptDataDoubleEscaped;
+
+                    if c == '\0' {
+                        self.parse_error(ParseErrorKind::UnexpectedNullCharacter);
+                        return Some(HtmlToken::Char(char::REPLACEMENT_CHARACTER));
+                    }
+
                     return Some(HtmlToken::Char(c));
                 }
+                State::ScriptDataDoubleEscapedLessThanSign => {
+                    if c == '/' {
+                        self.buf = String::new();
+                        self.state = State::ScriptDataDoubleEscapeEnd;
+                        return Some(HtmlToken::Char('/'));
+                    }
+
+                    self.reconsume = true;
+                    self.state = State::ScriptDataDoubleEscaped;
+                }
             }
         }
     }
@@ -442,10 +2048,43 @@ pub enum HtmlToken {
     EndTag { tag: String },
     /// 文字
     Char(char),
+    /// 入力中の連続した文字。文字参照などを含まない文字の並びは、1文字ずつではなくまとめて出力する。
+    /// 文字列は `HtmlTokenizer::text` で取り出す
+    Text(TextSpan),
+    /// コメント
+    Comment(String),
+    /// DOCTYPE
+    Doctype {
+        name: Option<String>,
+        public_id: Option<String>,
+        system_id: Option<String>,
+        force_quirks: bool,
+    },
     /// ファイルの終了 (End Of File)
     Eof,
 }
 
+/// トークナイザの入力中の範囲 (バイト単位)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextSpan {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl TextSpan {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum State {
     Data,
@@ -461,11 +2100,72 @@ pub enum State {
     AttributeValueUnquoted,
     AfterAttributeValueQuoted,
     SelfClosingStartTag,
+    RcData,
+    RcDataLessThanSign,
+    RcDataEndTagOpen,
+    RcDataEndTagName,
+    RawText,
+    RawTextLessThanSign,
+    RawTextEndTagOpen,
+    RawTextEndTagName,
     ScriptData,
     ScriptDataLessThanSign,
     ScriptDataEndTagOpen,
     ScriptDataEndTagName,
-    TemporaryBuffer,
+    ScriptDataEscapeStart,
+    ScriptDataEscapeStartDash,
+    ScriptDataEscaped,
+    ScriptDataEscapedDash,
+    ScriptDataEscapedDashDash,
+    ScriptDataEscapedLessThanSign,
+    ScriptDataEscapedEndTagOpen,
+    ScriptDataEscapedEndTagName,
+    ScriptDataDoubleEscapeStart,
+    ScriptDataDoubleEscaped,
+    ScriptDataDoubleEscapedDash,
+    ScriptDataDoubleEscapedDashDash,
+    ScriptDataDoubleEscapedLessThanSign,
+    ScriptDataDoubleEscapeEnd,
+    Plaintext,
+    CharacterReference,
+    NamedCharacterReference,
+    AmbiguousAmpersand,
+    NumericCharacterReference,
+    HexadecimalCharacterReferenceStart,
+    DecimalCharacterReferenceStart,
+    HexadecimalCharacterReference,
+    DecimalCharacterReference,
+    MarkupDeclarationOpen,
+    CommentStart,
+    CommentStartDash,
+    Comment,
+    CommentLessThanSign,
+    CommentLessThanSignBang,
+    CommentLessThanSignBangDash,
+    CommentLessThanSignBangDashDash,
+    CommentEndDash,
+    CommentEnd,
+    CommentEndBang,
+    BogusComment,
+    CdataSection,
+    CdataSectionBracket,
+    CdataSectionEnd,
+    Doctype,
+    BeforeDoctypeName,
+    DoctypeName,
+    AfterDoctypeName,
+    AfterDoctypePublicKeyword,
+    BeforeDoctypePublicIdentifier,
+    DoctypePublicIdentifierDoubleQuoted,
+    DoctypePublicIdentifierSingleQuoted,
+    AfterDoctypePublicIdentifier,
+    BetweenDoctypePublicAndSystemIdentifiers,
+    AfterDoctypeSystemKeyword,
+    BeforeDoctypeSystemIdentifier,
+    DoctypeSystemIdentifierDoubleQuoted,
+    DoctypeSystemIdentifierSingleQuoted,
+    AfterDoctypeSystemIdentifier,
+    BogusDoctype,
 }
 
 #[cfg(test)]
@@ -473,6 +2173,23 @@ mod tests {
     use super::*;
     use alloc::{string::ToString, vec};
 
+    /// `HtmlToken::Text` を 1文字ずつの `HtmlToken::Char` に分ける
+    fn split_text(tokenizer: &HtmlTokenizer, token: HtmlToken) -> Vec<HtmlToken> {
+        match token {
+            HtmlToken::Text(span) => tokenizer.text(span).chars().map(HtmlToken::Char).collect(),
+            token => vec![token],
+        }
+    }
+
+    /// 残りのトークンをすべて読み込む。連続した文字は 1文字ずつのトークンにする
+    fn collect_chars(mut tokenizer: HtmlTokenizer) -> Vec<HtmlToken> {
+        let mut tokens = Vec::new();
+        while let Some(token) = tokenizer.next() {
+            tokens.extend(split_text(&tokenizer, token));
+        }
+        tokens
+    }
+
     #[test]
     fn test_empty() {
         let html = "".to_string();
@@ -543,6 +2260,70 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_duplicate_attribute() {
+        let html = "<p id=a ID=\"b\" class=c id>".to_string();
+        let mut tokenizer = HtmlTokenizer::new(html);
+        // 最初の属性が優先され、後から出てきた同じ名前の属性は値ごと取り除かれる
+        assert_eq!(
+            Some(HtmlToken::StartTag {
+                tag: "p".to_string(),
+                self_closing: false,
+                attributes: vec![
+                    Attribute::new_with_value("id", "a"),
+                    Attribute::new_with_value("class", "c"),
+                ],
+            }),
+            tokenizer.next()
+        );
+        let errors: Vec<_> = tokenizer
+            .take_errors()
+            .iter()
+            .map(|e| (e.kind(), e.position()))
+            .collect();
+        assert_eq!(
+            vec![
+                (
+                    ParseErrorKind::DuplicateAttribute,
+                    SourcePosition::new(1, 11)
+                ),
+                (
+                    ParseErrorKind::DuplicateAttribute,
+                    SourcePosition::new(1, 26)
+                ),
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn test_many_duplicate_attributes() {
+        // 属性が多い場合は集合で重複を調べるが、結果は同じになる
+        let mut html = "<p".to_string();
+        for i in 0..100 {
+            html.push_str(&alloc::format!(" a{}=x a{}=y", i % 50, i));
+        }
+        html.push('>');
+        let mut tokenizer = HtmlTokenizer::new(html);
+        let Some(HtmlToken::StartTag { attributes, .. }) = tokenizer.next() else {
+            panic!("failed to get a start tag");
+        };
+        let names: Vec<String> = attributes.iter().map(|a| a.name()).collect();
+        let expected: Vec<String> = (0..100).map(|i| alloc::format!("a{}", i)).collect();
+        assert_eq!(expected, names);
+        assert!(attributes
+            .iter()
+            .all(|a| a.value() == "x" || a.value() == "y"));
+        assert_eq!(
+            100,
+            tokenizer
+                .take_errors()
+                .iter()
+                .filter(|e| e.kind() == ParseErrorKind::DuplicateAttribute)
+                .count()
+        );
+    }
+
     #[test]
     fn test_self_closing_tag() {
         let html = "<img />".to_string();
@@ -560,7 +2341,7 @@ mod tests {
     #[test]
     fn test_script_tag() {
         let html = "<script>js code;</script>".to_string();
-        let mut tokenizer = HtmlTokenizer::new(html);
+        let tokenizer = HtmlTokenizer::new(html);
         let mut expected = vec![HtmlToken::StartTag {
             tag: "script".to_string(),
             self_closing: false,
@@ -573,8 +2354,334 @@ mod tests {
             tag: "script".to_string(),
         });
 
-        for e in expected {
-            assert_eq!(Some(e), tokenizer.next());
+        assert_eq!(expected, collect_chars(tokenizer));
+    }
+
+    #[test]
+    fn test_text_runs() {
+        let html = "<p>Hello, 世界!</p>&amp;x".to_string();
+        let mut tokenizer = HtmlTokenizer::new(html);
+        assert!(matches!(tokenizer.next(), Some(HtmlToken::StartTag { .. })));
+
+        // 文字参照などを含まない文字の並びは、入力の範囲としてまとめて出力する
+        let Some(HtmlToken::Text(span)) = tokenizer.next() else {
+            panic!("a text run is expected");
+        };
+        assert_eq!(TextSpan::new(3, 17), span);
+        assert_eq!("Hello, 世界!", tokenizer.text(span));
+
+        assert!(matches!(tokenizer.next(), Some(HtmlToken::EndTag { .. })));
+        assert_eq!(Some(HtmlToken::Char('&')), tokenizer.next());
+        let Some(HtmlToken::Text(span)) = tokenizer.next() else {
+            panic!("a text run is expected");
+        };
+        assert_eq!("x", tokenizer.text(span));
+        assert_eq!(None, tokenizer.next());
+    }
+
+    #[test]
+    fn test_character_reference_in_data() {
+        let html = "&amp;&lt;&#x41;&#66;&nbsp;&notit;&#x80;&#0;&unknown;".to_string();
+        let tokenizer = HtmlTokenizer::new(html);
+        let expected: Vec<_> = "&<AB\u{a0}\u{ac}it;\u{20ac}\u{fffd}&unknown;"
+            .chars()
+            .map(HtmlToken::Char)
+            .collect();
+
+        assert_eq!(expected, collect_chars(tokenizer));
+    }
+
+    #[test]
+    fn test_character_reference_without_semicolon() {
+        let html = "&amp &copy2 &#65x".to_string();
+        let tokenizer = HtmlTokenizer::new(html);
+        let expected: Vec<_> = "& \u{a9}2 Ax".chars().map(HtmlToken::Char).collect();
+
+        assert_eq!(expected, collect_chars(tokenizer));
+    }
+
+    #[test]
+    fn test_character_reference_in_attribute_value() {
+        let html = "<a href=\"?a=1&amp;b=2&copy=3\" title='&lt;&#x3E;' alt=&quot;x>".to_string();
+        let mut tokenizer = HtmlTokenizer::new(html);
+
+        let mut attr1 = Attribute::new();
+        for c in "href".chars() {
+            attr1.add_char(c, true);
+        }
+        for c in "?a=1&b=2&copy=3".chars() {
+            attr1.add_char(c, false);
+        }
+
+        let mut attr2 = Attribute::new();
+        for c in "title".chars() {
+            attr2.add_char(c, true);
+        }
+        for c in "<>".chars() {
+            attr2.add_char(c, false);
+        }
+
+        let mut attr3 = Attribute::new();
+        for c in "alt".chars() {
+            attr3.add_char(c, true);
+        }
+        for c in "\"x".chars() {
+            attr3.add_char(c, false);
+        }
+
+        let expected = HtmlToken::StartTag {
+            tag: "a".to_string(),
+            self_closing: false,
+            attributes: vec![attr1, attr2, attr3],
+        };
+        assert_eq!(Some(expected), tokenizer.next());
+    }
+
+    #[test]
+    fn test_comment() {
+        let html = "<!-- comment --><!---->x<!--a--!>".to_string();
+        let tokenizer = HtmlTokenizer::new(html);
+        let expected = vec![
+            HtmlToken::Comment(" comment ".to_string()),
+            HtmlToken::Comment(String::new()),
+            HtmlToken::Char('x'),
+            HtmlToken::Comment("a".to_string()),
+        ];
+
+        assert_eq!(expected, collect_chars(tokenizer));
+    }
+
+    #[test]
+    fn test_bogus_comment() {
+        let html = "<?xml version=\"1.0\"?></ foo><!foo>".to_string();
+        let tokenizer = HtmlTokenizer::new(html);
+        let expected = vec![
+            HtmlToken::Comment("?xml version=\"1.0\"?".to_string()),
+            HtmlToken::Comment(" foo".to_string()),
+            HtmlToken::Comment("foo".to_string()),
+        ];
+
+        assert_eq!(expected, collect_chars(tokenizer));
+    }
+
+    #[test]
+    fn test_doctype() {
+        let html = "<!DOCTYPE html><!doctype HTML PUBLIC \"-//W3C//DTD HTML 4.01//EN\" 'http://www.w3.org/TR/html4/strict.dtd'><!DOCTYPE>".to_string();
+        let tokenizer = HtmlTokenizer::new(html);
+        let expected = vec![
+            HtmlToken::Doctype {
+                name: Some("html".to_string()),
+                public_id: None,
+                system_id: None,
+                force_quirks: false,
+            },
+            HtmlToken::Doctype {
+                name: Some("html".to_string()),
+                public_id: Some("-//W3C//DTD HTML 4.01//EN".to_string()),
+                system_id: Some("http://www.w3.org/TR/html4/strict.dtd".to_string()),
+                force_quirks: false,
+            },
+            HtmlToken::Doctype {
+                name: None,
+                public_id: None,
+                system_id: None,
+                force_quirks: true,
+            },
+        ];
+
+        assert_eq!(expected, collect_chars(tokenizer));
+    }
+
+    #[test]
+    fn test_rcdata() {
+        let html = "<title><b>&amp;</titles></title>".to_string();
+        let mut tokenizer = HtmlTokenizer::new(html);
+        assert_eq!(
+            Some(HtmlToken::StartTag {
+                tag: "title".to_string(),
+                self_closing: false,
+                attributes: Vec::new(),
+            }),
+            tokenizer.next()
+        );
+
+        tokenizer.set_state(State::RcData);
+        let mut expected: Vec<_> = "<b>&</titles>".chars().map(HtmlToken::Char).collect();
+        expected.push(HtmlToken::EndTag {
+            tag: "title".to_string(),
+        });
+        assert_eq!(expected, collect_chars(tokenizer));
+    }
+
+    #[test]
+    fn test_rawtext() {
+        let html = "<style>a &amp; <b></style>".to_string();
+        let mut tokenizer = HtmlTokenizer::new(html);
+        assert_eq!(
+            Some(HtmlToken::StartTag {
+                tag: "style".to_string(),
+                self_closing: false,
+                attributes: Vec::new(),
+            }),
+            tokenizer.next()
+        );
+
+        tokenizer.set_state(State::RawText);
+        let mut expected: Vec<_> = "a &amp; <b>".chars().map(HtmlToken::Char).collect();
+        expected.push(HtmlToken::EndTag {
+            tag: "style".to_string(),
+        });
+        assert_eq!(expected, collect_chars(tokenizer));
+    }
+
+    #[test]
+    fn test_script_data_escaped() {
+        let html = "<script><!--<script></script>--></script>".to_string();
+        let mut tokenizer = HtmlTokenizer::new(html);
+        assert_eq!(
+            Some(HtmlToken::StartTag {
+                tag: "script".to_string(),
+                self_closing: false,
+                attributes: Vec::new(),
+            }),
+            tokenizer.next()
+        );
+
+        tokenizer.set_state(State::ScriptData);
+        let mut expected: Vec<_> = "<!--<script></script>-->"
+            .chars()
+            .map(HtmlToken::Char)
+            .collect();
+        expected.push(HtmlToken::EndTag {
+            tag: "script".to_string(),
+        });
+        assert_eq!(expected, collect_chars(tokenizer));
+    }
+
+    #[test]
+    fn test_cdata_section() {
+        let html = "<![CDATA[a]]b<]]]>".to_string();
+        let mut tokenizer = HtmlTokenizer::new(html);
+        tokenizer.set_in_foreign_content(true);
+        let expected: Vec<_> = "a]]b<]".chars().map(HtmlToken::Char).collect();
+        assert_eq!(expected, collect_chars(tokenizer));
+
+        // HTML の中では CDATA セクションはコメントとして扱われる
+        let html = "<![CDATA[a]]>".to_string();
+        let mut tokenizer = HtmlTokenizer::new(html);
+        assert_eq!(
+            Some(HtmlToken::Comment("[CDATA[a]]".to_string())),
+            tokenizer.next()
+        );
+        assert!(tokenizer.next().is_none());
+    }
+
+    #[test]
+    fn test_token_position() {
+        let html = "<p>a\n  <b c=d>".to_string();
+        let mut tokenizer = HtmlTokenizer::new(html);
+        let mut positions = Vec::new();
+        while tokenizer.next().is_some() {
+            positions.push(tokenizer.token_position());
+        }
+        assert_eq!(
+            vec![
+                SourcePosition::new(1, 1),
+                SourcePosition::new(1, 4),
+                SourcePosition::new(2, 3),
+            ],
+            positions
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let html = "<a\0>\n</a x>&#0;<b".to_string();
+        let mut tokenizer = HtmlTokenizer::new(html);
+        while tokenizer.next().is_some() {}
+        let errors: Vec<_> = tokenizer
+            .take_errors()
+            .iter()
+            .map(|e| (e.kind(), e.position()))
+            .collect();
+        assert_eq!(
+            vec![
+                (
+                    ParseErrorKind::UnexpectedNullCharacter,
+                    SourcePosition::new(1, 3)
+                ),
+                (
+                    ParseErrorKind::EndTagWithAttributes,
+                    SourcePosition::new(2, 5)
+                ),
+                (
+                    ParseErrorKind::NullCharacterReference,
+                    SourcePosition::new(2, 10)
+                ),
+                (ParseErrorKind::EofInTag, SourcePosition::new(2, 13)),
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn test_parse_errors_on_long_line() {
+        // 1行にトークンとパースの失敗が交互に並んでも、位置を求めるたびに行の先頭から数え直さない
+        let html = "é<a\0>".repeat(50_000);
+        let mut tokenizer = HtmlTokenizer::new(html);
+        let mut positions = Vec::new();
+        while let Some(token) = tokenizer.next() {
+            if let HtmlToken::StartTag { .. } = token {
+                positions.push(tokenizer.token_position());
+            }
+        }
+        let errors = tokenizer.take_errors();
+        assert_eq!(50_000, positions.len());
+        assert_eq!(50_000, errors.len());
+        for (i, (position, error)) in positions.iter().zip(errors.iter()).enumerate() {
+            assert_eq!(SourcePosition::new(1, i * 5 + 2), *position);
+            assert_eq!(SourcePosition::new(1, i * 5 + 4), error.position());
+        }
+    }
+
+    #[test]
+    fn test_streaming() {
+        let html = "<!DOCTYPE html>\r\n<p class='a'>x &amp; y&notin<!-- c --></p>\r\n";
+        let expected = collect_chars(HtmlTokenizer::new(html.to_string()));
+
+        // 1文字ずつ追加しても、まとめて渡した場合と同じトークンになる
+        let mut tokenizer = HtmlTokenizer::new_streaming();
+        let mut tokens = Vec::new();
+        for c in html.chars() {
+            tokenizer.feed(c.encode_utf8(&mut [0; 4]));
+            while let Some(token) = tokenizer.next() {
+                tokens.extend(split_text(&tokenizer, token));
+            }
         }
+        tokenizer.finish();
+        tokens.extend(collect_chars(tokenizer));
+        assert_eq!(expected, tokens);
+    }
+
+    #[test]
+    fn test_streaming_yields_completed_tokens() {
+        let mut tokenizer = HtmlTokenizer::new_streaming();
+        tokenizer.feed("a<di");
+        assert_eq!(Some(HtmlToken::Text(TextSpan::new(0, 1))), tokenizer.next());
+        // タグが閉じられるまでは返さない
+        assert_eq!(None, tokenizer.next());
+        assert!(!tokenizer.is_finished());
+
+        tokenizer.feed("v>");
+        assert_eq!(
+            Some(HtmlToken::StartTag {
+                tag: "div".to_string(),
+                self_closing: false,
+                attributes: Vec::new(),
+            }),
+            tokenizer.next()
+        );
+        tokenizer.finish();
+        assert_eq!(None, tokenizer.next());
     }
 }
@@ -1,4 +1,8 @@
-use alloc::{string::String, vec::Vec};
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Url {
@@ -42,13 +46,56 @@ impl Url {
     }
 
     pub fn path(&self) -> String {
-        self.port.clone()
+        self.path.clone()
     }
 
     pub fn searchpart(&self) -> String {
         self.searchpart.clone()
     }
 
+    /// `input` (href 属性の値など) をこの URL を基準として解決し、絶対 URL を返す。`parse` 済みの
+    /// URL に対して呼ぶ。スキームが付いている場合はそのまま返す
+    /// https://url.spec.whatwg.org/#concept-basic-url-parser
+    pub fn join(&self, input: &str) -> String {
+        let input = input.trim();
+        if has_scheme(input) {
+            return input.to_string();
+        }
+        if let Some(rest) = input.strip_prefix("//") {
+            return format!("http://{}", rest);
+        }
+
+        let authority = if self.port == "80" {
+            format!("http://{}", self.host)
+        } else {
+            format!("http://{}:{}", self.host, self.port)
+        };
+        // 基準の URL の path には、? がない場合に # 以降が含まれていることがある
+        let base_path = self.path.split('#').next().unwrap_or("");
+        let base_without_fragment = self.url.split('#').next().unwrap_or("");
+
+        if input.is_empty() || input.starts_with('#') {
+            return format!("{}{}", base_without_fragment, input);
+        }
+        if input.starts_with('?') {
+            return format!("{}/{}{}", authority, base_path, input);
+        }
+
+        // path と、その後ろの ? や # 以降に分ける
+        let end = input.find(['?', '#']).unwrap_or(input.len());
+        let (path, rest) = input.split_at(end);
+        let path = if path.starts_with('/') {
+            path.to_string()
+        } else {
+            // 基準の URL の最後のセグメントを取り除いて連結する
+            match base_path.rfind('/') {
+                Some(i) => format!("/{}/{}", &base_path[..i], path),
+                None => format!("/{}", path),
+            }
+        };
+        format!("{}{}{}", authority, remove_dot_segments(&path), rest)
+    }
+
     fn is_http(&self) -> bool {
         self.url.starts_with("http://")
     }
@@ -110,6 +157,43 @@ impl Url {
     }
 }
 
+/// "http:" や "mailto:" のようにスキームで始まるかどうか
+/// https://url.spec.whatwg.org/#scheme-state
+fn has_scheme(input: &str) -> bool {
+    let Some(colon) = input.find(':') else {
+        return false;
+    };
+    let scheme = &input[..colon];
+    let mut chars = scheme.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+}
+
+/// path の "." と ".." のセグメントを取り除く
+/// https://url.spec.whatwg.org/#path-state
+fn remove_dot_segments(path: &str) -> String {
+    let mut segments: Vec<&str> = Vec::new();
+    let mut iter = path.split('/').skip(1).peekable();
+    while let Some(segment) = iter.next() {
+        let is_last = iter.peek().is_none();
+        match segment {
+            "." => {
+                if is_last {
+                    segments.push("");
+                }
+            }
+            ".." => {
+                segments.pop();
+                if is_last {
+                    segments.push("");
+                }
+            }
+            _ => segments.push(segment),
+        }
+    }
+    format!("/{}", segments.join("/"))
+}
+
 #[cfg(test)]
 mod tests {
     use alloc::string::ToString;
@@ -194,4 +278,28 @@ mod tests {
         let expected = Err("Only HTTP scheme is supported.".into());
         assert_eq!(expected, Url::new(url).parse());
     }
+
+    #[test]
+    fn test_join() {
+        let base = Url::new("http://example.com:8888/a/b/index.html?q=1#top".to_string())
+            .parse()
+            .expect("failed to parse a base url");
+        assert_eq!("http://other.com/x", base.join("http://other.com/x"));
+        assert_eq!("mailto:a@example.com", base.join("mailto:a@example.com"));
+        assert_eq!("http://other.com/x", base.join("//other.com/x"));
+        assert_eq!("http://example.com:8888/x?y#z", base.join("/x?y#z"));
+        assert_eq!("http://example.com:8888/a/b/c.html", base.join("c.html"));
+        assert_eq!("http://example.com:8888/a/c.html", base.join("../c.html"));
+        assert_eq!("http://example.com:8888/a/b/", base.join("./"));
+        assert_eq!("http://example.com:8888/a/b/index.html?r", base.join("?r"));
+        assert_eq!(
+            "http://example.com:8888/a/b/index.html?q=1#foo",
+            base.join("#foo")
+        );
+
+        let base = Url::new("http://example.com".to_string())
+            .parse()
+            .expect("failed to parse a base url");
+        assert_eq!("http://example.com/example.com", base.join("example.com"));
+    }
 }
@@ -1,25 +1,56 @@
-use core::cell::RefCell;
+use alloc::{format, string::String, vec::Vec};
 
-use alloc::{format, rc::Rc, string::String};
+use crate::renderer::dom::node::{Dom, NodeId};
 
-use crate::renderer::dom::node::Node;
-
-pub fn convert_dom_to_string(root: &Option<Rc<RefCell<Node>>>) -> String {
+pub fn convert_dom_to_string(dom: &Dom, root: Option<NodeId>) -> String {
     let mut result = String::from("\n");
-    convert_dom_to_string_inner(root, 0, &mut result);
+    // 深い木でもスタックがあふれないように、これから書き出すノードとその深さを積んでたどる
+    let mut stack = Vec::from_iter(root.map(|root| (root, 0)));
+    while let Some((node, depth)) = stack.pop() {
+        result.push_str(&"  ".repeat(depth));
+        result.push_str(&format!("{:?}", dom.node(node).kind()));
+        result.push('\n');
+        // 子孫を書き出してから次の兄弟を書き出す
+        if let Some(sibling) = dom.node(node).next_sibling() {
+            stack.push((sibling, depth));
+        }
+        if let Some(child) = dom.node(node).first_child() {
+            stack.push((child, depth + 1));
+        }
+    }
     result
 }
 
-fn convert_dom_to_string_inner(
-    node: &Option<Rc<RefCell<Node>>>,
-    depth: usize,
-    result: &mut String,
-) {
-    if let Some(node) = node {
-        result.push_str(&"  ".repeat(depth));
-        result.push_str(&format!("{:?}", node.borrow().kind()));
-        result.push('\n');
-        convert_dom_to_string_inner(&node.borrow().first_child(), depth + 1, result);
-        convert_dom_to_string_inner(&node.borrow().next_sibling(), depth, result);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::html::{parser::HtmlParser, token::HtmlTokenizer};
+    use alloc::{string::ToString, vec};
+
+    /// 各行の字下げの深さを返す
+    fn depths(s: &str) -> Vec<usize> {
+        s.lines()
+            .skip(1)
+            .map(|line| (line.len() - line.trim_start().len()) / 2)
+            .collect()
+    }
+
+    #[test]
+    fn test_convert_dom_to_string() {
+        let window = HtmlParser::new(HtmlTokenizer::new("<p>a</p>b".to_string())).construct_tree();
+        let s = convert_dom_to_string(window.dom(), Some(window.document()));
+        // Document, html, head, body, p, "a", "b" の順に木順で並ぶ
+        assert_eq!(vec![0, 1, 2, 2, 3, 4, 3], depths(&s));
+        assert_eq!("\n", convert_dom_to_string(window.dom(), None));
+    }
+
+    #[test]
+    fn test_many_siblings() {
+        // 兄弟の数だけ再帰しないので、兄弟が多くてもスタックがあふれない
+        let count = 100_000;
+        let window = HtmlParser::new(HtmlTokenizer::new("<br>".repeat(count))).construct_tree();
+        let s = convert_dom_to_string(window.dom(), Some(window.document()));
+        let expected: Vec<usize> = [0, 1, 2, 2].into_iter().chain([3].repeat(count)).collect();
+        assert_eq!(expected, depths(&s));
     }
 }
@@ -0,0 +1,131 @@
+//! html5lib-tests のテストデータを使って、HtmlTokenizer と HtmlParser が仕様にどれだけ沿っているかを
+//! 確かめるテスト
+//!
+//! テストデータは tests/html5lib/tokenizer と tests/html5lib/tree-construction に置いている。
+//! 各テストは通った数と失敗した数を表示し、既知の失敗 (`EXPECTED_FAILURES`) 以外のテストが失敗した
+//! 場合や、既知の失敗が通るようになった場合、どのテストにも当たらない既知の失敗が残っている場合に
+//! 失敗する。パースの失敗 (`errors`・`#errors`) は比べない。
+
+mod json;
+mod tokenizer;
+mod tree_construction;
+
+use std::{collections::BTreeSet, fs, path::PathBuf};
+
+/// `dir` 以下にある拡張子が `extension` のファイルを名前順に読み込む
+fn read_test_files(dir: &str, extension: &str) -> Vec<(String, String)> {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("html5lib")
+        .join(dir);
+    let mut files: Vec<_> = fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("failed to read {}: {}", dir.display(), e))
+        .map(|entry| entry.expect("failed to read a directory entry").path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == extension))
+        .collect();
+    files.sort();
+
+    files
+        .into_iter()
+        .map(|path| {
+            let name = path
+                .file_name()
+                .expect("failed to get a file name")
+                .to_string_lossy()
+                .into_owned();
+            let content = fs::read_to_string(&path)
+                .unwrap_or_else(|e| panic!("failed to read {}: {}", path.display(), e));
+            (name, content)
+        })
+        .collect()
+}
+
+/// テストの結果を集計する
+#[derive(Debug, Default)]
+struct Summary {
+    passed: usize,
+    skipped: usize,
+    /// 既知の失敗
+    expected_failures: usize,
+    /// 既知の失敗以外で失敗したテスト
+    failures: Vec<String>,
+    /// 既知の失敗に含まれているのに通ったテスト
+    unexpected_passes: Vec<String>,
+    /// 実行したテストのうち、既知の失敗に含まれていたもの
+    matched_expected_failures: BTreeSet<String>,
+}
+
+impl Summary {
+    fn record(
+        &mut self,
+        name: &str,
+        passed: bool,
+        expected_to_fail: bool,
+        detail: impl FnOnce() -> String,
+    ) {
+        match (passed, expected_to_fail) {
+            (true, false) => self.passed += 1,
+            (true, true) => self.unexpected_passes.push(name.to_string()),
+            (false, true) => self.expected_failures += 1,
+            (false, false) => self.failures.push(detail()),
+        }
+    }
+
+    /// `key` のテストが既知の失敗かどうかを返し、既知の失敗が実際に使われたことを記録する
+    fn is_expected_failure(&mut self, expected_failures: &[&str], key: &str) -> bool {
+        let expected = expected_failures.contains(&key);
+        if expected {
+            self.matched_expected_failures.insert(key.to_string());
+        }
+        expected
+    }
+
+    fn skip(&mut self) {
+        self.skipped += 1;
+    }
+
+    /// 集計結果を表示し、想定外の結果があればパニックする。テストデータを差し替えたときに古い項目が
+    /// 残らないように、どのテストにも当たらなかった `expected_failures` の項目も想定外として扱う
+    fn report(&self, suite: &str, expected_failures: &[&str]) {
+        let unknown_expected_failures: Vec<&str> = expected_failures
+            .iter()
+            .copied()
+            .filter(|key| !self.matched_expected_failures.contains(*key))
+            .collect();
+
+        println!(
+            "{}: {} passed, {} failed, {} expected failures, {} skipped",
+            suite,
+            self.passed,
+            self.failures.len(),
+            self.expected_failures,
+            self.skipped,
+        );
+        for failure in &self.failures {
+            println!("FAIL: {}", failure);
+        }
+        for name in &self.unexpected_passes {
+            println!(
+                "UNEXPECTED PASS (remove it from EXPECTED_FAILURES): {}",
+                name
+            );
+        }
+        for key in &unknown_expected_failures {
+            println!(
+                "UNKNOWN EXPECTED FAILURE (no test matches it, remove it from EXPECTED_FAILURES): {:?}",
+                key
+            );
+        }
+
+        assert!(
+            self.failures.is_empty()
+                && self.unexpected_passes.is_empty()
+                && unknown_expected_failures.is_empty(),
+            "{}: {} failures, {} unexpected passes, {} unknown expected failures",
+            suite,
+            self.failures.len(),
+            self.unexpected_passes.len(),
+            unknown_expected_failures.len(),
+        );
+    }
+}
@@ -0,0 +1,203 @@
+//! html5lib-tests のトークナイザのテスト (tokenizer/*.test)
+//! https://github.com/html5lib/html5lib-tests/tree/master/tokenizer
+
+use std::collections::BTreeMap;
+
+use saba_core::renderer::html::token::{HtmlToken, HtmlTokenizer, State};
+
+use super::{
+    json::{self, Value},
+    read_test_files, Summary,
+};
+
+/// まだ仕様どおりに動かないテストの description
+const EXPECTED_FAILURES: &[&str] = &[];
+
+#[test]
+fn tokenizer() {
+    let mut summary = Summary::default();
+
+    for (file, content) in read_test_files("tokenizer", "test") {
+        let root = json::parse(&content).unwrap_or_else(|e| panic!("{}: {}", file, e));
+        let tests = root
+            .get("tests")
+            .and_then(Value::as_array)
+            .unwrap_or_else(|| panic!("{}: `tests` is not found", file));
+
+        for test in tests {
+            run_test(&file, test, &mut summary);
+        }
+    }
+
+    summary.report("tokenizer", EXPECTED_FAILURES);
+}
+
+fn run_test(file: &str, test: &Value, summary: &mut Summary) {
+    let description = test
+        .get("description")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    let double_escaped = test
+        .get("doubleEscaped")
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+
+    let mut input = test
+        .get("input")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    let mut expected = test.get("output").cloned().unwrap_or(Value::Null);
+    if double_escaped {
+        input = unescape(&input);
+        expected = unescape_value(&expected);
+    }
+
+    let initial_states = match test.get("initialStates").and_then(Value::as_array) {
+        Some(states) => states
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect(),
+        None => vec!["Data state".to_string()],
+    };
+    let last_start_tag = test.get("lastStartTag").and_then(Value::as_str);
+
+    for initial_state in initial_states {
+        let name = format!("{}: {} ({})", file, description, initial_state);
+        let Some(state) = state_from_name(&initial_state) else {
+            summary.skip();
+            continue;
+        };
+
+        let mut tokenizer = HtmlTokenizer::new(input.clone());
+        tokenizer.set_state(state);
+        if let Some(tag) = last_start_tag {
+            tokenizer.set_last_start_tag(tag);
+        }
+        let actual = tokens_to_value(tokenizer);
+
+        let expected_to_fail = summary.is_expected_failure(EXPECTED_FAILURES, description);
+        summary.record(
+            &name,
+            actual == expected,
+            expected_to_fail,
+            || {
+                format!(
+                    "{}\n  input:    {:?}\n  expected: {:?}\n  actual:   {:?}",
+                    name, input, expected, actual
+                )
+            },
+        );
+    }
+}
+
+fn state_from_name(name: &str) -> Option<State> {
+    match name {
+        "Data state" => Some(State::Data),
+        "PLAINTEXT state" => Some(State::Plaintext),
+        "RCDATA state" => Some(State::RcData),
+        "RAWTEXT state" => Some(State::RawText),
+        "Script data state" => Some(State::ScriptData),
+        "CDATA section state" => Some(State::CdataSection),
+        _ => None,
+    }
+}
+
+/// トークンを html5lib-tests の出力形式に変換する。連続する文字はひとつにまとめる
+fn tokens_to_value(mut tokenizer: HtmlTokenizer) -> Value {
+    let mut output = Vec::new();
+    let mut characters = String::new();
+
+    while let Some(token) = tokenizer.next() {
+        match token {
+            HtmlToken::Char(c) => {
+                characters.push(c);
+                continue;
+            }
+            HtmlToken::Text(span) => {
+                characters.push_str(tokenizer.text(span));
+                continue;
+            }
+            _ => {}
+        }
+        if !characters.is_empty() {
+            output.push(array(vec![
+                string("Character"),
+                string(&std::mem::take(&mut characters)),
+            ]));
+        }
+
+        match token {
+            HtmlToken::StartTag {
+                tag,
+                self_closing,
+                attributes,
+            } => {
+                let mut map = BTreeMap::new();
+                for attr in attributes {
+                    map.insert(attr.name(), Value::String(attr.value()));
+                }
+                let mut values = vec![string("StartTag"), string(&tag), Value::Object(map)];
+                if self_closing {
+                    values.push(Value::Bool(true));
+                }
+                output.push(array(values));
+            }
+            HtmlToken::EndTag { tag } => output.push(array(vec![string("EndTag"), string(&tag)])),
+            HtmlToken::Comment(data) => output.push(array(vec![string("Comment"), string(&data)])),
+            HtmlToken::Doctype {
+                name,
+                public_id,
+                system_id,
+                force_quirks,
+            } => output.push(array(vec![
+                string("DOCTYPE"),
+                optional_string(name),
+                optional_string(public_id),
+                optional_string(system_id),
+                Value::Bool(!force_quirks),
+            ])),
+            HtmlToken::Char(_) | HtmlToken::Text(_) | HtmlToken::Eof => {}
+        }
+    }
+
+    if !characters.is_empty() {
+        output.push(array(vec![string("Character"), string(&characters)]));
+    }
+
+    Value::Array(output)
+}
+
+fn string(s: &str) -> Value {
+    Value::String(s.to_string())
+}
+
+fn optional_string(s: Option<String>) -> Value {
+    s.map_or(Value::Null, Value::String)
+}
+
+fn array(values: Vec<Value>) -> Value {
+    Value::Array(values)
+}
+
+/// doubleEscaped なテストの "\uXXXX" を文字に戻す
+fn unescape(s: &str) -> String {
+    match json::parse(&format!("\"{}\"", s.replace('"', "\\\""))) {
+        Ok(Value::String(unescaped)) => unescaped,
+        _ => s.to_string(),
+    }
+}
+
+fn unescape_value(value: &Value) -> Value {
+    match value {
+        Value::String(s) => Value::String(unescape(s)),
+        Value::Array(values) => Value::Array(values.iter().map(unescape_value).collect()),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(k, v)| (unescape(k), unescape_value(v)))
+                .collect(),
+        ),
+        v => v.clone(),
+    }
+}
@@ -0,0 +1,197 @@
+//! html5lib-tests の木構築のテスト (tree-construction/*.dat)
+//! https://github.com/html5lib/html5lib-tests/tree/master/tree-construction
+
+use saba_core::renderer::{
+    dom::node::{Dom, Element, Namespace, NodeId, NodeKind, Window},
+    html::{parser::HtmlParser, token::HtmlTokenizer},
+};
+
+use super::{read_test_files, Summary};
+
+/// まだ仕様どおりに動かないテストの #data
+const EXPECTED_FAILURES: &[&str] = &[];
+
+#[test]
+fn tree_construction() {
+    let mut summary = Summary::default();
+
+    for (file, content) in read_test_files("tree-construction", "dat") {
+        for (i, test) in parse_dat(&content).into_iter().enumerate() {
+            let name = format!("{} #{}: {:?}", file, i + 1, test.data);
+
+            // スクリプトが無効な場合のテストには、まだ対応していない
+            if test.script_off {
+                summary.skip();
+                continue;
+            }
+
+            let actual = match test.fragment_context {
+                Some(ref context) => dump_fragment(context, &test.data),
+                None => dump_document(&test.data),
+            };
+            let expected_to_fail = summary.is_expected_failure(EXPECTED_FAILURES, &test.data);
+            summary.record(
+                &name,
+                actual == test.document,
+                expected_to_fail,
+                || {
+                    format!(
+                        "{}\n--- expected\n{}\n--- actual\n{}",
+                        name, test.document, actual
+                    )
+                },
+            );
+        }
+    }
+
+    summary.report("tree-construction", EXPECTED_FAILURES);
+}
+
+#[derive(Debug, Default)]
+struct TestCase {
+    data: String,
+    fragment_context: Option<String>,
+    script_off: bool,
+    document: String,
+}
+
+/// .dat ファイルをテストごとに分ける
+fn parse_dat(content: &str) -> Vec<TestCase> {
+    let mut tests = Vec::new();
+    let mut test = TestCase::default();
+    let mut section = "";
+    let mut data_lines: Vec<&str> = Vec::new();
+    let mut document_lines: Vec<&str> = Vec::new();
+
+    let mut finish = |test: &mut TestCase, data: &mut Vec<&str>, document: &mut Vec<&str>| {
+        test.data = data.join("\n");
+        // テストの区切りの空行を取り除く
+        while document.last() == Some(&"") {
+            document.pop();
+        }
+        test.document = document.join("\n");
+        data.clear();
+        document.clear();
+        tests.push(std::mem::take(test));
+    };
+
+    for line in content.lines() {
+        match line {
+            "#data" => {
+                if !section.is_empty() {
+                    finish(&mut test, &mut data_lines, &mut document_lines);
+                }
+                section = line;
+            }
+            // #errors と #new-errors の中身は比べないので、読み飛ばす
+            "#errors" | "#new-errors" | "#document" | "#document-fragment" => section = line,
+            "#script-off" => test.script_off = true,
+            "#script-on" => {}
+            _ => match section {
+                "#data" => data_lines.push(line),
+                "#document-fragment" => test.fragment_context = Some(line.to_string()),
+                "#document" => document_lines.push(line),
+                _ => {}
+            },
+        }
+    }
+    if !section.is_empty() {
+        finish(&mut test, &mut data_lines, &mut document_lines);
+    }
+
+    tests
+}
+
+fn dump_document(html: &str) -> String {
+    let t = HtmlTokenizer::new(html.to_string());
+    let window = HtmlParser::new(t).construct_tree();
+
+    let mut lines = Vec::new();
+    dump_children(window.dom(), window.document(), 0, &mut lines);
+    lines.join("\n")
+}
+
+/// `context` は "td" や "svg path" のような、名前空間の接頭辞がつくこともある要素名
+fn dump_fragment(context: &str, html: &str) -> String {
+    let (namespace, name) = match context.split_once(' ') {
+        Some(("svg", name)) => (Namespace::Svg, name),
+        Some(("math", name)) => (Namespace::MathMl, name),
+        _ => (Namespace::Html, context),
+    };
+    let mut window = Window::new();
+    let dom = window.dom_mut();
+    let context = dom.create_node(NodeKind::Element(Element::new_with_namespace(
+        name,
+        Vec::new(),
+        namespace,
+    )));
+
+    let mut lines = Vec::new();
+    for node in HtmlParser::parse_fragment(dom, context, html) {
+        dump_node(dom, node, 0, &mut lines);
+    }
+    lines.join("\n")
+}
+
+fn dump_children(dom: &Dom, node: NodeId, depth: usize, lines: &mut Vec<String>) {
+    let mut child = dom.node(node).first_child();
+    while let Some(c) = child {
+        dump_node(dom, c, depth, lines);
+        child = dom.node(c).next_sibling();
+    }
+}
+
+/// html5lib-tests の "| <tag>" 形式でノードを出力する
+fn dump_node(dom: &Dom, node: NodeId, depth: usize, lines: &mut Vec<String>) {
+    let indent = format!("| {}", "  ".repeat(depth));
+
+    match dom.node(node).kind() {
+        NodeKind::Document(_) | NodeKind::DocumentFragment => {}
+        NodeKind::DocumentType(doctype) => {
+            if doctype.public_id().is_empty() && doctype.system_id().is_empty() {
+                lines.push(format!("{}<!DOCTYPE {}>", indent, doctype.name()));
+            } else {
+                lines.push(format!(
+                    "{}<!DOCTYPE {} \"{}\" \"{}\">",
+                    indent,
+                    doctype.name(),
+                    doctype.public_id(),
+                    doctype.system_id()
+                ));
+            }
+        }
+        NodeKind::Text(text) => lines.push(format!("{}\"{}\"", indent, text)),
+        NodeKind::Comment(data) => lines.push(format!("{}<!-- {} -->", indent, data)),
+        NodeKind::Element(element) => {
+            let prefix = match element.namespace() {
+                Namespace::Svg => "svg ",
+                Namespace::MathMl => "math ",
+                _ => "",
+            };
+            lines.push(format!("{}<{}{}>", indent, prefix, element.local_name()));
+
+            let mut attributes: Vec<(String, String)> = element
+                .attributes()
+                .iter()
+                .map(|attr| {
+                    let name = match attr.prefix() {
+                        Some(prefix) => format!("{} {}", prefix, attr.name()),
+                        None => attr.name(),
+                    };
+                    (name, attr.value())
+                })
+                .collect();
+            attributes.sort();
+            for (name, value) in attributes {
+                lines.push(format!("{}  {}=\"{}\"", indent, name, value));
+            }
+
+            if let Some(contents) = element.template_contents() {
+                lines.push(format!("{}  content", indent));
+                dump_children(dom, contents, depth + 2, lines);
+            }
+        }
+    }
+
+    dump_children(dom, node, depth + 1, lines);
+}
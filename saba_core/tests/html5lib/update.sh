@@ -0,0 +1,27 @@
+#!/bin/bash -xe
+
+# html5lib-tests からテストデータを取得し、手を加えずにこのディレクトリへ置く。取得したコミットは
+# REVISION に記録する。
+#
+# 使い方: ./update.sh [ブランチ、タグ、またはコミット]
+
+REF="${1:-master}"
+REPOSITORY="https://github.com/html5lib/html5lib-tests.git"
+DEST_PATH="$(cd "$(dirname "$0")" && pwd)"
+WORK_PATH="$(mktemp -d)"
+trap 'rm -rf "$WORK_PATH"' EXIT
+
+TOKENIZER_FILES="test1.test escapeFlag.test"
+TREE_CONSTRUCTION_FILES="tests1.dat tests2.dat tests3.dat adoption01.dat tables01.dat"
+
+git clone --quiet "$REPOSITORY" "$WORK_PATH"
+git -C "$WORK_PATH" checkout --quiet "$REF"
+
+for file in $TOKENIZER_FILES; do
+	cp "$WORK_PATH/tokenizer/$file" "$DEST_PATH/tokenizer/$file"
+done
+for file in $TREE_CONSTRUCTION_FILES; do
+	cp "$WORK_PATH/tree-construction/$file" "$DEST_PATH/tree-construction/$file"
+done
+
+git -C "$WORK_PATH" rev-parse HEAD > "$DEST_PATH/REVISION"